@@ -92,7 +92,8 @@ impl Command {
     }
 
     fn parse_lines(&self, lines: Vec<String>) -> Vec<LogEntry> {
-        let regex_str = r"^\[INFO\] -> (\d+): (\d+)\|(\d+)\|([a-z])\|(.*)$";
+        // a trailing ` x<count>` stands for a run of identical events, see --dedup of cairn-fuse
+        let regex_str = r"^\[INFO\] -> (\d+): (\d+)\|(\d+)\|([a-z])\|(.*?)(?: x\d+)?$";
         let regex = Regex::new(regex_str).unwrap();
 
         let mut order = 0;
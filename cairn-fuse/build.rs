@@ -0,0 +1,33 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// the version of flatc that produced schema/trace_generated.rs, matching the flatbuffers crate
+const FLATC_VERSION: &str = "23.5.26";
+
+fn main() {
+    let schema_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../schema");
+    let schema = schema_dir.join("trace.fbs");
+    let pregenerated = schema_dir.join("trace_generated.rs");
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    println!("cargo:rerun-if-changed={}", schema.display());
+    println!("cargo:rerun-if-changed={}", pregenerated.display());
+
+    // other versions of flatc generate code the flatbuffers crate does not compile with
+    let flatc = Command::new("flatc").arg("--version").output();
+    if flatc.is_ok_and(|x| String::from_utf8_lossy(&x.stdout).contains(FLATC_VERSION)) {
+        let status = Command::new("flatc")
+            .arg("--rust")
+            .arg("-o")
+            .arg(&out_dir)
+            .arg(&schema)
+            .status()
+            .expect("failed to run flatc");
+        assert!(status.success(), "flatc failed on {}", schema.display());
+    } else {
+        fs::copy(&pregenerated, out_dir.join("trace_generated.rs"))
+            .expect("failed to copy the pre-generated trace schema");
+    }
+}
@@ -0,0 +1,48 @@
+use cairn_fuse::{fbs, TraceFormat};
+use clap::{crate_version, Arg, Command};
+use std::io::{self, Write};
+use std::{fs, process};
+
+fn cli() -> Command {
+    Command::new("cairn-trace")
+        .version(crate_version!())
+        .about("Inspects binary traces written with `--trace-dest fbs:<path>`")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("dump")
+                .about("Print every event of a trace as a JSON line")
+                .arg(Arg::new("trace").required(true).value_name("TRACE")),
+        )
+}
+
+fn dump(path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+    let mut stdout = io::stdout().lock();
+    for event in fbs::decode(&bytes) {
+        let event = event.map_err(|e| format!("Invalid event in {path}: {e}"))?;
+        let line = match event.render(TraceFormat::Json) {
+            Some(x) => x,
+            None => continue,
+        };
+        match writeln!(stdout, "{line}") {
+            Ok(()) => {}
+            // the reader, like `head`, has seen enough
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(format!("Failed to write: {e}")),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = cli().get_matches();
+
+    let result = match matches.subcommand() {
+        Some(("dump", args)) => dump(args.get_one::<String>("trace").unwrap()),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
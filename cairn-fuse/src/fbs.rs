@@ -0,0 +1,160 @@
+use crate::{Layer, TraceEvent, TraceOp};
+use flatbuffers::{FlatBufferBuilder, InvalidFlatbuffer};
+use std::fmt;
+
+// flatc output, not held to the lints of the crate
+#[allow(clippy::all, warnings)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/trace_generated.rs"));
+}
+
+use generated::cairn::trace::{self as schema, Op, TraceEventArgs};
+
+// size of the little endian length in front of every event
+const PREFIX_LEN: usize = 4;
+
+// The variants are in CamelCase, the names of the operations in snake_case
+fn same_name(variant: &str, name: &str) -> bool {
+    let name = name.bytes().filter(|&x| x != b'_');
+    variant.bytes().map(|x| x.to_ascii_lowercase()).eq(name)
+}
+
+fn op_from_name(name: &str) -> Op {
+    Op::ENUM_VALUES
+        .iter()
+        .copied()
+        .find(|op| op.variant_name().is_some_and(|x| same_name(x, name)))
+        .unwrap_or(Op::Unknown)
+}
+
+// Serializes an event as a size prefixed buffer, the builder is reused across events so the
+// allocation sticks around
+pub fn encode<'a>(builder: &'a mut FlatBufferBuilder<'static>, event: &TraceEvent) -> &'a [u8] {
+    builder.reset();
+
+    let comm = event.comm.as_deref().map(|x| builder.create_string(x));
+    let cmdline = event.cmdline.as_deref().map(|args| {
+        let args: Vec<_> = args.iter().map(|x| builder.create_string(x)).collect();
+        builder.create_vector(&args)
+    });
+    let path = Some(builder.create_string(&event.path));
+    let target = event.target.as_deref().map(|x| builder.create_string(x));
+    let sha256 = event.sha256.as_deref().map(|x| builder.create_string(x));
+    let layer = event.layer.map(|x| builder.create_string(x.name()));
+
+    let root = schema::TraceEvent::create(
+        builder,
+        &TraceEventArgs {
+            ts: event.ts,
+            monotonic_us: event.monotonic_us,
+            latency_us: event.latency_us,
+            pid: event.pid,
+            ppid: event.ppid,
+            uid: event.uid,
+            gid: event.gid,
+            comm,
+            cmdline,
+            kind: event.kind as u8,
+            op: op_from_name(event.op.name()),
+            ino: event.ino,
+            path,
+            target,
+            offset: event.offset,
+            size: event.size,
+            errno: event.result.err().unwrap_or(0),
+            count: event.count,
+            bytes_read: event.bytes_read,
+            bytes_written: event.bytes_written,
+            coverage: event.coverage,
+            sha256,
+            tid: event.tid,
+            first_offset: event.first_offset,
+            last_offset: event.last_offset,
+            layer,
+            materialized: event.materialized,
+        },
+    );
+    builder.finish_size_prefixed(root, None);
+    builder.finished_data()
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Invalid(InvalidFlatbuffer),
+    // written by a newer version, which traces operations this one does not know about
+    UnknownOp(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Invalid(e) => write!(f, "{}", e),
+            DecodeError::UnknownOp(op) => write!(f, "unknown operation {}", op),
+        }
+    }
+}
+
+fn to_event(event: schema::TraceEvent) -> Result<TraceEvent, DecodeError> {
+    let op = event
+        .op()
+        .variant_name()
+        .and_then(|variant| {
+            TraceOp::ALL
+                .into_iter()
+                .find(|op| same_name(variant, op.name()))
+        })
+        .ok_or(DecodeError::UnknownOp(event.op().0))?;
+
+    Ok(TraceEvent {
+        ts: event.ts(),
+        pid: event.pid(),
+        ppid: event.ppid(),
+        kind: event.kind() as char,
+        uid: event.uid(),
+        gid: event.gid(),
+        comm: event.comm().map(str::to_string),
+        cmdline: event
+            .cmdline()
+            .map(|args| args.iter().map(str::to_string).collect()),
+        op,
+        ino: event.ino(),
+        path: event.path().unwrap_or_default().to_string(),
+        target: event.target().map(str::to_string),
+        result: match event.errno() {
+            0 => Ok(()),
+            errno => Err(errno),
+        },
+        offset: event.offset(),
+        size: event.size(),
+        latency_us: event.latency_us(),
+        monotonic_us: event.monotonic_us(),
+        count: event.count(),
+        bytes_read: event.bytes_read(),
+        bytes_written: event.bytes_written(),
+        coverage: event.coverage(),
+        first_offset: event.first_offset(),
+        last_offset: event.last_offset(),
+        sha256: event.sha256().map(str::to_string),
+        tid: event.tid(),
+        layer: event.layer().and_then(Layer::from_name),
+        materialized: event.materialized(),
+        group: None,
+    })
+}
+
+// Reads back the events of a binary trace, a truncated event at the end, as left behind by a
+// crash, ends the trace instead of failing it
+pub fn decode(bytes: &[u8]) -> impl Iterator<Item = Result<TraceEvent, DecodeError>> + '_ {
+    let mut rest = bytes;
+    std::iter::from_fn(move || {
+        let len = rest.get(..PREFIX_LEN)?;
+        let len = PREFIX_LEN + u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let buf = rest.get(..len)?;
+        rest = &rest[len..];
+        Some(
+            schema::size_prefixed_root_as_trace_event(buf)
+                .map_err(DecodeError::Invalid)
+                .and_then(to_event),
+        )
+    })
+}
@@ -0,0 +1,173 @@
+use crate::InodeAttributes;
+use fuser::ReplyEntry;
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::raw::c_int;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how often a running fetch command is checked on
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// A path the fetch command was run for, handed back to the filesystem with `finished`
+pub(crate) struct Fetched {
+    pub pid: u32,
+    pub path: PathBuf,
+    // the attributes replied to the lookups along with the device and inode number of the
+    // backing file, or the errno they failed with
+    pub result: Result<(InodeAttributes, (u64, u64)), c_int>,
+    pub lookups: u64,
+}
+
+// Faults in the missing paths below a subtree of the root by running a command for them, on a
+// thread of their own so the filesystem keeps serving everything else. The lookups of a path
+// being fetched already wait for the same fetch, and are replied to once it is done
+pub(crate) struct Fetcher {
+    command: String,
+    root: PathBuf,
+    // relative to the root
+    under: PathBuf,
+    timeout: Duration,
+    // the lookups waiting for each path being fetched, all of them get the same inode number
+    pending: Arc<Mutex<BTreeMap<PathBuf, Vec<ReplyEntry>>>>,
+    done: Sender<Fetched>,
+    finished: Receiver<Fetched>,
+}
+
+impl Fetcher {
+    pub fn new(command: String, root: PathBuf, under: PathBuf, timeout: Duration) -> Fetcher {
+        let (done, finished) = mpsc::channel();
+        Fetcher {
+            command,
+            root,
+            under,
+            timeout,
+            pending: Arc::default(),
+            done,
+            finished,
+        }
+    }
+
+    // The path relative to the root that `path` is fetched as, when it is below the subtree
+    pub fn relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        (relative.starts_with(&self.under) && relative != self.under).then_some(relative)
+    }
+
+    // Replies to the lookup of `path` once it is fetched, with the inode number `reserve` hands
+    // out unless the path is being fetched already
+    pub fn fetch(
+        &self,
+        pid: u32,
+        path: PathBuf,
+        ttl: Duration,
+        reserve: impl FnOnce() -> u64,
+        reply: ReplyEntry,
+    ) {
+        let relative = match self.relative(&path) {
+            Some(x) => x.to_path_buf(),
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if let Some(replies) = pending.get_mut(&path) {
+                replies.push(reply);
+                return;
+            }
+            pending.insert(path.clone(), vec![reply]);
+        }
+        let ino = reserve();
+
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{} \"$1\"", self.command))
+            .arg("sh")
+            .arg(&relative)
+            .current_dir(&self.root)
+            .stdin(Stdio::null());
+        let timeout = self.timeout;
+        let pending = self.pending.clone();
+        let done = self.done.clone();
+        let key = path.clone();
+        let spawned = thread::Builder::new()
+            .name("fetch".to_string())
+            .spawn(move || {
+                let result = run(command, timeout, &relative)
+                    .and_then(|_| fs::symlink_metadata(&path).map_err(|_| libc::ENOENT))
+                    .map(|metadata| {
+                        let backing = (metadata.dev(), metadata.ino());
+                        let attrs = InodeAttributes {
+                            ino,
+                            ..(metadata, path.clone()).into()
+                        };
+                        (attrs, backing)
+                    });
+                let replies = pending.lock().unwrap().remove(&path).unwrap_or_default();
+                // known to the filesystem before the kernel can ask about the inode
+                let _ = done.send(Fetched {
+                    pid,
+                    path,
+                    result: result.clone(),
+                    lookups: replies.len() as u64,
+                });
+                for reply in replies {
+                    match &result {
+                        Ok((attrs, _)) => reply.entry(&ttl, &attrs.clone().into(), 0),
+                        Err(e) => reply.error(*e),
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("Failed to start fetching {}: {}", key.display(), e);
+            if let Some(replies) = self.pending.lock().unwrap().remove(&key) {
+                for reply in replies {
+                    reply.error(libc::ENOENT);
+                }
+            }
+        }
+    }
+
+    // The fetches done since the last call
+    pub fn finished(&self) -> Vec<Fetched> {
+        self.finished.try_iter().collect()
+    }
+}
+
+// Runs the fetch command of `relative` to completion, killing it once it takes too long
+fn run(mut command: Command, timeout: Duration, relative: &Path) -> Result<(), c_int> {
+    let mut child = command.spawn().map_err(|e| {
+        warn!("Failed to fetch {}: {}", relative.display(), e);
+        libc::ENOENT
+    })?;
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => {
+                warn!("Failed to fetch {}: {}", relative.display(), status);
+                return Err(libc::ENOENT);
+            }
+            Ok(None) if start.elapsed() < timeout => thread::sleep(POLL_INTERVAL),
+            Ok(None) => {
+                warn!("Fetching {} timed out", relative.display());
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(libc::ENOENT);
+            }
+            Err(e) => {
+                warn!("Failed to fetch {}: {}", relative.display(), e);
+                return Err(libc::ENOENT);
+            }
+        }
+    }
+}
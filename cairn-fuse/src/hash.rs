@@ -0,0 +1,123 @@
+use crate::TraceEvent;
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// enough to keep a disk busy without taking cores away from the build itself
+const WORKERS: usize = 2;
+
+struct Job {
+    path: PathBuf,
+    // order the jobs were handed out in, a path written again is hashed again
+    seq: u64,
+    event: TraceEvent,
+}
+
+// Hashes the outputs on worker threads, so a large file does not hold up the filesystem. The
+// release event of a file waits for its digest, and is picked up again with `finished`
+pub(crate) struct Hasher {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    finished: Receiver<TraceEvent>,
+    // the digest of the last job of every path
+    digests: Arc<Mutex<BTreeMap<String, (u64, String)>>>,
+    seq: u64,
+}
+
+impl Hasher {
+    pub fn new() -> Hasher {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (done, finished) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let digests = Arc::new(Mutex::new(BTreeMap::new()));
+
+        let workers = (0..WORKERS)
+            .map(|_| {
+                let queue = queue.clone();
+                let done = done.clone();
+                let digests = digests.clone();
+                thread::Builder::new()
+                    .name("output-hasher".to_string())
+                    .spawn(move || loop {
+                        let job = match queue.lock().unwrap().recv() {
+                            Ok(x) => x,
+                            Err(_) => return,
+                        };
+                        let mut event = job.event;
+                        match sha256(&job.path) {
+                            Ok(digest) => {
+                                let path = job.path.to_string_lossy().into_owned();
+                                let mut digests = digests.lock().unwrap();
+                                // a later job of the same path may have finished first
+                                if !matches!(digests.get(&path), Some((seq, _)) if *seq > job.seq) {
+                                    digests.insert(path, (job.seq, digest.clone()));
+                                }
+                                event.sha256 = Some(digest);
+                            }
+                            Err(e) => warn!("Failed to hash {}: {}", job.path.display(), e),
+                        }
+                        let _ = done.send(event);
+                    })
+                    .expect("failed to start an output hasher")
+            })
+            .collect();
+
+        Hasher {
+            jobs: Some(jobs),
+            workers,
+            finished,
+            digests,
+            seq: 0,
+        }
+    }
+
+    pub fn hash(&mut self, path: PathBuf, event: TraceEvent) {
+        self.seq += 1;
+        let job = Job {
+            path,
+            seq: self.seq,
+            event,
+        };
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(job);
+        }
+    }
+
+    // The release events hashed since the last call
+    pub fn finished(&self) -> Vec<TraceEvent> {
+        self.finished.try_iter().collect()
+    }
+
+    // Waits for the outstanding jobs, no more are taken afterwards
+    pub fn finish(&mut self) -> Vec<TraceEvent> {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.finished()
+    }
+
+    pub fn digests(&self) -> BTreeMap<String, String> {
+        let digests = self.digests.lock().unwrap();
+        digests
+            .iter()
+            .map(|(path, (_, digest))| (path.clone(), digest.clone()))
+            .collect()
+    }
+}
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
@@ -0,0 +1,5763 @@
+// Based on https://github.com/cberner/fuser/blob/master/examples/simple.rs
+
+use fuser::{
+    consts, fuse_forget_one, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyLock, ReplyLseek, ReplyOpen,
+    ReplyPoll, ReplyStatfs, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
+};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{debug, error, info, warn};
+use serde::{Serialize, Serializer};
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::Write;
+use std::num::Wrapping;
+use std::os::fd::{AsRawFd, IntoRawFd};
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs as ufs;
+use std::os::unix::fs::{DirBuilderExt, FileExt, OpenOptionsExt};
+use std::os::unix::prelude::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fmt, fs, io, thread};
+use walkdir::WalkDir;
+
+use fetch::{Fetched, Fetcher};
+use hash::Hasher;
+use metrics::Metrics;
+use workers::{Done, Io, Job, Workers};
+
+pub mod fbs;
+mod fetch;
+mod hash;
+pub mod metrics;
+pub mod sink;
+mod workers;
+
+#[cfg(target_os = "linux")]
+const FMODE_EXEC: i32 = 0x20;
+
+// S_ISUID | S_ISGID, which are narrower than a mode on macOS
+const SETID_BITS: u32 = 0o6000;
+
+// flags of rename() as the kernel passes them on, whatever the platform calls them
+const RENAME_NOREPLACE: u32 = 1;
+const RENAME_EXCHANGE: u32 = 2;
+
+// macOS has no locks owned by the open file description, fcntl_lock() refuses them there
+#[cfg(target_os = "macos")]
+use libc::{F_GETLK as F_OFD_GETLK, F_SETLK as F_OFD_SETLK, F_SETLKW as F_OFD_SETLKW};
+#[cfg(target_os = "linux")]
+use libc::{F_OFD_GETLK, F_OFD_SETLK, F_OFD_SETLKW};
+
+// Asked of the kernel in init(), it settles for less when it does not support as much. Without
+// FUSE_MAX_PAGES it still splits writes at 32 pages
+const MAX_WRITE: u32 = 1 << 20;
+const MAX_READAHEAD: u32 = 1 << 20;
+
+// what poll() reports for regular files, they never block
+const READY: u32 = (libc::POLLIN | libc::POLLOUT | libc::POLLRDNORM | libc::POLLWRNORM) as u32;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    Socket,
+    Fifo,
+    CharDevice,
+    BlockDevice,
+}
+
+enum Reply {
+    Entry(ReplyEntry),
+    Attr(ReplyAttr),
+    Data(ReplyData),
+    Empty(ReplyEmpty),
+    Open(ReplyOpen),
+    Write(ReplyWrite),
+    Statfs(ReplyStatfs),
+    Create(ReplyCreate),
+    Lock(ReplyLock),
+    Lseek(ReplyLseek),
+    Poll(ReplyPoll),
+}
+
+impl Reply {
+    // Every failure of the backing store reaches the kernel through here
+    fn error(self, e: io::Error) {
+        let errno = errno(&e);
+        match self {
+            Reply::Entry(r) => r.error(errno),
+            Reply::Attr(r) => r.error(errno),
+            Reply::Data(r) => r.error(errno),
+            Reply::Empty(r) => r.error(errno),
+            Reply::Open(r) => r.error(errno),
+            Reply::Write(r) => r.error(errno),
+            Reply::Statfs(r) => r.error(errno),
+            Reply::Create(r) => r.error(errno),
+            Reply::Lock(r) => r.error(errno),
+            Reply::Lseek(r) => r.error(errno),
+            Reply::Poll(r) => r.error(errno),
+        }
+    }
+}
+
+// The replies that can follow a change, see TracerFS::handle_metadata_on_change
+enum ChangeReply {
+    Entry(ReplyEntry),
+    Attr(ReplyAttr),
+    Empty(ReplyEmpty),
+}
+
+impl From<ChangeReply> for Reply {
+    fn from(reply: ChangeReply) -> Self {
+        match reply {
+            ChangeReply::Entry(r) => Reply::Entry(r),
+            ChangeReply::Attr(r) => Reply::Attr(r),
+            ChangeReply::Empty(r) => Reply::Empty(r),
+        }
+    }
+}
+
+impl From<FileKind> for fuser::FileType {
+    fn from(kind: FileKind) -> Self {
+        match kind {
+            FileKind::File => fuser::FileType::RegularFile,
+            FileKind::Directory => fuser::FileType::Directory,
+            FileKind::Symlink => fuser::FileType::Symlink,
+            FileKind::Socket => fuser::FileType::Socket,
+            FileKind::Fifo => fuser::FileType::NamedPipe,
+            FileKind::CharDevice => fuser::FileType::CharDevice,
+            FileKind::BlockDevice => fuser::FileType::BlockDevice,
+        }
+    }
+}
+
+// The kernel expects rdev in its own 32 bit encoding (new_encode_dev), not as a glibc dev_t
+fn encode_rdev(rdev: u64) -> u32 {
+    let major = (((rdev >> 32) & 0xffff_f000) | ((rdev >> 8) & 0xfff)) as u32;
+    let minor = (((rdev >> 12) & 0xffff_ff00) | (rdev & 0xff)) as u32;
+    (minor & 0xff) | ((major & 0xfff) << 8) | ((minor & !0xff) << 12)
+}
+
+fn system_time_from_time(secs: i64, nsecs: u32) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nsecs)
+    } else {
+        UNIX_EPOCH - Duration::new((-secs) as u64, nsecs)
+    }
+}
+
+fn time_from_system_time(system_time: &SystemTime) -> (i64, u32) {
+    // Convert to signed 64-bit time with epoch at 0
+    match system_time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+        Err(before_epoch_error) => (
+            -(before_epoch_error.duration().as_secs() as i64),
+            before_epoch_error.duration().subsec_nanos(),
+        ),
+    }
+}
+
+#[derive(Clone)]
+pub struct InodeAttributes {
+    // pub metadata: fs::Metadata,
+    pub ino: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub atime: (i64, u32),
+    pub mtime: (i64, u32),
+    pub ctime: (i64, u32),
+    pub crtime: (i64, u32),
+    pub kind: FileKind,
+    pub len: u64,
+    pub nlinks: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub rdev: u64,
+    pub real_path: PathBuf,
+}
+
+impl From<(fs::Metadata, PathBuf)> for InodeAttributes {
+    fn from(payload: (fs::Metadata, PathBuf)) -> Self {
+        let ino = payload.0.ino();
+        let uid = payload.0.uid();
+        let gid = payload.0.gid();
+        let mode = payload.0.mode();
+        // a type unknown to FUSE is passed on as a regular file, it is not listed anyway
+        let kind = as_file_kind(mode).unwrap_or(FileKind::File);
+        let len = payload.0.len();
+        let nlinks = payload.0.nlink();
+        let blksize = payload.0.blksize();
+        let blocks = payload.0.blocks();
+        let rdev = payload.0.rdev();
+        let real_path = payload.1;
+
+        let atime = time_from_system_time(&match payload.0.accessed() {
+            Ok(x) => x,
+            Err(_) => panic!("Access time not supported on this platform."),
+        });
+        let mtime = time_from_system_time(&match payload.0.modified() {
+            Ok(x) => x,
+            Err(_) => panic!("Modification time not supported on this platform."),
+        });
+        let ctime = (payload.0.ctime(), payload.0.ctime_nsec() as u32);
+        // the birth time comes from statx, not every filesystem (or kernel) records one
+        let crtime = match payload.0.created() {
+            Ok(x) => time_from_system_time(&x),
+            Err(_) => (0, 0),
+        };
+
+        InodeAttributes {
+            ino,
+            uid,
+            gid,
+            mode,
+            atime,
+            mtime,
+            ctime,
+            crtime,
+            kind,
+            len,
+            nlinks,
+            blksize,
+            blocks,
+            rdev,
+            real_path,
+        }
+    }
+}
+
+impl From<InodeAttributes> for fuser::FileAttr {
+    fn from(attrs: InodeAttributes) -> Self {
+        fuser::FileAttr {
+            ino: attrs.ino,
+            size: attrs.len,
+            blocks: attrs.blocks,
+            atime: system_time_from_time(attrs.atime.0, attrs.atime.1),
+            mtime: system_time_from_time(attrs.mtime.0, attrs.mtime.1),
+            ctime: system_time_from_time(attrs.ctime.0, attrs.ctime.1),
+            crtime: system_time_from_time(attrs.crtime.0, attrs.crtime.1),
+            kind: attrs.kind.into(),
+            // the file type is carried by `kind`, only setuid/setgid/sticky and rwx go here
+            perm: (attrs.mode & 0o7777) as u16,
+            nlink: attrs.nlinks as u32,
+            uid: attrs.uid,
+            gid: attrs.gid,
+            rdev: encode_rdev(attrs.rdev),
+            blksize: attrs.blksize as u32,
+            flags: 0,
+        }
+    }
+}
+
+// A backing file kept open for as long as the kernel holds the handle
+struct FileHandle {
+    ino: u64,
+    // shared with the workers serving its reads and writes, see TracerFS::io_threads
+    file: Arc<File>,
+    flags: i32,
+    // the process that opened the handle, the bytes moved through it and the range it wrote,
+    // for its session event
+    pid: u32,
+    read: u64,
+    written: u64,
+    span: Option<(i64, i64)>,
+}
+
+// Tells apart the files that had the same inode number, the backing filesystem hands it out
+// again once a file is gone. The kernel drops what it knows of an inode whose generation changed
+#[derive(Default)]
+struct Generations {
+    // birth time of the file currently behind each number, None once it has been removed
+    inodes: BTreeMap<u64, (Option<(i64, u32)>, u64)>,
+}
+
+impl Generations {
+    fn of(&mut self, attrs: &InodeAttributes) -> u64 {
+        let birth = Some(attrs.crtime);
+        match self.inodes.get_mut(&attrs.ino) {
+            // a rename keeps both, a file created outside the mount only has another birth time
+            Some((known, generation)) => {
+                if *known != birth {
+                    *known = birth;
+                    *generation += 1;
+                }
+                *generation
+            }
+            None => {
+                self.inodes.insert(attrs.ino, (birth, 0));
+                0
+            }
+        }
+    }
+
+    // The next file with this number is another one
+    fn retire(&mut self, ino: u64) {
+        self.inodes.entry(ino).or_insert((None, 0)).0 = None;
+    }
+
+    // A file copied up by an overlay is still the same one, with the birth time of the copy
+    fn carry(&mut self, attrs: &InodeAttributes) {
+        if let Some((known, _)) = self.inodes.get_mut(&attrs.ino) {
+            *known = Some(attrs.crtime);
+        }
+    }
+
+    // The kernel forgot the number, it has no generation to compare with anymore
+    fn forget(&mut self, ino: u64) {
+        self.inodes.remove(&ino);
+    }
+}
+
+// The inode numbers handed to the kernel. A backing file keeps its own number unless it is taken,
+// by the root, a synthetic file or a file of another filesystem mounted below the root, then it
+// gets one counted down from below the synthetic ones
+struct Inodes {
+    // by device and inode number of the backing file, hard links share theirs
+    numbers: BTreeMap<(u64, u64), u64>,
+    // the other way around, the copy of a file made by an overlay shares the number of the original
+    backings: BTreeMap<u64, BTreeSet<(u64, u64)>>,
+    taken: BTreeSet<u64>,
+    next: u64,
+    // directories leading to injected files that the root lacks, by their path relative to it
+    unbacked: BTreeMap<PathBuf, u64>,
+    unbacked_numbers: BTreeSet<u64>,
+}
+
+impl Default for Inodes {
+    fn default() -> Inodes {
+        Inodes {
+            numbers: BTreeMap::new(),
+            backings: BTreeMap::new(),
+            taken: BTreeSet::from([FUSE_ROOT_ID]),
+            next: CONTROL_INO - 1,
+            unbacked: BTreeMap::new(),
+            unbacked_numbers: BTreeSet::new(),
+        }
+    }
+}
+
+impl Inodes {
+    // The root is FUSE_ROOT_ID whatever its own number
+    fn root(&mut self, metadata: &Metadata) {
+        self.map((metadata.dev(), metadata.ino()), FUSE_ROOT_ID);
+    }
+
+    fn of(&mut self, metadata: &Metadata) -> u64 {
+        let key = (metadata.dev(), metadata.ino());
+        if let Some(ino) = self.numbers.get(&key) {
+            return *ino;
+        }
+        let ino = match key.1 {
+            ino if ino == 0 || is_synthetic(ino) || self.taken.contains(&ino) => self.fresh(),
+            ino => {
+                self.taken.insert(ino);
+                ino
+            }
+        };
+        self.map(key, ino);
+        ino
+    }
+
+    fn map(&mut self, backing: (u64, u64), ino: u64) {
+        if let Some(previous) = self.numbers.insert(backing, ino) {
+            if let Some(backings) = self.backings.get_mut(&previous) {
+                backings.remove(&backing);
+            }
+        }
+        self.backings.entry(ino).or_default().insert(backing);
+    }
+
+    fn fresh(&mut self) -> u64 {
+        while self.taken.contains(&self.next) {
+            self.next -= 1;
+        }
+        self.taken.insert(self.next);
+        self.next
+    }
+
+    fn unbacked(&mut self, relative: &Path) -> u64 {
+        if let Some(ino) = self.unbacked.get(relative) {
+            return *ino;
+        }
+        let ino = self.fresh();
+        self.unbacked.insert(relative.to_path_buf(), ino);
+        self.unbacked_numbers.insert(ino);
+        ino
+    }
+
+    fn is_unbacked(&self, ino: u64) -> bool {
+        self.unbacked_numbers.contains(&ino)
+    }
+
+    // A file fetched for a lookup gets the number replied to it, see Fetcher
+    fn assign(&mut self, backing: (u64, u64), ino: u64) {
+        self.map(backing, ino);
+    }
+
+    // The copy of a file made by an overlay takes over its number
+    fn alias(&mut self, original: &Metadata, copy: &Metadata) -> u64 {
+        let ino = self.of(original);
+        self.map((copy.dev(), copy.ino()), ino);
+        ino
+    }
+
+    // The kernel forgot the number, the files behind it get another one if they are looked up
+    // again. The root and the unbacked directories keep theirs
+    fn forget(&mut self, ino: u64) {
+        if ino == FUSE_ROOT_ID || self.is_unbacked(ino) {
+            return;
+        }
+        for backing in self.backings.remove(&ino).unwrap_or_default() {
+            self.numbers.remove(&backing);
+        }
+        self.taken.remove(&ino);
+    }
+
+    fn len(&self) -> usize {
+        self.numbers.len()
+    }
+
+    fn attributes(&mut self, metadata: fs::Metadata, real_path: PathBuf) -> InodeAttributes {
+        let ino = self.of(&metadata);
+        InodeAttributes {
+            ino,
+            ..(metadata, real_path).into()
+        }
+    }
+}
+
+// The entries of a directory as of opendir(), so offsets stay stable across readdir() calls
+struct DirHandle {
+    dir: File,
+    entries: Vec<(u64, FileKind, OsString)>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TraceFormat {
+    Human,
+    Json,
+}
+
+// log target of the trace lines, so they can be told apart from the diagnostics
+pub const TRACE_TARGET: &str = "cairn_fuse::trace";
+
+// read-only file in the root of the mount listing the recent events, see TracerFS::ring
+pub const EVENTS_FILE: &str = ".cairn-events";
+// directory in the root of the mount with files about the tracer itself, like its stats
+pub const CAIRN_DIR: &str = ".cairn";
+// the live counters of the tracer as JSON, rendered on every open()
+pub const STATS_FILE: &str = "stats";
+// takes commands for the tracer, one per line, and reads back whether it is tracing
+pub const CONTROL_FILE: &str = "control";
+// inodes of the synthetic files count down from the top, far from any number the backing
+// filesystem hands out
+const EVENTS_INO: u64 = u64::MAX;
+const CAIRN_DIR_INO: u64 = u64::MAX - 1;
+const STATS_INO: u64 = u64::MAX - 2;
+const CONTROL_INO: u64 = u64::MAX - 3;
+
+fn is_synthetic(ino: u64) -> bool {
+    ino >= CONTROL_INO
+}
+
+// The listing of the synthetic directory
+fn synthetic_entries() -> impl Iterator<Item = (u64, FileKind, &'static str)> {
+    [
+        (CAIRN_DIR_INO, FileKind::Directory, "."),
+        (FUSE_ROOT_ID, FileKind::Directory, ".."),
+        (STATS_INO, FileKind::File, STATS_FILE),
+        (CONTROL_INO, FileKind::File, CONTROL_FILE),
+    ]
+    .into_iter()
+}
+
+// A line written to the control file
+enum Command {
+    // the trace so far is synced to disk
+    Flush,
+    Marker(String),
+    Pause,
+    Resume,
+}
+
+// Empty lines are skipped, none of the commands are taken when any line is not one
+fn parse_commands(data: &[u8]) -> Option<Vec<Command>> {
+    let mut commands = Vec::new();
+    for line in std::str::from_utf8(data).ok()?.lines().map(str::trim) {
+        let command = match line.split_once(' ') {
+            _ if line.is_empty() => continue,
+            Some(("marker", label)) => Command::Marker(label.trim_start().to_string()),
+            None if line == "flush" => Command::Flush,
+            None if line == "pause" => Command::Pause,
+            None if line == "resume" => Command::Resume,
+            _ => return None,
+        };
+        commands.push(command);
+    }
+    Some(commands)
+}
+
+// The operations that end up in the trace, in the order of the Op enum of schema/trace.fbs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceOp {
+    Open,
+    Create,
+    Read,
+    Write,
+    Readlink,
+    Chmod,
+    Chown,
+    Truncate,
+    Utime,
+    Mknod,
+    Mkdir,
+    Unlink,
+    Rmdir,
+    Symlink,
+    Rename,
+    Link,
+    Statfs,
+    Release,
+    LookupMiss,
+    Fallocate,
+    Setxattr,
+    Removexattr,
+    // what a handle read or wrote in all, in place of its other events, see TracerFS::sessions
+    Output,
+    Input,
+    // a label written to the control file, which takes the place of the path
+    Marker,
+    // an access kept from the caller by the allowlist, see TracerFS::allowlist
+    Denied,
+    // a missing path the fetch command did not fault in, see TracerFS::fetch
+    FetchFailed,
+    // a change outside of the writable directories, see TracerFS::writable_dirs
+    DeniedWrite,
+}
+
+impl TraceOp {
+    pub const ALL: [TraceOp; 28] = [
+        TraceOp::Open,
+        TraceOp::Create,
+        TraceOp::Read,
+        TraceOp::Write,
+        TraceOp::Readlink,
+        TraceOp::Chmod,
+        TraceOp::Chown,
+        TraceOp::Truncate,
+        TraceOp::Utime,
+        TraceOp::Mknod,
+        TraceOp::Mkdir,
+        TraceOp::Unlink,
+        TraceOp::Rmdir,
+        TraceOp::Symlink,
+        TraceOp::Rename,
+        TraceOp::Link,
+        TraceOp::Statfs,
+        TraceOp::Release,
+        TraceOp::LookupMiss,
+        TraceOp::Fallocate,
+        TraceOp::Setxattr,
+        TraceOp::Removexattr,
+        TraceOp::Output,
+        TraceOp::Input,
+        TraceOp::Marker,
+        TraceOp::Denied,
+        TraceOp::FetchFailed,
+        TraceOp::DeniedWrite,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceOp::Open => "open",
+            TraceOp::Create => "create",
+            TraceOp::Read => "read",
+            TraceOp::Write => "write",
+            TraceOp::Readlink => "readlink",
+            TraceOp::Chmod => "chmod",
+            TraceOp::Chown => "chown",
+            TraceOp::Truncate => "truncate",
+            TraceOp::Utime => "utime",
+            TraceOp::Mknod => "mknod",
+            TraceOp::Mkdir => "mkdir",
+            TraceOp::Unlink => "unlink",
+            TraceOp::Rmdir => "rmdir",
+            TraceOp::Symlink => "symlink",
+            TraceOp::Rename => "rename",
+            TraceOp::Link => "link",
+            TraceOp::Statfs => "statfs",
+            TraceOp::Release => "release",
+            TraceOp::LookupMiss => "lookup_miss",
+            TraceOp::Fallocate => "fallocate",
+            TraceOp::Setxattr => "setxattr",
+            TraceOp::Removexattr => "removexattr",
+            TraceOp::Output => "output",
+            TraceOp::Input => "input",
+            TraceOp::Marker => "marker",
+            TraceOp::Denied => "denied",
+            TraceOp::FetchFailed => "fetch_failed",
+            TraceOp::DeniedWrite => "denied_write",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TraceOp> {
+        TraceOp::ALL.into_iter().find(|op| op.name() == name)
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+// The operations recorded by the sink, checked for every event so the filter costs a single AND
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceOps(u32);
+
+impl TraceOps {
+    pub const ALL: TraceOps = TraceOps((1 << TraceOp::ALL.len()) - 1);
+
+    pub fn contains(self, op: TraceOp) -> bool {
+        self.0 & op.bit() != 0
+    }
+}
+
+// `all` or a comma separated list of operation names, like `read,write,rename`. A name prefixed
+// with `-` is taken out of the ones before it, like `all,-lookup_miss`
+impl FromStr for TraceOps {
+    type Err = String;
+
+    fn from_str(list: &str) -> Result<TraceOps, String> {
+        let mut ops = TraceOps(0);
+        for name in list.split(',').map(str::trim) {
+            let (name, removed) = match name.strip_prefix('-') {
+                Some(x) => (x, true),
+                None => (name, false),
+            };
+            let bits = match TraceOp::from_name(name) {
+                Some(op) => op.bit(),
+                None if name == "all" => TraceOps::ALL.0,
+                None => return Err(format!("unknown operation `{name}`")),
+            };
+            if removed {
+                ops.0 &= !bits;
+            } else {
+                ops.0 |= bits;
+            }
+        }
+        Ok(ops)
+    }
+}
+
+impl fmt::Display for TraceOps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == TraceOps::ALL {
+            return f.write_str("all");
+        }
+        let names: Vec<&str> = TraceOp::ALL
+            .into_iter()
+            .filter(|op| self.contains(*op))
+            .map(TraceOp::name)
+            .collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+impl Serialize for TraceOps {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// Written once when the filesystem is mounted, ahead of the first event
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceHeader {
+    // events of any other operation are left out of the trace
+    pub trace_ops: TraceOps,
+}
+
+pub fn render_header(header: &TraceHeader, format: TraceFormat) -> String {
+    match format {
+        TraceFormat::Human => format!("trace-ops: {}", header.trace_ops),
+        TraceFormat::Json => serde_json::json!({ "header": header }).to_string(),
+    }
+}
+
+// The layer of the overlay holding the path of an event, see TracerFS::overlay
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layer {
+    Lower,
+    Upper,
+}
+
+impl Layer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layer::Lower => "lower",
+            Layer::Upper => "upper",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layer> {
+        [Layer::Lower, Layer::Upper]
+            .into_iter()
+            .find(|layer| layer.name() == name)
+    }
+}
+
+// A single traced operation, rendered either as a human readable line or as a JSON object
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceEvent {
+    pub ts: i64,
+    pub pid: u32,
+    pub ppid: i32,
+    // the filesystem thread that served the operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tid: Option<u32>,
+    pub kind: char,
+    // credentials and executable of the caller, known for requests coming through the kernel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comm: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<Arc<[String]>>,
+    pub op: TraceOp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ino: Option<u64>,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    // "ok" or the errno the operation failed with
+    #[serde(serialize_with = "serialize_result")]
+    pub result: Result<(), c_int>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u32>,
+    // distinct bytes of the file read and written by the session so far, and the share of the
+    // file read, given on release. Output and input events count the bytes of their handle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_read: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_written: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<f64>,
+    // start of the first byte and end of the last byte written by the handle, on output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_offset: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_offset: Option<i64>,
+    // of the contents of a written file as of its last release, see TracerFS::hash_outputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    // time from the start of the operation until it was traced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_us: Option<u64>,
+    // time since the filesystem was mounted, unlike `ts` it never goes backwards
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monotonic_us: Option<u64>,
+    // identical events in a row coalesced into this one, see DedupSink
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+    // read from the root or written to the upper directory, with TracerFS::overlay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<Layer>,
+    // of a path faulted in by the fetch command, see TracerFS::fetch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub materialized: Option<bool>,
+    // pid and start time of the root of the process tree of the caller, see
+    // TracerFS::per_process
+    #[serde(skip)]
+    pub group: Option<(u32, u64)>,
+}
+
+impl TraceEvent {
+    pub fn new(pid: u32, kind: char, op: TraceOp, path: &str) -> TraceEvent {
+        TraceEvent {
+            ts: time_from_system_time(&SystemTime::now()).0,
+            pid,
+            ppid: parent_pid(pid),
+            kind,
+            uid: None,
+            gid: None,
+            comm: None,
+            cmdline: None,
+            op,
+            ino: None,
+            path: path.to_string(),
+            target: None,
+            result: Ok(()),
+            offset: None,
+            size: None,
+            bytes_read: None,
+            bytes_written: None,
+            coverage: None,
+            first_offset: None,
+            last_offset: None,
+            sha256: None,
+            tid: None,
+            latency_us: None,
+            monotonic_us: None,
+            count: None,
+            layer: None,
+            materialized: None,
+            group: None,
+        }
+    }
+
+    pub fn render(&self, format: TraceFormat) -> Option<String> {
+        match format {
+            // byte ranges are far too noisy for the human readable log, open() already has them,
+            // as do closes, and cairn-cli only ever saw the operations that went through, or
+            // were denied
+            TraceFormat::Human
+                if self.offset.is_some()
+                    || self.op == TraceOp::Release
+                    || (self.result.is_err()
+                        && !matches!(self.op, TraceOp::Denied | TraceOp::DeniedWrite)) =>
+            {
+                None
+            }
+            TraceFormat::Human => {
+                let mut paths = vec![self.path.as_str()];
+                paths.extend(self.target.as_deref());
+                // the operation name is only kept around in debug builds
+                #[cfg(debug_assertions)]
+                paths.push(self.op.name());
+
+                let mut line = format!(
+                    "-> {}: {}|{}|{}|{}",
+                    self.ts,
+                    self.pid,
+                    self.ppid,
+                    self.kind,
+                    paths.join("|")
+                );
+                if let Some(count) = self.count {
+                    line.push_str(&format!(" x{}", count));
+                }
+                Some(line)
+            }
+            TraceFormat::Json => serde_json::to_string(self).ok(),
+        }
+    }
+}
+
+fn serialize_result<S: Serializer>(
+    result: &Result<(), c_int>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match result {
+        Ok(()) => serializer.serialize_str("ok"),
+        Err(errno) => serializer.serialize_i32(*errno),
+    }
+}
+
+// Latencies of a single kind of operation over the lifetime of the mount
+#[derive(Clone, Debug, Serialize)]
+pub struct OpSummary {
+    pub count: usize,
+    pub total_us: u64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub bytes_read: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub bytes_written: u64,
+    // failures of the backing store by errno
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<i32, u64>,
+}
+
+fn is_zero(x: &u64) -> bool {
+    *x == 0
+}
+
+pub type LatencySummary = BTreeMap<String, OpSummary>;
+
+pub fn render_summary(summary: &LatencySummary, format: TraceFormat) -> Vec<String> {
+    match format {
+        TraceFormat::Human => summary
+            .iter()
+            .map(|(op, stats)| {
+                let mut line = format!(
+                    "latency of {}: count={} total={}us p50={}us p95={}us p99={}us",
+                    op, stats.count, stats.total_us, stats.p50_us, stats.p95_us, stats.p99_us
+                );
+                if stats.bytes_read > 0 {
+                    line.push_str(&format!(" read={}", stats.bytes_read));
+                }
+                if stats.bytes_written > 0 {
+                    line.push_str(&format!(" written={}", stats.bytes_written));
+                }
+                for (errno, count) in &stats.errors {
+                    line.push_str(&format!(" errno{}={}", errno, count));
+                }
+                line
+            })
+            .collect(),
+        TraceFormat::Json => vec![serde_json::json!({ "summary": summary }).to_string()],
+    }
+}
+
+// Receives every traced operation, the binary writes them to the trace log
+pub trait TraceSink {
+    // Called once when the filesystem is mounted, before any event
+    fn header(&mut self, _header: &TraceHeader) {}
+
+    fn record(&mut self, event: TraceEvent);
+
+    // Called once when the filesystem is unmounted
+    fn summary(&mut self, _summary: &LatencySummary) {}
+
+    // Called when the events so far have to reach their destination, like before unmounting
+    fn flush(&mut self) {}
+
+    // Called when the events so far have to be on disk, on a flush through the control file
+    fn sync(&mut self) {
+        self.flush();
+    }
+}
+
+// The destination is only known once the command line has been parsed
+impl<T: TraceSink + ?Sized> TraceSink for Box<T> {
+    fn header(&mut self, header: &TraceHeader) {
+        (**self).header(header);
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        (**self).record(event);
+    }
+
+    fn summary(&mut self, summary: &LatencySummary) {
+        (**self).summary(summary);
+    }
+
+    fn flush(&mut self) {
+        (**self).flush();
+    }
+
+    fn sync(&mut self) {
+        (**self).sync();
+    }
+}
+
+// Keeps the events in memory, for inspecting them without going through the log
+#[derive(Default)]
+pub struct VecSink {
+    pub events: Vec<TraceEvent>,
+    pub summary: Option<LatencySummary>,
+}
+
+impl TraceSink for VecSink {
+    fn record(&mut self, event: TraceEvent) {
+        self.events.push(event);
+    }
+
+    fn summary(&mut self, summary: &LatencySummary) {
+        self.summary = Some(summary.clone());
+    }
+}
+
+// Stamps every event with the time its operation has been running for
+struct Recorder<S: TraceSink> {
+    inner: S,
+    mounted: Instant,
+    started: Instant,
+    // pid, uid and gid of the request being handled
+    caller: Option<(u32, u32, u32)>,
+    processes: Processes,
+    // events of other operations are dropped before they reach the sink
+    ops: TraceOps,
+    // the globs below match paths relative to it, or to the upper directory of an overlay
+    root: PathBuf,
+    upper: Option<PathBuf>,
+    // the upper directory is a scratch one, see TracerFS::shadow
+    shadow: bool,
+    // only paths matching these are traced, whether excluded or not
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    // the last `ring_size` events, none are kept when it is 0
+    ring: VecDeque<TraceEvent>,
+    ring_size: usize,
+    // events are attributed to process trees starting below these executables, if at all
+    parents: Option<Vec<String>>,
+    // process tree of the caller
+    group: Option<(u32, u64)>,
+    // shared with the timers of the operations
+    latencies: Arc<Mutex<Latencies>>,
+    // handles are traced by a single event on release, see TracerFS::sessions
+    sessions: bool,
+    // nothing is traced until tracing is resumed through the control file
+    paused: bool,
+    // the paths faulted in by the fetch command, see TracerFS::fetch
+    materialized: BTreeSet<String>,
+    // paths are traced relative to the root, see TracerFS::relative_paths
+    relative_paths: bool,
+}
+
+impl<S: TraceSink> TraceSink for Recorder<S> {
+    fn record(&mut self, mut event: TraceEvent) {
+        self.stamp(&mut event);
+        if let (Some(upper), true) = (&self.upper, self.shadow) {
+            // traced by the paths the changes were meant for
+            for path in [Some(&mut event.path), event.target.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                let relative = Path::new(path.as_str()).strip_prefix(upper).ok();
+                if let Some(meant) = relative.map(|x| self.root.join(x)) {
+                    *path = meant.to_string_lossy().into_owned();
+                }
+            }
+        } else if let Some(upper) = &self.upper {
+            // the label of a marker is no path
+            if event.op != TraceOp::Marker && !event.path.is_empty() {
+                event.layer = Some(match Path::new(&event.path).starts_with(upper) {
+                    true => Layer::Upper,
+                    false => Layer::Lower,
+                });
+            }
+        }
+        if self.materialized.contains(&event.path) {
+            event.materialized = Some(true);
+        }
+        if self.relative_paths && event.op != TraceOp::Marker {
+            for path in [Some(&mut event.path), event.target.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                // the root itself is `.`, paths outside of it stay absolute
+                let relative = self.relative(Path::new(path.as_str())).map(|x| {
+                    match x.as_os_str().is_empty() {
+                        true => ".".to_string(),
+                        false => x.to_string_lossy().into_owned(),
+                    }
+                });
+                if let Some(relative) = relative {
+                    *path = relative;
+                }
+            }
+        }
+        if let Some((pid, uid, gid)) = self.caller {
+            if pid == event.pid {
+                event.uid = Some(uid);
+                event.gid = Some(gid);
+            }
+        }
+        if let Some(process) = self.processes.get(event.pid) {
+            event.comm = Some(process.comm.clone());
+            event.cmdline = Some(process.cmdline.clone());
+        }
+        if let (Some(parents), None) = (&self.parents, event.group) {
+            event.group = match self.caller {
+                Some((pid, _, _)) if pid == event.pid => self.group,
+                _ => self.processes.group(event.pid, parents),
+            };
+            // gone before it could be told apart from a process reusing the pid, the kernel
+            // itself, as in the release after a process exited, belongs to no tree
+            if event.pid != 0 {
+                event.group.get_or_insert((event.pid, 0));
+            }
+        }
+        if self.ring_size > 0 {
+            if self.ring.len() == self.ring_size {
+                self.ring.pop_front();
+            }
+            self.ring.push_back(event.clone());
+        }
+        self.inner.record(event);
+    }
+
+    fn summary(&mut self, summary: &LatencySummary) {
+        self.inner.summary(summary);
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush();
+    }
+
+    fn sync(&mut self) {
+        self.inner.sync();
+    }
+}
+
+struct Process {
+    start: u64,
+    comm: String,
+    // shared by the events of the process, xargs hands out thousands of arguments
+    cmdline: Arc<[String]>,
+}
+
+// Executables of the callers seen so far, a build issues thousands of operations per process
+#[derive(Default)]
+struct Processes {
+    known: BTreeMap<u32, Process>,
+    // the root of the tree of every (pid, start time) walked through
+    groups: BTreeMap<(u32, u64), (u32, u64)>,
+}
+
+impl Processes {
+    // The tree of processes `pid` belongs to, rooted in its ancestor right below the first one
+    // running one of `parents`, or in the topmost one. A thread counts as its process, a reused
+    // pid has another start time and so starts another tree
+    fn group(&mut self, pid: u32, parents: &[String]) -> Option<(u32, u64)> {
+        let pid = thread_group(pid)?;
+        let mut current = (pid, process_stat(pid)?.1);
+        let mut walked = Vec::new();
+        let root = loop {
+            if let Some(root) = self.groups.get(&current) {
+                break *root;
+            }
+            walked.push(current);
+            let ppid = parent_pid(current.0);
+            match (ppid > 1).then(|| process_stat(ppid as u32)).flatten() {
+                Some((comm, start)) if !parents.contains(&comm) => current = (ppid as u32, start),
+                _ => break current,
+            }
+        };
+        for process in walked {
+            self.groups.insert(process, root);
+        }
+        Some(root)
+    }
+
+    fn get(&mut self, pid: u32) -> Option<&Process> {
+        // the start time tells a reused pid apart and the name changes with every exec(), both
+        // come from the stat line, the command line is only read again when either changed
+        if let Some((comm, start)) = process_stat(pid) {
+            let stale = match self.known.get(&pid) {
+                Some(process) => process.start != start || process.comm != comm,
+                None => true,
+            };
+            if stale {
+                let cmdline = fs::read(format!("/proc/{pid}/cmdline"))
+                    .map(|cmdline| {
+                        cmdline
+                            .split(|&byte| byte == 0)
+                            .filter(|arg| !arg.is_empty())
+                            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                            .collect()
+                    })
+                    .unwrap_or_else(|_| Vec::new().into());
+                self.known.insert(
+                    pid,
+                    Process {
+                        start,
+                        comm,
+                        cmdline,
+                    },
+                );
+            }
+        }
+        // a process that has already exited keeps what was last seen of it
+        self.known.get(&pid)
+    }
+}
+
+impl<S: TraceSink> Recorder<S> {
+    // Times the event as of now, an event recorded later on keeps these
+    fn stamp(&self, event: &mut TraceEvent) {
+        if event.latency_us.is_none() {
+            event.latency_us = Some(self.started.elapsed().as_micros() as u64);
+        }
+        if event.monotonic_us.is_none() {
+            event.monotonic_us = Some(self.mounted.elapsed().as_micros() as u64);
+        }
+        if event.tid.is_none() {
+            event.tid = Some(gettid());
+        }
+    }
+
+    // `path` relative to the upper directory of an overlay or to the root, if below either
+    fn relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        self.upper
+            .as_ref()
+            .and_then(|upper| path.strip_prefix(upper).ok())
+            .or_else(|| path.strip_prefix(&self.root).ok())
+    }
+
+    fn traces_path(&self, path: &str) -> bool {
+        // nothing to match an operation on the inode alone against
+        if path.is_empty() {
+            return true;
+        }
+        let path = Path::new(path);
+        let relative = self.relative(path).unwrap_or(path);
+        let matches = |globs: &GlobSet| globs.is_match(relative) || globs.is_match(path);
+        match &self.include {
+            Some(include) => matches(include),
+            None => !matches(&self.exclude),
+        }
+    }
+
+    // Every traced operation goes through here once its outcome is known
+    fn trace_event<T>(&mut self, event: TraceEvent, result: &io::Result<T>) {
+        if let Ok(mut latencies) = self.latencies.lock() {
+            latencies.count(&event, result);
+        }
+        if self.paused || !self.ops.contains(event.op) {
+            return;
+        }
+        // the label of a marker is no path
+        if event.op != TraceOp::Marker && !self.traces_path(&event.path) {
+            return;
+        }
+        let session = matches!(
+            event.op,
+            TraceOp::Open | TraceOp::Create | TraceOp::Read | TraceOp::Write | TraceOp::Release
+        );
+        // what went wrong is not in the session event
+        if self.sessions && session && result.is_ok() {
+            return;
+        }
+        self.record(TraceEvent {
+            result: result.as_ref().map(|_| ()).map_err(errno),
+            ..event
+        });
+    }
+}
+
+// bucket i counts latencies below 2^i us, the last one whatever takes longer than half an hour
+const BUCKETS: usize = 32;
+
+#[derive(Default)]
+struct OpStats {
+    count: u64,
+    total_us: u64,
+    max_us: u64,
+    buckets: [u64; BUCKETS],
+    bytes_read: u64,
+    bytes_written: u64,
+    errors: BTreeMap<i32, u64>,
+}
+
+impl OpStats {
+    fn add(&mut self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        self.count += 1;
+        self.total_us += us;
+        self.max_us = max(self.max_us, us);
+        let bucket = (u64::BITS - us.leading_zeros()) as usize;
+        self.buckets[min(bucket, BUCKETS - 1)] += 1;
+    }
+
+    // The upper bound of the bucket the percentile falls into, no more than the slowest one
+    fn percentile(&self, p: u64) -> u64 {
+        let rank = self.count.saturating_sub(1) * p / 100;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen > rank {
+                return min((1 << bucket) - 1, self.max_us);
+            }
+        }
+        self.max_us
+    }
+}
+
+// Wall clock time spent in each operation, with the bytes it moved and the ways it failed,
+// summarized once the filesystem is unmounted. Plain counters and a histogram of fixed buckets, a
+// build issues millions of operations
+#[derive(Default)]
+struct Latencies {
+    ops: BTreeMap<&'static str, OpStats>,
+    // the operation being handled, its bytes and failures are counted as they are traced
+    current: &'static str,
+}
+
+impl Latencies {
+    fn count<T>(&mut self, event: &TraceEvent, result: &io::Result<T>) {
+        let stats = self.ops.entry(self.current).or_default();
+        match result {
+            Err(e) => *stats.errors.entry(errno(e)).or_default() += 1,
+            Ok(_) if event.op == TraceOp::Read => {
+                stats.bytes_read += event.size.unwrap_or(0) as u64;
+            }
+            Ok(_) if event.op == TraceOp::Write => {
+                stats.bytes_written += event.size.unwrap_or(0) as u64;
+            }
+            Ok(_) => {}
+        }
+    }
+
+    fn summary(&self) -> LatencySummary {
+        self.ops
+            .iter()
+            // counted before the first operation was timed
+            .filter(|(_, stats)| stats.count > 0)
+            .map(|(op, stats)| {
+                let summary = OpSummary {
+                    count: stats.count as usize,
+                    total_us: stats.total_us,
+                    p50_us: stats.percentile(50),
+                    p95_us: stats.percentile(95),
+                    p99_us: stats.percentile(99),
+                    bytes_read: stats.bytes_read,
+                    bytes_written: stats.bytes_written,
+                    errors: stats.errors.clone(),
+                };
+                (op.to_string(), summary)
+            })
+            .collect()
+    }
+}
+
+// Stops the clock of an operation when it goes out of scope, whichever way it returns
+struct OpTimer {
+    op: &'static str,
+    start: Instant,
+    latencies: Arc<Mutex<Latencies>>,
+}
+
+impl Drop for OpTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        if let Ok(mut latencies) = self.latencies.lock() {
+            latencies.ops.entry(self.op).or_default().add(elapsed);
+        }
+    }
+}
+
+// What an operation did to a path
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Access {
+    // looked up or stat()ed, names that do not exist included
+    Stat,
+    // looked up while it did not exist
+    Miss,
+    Read,
+    Write,
+    Create,
+    Remove,
+}
+
+// Paths read and written by each process, for extracting the dependencies of a build step
+#[derive(Default)]
+struct Dependencies {
+    enabled: bool,
+    processes: BTreeMap<u32, ProcessDependencies>,
+    // where the accesses of the whole session are summarized when unmounting
+    manifest: Option<PathBuf>,
+    paths: BTreeMap<String, PathState>,
+    // the accesses of every process tree, each summarized in a manifest of its own in the
+    // directory, see TracerFS::per_process
+    group_dir: Option<PathBuf>,
+    groups: BTreeMap<(u32, u64), BTreeMap<String, PathState>>,
+    // process tree of the caller
+    group: Option<(u32, u64)>,
+    // what was read and written of every file, kept whether or not there is a manifest
+    transfers: BTreeMap<String, Transfers>,
+}
+
+// Distinct bytes of a file, as ranges from their start to their end with overlapping and
+// adjacent ones merged
+#[derive(Default)]
+struct ByteRanges(BTreeMap<u64, u64>);
+
+impl ByteRanges {
+    fn insert(&mut self, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let (mut start, mut end) = (offset, offset + len);
+        if let Some((&before, &before_end)) = self.0.range(..=start).next_back() {
+            if before_end >= start {
+                start = before;
+            }
+        }
+        let merged: Vec<u64> = self.0.range(start..=end).map(|(&x, _)| x).collect();
+        for range in merged {
+            end = end.max(self.0.remove(&range).unwrap());
+        }
+        self.0.insert(start, end);
+    }
+
+    fn len(&self) -> u64 {
+        self.0.iter().map(|(start, end)| end - start).sum()
+    }
+}
+
+#[derive(Default)]
+struct Transfers {
+    read: ByteRanges,
+    written: ByteRanges,
+}
+
+#[derive(Default, Serialize)]
+struct ProcessDependencies {
+    inputs: BTreeSet<String>,
+    outputs: BTreeSet<String>,
+}
+
+// Everything the session did to a single path
+#[derive(Default)]
+struct PathState {
+    stat: bool,
+    // looked up before the session created it, if it did at all
+    missing: bool,
+    // read while it still had the contents it had before the session
+    read: bool,
+    // did not exist until the session created it
+    created: bool,
+    written: bool,
+    // gone by the end of the session
+    removed: bool,
+}
+
+impl PathState {
+    fn record(&mut self, access: Access) {
+        let touched = self.created || self.written || self.removed;
+        match access {
+            Access::Stat => self.stat = true,
+            Access::Miss => self.missing |= !touched,
+            Access::Read => self.read |= !touched,
+            Access::Write => {
+                self.written = true;
+                self.removed = false;
+            }
+            Access::Create => {
+                self.created |= !touched;
+                self.written = true;
+                self.removed = false;
+            }
+            Access::Remove => self.removed = true,
+        }
+    }
+}
+
+// The dependencies of the whole session, every list sorted
+#[derive(Default, Serialize)]
+struct Manifest {
+    // read before the session wrote them, if it did at all
+    inputs: BTreeSet<String>,
+    outputs: BTreeSet<String>,
+    // created and removed again within the session
+    temporaries: BTreeSet<String>,
+    // existed before the session and were removed by it
+    removed: BTreeSet<String>,
+    // looked up but did not exist, creating one of them may change the outcome of the session
+    missing: BTreeSet<String>,
+    // only looked up or stat()ed, their existence may still matter
+    stat_only: BTreeSet<String>,
+    // sha256 of the outputs as they were left, see TracerFS::hash_outputs
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    digests: BTreeMap<String, String>,
+}
+
+impl Dependencies {
+    fn access(&mut self, pid: u32, path: &Path, access: Access) {
+        if self.enabled {
+            let process = self.processes.entry(pid).or_default();
+            let paths = match access {
+                Access::Stat | Access::Miss | Access::Read => &mut process.inputs,
+                Access::Write | Access::Create | Access::Remove => &mut process.outputs,
+            };
+            paths.insert(path.to_string_lossy().into_owned());
+        }
+
+        if self.manifest.is_some() {
+            let path = path.to_string_lossy().into_owned();
+            if let (Some(group), Some(_)) = (self.group, &self.group_dir) {
+                let paths = self.groups.entry(group).or_default();
+                paths.entry(path.clone()).or_default().record(access);
+            }
+            self.paths.entry(path).or_default().record(access);
+        }
+    }
+
+    fn transfer(&mut self, path: &Path, access: Access, offset: i64, len: usize) {
+        let transfers = self
+            .transfers
+            .entry(path.to_string_lossy().into_owned())
+            .or_default();
+        let ranges = match access {
+            Access::Read => &mut transfers.read,
+            _ => &mut transfers.written,
+        };
+        ranges.insert(offset as u64, len as u64);
+    }
+
+    fn classify(paths: &BTreeMap<String, PathState>) -> Manifest {
+        let mut manifest = Manifest::default();
+        for (path, state) in paths {
+            if state.read {
+                manifest.inputs.insert(path.clone());
+            }
+            if state.missing && !state.created {
+                manifest.missing.insert(path.clone());
+            }
+            if state.removed && state.created {
+                manifest.temporaries.insert(path.clone());
+            } else if state.removed {
+                manifest.removed.insert(path.clone());
+            } else if state.written {
+                manifest.outputs.insert(path.clone());
+            } else if state.stat && !state.read {
+                manifest.stat_only.insert(path.clone());
+            }
+        }
+        manifest
+    }
+}
+
+// The paths a hermetic build may use, see TracerFS::allowlist
+pub struct Allowlist {
+    root: PathBuf,
+    globs: GlobSet,
+    // the literal leading components of every glob, and whether a pattern follows them
+    prefixes: Vec<(PathBuf, bool)>,
+    // created through the mount, a build may always use its own outputs
+    produced: BTreeSet<PathBuf>,
+    // creating paths matching none of the globs is denied as well
+    deny_writes: bool,
+}
+
+impl Allowlist {
+    // The globs match paths relative to the root or absolute ones
+    pub fn new(globs: &[Glob]) -> Result<Allowlist, globset::Error> {
+        let mut set = GlobSetBuilder::new();
+        for glob in globs {
+            set.add(glob.clone());
+        }
+        Ok(Allowlist {
+            root: PathBuf::new(),
+            globs: set.build()?,
+            prefixes: globs
+                .iter()
+                .map(|glob| literal_prefix(glob.glob()))
+                .collect(),
+            produced: BTreeSet::new(),
+            deny_writes: false,
+        })
+    }
+
+    fn declares(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.globs.is_match(relative)
+            || self.globs.is_match(path)
+            || path.ancestors().any(|x| self.produced.contains(x))
+    }
+
+    // Directories have to be passed through on the way to what is declared below them
+    fn leads_to_declared(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.prefixes.iter().any(|(prefix, pattern)| {
+            [relative, path]
+                .iter()
+                .any(|x| prefix.starts_with(x) || (*pattern && x.starts_with(prefix)))
+        })
+    }
+
+    fn permits(&self, path: &Path, directory: bool) -> bool {
+        self.declares(path) || (directory && self.leads_to_declared(path))
+    }
+}
+
+fn literal_prefix(glob: &str) -> (PathBuf, bool) {
+    let mut prefix = PathBuf::from(if glob.starts_with('/') { "/" } else { "" });
+    for component in glob.split('/').filter(|x| !x.is_empty()) {
+        if component.contains(|c| "*?[{\\".contains(c)) {
+            return (prefix, true);
+        }
+        prefix.push(component);
+    }
+    (prefix, false)
+}
+
+// hides the lower entry of the same name, next to where the upper one would be
+const WHITEOUT_PREFIX: &str = ".wh.";
+// in an upper directory replacing a lower one, none of the lower entries show through
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+// The two layers of a copy-on-write mount, see TracerFS::overlay. A path of either layer stands
+// for the same path of the merged view
+struct Overlay {
+    lower: PathBuf,
+    upper: PathBuf,
+}
+
+impl Overlay {
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.upper)
+            .or_else(|_| path.strip_prefix(&self.lower))
+            .unwrap_or(path)
+    }
+
+    fn upper(&self, path: &Path) -> PathBuf {
+        self.upper.join(self.relative(path))
+    }
+
+    fn lower(&self, path: &Path) -> PathBuf {
+        self.lower.join(self.relative(path))
+    }
+
+    fn whiteout(&self, path: &Path) -> PathBuf {
+        let upper = self.upper(path);
+        let mut name = OsString::from(WHITEOUT_PREFIX);
+        name.push(upper.file_name().unwrap_or_default());
+        upper.with_file_name(name)
+    }
+
+    // Whether the lower entry of `path` is covered, by a whiteout or by an opaque directory
+    fn hides(&self, path: &Path) -> bool {
+        let upper = self.upper(path);
+        self.whiteout(path).symlink_metadata().is_ok()
+            || upper
+                .parent()
+                .is_some_and(|dir| dir.join(OPAQUE_MARKER).symlink_metadata().is_ok())
+    }
+
+    // The path of the layer `path` is found in, the upper one for a hidden entry, which does not
+    // exist then
+    fn resolve(&self, path: &Path) -> PathBuf {
+        let upper = self.upper(path);
+        if upper.symlink_metadata().is_ok() || self.hides(path) {
+            return upper;
+        }
+        self.lower(path)
+    }
+
+    // The name, path and metadata of the entries of the merged directory, the upper ones first
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, PathBuf, Metadata)>> {
+        let upper = self.upper(path);
+        let opaque = upper.join(OPAQUE_MARKER).symlink_metadata().is_ok();
+        let mut seen = BTreeSet::new();
+        let mut entries = Vec::new();
+        let mut found = false;
+        for (dir, is_upper) in [(upper, true), (self.lower(path), false)] {
+            if opaque && !is_upper {
+                break;
+            }
+            let listing = match fs::read_dir(&dir) {
+                Ok(x) => x,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            found = true;
+            for entry in listing {
+                let entry = entry?;
+                let name = entry.file_name();
+                // the opaque marker hides nothing by name, it starts like any whiteout though
+                if let (true, Some(hidden)) = (
+                    is_upper,
+                    name.as_bytes().strip_prefix(WHITEOUT_PREFIX.as_bytes()),
+                ) {
+                    seen.insert(OsStr::from_bytes(hidden).to_os_string());
+                    continue;
+                }
+                if seen.insert(name.clone()) {
+                    entries.push((name, entry.path(), entry.metadata()?));
+                }
+            }
+        }
+        if !found {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        Ok(entries)
+    }
+
+    // Copies the lower entry of `path` to the upper layer, whose parent directory has to be there
+    // already. Returns the metadata of the original and the copy, if one was made
+    fn copy_up(&self, path: &Path) -> io::Result<Option<(Metadata, Metadata)>> {
+        let upper = self.upper(path);
+        if upper.symlink_metadata().is_ok() {
+            return Ok(None);
+        }
+        let lower = self.lower(path);
+        let metadata = fs::symlink_metadata(&lower)?;
+        match as_file_kind(metadata.mode()) {
+            Some(FileKind::Directory) => fs::create_dir(&upper)?,
+            Some(FileKind::Symlink) => ufs::symlink(fs::read_link(&lower)?, &upper)?,
+            Some(FileKind::File) => {
+                fs::copy(&lower, &upper)?;
+            }
+            _ => make_node(&upper, metadata.mode(), metadata.rdev() as u32)?,
+        }
+        if !metadata.file_type().is_symlink() {
+            fs::set_permissions(&upper, metadata.permissions())?;
+        }
+        let copy = fs::symlink_metadata(&upper)?;
+        Ok(Some((metadata, copy)))
+    }
+
+    // Once `path` is created in the upper layer, whatever the lower one has there stays hidden
+    fn created(&self, path: &Path) -> io::Result<()> {
+        let is_dir = |path: &Path| fs::symlink_metadata(path).is_ok_and(|x| x.is_dir());
+        let upper = self.upper(path);
+        if is_dir(&upper) && is_dir(&self.lower(path)) {
+            File::create(upper.join(OPAQUE_MARKER))?;
+        }
+        match fs::remove_file(self.whiteout(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // Removes the upper entry of `path`, an empty directory may still hold whiteouts and the
+    // opaque marker. The lower entry is left for a whiteout to hide
+    fn remove(&self, path: &Path, directory: bool) -> io::Result<()> {
+        let upper = self.upper(path);
+        let result = if directory {
+            if !self.read_dir(path)?.is_empty() {
+                return Err(io::Error::from_raw_os_error(libc::ENOTEMPTY));
+            }
+            match fs::read_dir(&upper) {
+                Ok(entries) => {
+                    for entry in entries {
+                        fs::remove_file(entry?.path())?;
+                    }
+                    fs::remove_dir(&upper)
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            fs::remove_file(&upper)
+        };
+        match result {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Files presented in the mount whether the root has them or not, see TracerFS::inject
+#[derive(Default)]
+pub struct Injections {
+    // the file read in place of each path relative to the root
+    files: BTreeMap<PathBuf, PathBuf>,
+    // the directories leading to them, relative to the root as well
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl Injections {
+    // The paths are taken relative to the root even with a leading slash, the files they are read
+    // from have to exist
+    pub fn new(files: BTreeMap<PathBuf, PathBuf>) -> io::Result<Injections> {
+        let files = files
+            .into_iter()
+            .map(|(path, source)| {
+                let path: PathBuf = path
+                    .components()
+                    .filter(|x| matches!(x, Component::Normal(_)))
+                    .collect();
+                Ok((path, source.canonicalize()?))
+            })
+            .collect::<io::Result<BTreeMap<_, _>>>()?;
+        let dirs = files
+            .keys()
+            .flat_map(|path| path.ancestors().skip(1))
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .collect();
+        Ok(Injections { files, dirs })
+    }
+
+    fn source(&self, relative: &Path) -> Option<&Path> {
+        self.files.get(relative).map(PathBuf::as_path)
+    }
+
+    fn is_dir(&self, relative: &Path) -> bool {
+        self.dirs.contains(relative)
+    }
+
+    fn injects(&self, source: &Path) -> bool {
+        self.files.values().any(|x| x == source)
+    }
+
+    // The names injected right below `dir`, with the file each is read from, None for directories
+    fn children<'a>(
+        &'a self,
+        dir: &'a Path,
+    ) -> impl Iterator<Item = (&'a OsStr, Option<&'a Path>)> {
+        let files = (self.files.iter()).map(|(path, source)| (path, Some(source.as_path())));
+        let dirs = self.dirs.iter().map(|path| (path, None));
+        files
+            .chain(dirs)
+            .filter(move |(path, _)| path.parent() == Some(dir))
+            .filter_map(|(path, source)| Some((path.file_name()?, source)))
+    }
+}
+
+/// In memory storing of the attributes of the files, every operation is reported to the sink
+///
+/// ```
+/// use cairn_fuse::{TraceOp, TracerFS, VecSink};
+/// use std::os::unix::fs::MetadataExt;
+/// use std::path::Path;
+///
+/// let root = tempfile::tempdir().unwrap();
+/// let link = root.path().join("link");
+/// std::os::unix::fs::symlink("target.txt", &link).unwrap();
+/// let ino = std::fs::symlink_metadata(&link).unwrap().ino();
+///
+/// let (destroy, _) = std::sync::mpsc::channel();
+/// let root_dir = root.path().to_str().unwrap().to_string();
+/// let mut tracer = TracerFS::new(root_dir, destroy, VecSink::default()).preload_attrs();
+/// tracer.scan().unwrap();
+///
+/// let pid = std::process::id();
+/// assert_eq!(tracer.read_link(pid, ino), Ok(Path::new("target.txt").to_path_buf()));
+///
+/// let events = &tracer.sink().events;
+/// assert_eq!(events.len(), 1);
+/// assert_eq!((events[0].pid, events[0].kind), (pid, 'r'));
+/// assert_eq!(events[0].op, TraceOp::Readlink);
+/// assert_eq!(events[0].path, link.to_str().unwrap());
+/// assert_eq!(events[0].target.as_deref(), Some("target.txt"));
+/// ```
+pub struct TracerFS<S: TraceSink> {
+    root: String,
+    attrs: BTreeMap<u64, InodeAttributes>,
+    handles: BTreeMap<u64, FileHandle>,
+    // the kernel poll handles of the file handles that asked to be notified, by file handle,
+    // nothing wakes them up yet
+    poll_handles: BTreeMap<u64, u64>,
+    dir_handles: BTreeMap<u64, DirHandle>,
+    next_fh: u64,
+    // inodes removed from the tree while still open, evicted on their last release
+    unlinked: BTreeSet<u64>,
+    // number of lookups the kernel holds per inode, released by forget()
+    lookups: BTreeMap<u64, u64>,
+    generations: Generations,
+    inodes: Inodes,
+    // inodes written or truncated since their length was cached, read() stats those again
+    dirty: BTreeSet<u64>,
+    // open file descriptions holding the record locks of each (inode, lock owner)
+    locks: BTreeMap<(u64, u64), File>,
+    dependencies: Dependencies,
+    // mutating operations fail with EROFS instead of reaching the backing store
+    read_only: bool,
+    // or those of paths outside of these directories
+    writable_dirs: Option<Vec<PathBuf>>,
+    // canonical root every resolved path has to stay below, symlinks may lead anywhere without it
+    confined: Option<PathBuf>,
+    // where the filesystem is mounted, spelled like the paths of the root when it is below it
+    mountpoint: Option<PathBuf>,
+    allowlist: Option<Allowlist>,
+    overlay: Option<Overlay>,
+    // the upper directory of the overlay is removed on unmount
+    shadow: bool,
+    injections: Injections,
+    fetcher: Option<Fetcher>,
+    // FOPEN_* flags handed to the kernel with every opened file, controlling its page cache
+    open_flags: u32,
+    // every file below the root is cached in init(), not just the root
+    preload_attrs: bool,
+    // init() fails when the scan cannot read part of the root, instead of leaving it out
+    strict_init: bool,
+    // the kernel caches writes and flushes them in pages, asked for in init()
+    writeback_cache: bool,
+    // the kernel drops the cached pages of a file once its mtime or size changes
+    auto_cache: bool,
+    // how long the kernel may keep the entries and attributes of the backing files, and the
+    // names found missing, none of them by default
+    entry_ttl: Duration,
+    attr_ttl: Duration,
+    negative_ttl: Duration,
+    // created once the filesystem is up, for scripts polling for the mount
+    ready_file: Option<PathBuf>,
+    // inherited from a supervisor, written to and closed once the filesystem is up
+    ready_fd: Option<File>,
+    hasher: Option<Hasher>,
+    workers: Option<Workers>,
+    metrics: Option<Metrics>,
+    // inodes released from a writable handle since they were last hashed
+    unhashed: BTreeSet<u64>,
+    // the synthetic files as of each open() of them
+    listings: BTreeMap<u64, Vec<u8>>,
+    sink: Recorder<S>,
+    // the summary of the operations is also written there on unmount
+    summary_file: Option<PathBuf>,
+    // told Ok once unmounted, or the errno init() failed with
+    destroy: Sender<Result<(), c_int>>,
+}
+
+impl<S: TraceSink> TracerFS<S> {
+    pub fn new(root: String, destroy: Sender<Result<(), c_int>>, sink: S) -> TracerFS<S> {
+        {
+            let relative_to = PathBuf::from(&root);
+            TracerFS {
+                root,
+                attrs: BTreeMap::new(),
+                handles: BTreeMap::new(),
+                poll_handles: BTreeMap::new(),
+                dir_handles: BTreeMap::new(),
+                next_fh: 1,
+                unlinked: BTreeSet::new(),
+                lookups: BTreeMap::new(),
+                generations: Generations::default(),
+                inodes: Inodes::default(),
+                dirty: BTreeSet::new(),
+                locks: BTreeMap::new(),
+                dependencies: Dependencies::default(),
+                read_only: false,
+                writable_dirs: None,
+                confined: None,
+                mountpoint: None,
+                allowlist: None,
+                overlay: None,
+                shadow: false,
+                injections: Injections::default(),
+                fetcher: None,
+                open_flags: 0,
+                preload_attrs: false,
+                strict_init: false,
+                writeback_cache: false,
+                auto_cache: false,
+                entry_ttl: Duration::ZERO,
+                attr_ttl: Duration::ZERO,
+                negative_ttl: Duration::ZERO,
+                ready_file: None,
+                ready_fd: None,
+                hasher: None,
+                workers: None,
+                metrics: None,
+                unhashed: BTreeSet::new(),
+                listings: BTreeMap::new(),
+                sink: Recorder {
+                    inner: sink,
+                    mounted: Instant::now(),
+                    started: Instant::now(),
+                    caller: None,
+                    processes: Processes::default(),
+                    ops: TraceOps::ALL,
+                    root: relative_to,
+                    upper: None,
+                    shadow: false,
+                    include: None,
+                    exclude: GlobSet::empty(),
+                    ring: VecDeque::new(),
+                    ring_size: 0,
+                    parents: None,
+                    group: None,
+                    latencies: Arc::default(),
+                    sessions: false,
+                    paused: false,
+                    materialized: BTreeSet::new(),
+                    relative_paths: false,
+                },
+                summary_file: None,
+                destroy,
+            }
+        }
+    }
+
+    // Records the inputs and outputs of every process, emitted once the filesystem is unmounted
+    pub fn track_dependencies(mut self) -> TracerFS<S> {
+        self.dependencies.enabled = true;
+        self
+    }
+
+    // Leaves the events of every other operation out of the trace, the filesystem itself and its
+    // debug log behave the same either way
+    pub fn trace_ops(mut self, ops: TraceOps) -> TracerFS<S> {
+        self.sink.ops = ops;
+        self
+    }
+
+    // Leaves the events of paths matching `globs`, relative to the root, out of the trace
+    pub fn trace_exclude(mut self, globs: GlobSet) -> TracerFS<S> {
+        self.sink.exclude = globs;
+        self
+    }
+
+    // Only traces the paths matching `globs`, relative to the root, excluded ones included
+    pub fn trace_include(mut self, globs: GlobSet) -> TracerFS<S> {
+        self.sink.include = Some(globs);
+        self
+    }
+
+    // Writes the inputs and outputs of the whole session to `path` once the filesystem is
+    // unmounted, paths written by the session do not count as inputs when read afterwards
+    pub fn manifest(mut self, path: PathBuf) -> TracerFS<S> {
+        self.dependencies.manifest = Some(path);
+        self
+    }
+
+    // Audits a workload against a frozen tree, the backing store is never modified but for access
+    // times. The attempts to change it are traced, failed with EROFS
+    pub fn read_only(mut self) -> TracerFS<S> {
+        self.read_only = true;
+        self
+    }
+
+    // Refuses the changes of paths outside of `dirs`, relative to the root or absolute, with EROFS.
+    // They are traced as denied_write, setting the times of a file is let through
+    pub fn writable_dirs(mut self, dirs: Vec<PathBuf>) -> TracerFS<S> {
+        let root = Path::new(&self.root);
+        self.writable_dirs = Some(dirs.iter().map(|dir| root.join(dir)).collect());
+        self
+    }
+
+    // Lets the kernel cache attributes for `ttl`, changes made to the backing store behind our
+    // back go unnoticed that long
+    pub fn attr_ttl(mut self, ttl: Duration) -> TracerFS<S> {
+        self.attr_ttl = ttl;
+        self
+    }
+
+    // Lets the kernel cache names for `ttl`, along with the attributes replied with them, fuser
+    // has a single timeout for both
+    pub fn entry_ttl(mut self, ttl: Duration) -> TracerFS<S> {
+        self.entry_ttl = ttl;
+        self
+    }
+
+    // Lets the kernel remember the names found missing for `ttl`, a path created behind our back
+    // stays missing that long and only the first lookup of it is traced
+    pub fn negative_ttl(mut self, ttl: Duration) -> TracerFS<S> {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    // Denies symlinks and backing paths resolving outside of the root, with EACCES
+    pub fn confine(mut self) -> TracerFS<S> {
+        let root = Path::new(&self.root);
+        self.confined = Some(root.canonicalize().unwrap_or_else(|_| root.to_path_buf()));
+        self
+    }
+
+    // Fails the lookups of `mountpoint` and whatever is below it with ELOOP, when the root holds
+    // the mount itself. Serving them would go through the mount again and wait on itself
+    pub fn mountpoint(mut self, mountpoint: &Path) -> TracerFS<S> {
+        let (root, mountpoint) = (Path::new(&self.root), resolve(mountpoint));
+        self.mountpoint = Some(match mountpoint.strip_prefix(resolve(root)) {
+            Ok(relative) => root.join(relative),
+            Err(_) => mountpoint,
+        });
+        self
+    }
+
+    // Enforces the declared dependencies of a hermetic build, whatever matches none of the globs
+    // of `allowlist` cannot be looked up, opened or listed, with EACCES. The directories leading
+    // to declared paths can be passed through, and files created through the mount are declared
+    pub fn allowlist(mut self, mut allowlist: Allowlist) -> TracerFS<S> {
+        allowlist.root = PathBuf::from(&self.root);
+        self.allowlist = Some(allowlist);
+        self
+    }
+
+    // Creating undeclared files, directories and links is denied too, see TracerFS::allowlist
+    pub fn deny_undeclared_writes(mut self) -> TracerFS<S> {
+        if let Some(allowlist) = &mut self.allowlist {
+            allowlist.deny_writes = true;
+        }
+        self
+    }
+
+    // Leaves the root as it is, every change goes to `upper` instead. A file of the root is copied
+    // up before its first change, a removed one is hidden by a whiteout, `.wh.<name>` where the
+    // upper one would be. Paths found in both show the upper one, directories show the entries
+    // of both
+    pub fn overlay(mut self, upper: PathBuf) -> TracerFS<S> {
+        self.sink.upper = Some(upper.clone());
+        self.overlay = Some(Overlay {
+            lower: PathBuf::from(&self.root),
+            upper,
+        });
+        self
+    }
+
+    // Leaves the root as it is without keeping the changes. They are made in an overlay over the
+    // scratch directory `scratch`, so they can be read back, which is removed on unmount. Events
+    // are traced by the paths of the root, as if the changes had been made there
+    pub fn shadow(mut self, scratch: PathBuf) -> TracerFS<S> {
+        self.sink.shadow = true;
+        self.shadow = true;
+        self.overlay(scratch)
+    }
+
+    // Presents the files of `injections` at their paths, in place of whatever the root has there,
+    // along with the directories leading to them. They cannot be changed, and are traced by the
+    // path of the file they are read from
+    pub fn inject(mut self, injections: Injections) -> TracerFS<S> {
+        self.injections = injections;
+        self
+    }
+
+    // Faults in the missing paths below `under`, relative to the root, on their first lookup by
+    // running `command` with the relative path as its last argument from the root. The lookup
+    // waits for it, up to `timeout`, without holding up other operations, and fails with ENOENT
+    // if the path is still missing then. The events of fetched paths are marked materialized
+    pub fn fetch(mut self, command: String, under: PathBuf, timeout: Duration) -> TracerFS<S> {
+        let root = PathBuf::from(&self.root);
+        self.fetcher = Some(Fetcher::new(command, root, under, timeout));
+        self
+    }
+
+    // Bypasses the page cache, so every read() and write() of a process reaches the trace
+    pub fn direct_io(mut self) -> TracerFS<S> {
+        self.open_flags = consts::FOPEN_DIRECT_IO;
+        self
+    }
+
+    // Caches the attributes of every file below the root before the mount is ready, instead of
+    // on their first lookup. Slow on big roots, the kernel only ever asks for inodes it looked up
+    pub fn preload_attrs(mut self) -> TracerFS<S> {
+        self.preload_attrs = true;
+        self
+    }
+
+    // Fails the mount when the root, or with preload_attrs anything below it, cannot be read,
+    // rather than serving the rest of the tree
+    pub fn strict_init(mut self) -> TracerFS<S> {
+        self.strict_init = true;
+        self
+    }
+
+    // Keeps cached pages across opens, reads served from the cache are not traced
+    pub fn keep_cache(mut self) -> TracerFS<S> {
+        self.open_flags = consts::FOPEN_KEEP_CACHE;
+        self
+    }
+
+    // Keeps cached pages across opens until the file is seen to change, like keep_cache but
+    // without serving stale contents. Falls back to dropping them on every open
+    pub fn auto_cache(mut self) -> TracerFS<S> {
+        self.open_flags = consts::FOPEN_KEEP_CACHE;
+        self.auto_cache = true;
+        self
+    }
+
+    // Lets the kernel gather small writes in the page cache, they reach the trace as the pages
+    // they were flushed in, with the pid of whoever flushed them
+    pub fn writeback_cache(mut self) -> TracerFS<S> {
+        self.writeback_cache = true;
+        self
+    }
+
+    // Keeps the last `size` events in memory, listed as JSON lines by the events file in the root.
+    // Reading it is not traced, and it shadows a backing file of the same name
+    pub fn ring(mut self, size: usize) -> TracerFS<S> {
+        self.sink.ring_size = size;
+        self
+    }
+
+    // Attributes every operation to the process tree of its caller, rooted right below the first
+    // ancestor running one of `parents`, so every step of a parallel build can be told apart.
+    // With a manifest, the dependencies of every tree go to `dir`/manifest.<pid>.<start>.json
+    pub fn per_process(mut self, parents: Vec<String>, dir: PathBuf) -> TracerFS<S> {
+        self.sink.parents = Some(parents);
+        self.dependencies.group_dir = Some(dir);
+        self
+    }
+
+    // Traces one output or input event per file handle on its release, summing up the writes or
+    // reads through it, instead of its open, every read or write and the release itself
+    pub fn sessions(mut self) -> TracerFS<S> {
+        self.sink.sessions = true;
+        self
+    }
+
+    // Traces paths relative to the root, `.` for the root itself, and those written to the upper
+    // directory of an overlay relative to it, so the traces of a build in another root compare
+    // equal. Links are traced by their own path, and their target too if it is below the root
+    pub fn relative_paths(mut self) -> TracerFS<S> {
+        self.sink.relative_paths = true;
+        self
+    }
+
+    // Writes the summary of the operations as JSON to `path` once the filesystem is unmounted
+    pub fn summary_file(mut self, path: PathBuf) -> TracerFS<S> {
+        self.summary_file = Some(path);
+        self
+    }
+
+    // Hashes every file written through the mount once its last handle is released, the digest
+    // goes into the release event and the manifest
+    pub fn hash_outputs(mut self) -> TracerFS<S> {
+        self.hasher = Some(Hasher::new());
+        self
+    }
+
+    // Serves the reads and writes of open files on `threads` workers, the filesystem takes the
+    // next request meanwhile. Their events are traced once the next request comes in, in order
+    // for every file, and only by their monotonic_us across files
+    pub fn io_threads(mut self, threads: usize) -> TracerFS<S> {
+        self.workers = (threads > 1).then(|| Workers::new(threads, self.sink.mounted));
+        self
+    }
+
+    // Serves the operation counters, and how many files are open and cached, to every scrape of
+    // `metrics` in the Prometheus text format
+    pub fn metrics(mut self, mut metrics: Metrics) -> TracerFS<S> {
+        metrics.serve(self.sink.latencies.clone(), self.sink.mounted);
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Creates `path` once the kernel has initialized the filesystem
+    pub fn ready_file(mut self, path: PathBuf) -> TracerFS<S> {
+        self.ready_file = Some(path);
+        self
+    }
+
+    // Writes a newline to `fd` and closes it once the kernel has initialized the filesystem, the
+    // readiness notification of supervisors like s6
+    pub fn ready_fd(mut self, fd: File) -> TracerFS<S> {
+        self.ready_fd = Some(fd);
+        self
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink.inner
+    }
+
+    // Whether `path` of the root is the mount itself, or below it
+    fn reenters(&self, path: &Path) -> bool {
+        let mountpoint = match &self.mountpoint {
+            Some(x) => x,
+            None => return false,
+        };
+        // the kernel follows symlinks on its own, the names looked up in the root are none
+        if !path.starts_with(mountpoint) {
+            return false;
+        }
+        warn!("{:?} is below the mountpoint {:?}", path, mountpoint);
+        true
+    }
+
+    fn escapes(&self, op: &str, path: &Path) -> bool {
+        let root = match &self.confined {
+            Some(x) => x,
+            None => return false,
+        };
+
+        if self.injections.injects(path) {
+            return false;
+        }
+        let resolved = resolve(path);
+        if resolved.starts_with(root)
+            || (self.overlay.as_ref()).is_some_and(|x| resolved.starts_with(resolve(&x.upper)))
+        {
+            return false;
+        }
+        warn!(
+            "{}() {:?} resolves to {:?}, outside of the root",
+            op, path, resolved
+        );
+        true
+    }
+
+    // The path of the root standing for `path`, of either layer of an overlay
+    fn merged(&self, path: &Path) -> PathBuf {
+        match &self.overlay {
+            Some(overlay) => overlay.lower(path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    // The path relative to the root `path` stands for, none for the injected files
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let merged = self.merged(path);
+        merged.strip_prefix(&self.root).ok().map(Path::to_path_buf)
+    }
+
+    // Whether `ino` is injected, a file or a directory the root lacks
+    fn is_injected(&self, ino: u64) -> bool {
+        self.inodes.is_unbacked(ino)
+            || (self.attrs.get(&ino)).is_some_and(|x| self.injections.injects(&x.real_path))
+    }
+
+    // A directory leading to injected files that the root lacks, read-only and owned like the root
+    fn unbacked_dir(&mut self, relative: &Path, real_path: PathBuf) -> Option<InodeAttributes> {
+        let root = self.attrs.get(&FUSE_ROOT_ID)?.clone();
+        Some(InodeAttributes {
+            ino: self.inodes.unbacked(relative),
+            // the file type of the root, a directory too
+            mode: root.mode & !0o7777 | 0o555,
+            kind: FileKind::Directory,
+            len: 0,
+            nlinks: 2,
+            blocks: 0,
+            rdev: 0,
+            real_path,
+            ..root
+        })
+    }
+
+    // The path a change of `path` is made at, in an overlay the upper one, with the directories
+    // above it copied up
+    fn writable(&mut self, path: &Path) -> io::Result<PathBuf> {
+        let overlay = match &self.overlay {
+            Some(x) => x,
+            None => return Ok(path.to_path_buf()),
+        };
+        let missing: Vec<_> = overlay
+            .relative(path)
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| overlay.upper.join(dir).symlink_metadata().is_err())
+            .collect();
+        let mut copies = Vec::new();
+        for dir in missing.into_iter().rev() {
+            let dir = overlay.upper.join(dir);
+            copies.extend(overlay.copy_up(&dir)?.map(|copy| (copy, dir)));
+        }
+        let upper = overlay.upper(path);
+        for ((original, copy), dir) in copies {
+            self.copied(original, copy, dir);
+        }
+        Ok(upper)
+    }
+
+    // Copies `path` up to the upper layer of an overlay, unless it is there already, and returns
+    // where it is changed at
+    fn copy_up(&mut self, path: &Path) -> io::Result<PathBuf> {
+        let upper = self.writable(path)?;
+        let copy = match &self.overlay {
+            Some(overlay) => overlay.copy_up(&upper)?,
+            None => None,
+        };
+        if let Some((original, copy)) = copy {
+            self.copied(original, copy, upper.clone());
+        }
+        Ok(upper)
+    }
+
+    // The copy keeps the inode of the original, the kernel does not notice the change of layers
+    fn copied(&mut self, original: Metadata, copy: Metadata, path: PathBuf) {
+        self.inodes.alias(&original, &copy);
+        let attrs = self.inodes.attributes(copy, path);
+        self.generations.carry(&attrs);
+        self.attrs.insert(attrs.ino, attrs);
+    }
+
+    // Hides the lower entry replaced by `path` once it was created in the upper layer
+    fn created(&self, path: &Path) {
+        if let Some(overlay) = &self.overlay {
+            if let Err(e) = overlay.created(path) {
+                warn!("Failed to hide what {} replaces: {}", path.display(), e);
+            }
+        }
+    }
+
+    // Removes `path`, in an overlay from the upper layer, with a whiteout over the lower entry
+    fn remove(&mut self, path: &Path, directory: bool) -> io::Result<()> {
+        match &self.overlay {
+            Some(overlay) => overlay.remove(path, directory)?,
+            None if directory => return fs::remove_dir(path),
+            None => return fs::remove_file(path),
+        }
+        self.hide(path)
+    }
+
+    // Covers the lower entry of `path` with a whiteout, once the upper one is gone
+    fn hide(&mut self, path: &Path) -> io::Result<()> {
+        let overlay = match &self.overlay {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let exists = |path: PathBuf| path.symlink_metadata().is_ok();
+        if overlay.hides(path) || exists(overlay.upper(path)) || !exists(overlay.lower(path)) {
+            return Ok(());
+        }
+        let whiteout = overlay.whiteout(path);
+        self.writable(path)?;
+        File::create(whiteout).map(|_| ())
+    }
+
+    // How a file is opened with the writeback cache, the kernel reads around the partial pages of
+    // write-only files and appends at the end of file it keeps itself
+    fn cached_writes(&self, read: bool, write: bool, flags: i32) -> (bool, i32) {
+        match self.writeback_cache && write {
+            true => (true, flags & !libc::O_APPEND),
+            false => (read, flags),
+        }
+    }
+
+    // Whether a change of `path` is refused for being outside of the writable directories, which
+    // is traced as denied_write
+    fn unwritable(&mut self, req: &Request<'_>, path: &Path) -> bool {
+        let path = self.merged(path);
+        match &self.writable_dirs {
+            Some(dirs) if !dirs.iter().any(|dir| path.starts_with(dir)) => {
+                let path = path.to_string_lossy();
+                let event = TraceEvent::new(req.pid(), 'x', TraceOp::DeniedWrite, &path);
+                let result = Err::<(), _>(io::Error::from_raw_os_error(libc::EROFS));
+                self.sink.trace_event(event, &result);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Traces a change refused in read-only mode, the backing store is left alone
+    fn refuse(&mut self, event: TraceEvent) {
+        let result = Err::<(), _>(io::Error::from_raw_os_error(libc::EROFS));
+        self.sink.trace_event(event, &result);
+    }
+
+    // Extended attributes are not passed through, but changing them is still a change a read-only
+    // mount refuses and traces
+    fn xattr_change(&mut self, req: &Request<'_>, ino: u64, op: TraceOp, reply: ReplyEmpty) {
+        if !self.read_only {
+            reply.error(libc::ENOSYS);
+            return;
+        }
+        match self.attrs.get(&ino) {
+            Some(attrs) => {
+                let path = attrs.real_path.to_string_lossy().into_owned();
+                self.refuse(TraceEvent::new(req.pid(), 'w', op, &path));
+                reply.error(libc::EROFS);
+            }
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    // Takes in the inode a fetch replied to the lookups with, before any other operation can refer
+    // to it
+    fn fetched(&mut self, fetched: Fetched) {
+        let path = fetched.path.to_string_lossy().into_owned();
+        match fetched.result {
+            Ok((attrs, backing)) => {
+                self.inodes.assign(backing, attrs.ino);
+                self.generations.of(&attrs);
+                *self.lookups.entry(attrs.ino).or_insert(0) += fetched.lookups;
+                self.attrs.insert(attrs.ino, attrs);
+                self.sink.materialized.insert(path);
+            }
+            Err(e) => {
+                let event = TraceEvent::new(fetched.pid, 'n', TraceOp::FetchFailed, &path);
+                self.sink
+                    .trace_event(event, &Err::<(), _>(io::Error::from_raw_os_error(e)));
+            }
+        }
+    }
+
+    // Whether the allowlist keeps `path` from the caller, which is traced as denied
+    fn denied(&mut self, req: &Request<'_>, path: &Path, directory: bool) -> bool {
+        let path = &self.merged(path);
+        match &self.allowlist {
+            Some(allowlist) if !allowlist.permits(path, directory) => {
+                self.trace_denied(req, path);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Whether the allowlist keeps the caller from creating `path`, anything else it creates is
+    // declared from then on
+    fn denied_write(&mut self, req: &Request<'_>, path: &Path) -> bool {
+        let path = &self.merged(path);
+        match &mut self.allowlist {
+            Some(allowlist) if allowlist.deny_writes && !allowlist.declares(path) => {
+                self.trace_denied(req, path);
+                true
+            }
+            Some(allowlist) => {
+                allowlist.produced.insert(path.to_path_buf());
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn trace_denied(&mut self, req: &Request<'_>, path: &Path) {
+        warn!("{:?} is not in the allowlist", path);
+        self.sink.trace_event(
+            TraceEvent::new(req.pid(), 'x', TraceOp::Denied, &path.to_string_lossy()),
+            &Err::<(), _>(io::Error::from_raw_os_error(libc::EACCES)),
+        );
+    }
+
+    // Accounts for a read or write replied to by a worker
+    fn served(&mut self, done: Done) {
+        let path = PathBuf::from(&done.event.path);
+        if let Ok(&moved) = done.result.as_ref() {
+            let access = match done.event.op {
+                TraceOp::Write => Access::Write,
+                _ => Access::Read,
+            };
+            if let Some(handle) = self.handles.get_mut(&done.fh) {
+                let end = done.offset + moved as i64;
+                match access {
+                    Access::Write => {
+                        handle.written += moved as u64;
+                        handle.span = Some(match handle.span {
+                            Some((first, last)) => (min(first, done.offset), max(last, end)),
+                            None => (done.offset, end),
+                        });
+                    }
+                    _ => handle.read += moved as u64,
+                }
+            }
+            self.dependencies
+                .transfer(&path, access, done.offset, moved);
+        }
+        self.sink.trace_event(done.event, &done.result);
+    }
+
+    fn time(&mut self, op: &'static str, req: &Request<'_>) -> OpTimer {
+        if let Some(hasher) = &self.hasher {
+            for event in hasher.finished() {
+                self.sink.trace_event(event, &Ok::<(), io::Error>(()));
+            }
+        }
+        if let Some(fetcher) = &self.fetcher {
+            for fetched in fetcher.finished() {
+                self.fetched(fetched);
+            }
+        }
+        if let Some(workers) = &self.workers {
+            for done in workers.finished() {
+                self.served(done);
+            }
+        }
+
+        if let Some(parents) = &self.sink.parents {
+            let group = self.sink.processes.group(req.pid(), parents);
+            self.sink.group = group;
+            self.dependencies.group = group;
+        }
+
+        if let Ok(mut latencies) = self.sink.latencies.lock() {
+            latencies.current = op;
+        }
+        if let Some(metrics) = &self.metrics {
+            let gauges = &metrics.gauges;
+            gauges
+                .open_files
+                .store(self.handles.len(), Ordering::Relaxed);
+            gauges
+                .open_dirs
+                .store(self.dir_handles.len(), Ordering::Relaxed);
+            gauges.inodes.store(self.attrs.len(), Ordering::Relaxed);
+        }
+
+        let start = Instant::now();
+        self.sink.started = start;
+        self.sink.caller = Some((req.pid(), req.uid(), req.gid()));
+        OpTimer {
+            op,
+            start,
+            latencies: self.sink.latencies.clone(),
+        }
+    }
+
+    // Caches the attributes of the root, and with preload_attrs those of everything below it.
+    // Otherwise they are cached by lookup() and readdir() as they are first accessed. What cannot
+    // be read is left out with a warning, with strict_init the scan fails with its errno instead
+    pub fn scan(&mut self) -> Result<(), c_int> {
+        let root = PathBuf::from(&self.root);
+        match fs::metadata(&root) {
+            Ok(metadata) => {
+                self.inodes.root(&metadata);
+                if let Some(upper) = self.overlay.as_ref().map(|x| &x.upper) {
+                    match fs::symlink_metadata(upper) {
+                        Ok(metadata) => self.inodes.root(&metadata),
+                        Err(e) => warn!("Failed to stat {}: {}", upper.display(), e),
+                    }
+                }
+                let attrs = self.inodes.attributes(metadata, root);
+                self.attrs.insert(attrs.ino, attrs);
+            }
+            Err(e) => {
+                warn!("Failed to stat {}: {}", self.root, e);
+                if self.strict_init {
+                    return Err(errno(&e));
+                }
+            }
+        }
+        if !self.preload_attrs {
+            return Ok(());
+        }
+
+        let entries = WalkDir::new(&self.root).min_depth(1).into_iter();
+        // the mount itself is not walked into, see TracerFS::mountpoint
+        let mountpoint = self.mountpoint.clone();
+        let entries = entries.filter_entry(|entry| {
+            !mountpoint
+                .as_ref()
+                .is_some_and(|x| entry.path().starts_with(x))
+        });
+        for entry in entries {
+            let (metadata, entry) = match entry.and_then(|x| Ok((x.metadata()?, x))) {
+                Ok(x) => x,
+                Err(e) => {
+                    let path = e.path().unwrap_or(Path::new(&self.root));
+                    match e.io_error() {
+                        Some(cause) => warn!("Failed to scan {}: {}", path.display(), cause),
+                        None => warn!("Failed to scan {}: {}", path.display(), e),
+                    }
+                    if self.strict_init {
+                        return Err(e.io_error().map_or(libc::EIO, errno));
+                    }
+                    continue;
+                }
+            };
+            debug!("init() entry: {:?}", entry);
+            let attrs = self.inodes.attributes(metadata, entry.path().to_path_buf());
+            self.attrs.insert(attrs.ino, attrs);
+        }
+        Ok(())
+    }
+
+    pub fn read_link(&mut self, pid: u32, ino: u64) -> Result<PathBuf, c_int> {
+        let attrs = match self.attrs.get(&ino) {
+            Some(x) => x,
+            None => return Err(libc::ENOENT),
+        };
+
+        self.dependencies
+            .access(pid, &attrs.real_path, Access::Read);
+        if attrs.kind != FileKind::Symlink {
+            return Err(libc::EINVAL);
+        }
+
+        // the target is returned verbatim, it does not need to exist
+        let result = fs::read_link(&attrs.real_path).and_then(|link| {
+            // the kernel follows the link on its own, relative targets start at its directory
+            let target = match attrs.real_path.parent() {
+                Some(dir) => dir.join(&link),
+                None => link.clone(),
+            };
+            if self.escapes("readlink", &target) {
+                return Err(io::Error::from_raw_os_error(libc::EACCES));
+            }
+            Ok(link)
+        });
+
+        self.sink.trace_event(
+            TraceEvent {
+                target: result
+                    .as_ref()
+                    .ok()
+                    .map(|link| link.to_string_lossy().into_owned()),
+                ..TraceEvent::new(
+                    pid,
+                    'r',
+                    TraceOp::Readlink,
+                    &attrs.real_path.to_string_lossy(),
+                )
+            },
+            &result,
+        );
+
+        result.map_err(|e| errno(&e))
+    }
+
+    fn register_handle(&mut self, pid: u32, ino: u64, file: File, flags: i32) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        let handle = FileHandle {
+            pid,
+            ino,
+            file: Arc::new(file),
+            flags,
+            read: 0,
+            written: 0,
+            span: None,
+        };
+        self.handles.insert(fh, handle);
+        fh
+    }
+
+    // The inode of a synthetic entry, which shadows whatever the backing directory has by its name
+    fn synthetic_entry(&self, parent: u64, name: &OsStr) -> Option<u64> {
+        match (parent, name.to_str()?) {
+            (FUSE_ROOT_ID, EVENTS_FILE) if self.sink.ring_size > 0 => Some(EVENTS_INO),
+            (FUSE_ROOT_ID, CAIRN_DIR) => Some(CAIRN_DIR_INO),
+            (CAIRN_DIR_INO, STATS_FILE) => Some(STATS_INO),
+            (CAIRN_DIR_INO, CONTROL_FILE) => Some(CONTROL_INO),
+            _ => None,
+        }
+    }
+
+    // Names that cannot be created, removed or renamed, the synthetic and injected entries and
+    // whatever would go into the synthetic directory or one the root lacks
+    fn reserved(&self, parent: u64, name: &OsStr) -> bool {
+        if is_synthetic(parent) || self.inodes.is_unbacked(parent) {
+            return true;
+        }
+        let relative = self.get_path(parent, name).ok();
+        let relative = relative.and_then(|x| self.relative(&x));
+        self.synthetic_entry(parent, name).is_some()
+            || relative
+                .is_some_and(|x| self.injections.source(&x).is_some() || self.injections.is_dir(&x))
+    }
+
+    // Owned by the owner of the root and read-only but for the control file, the size of a file is
+    // unknown until it is read
+    fn synthetic_attr(&self, ino: u64) -> fuser::FileAttr {
+        let (uid, gid) = self
+            .attrs
+            .get(&FUSE_ROOT_ID)
+            .map_or((0, 0), |root| (root.uid, root.gid));
+        let (kind, perm, nlink) = match ino {
+            CAIRN_DIR_INO => (fuser::FileType::Directory, 0o555, 2),
+            CONTROL_INO => (fuser::FileType::RegularFile, 0o644, 1),
+            _ => (fuser::FileType::RegularFile, 0o444, 1),
+        };
+        let now = SystemTime::now();
+        fuser::FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind,
+            perm,
+            nlink,
+            uid,
+            gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    // The counters of the operations so far along with the state the tracer holds on to
+    fn stats(&self) -> Vec<u8> {
+        let ops = self.sink.latencies.lock().unwrap().summary();
+        let stats = serde_json::json!({
+            "uptime_s": self.sink.mounted.elapsed().as_secs(),
+            "bytes_read": ops.values().map(|op| op.bytes_read).sum::<u64>(),
+            "bytes_written": ops.values().map(|op| op.bytes_written).sum::<u64>(),
+            "open_files": self.handles.len(),
+            "open_dirs": self.dir_handles.len(),
+            "inodes": self.attrs.len(),
+            "ops": ops,
+        });
+        let mut stats = serde_json::to_vec_pretty(&stats).unwrap();
+        stats.push(b'\n');
+        stats
+    }
+
+    fn control(&mut self, req: &Request<'_>, command: Command) {
+        match command {
+            Command::Flush => self.sink.sync(),
+            Command::Marker(label) => self.sink.trace_event(
+                TraceEvent::new(req.pid(), 'l', TraceOp::Marker, &label),
+                &Ok::<(), io::Error>(()),
+            ),
+            Command::Pause => self.sink.paused = true,
+            Command::Resume => self.sink.paused = false,
+        }
+    }
+
+    fn register_dir_handle(&mut self, dir: File, entries: Vec<(u64, FileKind, OsString)>) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.dir_handles.insert(fh, DirHandle { dir, entries });
+        fh
+    }
+
+    fn list_dir(&mut self, ino: u64, path: &Path) -> io::Result<Vec<(u64, FileKind, OsString)>> {
+        // the root has no parent inside the filesystem, it is its own parent like on any mount
+        let parent = match path.parent() {
+            Some(parent) if ino != FUSE_ROOT_ID => {
+                let upper = self.overlay.as_ref().map(|x| x.upper.as_path());
+                let unbacked = self.relative(parent).filter(|x| self.injections.is_dir(x));
+                if parent == Path::new(&self.root) || Some(parent) == upper {
+                    FUSE_ROOT_ID
+                } else {
+                    match (fs::symlink_metadata(parent), unbacked) {
+                        (Ok(metadata), _) => self.inodes.of(&metadata),
+                        (Err(_), Some(relative)) => self.inodes.unbacked(&relative),
+                        (Err(e), None) => return Err(e),
+                    }
+                }
+            }
+            _ => FUSE_ROOT_ID,
+        };
+
+        let mut entries = vec![
+            (ino, FileKind::Directory, OsString::from(".")),
+            (parent, FileKind::Directory, OsString::from("..")),
+        ];
+        let injected: Vec<(OsString, Option<PathBuf>)> = match self.relative(path) {
+            Some(dir) => (self.injections.children(&dir))
+                .map(|(name, source)| (name.to_os_string(), source.map(Path::to_path_buf)))
+                .collect(),
+            None => Vec::new(),
+        };
+        let listing = match &self.overlay {
+            _ if self.inodes.is_unbacked(ino) => Vec::new(),
+            Some(overlay) => overlay.read_dir(path)?,
+            None => fs::read_dir(path)?
+                .map(|entry| {
+                    let entry = entry?;
+                    Ok((entry.file_name(), entry.path(), entry.metadata()?))
+                })
+                .collect::<io::Result<_>>()?,
+        };
+        for (name, path, metadata) in listing {
+            // shadowed by an injected file
+            if injected
+                .iter()
+                .any(|(x, source)| *x == name && source.is_some())
+            {
+                continue;
+            }
+            // what cannot be looked up is not listed either
+            if let Some(allowlist) = &self.allowlist {
+                if !allowlist.permits(&self.merged(&path), metadata.is_dir()) {
+                    continue;
+                }
+            }
+            let Some(kind) = as_file_kind(metadata.mode()) else {
+                warn!("{} has a file type FUSE does not know", path.display());
+                continue;
+            };
+            entries.push((self.inodes.of(&metadata), kind, name));
+        }
+        for (name, source) in injected {
+            match source {
+                Some(source) => {
+                    let metadata = fs::symlink_metadata(&source)?;
+                    let Some(kind) = as_file_kind(metadata.mode()) else {
+                        warn!("{} has a file type FUSE does not know", source.display());
+                        continue;
+                    };
+                    entries.push((self.inodes.of(&metadata), kind, name));
+                }
+                None if entries.iter().any(|(_, _, x)| *x == name) => {}
+                None => {
+                    let relative = self.relative(&path.join(&name)).unwrap_or_default();
+                    let ino = self.inodes.unbacked(&relative);
+                    entries.push((ino, FileKind::Directory, name));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    // OFD locks of different descriptions conflict like the POSIX locks of different processes
+    // would, so every lock owner gets a description of its own
+    fn lock_file(&mut self, ino: u64, lock_owner: u64) -> io::Result<&File> {
+        if !self.locks.contains_key(&(ino, lock_owner)) {
+            let real_path = match self.attrs.get(&ino) {
+                Some(x) => &x.real_path,
+                None => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+            };
+            // write locks need a writable description, read locks work either way
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(real_path)
+                .or_else(|_| File::open(real_path))?;
+            self.locks.insert((ino, lock_owner), file);
+        }
+        Ok(&self.locks[&(ino, lock_owner)])
+    }
+
+    fn is_open(&self, ino: u64) -> bool {
+        self.handles.values().any(|handle| handle.ino == ino)
+    }
+
+    // Every entry replied to the kernel has to be balanced by a forget()
+    fn remember(&mut self, ino: u64) {
+        *self.lookups.entry(ino).or_insert(0) += 1;
+    }
+
+    fn forget_inode(&mut self, ino: u64, nlookup: u64) {
+        let remaining = match self.lookups.get_mut(&ino) {
+            Some(count) => {
+                *count = count.saturating_sub(nlookup);
+                *count
+            }
+            None => 0,
+        };
+
+        if remaining == 0 {
+            self.lookups.remove(&ino);
+            if ino != FUSE_ROOT_ID && !self.is_open(ino) {
+                self.evict(ino);
+            }
+        }
+    }
+
+    // Drops all there is of an inode the kernel forgot and no handle refers to anymore
+    fn evict(&mut self, ino: u64) {
+        self.attrs.remove(&ino);
+        self.unlinked.remove(&ino);
+        self.dirty.remove(&ino);
+        self.inodes.forget(ino);
+        self.generations.forget(ino);
+    }
+
+    fn get_path(&self, parent: u64, name: &OsStr) -> Result<PathBuf, c_int> {
+        let parent_context = match self.attrs.get(&parent) {
+            Some(x) => x,
+            None => {
+                return Err(libc::ENOENT);
+            }
+        };
+        let path = parent_context.real_path.join(name);
+        Ok(match &self.overlay {
+            Some(overlay) => overlay.resolve(&path),
+            None => path,
+        })
+    }
+
+    // Rewrites the cached paths below a renamed directory
+    fn rebase_descendants(&mut self, from: &Path, to: &Path) {
+        for attrs in self.attrs.values_mut() {
+            if let Ok(rest) = attrs.real_path.strip_prefix(from) {
+                if !rest.as_os_str().is_empty() {
+                    attrs.real_path = to.join(rest);
+                }
+            }
+        }
+    }
+
+    // Swaps two entries and whatever is known below them, after RENAME_EXCHANGE
+    fn exchange_paths(&mut self, a: &Path, b: &Path) {
+        for attrs in self.attrs.values_mut() {
+            if let Ok(rest) = attrs.real_path.strip_prefix(a) {
+                attrs.real_path = b.join(rest);
+            } else if let Ok(rest) = attrs.real_path.strip_prefix(b) {
+                attrs.real_path = a.join(rest);
+            }
+        }
+    }
+
+    fn lookup_name(&mut self, parent: u64, name: &OsStr) -> Result<InodeAttributes, c_int> {
+        if self.overlay.is_some() && name.as_bytes().starts_with(WHITEOUT_PREFIX.as_bytes()) {
+            return Err(libc::ENOENT);
+        }
+        let path = match self.get_path(parent, name) {
+            Ok(x) => x,
+            Err(c) => {
+                return Err(c);
+            }
+        };
+        if self.reenters(&path) {
+            return Err(libc::ELOOP);
+        }
+        if let Some(relative) = self.relative(&path) {
+            if let Some(source) = self.injections.source(&relative) {
+                let source = source.to_path_buf();
+                return match fs::symlink_metadata(&source) {
+                    Ok(metadata) => Ok(self.inodes.attributes(metadata, source)),
+                    Err(e) => Err(errno(&e)),
+                };
+            }
+            if self.injections.is_dir(&relative) && fs::symlink_metadata(&path).is_err() {
+                return self.unbacked_dir(&relative, path).ok_or(libc::ENOENT);
+            }
+        }
+        let metadata = fs::symlink_metadata(path.clone());
+        match metadata {
+            Ok(metadata) => Ok(self.inodes.attributes(metadata, path)),
+            Err(e) => Err(errno(&e)),
+        }
+    }
+
+    fn handle_metadata_on_removal<T>(
+        &mut self,
+        metadata: io::Result<fs::Metadata>,
+        result: io::Result<T>,
+        reply: ReplyEmpty,
+    ) {
+        match result {
+            Ok(_) => match metadata {
+                Ok(metadata) => {
+                    let ino = self.inodes.of(&metadata);
+                    // the last name of the inode is gone, unless hard links remain
+                    if metadata.is_dir() || metadata.nlink() <= 1 {
+                        self.generations.retire(ino);
+                    }
+                    // open handles keep reading the removed inode until they are released
+                    if self.is_open(ino) {
+                        self.unlinked.insert(ino);
+                    } else {
+                        self.attrs.remove(&ino);
+                    }
+                    reply.ok();
+                }
+                Err(e) => {
+                    Reply::Empty(reply).error(e);
+                }
+            },
+            Err(e) => {
+                Reply::Empty(reply).error(e);
+            }
+        }
+    }
+
+    fn handle_metadata_on_change<T>(
+        &mut self,
+        path: &PathBuf,
+        result: io::Result<T>,
+        reply: ChangeReply,
+    ) {
+        match result {
+            // symlinks are reported as themselves, their target may not even exist
+            Ok(_) => match fs::symlink_metadata(path) {
+                Ok(metadata) => {
+                    let new_attrs = self.inodes.attributes(metadata, path.clone());
+                    let ino = new_attrs.ino;
+                    self.attrs.insert(ino, new_attrs.clone());
+                    match reply {
+                        ChangeReply::Entry(reply) => {
+                            self.remember(ino);
+                            let generation = self.generations.of(&new_attrs);
+                            reply.entry(&self.entry_ttl, &new_attrs.into(), generation);
+                        }
+                        ChangeReply::Attr(reply) => {
+                            reply.attr(&self.attr_ttl, &new_attrs.into());
+                        }
+                        ChangeReply::Empty(reply) => {
+                            reply.ok();
+                        }
+                    }
+                }
+                Err(e) => {
+                    Reply::from(reply).error(e);
+                }
+            },
+            Err(e) => {
+                Reply::from(reply).error(e);
+            }
+        }
+    }
+}
+
+// Sets a limit of the kernel to `wanted`, or the nearest value it accepts, which is returned
+fn negotiate(wanted: u32, mut set: impl FnMut(u32) -> Result<u32, u32>) -> u32 {
+    match set(wanted) {
+        Ok(_) => wanted,
+        Err(nearest) => {
+            let _ = set(nearest);
+            nearest
+        }
+    }
+}
+
+impl<S: TraceSink> Filesystem for TracerFS<S> {
+    fn init(&mut self, req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        let _timer = self.time("init", req);
+        if let Err(errno) = self.scan() {
+            error!("Not serving a partial tree of {}, unmounting", self.root);
+            // main() unmounts and fails on it, the kernel fails every request meanwhile
+            let _ = self.destroy.send(Err(errno));
+            return Err(errno);
+        }
+        self.sink.inner.header(&TraceHeader {
+            trace_ops: self.sink.ops,
+        });
+
+        // listings carry the attributes of their entries, saving a lookup() per entry
+        if let Err(unsupported) = config.add_capabilities(consts::FUSE_DO_READDIRPLUS) {
+            warn!("Kernel does not support readdirplus: {:#x}", unsupported);
+        }
+        // locks are taken on the backing files, so they also hold against processes outside.
+        // Without them macFUSE keeps the locks in the kernel, local to the mount
+        #[cfg(target_os = "linux")]
+        if let Err(unsupported) =
+            config.add_capabilities(consts::FUSE_POSIX_LOCKS | consts::FUSE_FLOCK_LOCKS)
+        {
+            warn!(
+                "Kernel does not support lock passthrough: {:#x}",
+                unsupported
+            );
+        }
+        if self.writeback_cache {
+            if let Err(unsupported) = config.add_capabilities(consts::FUSE_WRITEBACK_CACHE) {
+                warn!(
+                    "Kernel does not support writeback caching: {:#x}",
+                    unsupported
+                );
+                self.writeback_cache = false;
+            }
+        }
+        if self.auto_cache {
+            if let Err(unsupported) = config.add_capabilities(consts::FUSE_AUTO_INVAL_DATA) {
+                warn!("Kernel does not support auto_cache: {:#x}", unsupported);
+                self.open_flags = 0;
+                self.auto_cache = false;
+            }
+        }
+        // fewer, bigger write()s and reads, each one is a round trip
+        let max_write = negotiate(MAX_WRITE, |x| config.set_max_write(x));
+        let max_readahead = negotiate(MAX_READAHEAD, |x| config.set_max_readahead(x));
+        info!(
+            "Negotiated max_write={}, max_readahead={}, writeback_cache={}, auto_cache={}",
+            max_write, max_readahead, self.writeback_cache, self.auto_cache,
+        );
+
+        if let Some(path) = &self.ready_file {
+            if let Err(e) = File::create(path) {
+                warn!("Failed to create {}: {}", path.display(), e);
+            }
+        }
+        if let Some(mut fd) = self.ready_fd.take() {
+            if let Err(e) = fd.write_all(b"\n") {
+                warn!("Failed to signal readiness: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        debug!(
+            "destroy(root={}, cached={}, looked_up={}, numbered={})",
+            self.root,
+            self.attrs.len(),
+            self.lookups.len(),
+            self.inodes.len()
+        );
+        if let Some(workers) = &mut self.workers {
+            for done in workers.finish() {
+                self.served(done);
+            }
+        }
+        if let Some(hasher) = &mut self.hasher {
+            for event in hasher.finish() {
+                self.sink.trace_event(event, &Ok::<(), io::Error>(()));
+            }
+        }
+        // the events still queued come before anything logged from here on
+        self.sink.flush();
+        for (pid, process) in &self.dependencies.processes {
+            let manifest = serde_json::json!({
+                "pid": pid,
+                "inputs": process.inputs,
+                "outputs": process.outputs,
+            });
+            info!(target: TRACE_TARGET, "{}", manifest);
+        }
+        if let Some(path) = &self.dependencies.manifest {
+            let digests = self.hasher.as_ref().map(Hasher::digests);
+            let write = |path: &Path, paths: &BTreeMap<String, PathState>| {
+                let mut manifest = Dependencies::classify(paths);
+                if let Some(digests) = &digests {
+                    manifest.digests = digests.clone();
+                    manifest
+                        .digests
+                        .retain(|path, _| manifest.outputs.contains(path));
+                }
+                let result = serde_json::to_vec_pretty(&manifest)
+                    .map_err(io::Error::from)
+                    .and_then(|json| fs::write(path, json));
+                if let Err(e) = result {
+                    warn!("Failed to write the manifest to {}: {}", path.display(), e);
+                }
+            };
+            write(path, &self.dependencies.paths);
+            if let Some(dir) = &self.dependencies.group_dir {
+                for ((pid, start), paths) in &self.dependencies.groups {
+                    write(&dir.join(format!("manifest.{pid}.{start}.json")), paths);
+                }
+            }
+        }
+
+        let summary = self.sink.latencies.lock().unwrap().summary();
+        for line in render_summary(&summary, TraceFormat::Human) {
+            info!("{}", line);
+        }
+        if let Some(path) = &self.summary_file {
+            let result = serde_json::to_vec_pretty(&summary)
+                .map_err(io::Error::from)
+                .and_then(|json| fs::write(path, json));
+            if let Err(e) = result {
+                warn!("Failed to write the summary to {}: {}", path.display(), e);
+            }
+        }
+        self.sink.summary(&summary);
+        // the last events are on disk before the process goes away
+        self.sink.sync();
+        if let (Some(overlay), true) = (&self.overlay, self.shadow) {
+            if let Err(e) = fs::remove_dir_all(&overlay.upper) {
+                warn!("Failed to remove {}: {}", overlay.upper.display(), e);
+            }
+        }
+
+        let _ = self.destroy.send(Ok(()));
+    }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let _timer = self.time("lookup", req);
+        debug!(
+            "lookup({}, parent={}, name={:?})",
+            caller(req),
+            parent,
+            name
+        );
+        if let Some(ino) = self.synthetic_entry(parent, name) {
+            reply.entry(&Duration::new(0, 0), &self.synthetic_attr(ino), 0);
+            return;
+        }
+        if is_synthetic(parent) {
+            reply.error(libc::ENOENT);
+            return;
+        }
+
+        // a failed lookup is a dependency as well, creating the file changes the outcome
+        let path = self.get_path(parent, name);
+        if let Ok(path) = &path {
+            self.dependencies.access(req.pid(), path, Access::Stat);
+        }
+
+        // missing paths of the fetched subtree are looked up again once fetched
+        let fetched = match (&self.fetcher, &path) {
+            (Some(fetcher), Ok(path)) => fetcher.relative(path).is_some(),
+            _ => false,
+        };
+
+        match self.lookup_name(parent, name) {
+            Ok(attrs) if self.denied(req, &attrs.real_path, attrs.kind == FileKind::Directory) => {
+                reply.error(libc::EACCES);
+            }
+            Ok(attrs) => {
+                self.attrs.insert(attrs.ino, attrs.clone());
+                self.remember(attrs.ino);
+                let generation = self.generations.of(&attrs);
+                reply.entry(&self.entry_ttl, &attrs.into(), generation);
+            }
+            Err(libc::ENOENT) if fetched => {
+                let inodes = &mut self.inodes;
+                if let (Some(fetcher), Ok(path)) = (&self.fetcher, path) {
+                    fetcher.fetch(req.pid(), path, self.entry_ttl, || inodes.fresh(), reply);
+                }
+            }
+            Err(e) => {
+                if let (libc::ENOENT, Ok(path)) = (e, &path) {
+                    self.dependencies.access(req.pid(), path, Access::Miss);
+                    self.sink.trace_event(
+                        TraceEvent::new(
+                            req.pid(),
+                            'n',
+                            TraceOp::LookupMiss,
+                            &path.to_string_lossy(),
+                        ),
+                        &Err::<(), _>(io::Error::from_raw_os_error(e)),
+                    );
+                }
+                match e {
+                    // an entry without an inode is a name the kernel remembers as missing
+                    libc::ENOENT if !self.negative_ttl.is_zero() => {
+                        reply.entry(&self.negative_ttl, &missing_attr(), 0)
+                    }
+                    _ => reply.error(e),
+                }
+            }
+        }
+    }
+
+    fn forget(&mut self, req: &Request, ino: u64, nlookup: u64) {
+        let _timer = self.time("forget", req);
+        debug!("forget(ino={}, nlookup={})", ino, nlookup);
+        self.forget_inode(ino, nlookup);
+    }
+
+    fn batch_forget(&mut self, req: &Request, nodes: &[fuse_forget_one]) {
+        let _timer = self.time("batch_forget", req);
+        debug!("batch_forget(nodes={})", nodes.len());
+        for node in nodes {
+            self.forget_inode(node.nodeid, node.nlookup);
+        }
+    }
+
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        let _timer = self.time("getattr", req);
+        debug!("getattr({}, ino={})", caller(req), ino);
+        if is_synthetic(ino) {
+            reply.attr(&Duration::new(0, 0), &self.synthetic_attr(ino));
+            return;
+        }
+
+        let cached = match self.attrs.get(&ino) {
+            Some(x) => x,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+
+        self.dependencies
+            .access(req.pid(), &cached.real_path, Access::Stat);
+        if self.inodes.is_unbacked(ino) {
+            reply.attr(&self.attr_ttl, &cached.clone().into());
+            return;
+        }
+
+        // the backing tree may be changed behind our back, so the cache is only a path hint
+        let metadata = match fs::symlink_metadata(&cached.real_path) {
+            Ok(metadata) if self.inodes.of(&metadata) == ino => Ok(metadata),
+            _ => match self.handles.values().find(|handle| handle.ino == ino) {
+                // unlinked or replaced, but still reachable through an open handle
+                Some(handle) => handle.file.metadata(),
+                None => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+            },
+        };
+
+        match metadata {
+            Ok(metadata) => {
+                let attrs = self.inodes.attributes(metadata, cached.real_path.clone());
+                self.attrs.insert(ino, attrs.clone());
+                self.dirty.remove(&ino);
+                reply.attr(&self.attr_ttl, &attrs.into());
+            }
+            Err(e) => {
+                Reply::Attr(reply).error(e);
+            }
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let _timer = self.time("setattr", req);
+        // the truncation ahead of a command written by a shell
+        if ino == CONTROL_INO && mode.is_none() && uid.is_none() && gid.is_none() {
+            reply.attr(&Duration::new(0, 0), &self.synthetic_attr(ino));
+            return;
+        }
+        if is_synthetic(ino) {
+            reply.error(libc::EPERM);
+            return;
+        }
+
+        let attrs = match self.attrs.get(&ino) {
+            Some(attrs) => attrs.clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        // the access time alone is let through, reading the backing files updates it anyway
+        let atime_only =
+            mode.is_none() && uid.is_none() && gid.is_none() && size.is_none() && mtime.is_none();
+        if (self.read_only || self.is_injected(ino)) && !atime_only {
+            let op = if size.is_some() {
+                TraceOp::Truncate
+            } else if mode.is_some() {
+                TraceOp::Chmod
+            } else if uid.is_some() || gid.is_some() {
+                TraceOp::Chown
+            } else {
+                TraceOp::Utime
+            };
+            self.refuse(TraceEvent::new(
+                req.pid(),
+                'w',
+                op,
+                &attrs.real_path.to_string_lossy(),
+            ));
+            reply.error(libc::EROFS);
+            return;
+        }
+        let changed = size.is_some() || mode.is_some() || uid.is_some() || gid.is_some();
+        if changed && self.unwritable(req, &attrs.real_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
+        // macOS only attributes, sent along by `cp -p`, Finder and touch. The backing store has
+        // no place for them, failing the whole request would fail those as well
+        if crtime.is_some() || chgtime.is_some() || bkuptime.is_some() || flags.is_some() {
+            debug!(
+                "setattr() ignores crtime {:?}, chgtime {:?}, bkuptime {:?}, flags {:?}",
+                crtime, chgtime, bkuptime, flags
+            );
+        }
+
+        // the ownership rules are checked up front, a request refused in part changes nothing.
+        // Only the owner may change the mode, only root may give a file away, and the owner may
+        // only pick one of their own groups
+        if req.uid() != 0 {
+            let owner = req.uid() == attrs.uid;
+            let gives_away = uid.is_some_and(|uid| uid != attrs.uid);
+            let foreign_group =
+                gid.is_some_and(|gid| gid != attrs.gid && !caller_groups(req).contains(&gid));
+            let chown = uid.is_some() || gid.is_some();
+            if (mode.is_some() && !owner) || (chown && (gives_away || !owner || foreign_group)) {
+                reply.error(libc::EPERM);
+                return;
+            }
+        }
+
+        // in an overlay the changes go to the upper copy, an unlinked file is only reached
+        // through its handle
+        let attrs = match self.unlinked.contains(&ino) {
+            true => attrs,
+            false => match self.copy_up(&attrs.real_path) {
+                Ok(real_path) => InodeAttributes { real_path, ..attrs },
+                Err(e) => {
+                    Reply::Attr(reply).error(e);
+                    return;
+                }
+            },
+        };
+
+        // Attributes are applied one after the other, like `cp -p` or `tar -x` would do it
+        // themselves, and a single reply is sent once everything has been applied
+        if let Some(mode) = mode {
+            debug!("chmod() called with {:?}, {:o}", ino, mode);
+
+            // chmod() only takes the permission bits, the file type stays what it is
+            let result =
+                fs::set_permissions(&attrs.real_path, PermissionsExt::from_mode(mode & 0o7777));
+            self.sink.trace_event(
+                TraceEvent::new(
+                    req.pid(),
+                    'w',
+                    TraceOp::Chmod,
+                    &attrs.real_path.to_string_lossy(),
+                ),
+                &result,
+            );
+            if let Err(e) = result {
+                Reply::Attr(reply).error(e);
+                return;
+            }
+        }
+
+        if uid.is_some() || gid.is_some() {
+            debug!("chown() called with {:?} {:?} {:?}", ino, uid, gid);
+
+            let result = ufs::chown(&attrs.real_path, uid, gid);
+            self.sink.trace_event(
+                TraceEvent::new(
+                    req.pid(),
+                    'w',
+                    TraceOp::Chown,
+                    &attrs.real_path.to_string_lossy(),
+                ),
+                &result,
+            );
+            if let Err(e) = result {
+                Reply::Attr(reply).error(e);
+                return;
+            }
+
+            // a new owner must not inherit the privileges of an executable
+            let perm = attrs.mode & 0o7777;
+            if mode.is_none() && attrs.kind == FileKind::File && perm & 0o111 != 0 {
+                if let Err(e) = fs::set_permissions(
+                    &attrs.real_path,
+                    PermissionsExt::from_mode(perm & !SETID_BITS),
+                ) {
+                    Reply::Attr(reply).error(e);
+                    return;
+                }
+            }
+        }
+
+        if let Some(size) = size {
+            debug!("truncate() called with {:?} {:?}", ino, size);
+
+            // ftruncate() hands over its handle, which also works for unlinked files
+            let result = match fh.and_then(|fh| self.handles.get(&fh)) {
+                Some(handle) => handle.file.set_len(size),
+                None => {
+                    // open file and truncate it
+                    let file = match OpenOptions::new().write(true).open(&attrs.real_path) {
+                        Ok(file) => file,
+                        Err(e) => {
+                            Reply::Attr(reply).error(e);
+                            return;
+                        }
+                    };
+                    file.set_len(size)
+                }
+            };
+
+            self.sink.trace_event(
+                TraceEvent::new(
+                    req.pid(),
+                    'w',
+                    TraceOp::Truncate,
+                    &attrs.real_path.to_string_lossy(),
+                ),
+                &result,
+            );
+            if let Err(e) = result {
+                Reply::Attr(reply).error(e);
+                return;
+            }
+            self.dirty.insert(ino);
+        }
+
+        if atime.is_some() || mtime.is_some() {
+            // the kernel takes the current time itself and leaves the omitted one alone, so
+            // nothing races with a concurrent writer updating the other timestamp
+            let timespec = |time: Option<TimeOrNow>| match time {
+                Some(TimeOrNow::SpecificTime(time)) => {
+                    let (secs, nsecs) = time_from_system_time(&time);
+                    libc::timespec {
+                        tv_sec: secs,
+                        tv_nsec: nsecs as libc::c_long,
+                    }
+                }
+                Some(TimeOrNow::Now) => libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: libc::UTIME_NOW,
+                },
+                None => libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: libc::UTIME_OMIT,
+                },
+            };
+            let times = [timespec(atime), timespec(mtime)];
+
+            debug!(
+                "utimensat() called with {:?} {}.{:09} {}.{:09}",
+                ino, times[0].tv_sec, times[0].tv_nsec, times[1].tv_sec, times[1].tv_nsec
+            );
+
+            let path = match CString::new(attrs.real_path.as_os_str().as_bytes()) {
+                Ok(x) => x,
+                Err(_) => {
+                    reply.error(libc::EINVAL);
+                    return;
+                }
+            };
+
+            // symlinks are reported as themselves, so their own timestamps are changed
+            let result = unsafe {
+                libc::utimensat(
+                    libc::AT_FDCWD,
+                    path.as_ptr(),
+                    times.as_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            };
+            let result = if result == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            };
+            self.sink.trace_event(
+                TraceEvent::new(
+                    req.pid(),
+                    't',
+                    TraceOp::Utime,
+                    &attrs.real_path.to_string_lossy(),
+                ),
+                &result,
+            );
+            if let Err(e) = result {
+                Reply::Attr(reply).error(e);
+                return;
+            }
+        }
+
+        self.dependencies
+            .access(req.pid(), &attrs.real_path, Access::Write);
+
+        // stat once everything has been applied, through the handle if the caller passed one,
+        // which also works for unlinked files
+        match fh.and_then(|fh| self.handles.get(&fh)) {
+            Some(handle) => match handle.file.metadata() {
+                Ok(metadata) => {
+                    let new_attrs = self.inodes.attributes(metadata, attrs.real_path);
+                    self.attrs.insert(ino, new_attrs.clone());
+                    reply.attr(&self.attr_ttl, &new_attrs.into());
+                }
+                Err(e) => {
+                    Reply::Attr(reply).error(e);
+                }
+            },
+            None => {
+                self.handle_metadata_on_change(&attrs.real_path, Ok(()), ChangeReply::Attr(reply));
+            }
+        }
+    }
+
+    fn readlink(&mut self, req: &Request<'_>, ino: u64, reply: ReplyData) {
+        let _timer = self.time("readlink", req);
+        debug!("readlink({}, ino={})", caller(req), ino);
+
+        match self.read_link(req.pid(), ino) {
+            Ok(link) => reply.data(link.as_os_str().as_bytes()),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn mknod(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let _timer = self.time("mknod", req);
+        debug!(
+            "mknod({}, parent={}, name={:?}, mode={}, rdev={})",
+            caller(req),
+            parent,
+            name,
+            mode,
+            rdev
+        );
+        if self.reserved(parent, name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.get_path(parent, name) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        if self.read_only {
+            self.refuse(TraceEvent::new(
+                req.pid(),
+                'w',
+                TraceOp::Mknod,
+                &path.to_string_lossy(),
+            ));
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.unwritable(req, &path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        // check if file already exists
+        if self.lookup_name(parent, name).is_ok() {
+            reply.error(libc::EEXIST);
+            return;
+        }
+
+        if self.denied_write(req, &path) {
+            reply.error(libc::EACCES);
+            return;
+        }
+        let path = match self.writable(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Entry(reply).error(e);
+                return;
+            }
+        };
+        // the type of anything but a regular file is only set by mknod(2) itself
+        let result = if mode & libc::S_IFMT == libc::S_IFREG {
+            File::create(&path).map(|_| ())
+        } else {
+            make_node(&path, mode, rdev)
+        };
+        self.sink.trace_event(
+            TraceEvent::new(req.pid(), 'w', TraceOp::Mknod, &path.to_string_lossy()),
+            &result,
+        );
+        if result.is_ok() {
+            self.dependencies.access(req.pid(), &path, Access::Create);
+            self.created(&path);
+        }
+        self.handle_metadata_on_change(&path, result, ChangeReply::Entry(reply));
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let _timer = self.time("mkdir", req);
+        debug!(
+            "mkdir({}, parent={}, name={:?}, mode={})",
+            caller(req),
+            parent,
+            name,
+            mode
+        );
+        if self.reserved(parent, name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.get_path(parent, name) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        if self.read_only {
+            self.refuse(TraceEvent::new(
+                req.pid(),
+                'w',
+                TraceOp::Mkdir,
+                &path.to_string_lossy(),
+            ));
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.unwritable(req, &path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.denied_write(req, &path) {
+            reply.error(libc::EACCES);
+            return;
+        }
+        let path = match self.writable(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Entry(reply).error(e);
+                return;
+            }
+        };
+        // the setgid bit of the parent is still inherited by the backing filesystem
+        let result = fs::DirBuilder::new().mode(mode & !umask).create(&path);
+        self.sink.trace_event(
+            TraceEvent::new(req.pid(), 'w', TraceOp::Mkdir, &path.to_string_lossy()),
+            &result,
+        );
+        if result.is_ok() {
+            self.dependencies.access(req.pid(), &path, Access::Create);
+            self.created(&path);
+        }
+        self.handle_metadata_on_change(&path, result, ChangeReply::Entry(reply));
+    }
+
+    fn unlink(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let _timer = self.time("unlink", req);
+        debug!(
+            "unlink({}, parent={}, name={:?})",
+            caller(req),
+            parent,
+            name
+        );
+        if self.reserved(parent, name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.get_path(parent, name) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        if self.read_only {
+            self.refuse(TraceEvent::new(
+                req.pid(),
+                'd',
+                TraceOp::Unlink,
+                &path.to_string_lossy(),
+            ));
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.unwritable(req, &path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let metadata = fs::symlink_metadata(&path);
+
+        let result = self.remove(&path, false);
+        self.sink.trace_event(
+            TraceEvent::new(req.pid(), 'd', TraceOp::Unlink, &path.to_string_lossy()),
+            &result,
+        );
+        if result.is_ok() {
+            self.dependencies.access(req.pid(), &path, Access::Remove);
+        }
+        self.handle_metadata_on_removal(metadata, result, reply);
+    }
+
+    fn rmdir(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let _timer = self.time("rmdir", req);
+        debug!("rmdir({}, parent={}, name={:?})", caller(req), parent, name);
+        if self.reserved(parent, name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.get_path(parent, name) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        if self.read_only {
+            self.refuse(TraceEvent::new(
+                req.pid(),
+                'd',
+                TraceOp::Rmdir,
+                &path.to_string_lossy(),
+            ));
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.unwritable(req, &path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let metadata = fs::symlink_metadata(&path);
+
+        let result = self.remove(&path, true);
+        self.sink.trace_event(
+            TraceEvent::new(req.pid(), 'd', TraceOp::Rmdir, &path.to_string_lossy()),
+            &result,
+        );
+        if result.is_ok() {
+            self.dependencies.access(req.pid(), &path, Access::Remove);
+        }
+        self.handle_metadata_on_removal(metadata, result, reply);
+    }
+
+    fn symlink(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        link: &Path,
+        reply: ReplyEntry,
+    ) {
+        let _timer = self.time("symlink", req);
+        debug!(
+            "symlink({}, parent={}, name={:?}, link={:?})",
+            caller(req),
+            parent,
+            name,
+            link
+        );
+        if self.reserved(parent, name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.get_path(parent, name) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        if self.read_only {
+            self.refuse(TraceEvent {
+                target: Some(link.to_string_lossy().into_owned()),
+                ..TraceEvent::new(req.pid(), 'w', TraceOp::Symlink, &path.to_string_lossy())
+            });
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.unwritable(req, &path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.denied_write(req, &path) {
+            reply.error(libc::EACCES);
+            return;
+        }
+        let path = match self.writable(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Entry(reply).error(e);
+                return;
+            }
+        };
+        let result = ufs::symlink(link, &path);
+        self.sink.trace_event(
+            TraceEvent {
+                target: Some(link.to_string_lossy().into_owned()),
+                ..TraceEvent::new(req.pid(), 'w', TraceOp::Symlink, &path.to_string_lossy())
+            },
+            &result,
+        );
+        if result.is_ok() {
+            self.dependencies.access(req.pid(), &path, Access::Create);
+            self.created(&path);
+        }
+        self.handle_metadata_on_change(&path, result, ChangeReply::Entry(reply));
+    }
+
+    fn rename(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let _timer = self.time("rename", req);
+        debug!(
+            "rename({}, parent={}, name={:?}, newparent={}, newname={:?}, flags={:#x})",
+            caller(req),
+            parent,
+            name,
+            newparent,
+            newname,
+            flags
+        );
+        if self.reserved(parent, name) || self.reserved(newparent, newname) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.get_path(parent, name) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        let newpath = match self.get_path(newparent, newname) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        if self.read_only {
+            self.refuse(TraceEvent {
+                target: Some(newpath.to_string_lossy().into_owned()),
+                ..TraceEvent::new(req.pid(), 'm', TraceOp::Rename, &path.to_string_lossy())
+            });
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.unwritable(req, &path) || self.unwritable(req, &newpath) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.denied_write(req, &newpath) {
+            reply.error(libc::EACCES);
+            return;
+        }
+        // an entry replaced by the rename is no longer reachable
+        let replaced = fs::symlink_metadata(&newpath);
+        if let Some(overlay) = &self.overlay {
+            // the upper entry is moved, a lower directory would have to take its entries along,
+            // which mv does by copying them instead
+            let lower_dir = fs::symlink_metadata(overlay.lower(&path)).is_ok_and(|x| x.is_dir());
+            if lower_dir || flags & RENAME_EXCHANGE != 0 {
+                reply.error(libc::EXDEV);
+                return;
+            }
+            // renameat2() only sees the upper entry
+            if flags & RENAME_NOREPLACE != 0 && replaced.is_ok() {
+                reply.error(libc::EEXIST);
+                return;
+            }
+        }
+        let (path, newpath) = match self
+            .copy_up(&path)
+            .and_then(|path| Ok((path, self.writable(&newpath)?)))
+        {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Empty(reply).error(e);
+                return;
+            }
+        };
+
+        // renameat2() refuses to replace the target or swaps the two entries
+        let result = match flags {
+            0 => fs::rename(path.clone(), newpath.clone()),
+            _ => rename_with_flags(&path, &newpath, flags),
+        };
+        self.sink.trace_event(
+            TraceEvent {
+                target: Some(newpath.to_string_lossy().into_owned()),
+                ..TraceEvent::new(req.pid(), 'm', TraceOp::Rename, &path.to_string_lossy())
+            },
+            &result,
+        );
+        if result.is_ok() && flags & RENAME_EXCHANGE != 0 {
+            // both names still exist, each with the contents of the other
+            self.dependencies.access(req.pid(), &path, Access::Create);
+            self.dependencies
+                .access(req.pid(), &newpath, Access::Create);
+            self.exchange_paths(&path, &newpath);
+        } else if result.is_ok() {
+            self.dependencies.access(req.pid(), &path, Access::Remove);
+            self.dependencies
+                .access(req.pid(), &newpath, Access::Create);
+            if let Ok(metadata) = replaced {
+                let ino = self.inodes.of(&metadata);
+                if metadata.is_dir() || metadata.nlink() <= 1 {
+                    self.generations.retire(ino);
+                }
+                self.attrs.remove(&ino);
+            }
+            self.rebase_descendants(&path, &newpath);
+            if let Err(e) = self.hide(&path) {
+                warn!("Failed to hide {}: {}", path.display(), e);
+            }
+            self.created(&newpath);
+        }
+
+        self.handle_metadata_on_change(&newpath, result, ChangeReply::Empty(reply));
+    }
+
+    fn link(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let _timer = self.time("link", req);
+        debug!(
+            "link({}, ino={}, newparent={}, newname={:?})",
+            caller(req),
+            ino,
+            newparent,
+            newname
+        );
+        if is_synthetic(ino) || self.reserved(newparent, newname) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.attrs.get(&ino) {
+            Some(attrs) => attrs.real_path.clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        let newpath = match self.get_path(newparent, newname) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        if self.read_only {
+            self.refuse(TraceEvent {
+                target: Some(path.to_string_lossy().into_owned()),
+                ..TraceEvent::new(req.pid(), 'w', TraceOp::Link, &newpath.to_string_lossy())
+            });
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.unwritable(req, &newpath) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.denied_write(req, &newpath) {
+            reply.error(libc::EACCES);
+            return;
+        }
+        // in an overlay the new name links to the upper copy
+        let (path, newpath) = match self
+            .copy_up(&path)
+            .and_then(|path| Ok((path, self.writable(&newpath)?)))
+        {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Entry(reply).error(e);
+                return;
+            }
+        };
+        // both names share the inode, re-stating the new name refreshes its nlinks
+        let result = fs::hard_link(&path, &newpath);
+        self.sink.trace_event(
+            TraceEvent {
+                target: Some(path.to_string_lossy().into_owned()),
+                ..TraceEvent::new(req.pid(), 'w', TraceOp::Link, &newpath.to_string_lossy())
+            },
+            &result,
+        );
+        if result.is_ok() {
+            self.dependencies
+                .access(req.pid(), &newpath, Access::Create);
+            self.created(&newpath);
+        }
+        self.handle_metadata_on_change(&newpath, result, ChangeReply::Entry(reply));
+    }
+
+    fn open(&mut self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let _timer = self.time("open", req);
+        debug!("open({}, ino={}, flags={})", caller(req), ino, flags);
+        // listed as of now, reading it bypasses the page cache since its size is unknown
+        if ino == EVENTS_INO {
+            if flags & libc::O_ACCMODE != libc::O_RDONLY {
+                reply.error(libc::EACCES);
+                return;
+            }
+            let mut listing = Vec::new();
+            for event in &self.sink.ring {
+                if let Some(line) = event.render(TraceFormat::Json) {
+                    listing.extend_from_slice(line.as_bytes());
+                    listing.push(b'\n');
+                }
+            }
+            let fh = self.next_fh;
+            self.next_fh += 1;
+            self.listings.insert(fh, listing);
+            reply.opened(fh, consts::FOPEN_DIRECT_IO);
+            return;
+        }
+        if ino == STATS_INO {
+            if flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0 {
+                reply.error(libc::EPERM);
+                return;
+            }
+            let fh = self.next_fh;
+            self.next_fh += 1;
+            self.listings.insert(fh, self.stats());
+            reply.opened(fh, consts::FOPEN_DIRECT_IO);
+            return;
+        }
+        // truncating it is harmless, shells do so before writing a command
+        if ino == CONTROL_INO {
+            let state = if self.sink.paused {
+                "paused\n"
+            } else {
+                "tracing\n"
+            };
+            let fh = self.next_fh;
+            self.next_fh += 1;
+            self.listings.insert(fh, state.as_bytes().to_vec());
+            reply.opened(fh, consts::FOPEN_DIRECT_IO);
+            return;
+        }
+        let (mut access_mask, read, write) = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => {
+                if is_exec(flags) {
+                    // Open is from internal exec syscall
+                    (libc::X_OK, true, false)
+                } else {
+                    (libc::R_OK, true, false)
+                }
+            }
+            libc::O_WRONLY => (libc::W_OK, false, true),
+            libc::O_RDWR => (libc::R_OK | libc::W_OK, true, true),
+            // Exactly one access mode flag must be specified
+            _ => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+        let (read, flags) = self.cached_writes(read, write, flags);
+
+        // truncating needs write access, even when the file is only opened for reading
+        let truncate = flags & libc::O_TRUNC != 0;
+        if truncate {
+            access_mask |= libc::W_OK;
+        }
+        if (self.read_only || self.is_injected(ino)) && access_mask & libc::W_OK != 0 {
+            if let Some(attrs) = self.attrs.get(&ino) {
+                let path = attrs.real_path.to_string_lossy();
+                self.refuse(TraceEvent::new(req.pid(), 'w', TraceOp::Open, &path));
+            }
+            reply.error(libc::EROFS);
+            return;
+        }
+        // whatever is written later is refused by write(), truncating already changes the file
+        let path = self.attrs.get(&ino).map(|x| x.real_path.clone());
+        if truncate && path.is_some_and(|x| self.unwritable(req, &x)) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
+        match self.attrs.get(&ino) {
+            Some(attrs) => {
+                if attrs.kind == FileKind::File {
+                    // the backing file is opened with our credentials, not the caller's
+                    if !check_access(
+                        attrs.uid,
+                        attrs.gid,
+                        attrs.mode,
+                        req.uid(),
+                        &caller_groups(req),
+                        access_mask,
+                    ) {
+                        reply.error(libc::EACCES);
+                        return;
+                    }
+                    if self.escapes("open", &attrs.real_path) {
+                        reply.error(libc::EACCES);
+                        return;
+                    }
+                    let real_path = attrs.real_path.clone();
+                    if self.denied(req, &real_path, false) {
+                        reply.error(libc::EACCES);
+                        return;
+                    }
+                    // in an overlay the file is written through its upper copy
+                    let real_path = match write || truncate {
+                        true => match self.copy_up(&real_path) {
+                            Ok(x) => x,
+                            Err(e) => {
+                                Reply::Open(reply).error(e);
+                                return;
+                            }
+                        },
+                        false => real_path,
+                    };
+
+                    let result = OpenOptions::new()
+                        .read(read)
+                        .write(write || truncate)
+                        .append(write && flags & libc::O_APPEND != 0)
+                        .truncate(truncate)
+                        .open(&real_path);
+
+                    // access mode has already been checked, so we can safely default to a read trace
+                    let mode = if write { 'w' } else { 'r' };
+                    self.sink.trace_event(
+                        TraceEvent::new(
+                            req.pid(),
+                            mode,
+                            TraceOp::Open,
+                            &real_path.to_string_lossy(),
+                        ),
+                        &result,
+                    );
+                    let file = match result {
+                        Ok(x) => x,
+                        Err(e) => {
+                            Reply::Open(reply).error(e);
+                            return;
+                        }
+                    };
+
+                    if truncate {
+                        self.dirty.insert(ino);
+                    }
+                    let file_handle = self.register_handle(req.pid(), ino, file, flags);
+                    reply.opened(file_handle, self.open_flags);
+                } else {
+                    reply.error(libc::EISDIR);
+                }
+            }
+            None => {
+                reply.error(libc::ENOENT);
+            }
+        }
+    }
+
+    fn read(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let timer = self.time("read", req);
+        debug!(
+            "read({}, ino={}, fh={}, offset={}, size={})",
+            caller(req),
+            ino,
+            fh,
+            offset,
+            size
+        );
+        if let Some(listing) = self.listings.get(&fh) {
+            let start = min(offset as usize, listing.len());
+            let end = min(start + size as usize, listing.len());
+            reply.data(&listing[start..end]);
+            return;
+        }
+        match self.attrs.get(&ino) {
+            Some(attrs) => {
+                self.dependencies
+                    .access(req.pid(), &attrs.real_path, Access::Read);
+                if attrs.kind == FileKind::File {
+                    // the cached length is good enough to size the buffer until the file changes
+                    let len = match self.dirty.contains(&ino) {
+                        true => None,
+                        false => Some(attrs.len),
+                    };
+                    let event = TraceEvent {
+                        ino: Some(ino),
+                        offset: Some(offset),
+                        uid: Some(req.uid()),
+                        gid: Some(req.gid()),
+                        ..TraceEvent::new(
+                            req.pid(),
+                            'r',
+                            TraceOp::Read,
+                            &attrs.real_path.to_string_lossy(),
+                        )
+                    };
+                    if let (Some(workers), Some(handle)) = (&self.workers, self.handles.get(&fh)) {
+                        workers.submit(Job {
+                            fh,
+                            ino,
+                            file: handle.file.clone(),
+                            offset,
+                            io: Io::Read { size, len, reply },
+                            event,
+                            timer,
+                        });
+                        return;
+                    }
+
+                    let result = match self.handles.get(&fh) {
+                        Some(handle) => read_at(&handle.file, offset, size, len),
+                        None => match File::open(&attrs.real_path) {
+                            Ok(file) => read_at(&file, offset, size, len),
+                            Err(_) => {
+                                reply.error(libc::ENOENT);
+                                return;
+                            }
+                        },
+                    };
+
+                    // the size is what was read, which ends short at the end of the file
+                    self.sink.trace_event(
+                        TraceEvent {
+                            size: Some(
+                                result
+                                    .as_ref()
+                                    .map_or(size, |(buffer, _)| buffer.len() as u32),
+                            ),
+                            ..event
+                        },
+                        &result,
+                    );
+
+                    match result {
+                        Ok((buffer, metadata)) => {
+                            reply.data(&buffer);
+                            if let Some(handle) = self.handles.get_mut(&fh) {
+                                handle.read += buffer.len() as u64;
+                            }
+                            self.dependencies.transfer(
+                                &attrs.real_path,
+                                Access::Read,
+                                offset,
+                                buffer.len(),
+                            );
+
+                            if let Some(metadata) = metadata {
+                                debug!("read() re-stat ino={}, len={}", ino, metadata.len());
+                                let real_path = attrs.real_path.clone();
+                                let attrs = self.inodes.attributes(metadata, real_path);
+                                self.attrs.insert(ino, attrs);
+                                self.dirty.remove(&ino);
+                            }
+                        }
+                        Err(e) => {
+                            Reply::Data(reply).error(e);
+                        }
+                    }
+                } else {
+                    reply.error(libc::EISDIR);
+                }
+            }
+            None => {
+                reply.error(libc::ENOENT);
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let timer = self.time("write", req);
+        debug!(
+            "write({}, ino={}, fh={}, offset={}, size={})",
+            caller(req),
+            ino,
+            fh,
+            offset,
+            data.len()
+        );
+        // the backing store is not involved, commands are taken in read-only mode as well
+        if ino == CONTROL_INO {
+            match parse_commands(data) {
+                Some(commands) => {
+                    for command in commands {
+                        self.control(req, command);
+                    }
+                    reply.written(data.len() as u32);
+                }
+                None => reply.error(libc::EINVAL),
+            }
+            return;
+        }
+        let path = self.attrs.get(&ino).map(|x| x.real_path.clone());
+        if path.is_some_and(|x| self.unwritable(req, &x)) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let attrs = match self.attrs.get(&ino) {
+            Some(x) => x,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        if self.read_only {
+            let path = attrs.real_path.to_string_lossy();
+            self.refuse(TraceEvent::new(req.pid(), 'w', TraceOp::Write, &path));
+            reply.error(libc::EROFS);
+            return;
+        }
+
+        let event = TraceEvent {
+            ino: Some(ino),
+            offset: Some(offset),
+            size: Some(data.len() as u32),
+            uid: Some(req.uid()),
+            gid: Some(req.gid()),
+            ..TraceEvent::new(
+                req.pid(),
+                'w',
+                TraceOp::Write,
+                &attrs.real_path.to_string_lossy(),
+            )
+        };
+        if let (Some(workers), Some(handle)) = (&self.workers, self.handles.get(&fh)) {
+            // a getattr() taken before the worker is done stats the file already
+            self.dependencies
+                .access(req.pid(), &attrs.real_path, Access::Write);
+            self.dirty.insert(ino);
+            workers.submit(Job {
+                fh,
+                ino,
+                file: handle.file.clone(),
+                offset,
+                io: Io::Write {
+                    data: data.to_vec(),
+                    append: handle.flags & libc::O_APPEND != 0,
+                    reply,
+                },
+                event,
+                timer,
+            });
+            return;
+        }
+
+        let result = match self.handles.get(&fh) {
+            Some(handle) => write_at(
+                &handle.file,
+                offset,
+                data,
+                handle.flags & libc::O_APPEND != 0,
+            ),
+            None => OpenOptions::new()
+                .write(true)
+                .open(&attrs.real_path)
+                .and_then(|file| write_at(&file, offset, data, false)),
+        };
+
+        self.sink.trace_event(event, &result);
+
+        match result {
+            Ok(()) => {
+                if let Some(handle) = self.handles.get_mut(&fh) {
+                    let end = offset + data.len() as i64;
+                    handle.written += data.len() as u64;
+                    handle.span = Some(match handle.span {
+                        Some((first, last)) => (min(first, offset), max(last, end)),
+                        None => (offset, end),
+                    });
+                }
+                self.dependencies
+                    .access(req.pid(), &attrs.real_path, Access::Write);
+                self.dependencies
+                    .transfer(&attrs.real_path, Access::Write, offset, data.len());
+                self.dirty.insert(ino);
+                reply.written(data.len() as u32);
+            }
+            Err(e) => {
+                Reply::Write(reply).error(e);
+            }
+        }
+    }
+
+    fn flush(&mut self, req: &Request<'_>, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        let _timer = self.time("flush", req);
+        debug!(
+            "flush({}, ino={}, fh={}, lock_owner={})",
+            caller(req),
+            ino,
+            fh,
+            lock_owner
+        );
+
+        // closing any descriptor drops the POSIX locks of its owner on the file
+        self.locks.remove(&(ino, lock_owner));
+
+        // Writes are not buffered on our side, so flushing the backing file is all that is
+        // left to do. Called once per close() of every dup'd descriptor, so it must stay
+        // idempotent and must not release the handle.
+        let result = match self.handles.get_mut(&fh) {
+            Some(handle) => handle.file.as_ref().flush(),
+            None => Ok(()),
+        };
+
+        match result {
+            Ok(_) => reply.ok(),
+            Err(e) => Reply::Empty(reply).error(e),
+        }
+    }
+
+    fn release(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        flags: i32,
+        lock_owner: Option<u64>,
+        flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let _timer = self.time("release", req);
+        debug!(
+            "release({}, ino={}, fh={}, flags={}, flush={})",
+            caller(req),
+            ino,
+            fh,
+            flags,
+            flush
+        );
+        if self.listings.remove(&fh).is_some() {
+            reply.ok();
+            return;
+        }
+        self.poll_handles.remove(&fh);
+        let mut result = Ok(());
+        if let (Some(handle), Some(attrs)) = (self.handles.remove(&fh), self.attrs.get(&ino)) {
+            let path = attrs.real_path.to_string_lossy();
+            let writable = handle.flags & libc::O_ACCMODE != libc::O_RDONLY;
+            let mut event = match self.sink.sessions {
+                true if writable => TraceEvent {
+                    ino: Some(ino),
+                    bytes_read: (handle.read > 0).then_some(handle.read),
+                    bytes_written: Some(handle.written),
+                    first_offset: handle.span.map(|(first, _)| first),
+                    last_offset: handle.span.map(|(_, last)| last),
+                    ..TraceEvent::new(handle.pid, 'w', TraceOp::Output, &path)
+                },
+                true => TraceEvent {
+                    ino: Some(ino),
+                    bytes_read: Some(handle.read),
+                    ..TraceEvent::new(handle.pid, 'r', TraceOp::Input, &path)
+                },
+                false => {
+                    let (read, written) = match self.dependencies.transfers.get(path.as_ref()) {
+                        Some(x) => (x.read.len(), x.written.len()),
+                        None => (0, 0),
+                    };
+                    // the file may have grown or shrunk since it was last looked up
+                    let size = handle.file.metadata().map_or(attrs.len, |x| x.len());
+                    TraceEvent {
+                        ino: Some(ino),
+                        bytes_read: Some(read),
+                        bytes_written: Some(written),
+                        coverage: (size > 0).then(|| read.min(size) as f64 / size as f64),
+                        ..TraceEvent::new(req.pid(), 'c', TraceOp::Release, &path)
+                    }
+                }
+            };
+            // the kernel asks for the data to be flushed when no flush() came before
+            if flush {
+                result = handle.file.sync_all();
+            }
+            // the workers let go of the file before replying, nothing else holds on to it
+            if let Ok(file) = Arc::try_unwrap(handle.file) {
+                result = result.and(close(file));
+            }
+
+            if self.hasher.is_some() && writable {
+                self.unhashed.insert(ino);
+            }
+            // the contents only settle once the last handle is gone
+            let last = !self.is_open(ino);
+            match &mut self.hasher {
+                Some(hasher) if last && result.is_ok() && self.unhashed.remove(&ino) => {
+                    self.sink.stamp(&mut event);
+                    hasher.hash(attrs.real_path.clone(), event);
+                }
+                _ => self.sink.trace_event(event, &result),
+            }
+        }
+        // only set for flock() locks, which go away with the last reference to the file
+        if let Some(lock_owner) = lock_owner {
+            self.locks.remove(&(ino, lock_owner));
+        }
+        // forget() leaves the attributes of an inode still open to its last release, like the
+        // removal of an inode still open does
+        let forgotten = ino != FUSE_ROOT_ID && !self.lookups.contains_key(&ino);
+        if !self.is_open(ino) {
+            if forgotten {
+                self.evict(ino);
+            } else if self.unlinked.remove(&ino) {
+                self.attrs.remove(&ino);
+            }
+        }
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => Reply::Empty(reply).error(e),
+        }
+    }
+
+    fn fsync(&mut self, req: &Request<'_>, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        let _timer = self.time("fsync", req);
+        debug!(
+            "fsync({}, ino={}, fh={}, datasync={})",
+            caller(req),
+            ino,
+            fh,
+            datasync
+        );
+
+        let sync = |file: &File| -> io::Result<()> {
+            if datasync {
+                file.sync_data()
+            } else {
+                file.sync_all()
+            }
+        };
+
+        let result = match self.handles.get(&fh) {
+            Some(handle) => sync(&handle.file),
+            None => match self.attrs.get(&ino) {
+                Some(attrs) => File::open(&attrs.real_path).and_then(|file| sync(&file)),
+                None => {
+                    reply.error(libc::ENOENT);
+                    return;
+                }
+            },
+        };
+
+        match result {
+            Ok(_) => reply.ok(),
+            Err(e) => Reply::Empty(reply).error(e),
+        }
+    }
+
+    fn poll(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        kh: u64,
+        events: u32,
+        flags: u32,
+        reply: ReplyPoll,
+    ) {
+        let _timer = self.time("poll", req);
+        debug!(
+            "poll({}, ino={}, fh={}, kh={}, events={:#x}, flags={:#x})",
+            caller(req),
+            ino,
+            fh,
+            kh,
+            events,
+            flags
+        );
+        if flags & consts::FUSE_POLL_SCHEDULE_NOTIFY != 0 {
+            self.poll_handles.insert(fh, kh);
+        }
+
+        // regular files are always ready, anything else is as ready as its backing file
+        let file = match self.handles.get(&fh) {
+            Some(handle) if !handle.file.metadata().is_ok_and(|x| x.is_file()) => &handle.file,
+            _ => {
+                reply.poll(READY);
+                return;
+            }
+        };
+        let mut fd = libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: events as i16,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, 0) } {
+            -1 => Reply::Poll(reply).error(io::Error::last_os_error()),
+            _ => reply.poll(fd.revents as u16 as u32),
+        }
+    }
+
+    fn opendir(&mut self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let _timer = self.time("opendir", req);
+        debug!("opendir({}, ino={}, flags={})", caller(req), ino, flags);
+        // directories are only ever listed, writing goes through the entries themselves
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            reply.error(libc::EISDIR);
+            return;
+        }
+        // listed without a handle, no handle ever gets 0
+        if ino == CAIRN_DIR_INO {
+            reply.opened(0, 0);
+            return;
+        }
+
+        let attrs = match self.attrs.get(&ino) {
+            Some(x) => x,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        if attrs.kind != FileKind::Directory {
+            reply.error(libc::ENOTDIR);
+            return;
+        }
+
+        let groups = caller_groups(req);
+        if !check_access(
+            attrs.uid,
+            attrs.gid,
+            attrs.mode,
+            req.uid(),
+            &groups,
+            libc::R_OK,
+        ) || self.escapes("opendir", &attrs.real_path)
+        {
+            reply.error(libc::EACCES);
+            return;
+        }
+        let real_path = attrs.real_path.clone();
+        if self.denied(req, &real_path, true) {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        // a directory the root lacks has nothing to open, the root stands in for fsyncdir()
+        let backing = match self.inodes.is_unbacked(ino) {
+            true => PathBuf::from(&self.root),
+            false => real_path.clone(),
+        };
+        let dir = match OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(&backing)
+        {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Open(reply).error(e);
+                return;
+            }
+        };
+
+        let entries = match self.list_dir(ino, &real_path) {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Open(reply).error(e);
+                return;
+            }
+        };
+
+        let fh = self.register_dir_handle(dir, entries);
+        reply.opened(fh, 0);
+    }
+
+    fn readdir(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let _timer = self.time("readdir", req);
+        debug!(
+            "readdir({}, ino={}, fh={}, offset={})",
+            caller(req),
+            ino,
+            fh,
+            offset
+        );
+        if ino == CAIRN_DIR_INO {
+            for (i, (inode, kind, name)) in synthetic_entries().enumerate().skip(offset as usize) {
+                if reply.add(inode, i as i64 + 1, kind.into(), name) {
+                    break;
+                }
+            }
+            reply.ok();
+            return;
+        }
+        let handle = match self.dir_handles.get(&fh) {
+            Some(x) => x,
+            None => {
+                reply.error(libc::EBADF);
+                return;
+            }
+        };
+
+        // the offset of an entry is the index of the one following it in the snapshot
+        for (i, (inode, kind, name)) in handle.entries.iter().enumerate().skip(offset as usize) {
+            if reply.add(*inode, i as i64 + 1, (*kind).into(), name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn readdirplus(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectoryPlus,
+    ) {
+        let _timer = self.time("readdirplus", req);
+        debug!(
+            "readdirplus({}, ino={}, fh={}, offset={})",
+            caller(req),
+            ino,
+            fh,
+            offset
+        );
+        if ino == CAIRN_DIR_INO {
+            for (i, (inode, _, name)) in synthetic_entries().enumerate().skip(offset as usize) {
+                // as for any other directory, the attributes of the dot entries are unused
+                let attr = match name {
+                    "." | ".." => self.synthetic_attr(CAIRN_DIR_INO),
+                    _ => self.synthetic_attr(inode),
+                };
+                if reply.add(inode, i as i64 + 1, name, &Duration::new(0, 0), &attr, 0) {
+                    break;
+                }
+            }
+            reply.ok();
+            return;
+        }
+        let handle = match self.dir_handles.get(&fh) {
+            Some(x) => x,
+            None => {
+                reply.error(libc::EBADF);
+                return;
+            }
+        };
+        let dir = match self.attrs.get(&ino) {
+            Some(x) => x.clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+
+        let entries = handle.entries.clone();
+        let mut added = Vec::new();
+        for (i, (inode, _, name)) in entries.iter().enumerate().skip(offset as usize) {
+            // the kernel neither looks up nor caches the dot entries, their attributes are unused
+            let attrs = if name == "." || name == ".." {
+                dir.clone()
+            } else {
+                match self.lookup_name(ino, name) {
+                    Ok(x) => x,
+                    // removed since opendir(), lookup() would not find it either
+                    Err(_) => continue,
+                }
+            };
+
+            // the name may stand for another inode since opendir(), the kernel is handed the one
+            // it stands for now
+            let entry = match name == "." || name == ".." {
+                true => *inode,
+                false => attrs.ino,
+            };
+            let attr: fuser::FileAttr = attrs.clone().into();
+            let generation = self.generations.of(&attrs);
+            if reply.add(
+                entry,
+                i as i64 + 1,
+                name,
+                &self.entry_ttl,
+                &attr,
+                generation,
+            ) {
+                break;
+            }
+            if name != "." && name != ".." {
+                self.attrs.insert(attrs.ino, attrs);
+                added.push(entry);
+            }
+        }
+        reply.ok();
+
+        // every entry handed out counts as a lookup, released again by forget()
+        for inode in added {
+            self.remember(inode);
+        }
+    }
+
+    fn releasedir(&mut self, req: &Request<'_>, ino: u64, fh: u64, flags: i32, reply: ReplyEmpty) {
+        let _timer = self.time("releasedir", req);
+        debug!(
+            "releasedir({}, ino={}, fh={}, flags={})",
+            caller(req),
+            ino,
+            fh,
+            flags
+        );
+        self.dir_handles.remove(&fh);
+        reply.ok();
+    }
+
+    fn fsyncdir(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        let _timer = self.time("fsyncdir", req);
+        debug!(
+            "fsyncdir({}, ino={}, fh={}, datasync={})",
+            caller(req),
+            ino,
+            fh,
+            datasync
+        );
+
+        let attrs = match self.attrs.get(&ino) {
+            Some(x) => x,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+
+        let sync = |dir: &File| {
+            if datasync {
+                dir.sync_data()
+            } else {
+                dir.sync_all()
+            }
+        };
+        let result = match self.dir_handles.get(&fh) {
+            Some(handle) => sync(&handle.dir),
+            None => File::open(&attrs.real_path).and_then(|dir| sync(&dir)),
+        };
+
+        match result {
+            Ok(_) => reply.ok(),
+            Err(e) => Reply::Empty(reply).error(e),
+        }
+    }
+
+    fn statfs(&mut self, req: &Request<'_>, ino: u64, reply: ReplyStatfs) {
+        let _timer = self.time("statfs", req);
+        debug!("statfs({}, ino={})", caller(req), ino);
+
+        let mut statfs: libc::statvfs = unsafe { std::mem::zeroed() };
+        // every inode lives below the root, so the answer is the same whichever one is asked
+        // about, a symlink or a file deleted while open included
+        let path = match CString::new(self.root.as_bytes()) {
+            Ok(x) => x,
+            Err(_) => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+
+        let result = if unsafe { libc::statvfs(path.as_ptr(), &mut statfs) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        };
+        self.sink.trace_event(
+            TraceEvent::new(req.pid(), 'q', TraceOp::Statfs, &self.root),
+            &result,
+        );
+        if let Err(e) = result {
+            Reply::Statfs(reply).error(e);
+            return;
+        }
+
+        reply.statfs(
+            statfs.f_blocks,
+            statfs.f_bfree,
+            statfs.f_bavail,
+            statfs.f_files,
+            statfs.f_ffree,
+            statfs.f_bsize as u32,
+            statfs.f_namemax as u32,
+            statfs.f_frsize as u32,
+        );
+    }
+
+    fn access(&mut self, req: &Request<'_>, ino: u64, mask: i32, reply: ReplyEmpty) {
+        let _timer = self.time("access", req);
+        debug!("access({}, ino={}, mask={})", caller(req), ino, mask);
+        if is_synthetic(ino) {
+            let denied = match ino {
+                CAIRN_DIR_INO => libc::W_OK,
+                CONTROL_INO => libc::X_OK,
+                _ => libc::W_OK | libc::X_OK,
+            };
+            match mask & denied {
+                0 => reply.ok(),
+                _ => reply.error(libc::EACCES),
+            }
+            return;
+        }
+        match self.attrs.get(&ino) {
+            Some(attrs) => {
+                let groups = caller_groups(req);
+                if check_access(attrs.uid, attrs.gid, attrs.mode, req.uid(), &groups, mask) {
+                    reply.ok();
+                } else {
+                    reply.error(libc::EACCES);
+                }
+            }
+            None => {
+                reply.error(libc::ENOENT);
+            }
+        }
+    }
+
+    fn create(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let _timer = self.time("create", req);
+        debug!(
+            "create({}, parent={}, name={:?}, mode={:o}, flags={})",
+            caller(req),
+            parent,
+            name,
+            mode,
+            flags
+        );
+        if self.reserved(parent, name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.get_path(parent, name) {
+            Ok(x) => x,
+            Err(c) => {
+                reply.error(c);
+                return;
+            }
+        };
+        if self.read_only {
+            self.refuse(TraceEvent::new(
+                req.pid(),
+                'w',
+                TraceOp::Create,
+                &path.to_string_lossy(),
+            ));
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.unwritable(req, &path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        // O_CREAT follows a dangling symlink, creating its target wherever it points to
+        if self.escapes("create", &path) {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        if self.denied_write(req, &path) {
+            reply.error(libc::EACCES);
+            return;
+        }
+        let path = match self.writable(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Create(reply).error(e);
+                return;
+            }
+        };
+        let (read, write) = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => (true, false),
+            libc::O_WRONLY => (false, true),
+            libc::O_RDWR => (true, true),
+            // Exactly one access mode flag must be specified
+            _ => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+        let (read, flags) = self.cached_writes(read, write, flags);
+
+        let mut options = OpenOptions::new();
+        options.read(read).write(write).mode(mode & !umask);
+        if write {
+            if flags & libc::O_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+            options
+                .truncate(flags & libc::O_TRUNC != 0)
+                .append(flags & libc::O_APPEND != 0);
+        } else {
+            // std only creates files opened for writing, so leave it to open(2)
+            options.custom_flags(flags & (libc::O_CREAT | libc::O_EXCL));
+        }
+
+        let result = options.open(&path);
+        self.sink.trace_event(
+            TraceEvent::new(req.pid(), 'w', TraceOp::Create, &path.to_string_lossy()),
+            &result,
+        );
+        let file = match result {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Create(reply).error(e);
+                return;
+            }
+        };
+        let metadata = match file.metadata() {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Create(reply).error(e);
+                return;
+            }
+        };
+
+        self.dependencies.access(req.pid(), &path, Access::Create);
+        self.created(&path);
+
+        let attrs = self.inodes.attributes(metadata, path);
+        self.attrs.insert(attrs.ino, attrs.clone());
+        self.remember(attrs.ino);
+        let file_handle = self.register_handle(req.pid(), attrs.ino, file, flags);
+        let generation = self.generations.of(&attrs);
+        reply.created(
+            &self.entry_ttl,
+            &attrs.into(),
+            generation,
+            file_handle,
+            self.open_flags,
+        );
+    }
+
+    fn getlk(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        _pid: u32,
+        reply: ReplyLock,
+    ) {
+        let _timer = self.time("getlk", req);
+        debug!(
+            "getlk({}, ino={}, fh={}, lock_owner={}, start={}, end={}, typ={})",
+            caller(req),
+            ino,
+            fh,
+            lock_owner,
+            start,
+            end,
+            typ
+        );
+
+        let mut lock = as_flock(start, end, typ);
+        let result = self
+            .lock_file(ino, lock_owner)
+            .and_then(|file| fcntl_lock(file, F_OFD_GETLK, &mut lock));
+
+        match result {
+            Ok(()) if lock.l_type == libc::F_UNLCK as libc::c_short => {
+                reply.locked(start, end, libc::F_UNLCK, 0)
+            }
+            Ok(()) => {
+                let end = if lock.l_len == 0 {
+                    i64::MAX as u64
+                } else {
+                    (lock.l_start + lock.l_len - 1) as u64
+                };
+                // OFD locks are not held by any process, those report a pid of -1
+                let pid = lock.l_pid.max(0) as u32;
+                reply.locked(lock.l_start as u64, end, lock.l_type as i32, pid);
+            }
+            Err(e) => Reply::Lock(reply).error(e),
+        }
+    }
+
+    fn setlk(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        _pid: u32,
+        sleep: bool,
+        reply: ReplyEmpty,
+    ) {
+        let _timer = self.time("setlk", req);
+        debug!(
+            "setlk({}, ino={}, fh={}, lock_owner={}, start={}, end={}, typ={}, sleep={})",
+            caller(req),
+            ino,
+            fh,
+            lock_owner,
+            start,
+            end,
+            typ,
+            sleep
+        );
+
+        let mut lock = as_flock(start, end, typ);
+        let file = match self.lock_file(ino, lock_owner) {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Empty(reply).error(e);
+                return;
+            }
+        };
+
+        if !sleep {
+            match fcntl_lock(file, F_OFD_SETLK, &mut lock) {
+                Ok(()) => reply.ok(),
+                Err(e) => Reply::Empty(reply).error(e),
+            }
+            return;
+        }
+
+        // waiting for the lock must not hold up every other operation on the mount, the
+        // duplicated descriptor shares the description and with it the lock
+        let file = match file.try_clone() {
+            Ok(x) => x,
+            Err(e) => {
+                Reply::Empty(reply).error(e);
+                return;
+            }
+        };
+        thread::spawn(move || match fcntl_lock(&file, F_OFD_SETLKW, &mut lock) {
+            Ok(()) => reply.ok(),
+            Err(e) => Reply::Empty(reply).error(e),
+        });
+    }
+
+    fn lseek(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        let _timer = self.time("lseek", req);
+        debug!(
+            "lseek({}, ino={}, fh={}, offset={}, whence={})",
+            caller(req),
+            ino,
+            fh,
+            offset,
+            whence
+        );
+
+        // the kernel only forwards SEEK_DATA and SEEK_HOLE, which depend on the backing file
+        let seek = |file: &File| -> io::Result<i64> {
+            match unsafe { libc::lseek(file.as_raw_fd(), offset, whence) } {
+                -1 => Err(io::Error::last_os_error()),
+                offset => Ok(offset),
+            }
+        };
+
+        let result = match self.handles.get(&fh) {
+            Some(handle) => seek(&handle.file),
+            None => match self.attrs.get(&ino) {
+                Some(attrs) => File::open(&attrs.real_path).and_then(|file| seek(&file)),
+                None => {
+                    reply.error(libc::ENOENT);
+                    return;
+                }
+            },
+        };
+
+        match result {
+            Ok(offset) => reply.offset(offset),
+            Err(e) => Reply::Lseek(reply).error(e),
+        }
+    }
+
+    fn fallocate(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        let _timer = self.time("fallocate", req);
+        debug!(
+            "fallocate({}, ino={}, fh={}, offset={}, length={}, mode={:#x})",
+            caller(req),
+            ino,
+            fh,
+            offset,
+            length,
+            mode
+        );
+        let path = self.attrs.get(&ino).map(|x| x.real_path.clone());
+        if path.is_some_and(|x| self.unwritable(req, &x)) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let (handle, attrs) = match (self.handles.get(&fh), self.attrs.get(&ino)) {
+            (Some(handle), Some(attrs)) => (handle, attrs),
+            _ => {
+                reply.error(libc::EBADF);
+                return;
+            }
+        };
+        if self.read_only {
+            let path = attrs.real_path.to_string_lossy();
+            self.refuse(TraceEvent::new(req.pid(), 'w', TraceOp::Fallocate, &path));
+            reply.error(libc::EROFS);
+            return;
+        }
+
+        // punching holes and keeping the size are up to the backing filesystem
+        let result = allocate(&handle.file, mode, offset, length);
+        self.sink.trace_event(
+            TraceEvent {
+                ino: Some(ino),
+                offset: Some(offset),
+                size: u32::try_from(length).ok(),
+                ..TraceEvent::new(
+                    req.pid(),
+                    'w',
+                    TraceOp::Fallocate,
+                    &attrs.real_path.to_string_lossy(),
+                )
+            },
+            &result,
+        );
+        // the allocated blocks change even where the length does not
+        let result = result.and_then(|_| handle.file.metadata());
+        match result {
+            Ok(metadata) => {
+                let real_path = attrs.real_path.clone();
+                self.dependencies
+                    .access(req.pid(), &real_path, Access::Write);
+                let attrs = self.inodes.attributes(metadata, real_path);
+                self.attrs.insert(ino, attrs);
+                reply.ok();
+            }
+            Err(e) => Reply::Empty(reply).error(e),
+        }
+    }
+
+    fn setxattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _name: &OsStr,
+        _value: &[u8],
+        _flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        let _timer = self.time("setxattr", req);
+        self.xattr_change(req, ino, TraceOp::Setxattr, reply);
+    }
+
+    fn removexattr(&mut self, req: &Request<'_>, ino: u64, _name: &OsStr, reply: ReplyEmpty) {
+        let _timer = self.time("removexattr", req);
+        self.xattr_change(req, ino, TraceOp::Removexattr, reply);
+    }
+
+    fn copy_file_range(
+        &mut self,
+        req: &Request<'_>,
+        _ino_in: u64,
+        _fh_in: u64,
+        _offset_in: i64,
+        _ino_out: u64,
+        _fh_out: u64,
+        _offset_out: i64,
+        _len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let _timer = self.time("copy_file_range", req);
+        // not dispatched below ABI 7.28, the kernel copies with reads and writes on ENOSYS
+        reply.error(libc::ENOSYS);
+    }
+}
+
+fn check_access(
+    file_uid: u32,
+    file_gid: u32,
+    file_mode: u32,
+    uid: u32,
+    gids: &[u32],
+    mut access_mask: i32,
+) -> bool {
+    // F_OK tests for existence of file
+    if access_mask == libc::F_OK {
+        return true;
+    }
+
+    let file_mode: i32 = Wrapping(file_mode as i32).0;
+
+    // root is allowed to read & write anything
+    if uid == 0 {
+        // and to search any directory
+        if file_mode & libc::S_IFMT as i32 == libc::S_IFDIR as i32 {
+            return true;
+        }
+        // but only allowed to exec a file if one of the X bits is set
+        access_mask &= libc::X_OK;
+        access_mask -= access_mask & (file_mode >> 6);
+        access_mask -= access_mask & (file_mode >> 3);
+        access_mask -= access_mask & file_mode;
+        return access_mask == 0;
+    }
+
+    if uid == file_uid {
+        access_mask -= access_mask & (file_mode >> 6);
+    } else if gids.contains(&file_gid) {
+        access_mask -= access_mask & (file_mode >> 3);
+    } else {
+        access_mask -= access_mask & file_mode;
+    }
+
+    access_mask == 0
+}
+
+// The kernel hands over an inclusive range, with OFFSET_MAX standing for the end of the file
+fn as_flock(start: u64, end: u64, typ: i32) -> libc::flock {
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = typ as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    lock.l_start = start as libc::off_t;
+    lock.l_len = if end >= i64::MAX as u64 {
+        0
+    } else {
+        (end - start + 1) as libc::off_t
+    };
+    lock
+}
+
+#[cfg(target_os = "linux")]
+fn fcntl_lock(file: &File, cmd: c_int, lock: &mut libc::flock) -> io::Result<()> {
+    match unsafe { libc::fcntl(file.as_raw_fd(), cmd, lock as *mut libc::flock) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+// The process locks of the daemon would all be held by the same owner, the kernel keeps the
+// locks itself as the capability is not asked for in init()
+#[cfg(target_os = "macos")]
+fn fcntl_lock(_file: &File, _cmd: c_int, _lock: &mut libc::flock) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+#[cfg(target_os = "linux")]
+fn allocate(file: &File, mode: i32, offset: i64, length: i64) -> io::Result<()> {
+    match unsafe { libc::fallocate(file.as_raw_fd(), mode, offset, length) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+// Preallocates what the range adds past the end of the file and extends it, like fallocate()
+// without flags. Punching holes and keeping the size have no counterpart
+#[cfg(target_os = "macos")]
+fn allocate(file: &File, mode: i32, offset: i64, length: i64) -> io::Result<()> {
+    if mode != 0 {
+        return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP));
+    }
+    let end = offset
+        .checked_add(length)
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EFBIG))?;
+    let len = file.metadata()?.len() as i64;
+    if end <= len {
+        return Ok(());
+    }
+    let mut store = libc::fstore_t {
+        fst_flags: libc::F_ALLOCATEALL,
+        fst_posmode: libc::F_PEOFPOSMODE,
+        fst_offset: 0,
+        fst_length: end - len,
+        fst_bytesalloc: 0,
+    };
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_PREALLOCATE, &mut store) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    file.set_len(end as u64)
+}
+
+// Whether the kernel opens the file for exec(), which macFUSE does not tell apart
+#[cfg(target_os = "linux")]
+fn is_exec(flags: i32) -> bool {
+    flags & FMODE_EXEC != 0
+}
+
+#[cfg(target_os = "macos")]
+fn is_exec(_flags: i32) -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn gettid() -> u32 {
+    unsafe { libc::gettid() as u32 }
+}
+
+#[cfg(target_os = "macos")]
+fn gettid() -> u32 {
+    let mut tid = 0;
+    unsafe { libc::pthread_threadid_np(0, &mut tid) };
+    tid as u32
+}
+
+// Reads up to `size` bytes at `offset`, `len` is the cached length of the file, None when it has to
+// be stat()ed again. The metadata is handed back then
+fn read_at(
+    file: &File,
+    offset: i64,
+    size: u32,
+    len: Option<u64>,
+) -> io::Result<(Vec<u8>, Option<Metadata>)> {
+    let metadata = match len {
+        Some(_) => None,
+        None => Some(file.metadata()?),
+    };
+    let file_size = metadata.as_ref().map_or(len.unwrap_or(0), Metadata::len);
+    let read_size = min(size, file_size.saturating_sub(offset as u64) as u32);
+    let mut buffer = vec![0; read_size as usize];
+    // the backing file may still shrink behind our back, stop at its end
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read_at(&mut buffer[filled..], offset as u64 + filled as u64)? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    buffer.truncate(filled);
+    Ok((buffer, metadata))
+}
+
+fn write_at(mut file: &File, offset: i64, data: &[u8], append: bool) -> io::Result<()> {
+    if append {
+        // the offset may be stale, the backing O_APPEND picks the real end of file
+        file.write_all(data)
+    } else {
+        file.write_all_at(data, offset as u64)
+    }
+}
+
+// Unlike dropping the file, reports the errors of close(), like those of a write-back that failed
+fn close(file: File) -> io::Result<()> {
+    match unsafe { libc::close(file.into_raw_fd()) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+// Creates a FIFO, socket or device node, the file type is part of the mode
+fn make_node(path: &Path, mode: u32, rdev: u32) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+    match unsafe { libc::mknod(path.as_ptr(), mode as libc::mode_t, rdev as libc::dev_t) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn rename_with_flags(from: &Path, to: &Path, flags: u32) -> io::Result<()> {
+    let from = CString::new(from.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+    let to = CString::new(to.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+    match unsafe { renameat2(from.as_ptr(), to.as_ptr(), flags) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn renameat2(from: *const libc::c_char, to: *const libc::c_char, flags: u32) -> c_int {
+    libc::renameat2(libc::AT_FDCWD, from, libc::AT_FDCWD, to, flags)
+}
+
+// renameatx_np() has the same two flags under other names, RENAME_WHITEOUT has none
+#[cfg(target_os = "macos")]
+unsafe fn renameat2(from: *const libc::c_char, to: *const libc::c_char, flags: u32) -> c_int {
+    if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0 {
+        *libc::__error() = libc::EINVAL;
+        return -1;
+    }
+    let mut native = 0;
+    if flags & RENAME_NOREPLACE != 0 {
+        native |= libc::RENAME_EXCL;
+    }
+    if flags & RENAME_EXCHANGE != 0 {
+        native |= libc::RENAME_SWAP;
+    }
+    libc::renameatx_np(libc::AT_FDCWD, from, libc::AT_FDCWD, to, native)
+}
+
+fn as_file_kind(mode: u32) -> Option<FileKind> {
+    match mode as libc::mode_t & libc::S_IFMT {
+        libc::S_IFREG => Some(FileKind::File),
+        libc::S_IFLNK => Some(FileKind::Symlink),
+        libc::S_IFDIR => Some(FileKind::Directory),
+        libc::S_IFSOCK => Some(FileKind::Socket),
+        libc::S_IFIFO => Some(FileKind::Fifo),
+        libc::S_IFCHR => Some(FileKind::CharDevice),
+        libc::S_IFBLK => Some(FileKind::BlockDevice),
+        _ => None,
+    }
+}
+
+// errors without an errno, like those of std itself, are reported as EIO
+fn errno(e: &io::Error) -> c_int {
+    e.raw_os_error().unwrap_or(libc::EIO)
+}
+
+// Canonicalizes the deepest existing ancestor, the dangling rest of the path is applied lexically
+pub fn resolve(path: &Path) -> PathBuf {
+    let components: Vec<Component> = path.components().collect();
+    for existing in (1..=components.len()).rev() {
+        let mut resolved = match components[..existing]
+            .iter()
+            .collect::<PathBuf>()
+            .canonicalize()
+        {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        for component in &components[existing..] {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => resolved.push(name),
+                _ => {}
+            }
+        }
+        return resolved;
+    }
+    path.to_path_buf()
+}
+
+// The primary group of the calling process followed by its supplementary groups
+fn caller_groups(req: &Request<'_>) -> Vec<u32> {
+    let mut groups = vec![req.gid()];
+    if let Ok(status) = fs::read_to_string(format!("/proc/{}/status", req.pid())) {
+        if let Some(line) = status.lines().find(|line| line.starts_with("Groups:")) {
+            groups.extend(
+                line["Groups:".len()..]
+                    .split_whitespace()
+                    .filter_map(|gid| gid.parse::<u32>().ok()),
+            );
+        }
+    }
+    groups
+}
+
+// The attributes of a negative entry, the kernel only looks at its inode number of 0
+fn missing_attr() -> fuser::FileAttr {
+    fuser::FileAttr {
+        ino: 0,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: fuser::FileType::RegularFile,
+        perm: 0,
+        nlink: 0,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    }
+}
+
+// Identifies the calling process, so operations can be attributed to e.g. a compiler invocation
+fn caller(req: &Request<'_>) -> String {
+    match fs::read_to_string(format!("/proc/{}/comm", req.pid())) {
+        Ok(comm) => format!(
+            "pid={}, comm={}, uid={}, gid={}",
+            req.pid(),
+            comm.trim_end(),
+            req.uid(),
+            req.gid()
+        ),
+        Err(_) => format!("pid={}, uid={}, gid={}", req.pid(), req.uid(), req.gid()),
+    }
+}
+
+// Name and start time of a process, None once it has exited
+fn process_stat(pid: u32) -> Option<(String, u64)> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (name, rest) = stat.split_once(" (")?.1.rsplit_once(')')?;
+    // fields are counted from the state, which is the third one
+    let start = rest.split_whitespace().nth(22 - 3)?.parse().ok()?;
+    Some((name.to_string(), start))
+}
+
+// The process of a thread, the kernel passes on the thread that made the request
+fn thread_group(tid: u32) -> Option<u32> {
+    let status = fs::read_to_string(format!("/proc/{tid}/status")).ok()?;
+    let tgid = status.lines().find_map(|line| line.strip_prefix("Tgid:"))?;
+    tgid.trim().parse().ok()
+}
+
+fn parent_pid(pid: u32) -> i32 {
+    // the command name in parentheses may itself contain spaces, the ppid follows the state
+    match fs::read_to_string(format!("/proc/{pid}/stat")) {
+        Ok(stat) => stat
+            .rsplit_once(')')
+            .and_then(|(_, rest)| rest.split_whitespace().nth(1))
+            .and_then(|ppid| ppid.parse().ok())
+            .unwrap_or(-1),
+        Err(_) => -1,
+    }
+}
@@ -1,1439 +1,5517 @@
-// Based on https://github.com/cberner/fuser/blob/master/examples/simple.rs
-
-use clap::{crate_version, Arg, Command};
+use cairn_fuse::metrics::Metrics;
+use cairn_fuse::sink::{
+    AsyncSink, BroadcastSink, ChromeSink, DedupSink, FlatBufferSink, PerProcessSink, ReopenableLog,
+    RotatingFile, TeeSink, UnixSink, WriterSink, QUEUE_LIMIT,
+};
+use cairn_fuse::{
+    render_header, render_summary, resolve, Allowlist, Injections, LatencySummary, TraceEvent,
+    TraceFormat, TraceHeader, TraceOps, TraceSink, TracerFS, TRACE_TARGET,
+};
+use clap::{crate_version, Arg, ArgAction, ArgMatches, Command};
 use env_logger::fmt::Formatter;
 use env_logger::Builder;
-use fuser::{
-    Filesystem, KernelConfig, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
-    ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
-};
-use log::{debug, info, LevelFilter};
-use log::{warn, Record};
-use std::cmp::min;
+use fuser::{BackgroundSession, MountOption};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use libc::c_int;
+use log::{debug, info, warn, LevelFilter, Record};
 use std::collections::BTreeMap;
-use std::ffi::OsStr;
-use std::fs::{File, Metadata, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::num::Wrapping;
-use std::os::fd::AsRawFd;
-use std::os::raw::c_int;
-use std::os::unix::fs as ufs;
-use std::os::unix::fs::FileExt;
-use std::os::unix::prelude::{MetadataExt, PermissionsExt};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use std::{env, fs, io};
-use walkdir::WalkDir;
-
-const FMODE_EXEC: i32 = 0x20;
+use std::time::Duration;
+use std::{fs, io, process};
+
+// Exit codes, besides the 2 of clap for a command line it cannot parse and the 130 of a second
+// signal. Options that make no sense together or name unusable files exit with 1
+const EXIT_CONFIG: i32 = 1;
+// the log, the traces or one of the sockets cannot be written
+const EXIT_OUTPUT: i32 = 3;
+const EXIT_MOUNT: i32 = 4;
+const EXIT_SIGNALS: i32 = 5;
+// the filesystem gave up after mounting, as --strict-init does on a partial scan
+const EXIT_INIT: i32 = 6;
+
+// set by a SIGHUP, the log is opened again at its next record
+static REOPEN_LOG: AtomicBool = AtomicBool::new(false);
+
+fn create_new<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
 
-#[derive(Copy, Clone, PartialEq)]
-enum FileKind {
-    File,
-    Directory,
-    Symlink,
+fn get_logger_format(format: TraceFormat) -> impl Fn(&mut Formatter, &Record) -> io::Result<()> {
+    move |buf: &mut Formatter, record: &Record| match format {
+        TraceFormat::Human => writeln!(buf, "[{}] {}", record.level(), record.args()),
+        // every line has to stay a JSON object, so other records are wrapped into one
+        TraceFormat::Json if record.target() == TRACE_TARGET => writeln!(buf, "{}", record.args()),
+        TraceFormat::Json => writeln!(
+            buf,
+            "{}",
+            serde_json::json!({
+                "level": record.level().as_str(),
+                "message": record.args().to_string(),
+            })
+        ),
+    }
 }
 
-enum Reply {
-    Entry(ReplyEntry),
-    Attr(ReplyAttr),
-    // Data(ReplyData),
-    // Directory(ReplyDirectory),
-    Empty(ReplyEmpty),
-    // Open(ReplyOpen),
-    // Write(ReplyWrite),
-    // Statfs(ReplyStatfs),
+// Writes the traces through the logger, so they end up in the file chosen with --log
+struct LogSink {
+    format: TraceFormat,
 }
 
-impl From<FileKind> for fuser::FileType {
-    fn from(kind: FileKind) -> Self {
-        match kind {
-            FileKind::File => fuser::FileType::RegularFile,
-            FileKind::Directory => fuser::FileType::Directory,
-            FileKind::Symlink => fuser::FileType::Symlink,
+impl TraceSink for LogSink {
+    fn header(&mut self, header: &TraceHeader) {
+        info!(target: TRACE_TARGET, "{}", render_header(header, self.format));
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        if let Some(line) = event.render(self.format) {
+            info!(target: TRACE_TARGET, "{}", line);
+        }
+    }
+
+    // the filesystem logs the human readable summary itself
+    fn summary(&mut self, summary: &LatencySummary) {
+        if self.format == TraceFormat::Json {
+            for line in render_summary(summary, self.format) {
+                info!(target: TRACE_TARGET, "{}", line);
+            }
         }
     }
+
+    fn flush(&mut self) {
+        log::logger().flush();
+    }
+}
+
+fn cli() -> Command {
+    Command::new("Cairn")
+        .author("xelahalo <xelahalo@gmail.com>")
+        .version(crate_version!())
+        .about("Filesystem implementation for tracing I/O operations for forward build systems")
+        .arg(
+            Arg::new("root")
+                .help("Root directory for the filesystem")
+                .required(true),
+        )
+        .arg(
+            Arg::new("mount-point")
+                .help("Mountpoint for the filesystem")
+                .required(true),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .action(ArgAction::Count)
+                .help("Sets the level of verbosity, repeat for more (-v debug, -vv trace)"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .value_parser(["error", "warn", "info", "debug", "trace"])
+                .conflicts_with("verbose")
+                .help("Sets the level of verbosity by name [default: info]"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .value_parser(["human", "json"])
+                .default_value("human")
+                .help("Format of the trace lines, `json` writes one JSON object per line"),
+        )
+        .arg(
+            Arg::new("trace-ops")
+                .long("trace-ops")
+                .value_name("OPS")
+                .value_parser(|ops: &str| ops.parse::<TraceOps>())
+                .default_value("all")
+                .help(
+                    "Only trace these operations, a comma separated list like \
+                    `read,write,create,unlink,rename` or `all`, names prefixed with `-` are left \
+                    out, like `all,-lookup_miss`",
+                ),
+        )
+        .arg(
+            Arg::new("trace-exclude")
+                .long("trace-exclude")
+                .visible_alias("exclude")
+                .value_name("GLOB")
+                .action(ArgAction::Append)
+                .value_parser(|glob: &str| Glob::new(glob))
+                .help(
+                    "Leave paths matching GLOB, relative to the root or absolute, out of the \
+                    trace, like `.git/**`, repeatable. The ready file and trace log are always \
+                    left out",
+                ),
+        )
+        .arg(
+            Arg::new("trace-include")
+                .long("trace-include")
+                .visible_alias("include")
+                .value_name("GLOB")
+                .action(ArgAction::Append)
+                .value_parser(|glob: &str| Glob::new(glob))
+                .help(
+                    "Only trace paths matching GLOB, relative to the root or absolute, like \
+                    `*.c`, repeatable. Takes precedence over --trace-exclude, an included path \
+                    is traced even if excluded",
+                ),
+        )
+        .arg(
+            Arg::new("dependencies")
+                .long("dependencies")
+                .action(ArgAction::SetTrue)
+                .help("Log the inputs and outputs of every process when unmounting"),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_name("PATH")
+                .num_args(0..=1)
+                .help(
+                    "Write the inputs, outputs and temporaries of the whole session to PATH when \
+                    unmounting, `<root>/.cairn-manifest.json` by default",
+                ),
+        )
+        .arg(
+            Arg::new("read-only")
+                .long("read-only")
+                .action(ArgAction::SetTrue)
+                .help("Reject every operation that would modify the root directory"),
+        )
+        .arg(
+            Arg::new("writable")
+                .long("writable")
+                .value_name("DIR")
+                .action(ArgAction::Append)
+                .conflicts_with("read-only")
+                .help(
+                    "Only allow changes inside DIR, relative to the root directory or absolute. \
+                    Can be given more than once, the changes of every other path fail with \
+                    EROFS and are traced as denied_write",
+                ),
+        )
+        .arg(
+            Arg::new("upper")
+                .long("upper")
+                .value_name("DIR")
+                .conflicts_with("read-only")
+                .help(
+                    "Leave the root directory as it is and make every change in DIR instead, \
+                    files of the root are copied there on their first change and removed ones \
+                    are hidden by `.wh.<name>` whiteouts. Traced events carry the layer they hit",
+                ),
+        )
+        .arg(
+            Arg::new("shadow")
+                .long("shadow")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["read-only", "upper"])
+                .help(
+                    "Leave the root directory as it is and throw the changes away when \
+                    unmounting. Until then they are kept in a scratch directory, read back \
+                    through the mount and traced by the paths of the root they were meant for",
+                ),
+        )
+        .arg(
+            Arg::new("inject")
+                .long("inject")
+                .value_name("MANIFEST")
+                .help(
+                    "Present files that are not in the root directory, MANIFEST is a JSON object \
+                    mapping paths relative to the root to the files they are read from. They \
+                    are read-only and traced by the path of those files",
+                ),
+        )
+        .arg(
+            Arg::new("fetch-cmd")
+                .long("fetch-cmd")
+                .value_name("COMMAND")
+                .requires("fetch-under")
+                .help(
+                    "Fault in missing paths below --fetch-under on their first lookup, by running \
+                    COMMAND from the root directory with the path relative to it as the last \
+                    argument. Their events are marked materialized, the failures are traced \
+                    as fetch_failed",
+                ),
+        )
+        .arg(
+            Arg::new("fetch-under")
+                .long("fetch-under")
+                .value_name("DIR")
+                .requires("fetch-cmd")
+                .help("Directory of the root, like `deps`, whose missing paths --fetch-cmd fetches"),
+        )
+        .arg(
+            Arg::new("fetch-timeout")
+                .long("fetch-timeout")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("60")
+                .help("Give up on a --fetch-cmd after SECONDS, the path is missing then"),
+        )
+        .arg(
+            Arg::new("confine")
+                .long("confine")
+                .action(ArgAction::SetTrue)
+                .help("Deny symlinks and paths that resolve outside of the root directory"),
+        )
+        .arg(
+            Arg::new("allowlist")
+                .long("allowlist")
+                .value_name("FILE")
+                .help(
+                    "Deny the paths matching none of the globs in FILE, one per line and \
+                    relative to the root, with EACCES and trace them as denied. The directories \
+                    leading to them, the own files of the tracer and whatever is created \
+                    through the mount stay accessible",
+                ),
+        )
+        .arg(
+            Arg::new("deny-undeclared-writes")
+                .long("deny-undeclared-writes")
+                .action(ArgAction::SetTrue)
+                .requires("allowlist")
+                .help("Deny creating files, directories and links outside of the allowlist too"),
+        )
+        .arg(
+            Arg::new("no-cache")
+                .long("no-cache")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("keep-cache")
+                .help(
+                    "Bypass the page cache so every read and write is traced, slower and \
+                    breaks mmap() of files on the mount",
+                ),
+        )
+        .arg(
+            Arg::new("keep-cache")
+                .long("keep-cache")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Keep cached file contents across opens, faster but reads served from the \
+                    cache are missing from the trace",
+                ),
+        )
+        .arg(
+            Arg::new("auto-cache")
+                .long("auto-cache")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["keep-cache", "no-cache"])
+                .help(
+                    "Keep cached file contents across opens until the file changes, reads \
+                    served from the cache are missing from the trace",
+                ),
+        )
+        .arg(
+            Arg::new("writeback-cache")
+                .long("writeback-cache")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("no-cache")
+                .help(
+                    "Let the kernel gather writes in the page cache, much faster for many small \
+                    writes but they are traced as the pages they are flushed in",
+                ),
+        )
+        .arg(
+            Arg::new("preload-attrs")
+                .long("preload-attrs")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Stat every file of the root directory before the mount is ready, instead of \
+                    on first access. Slow on big roots",
+                ),
+        )
+        .arg(
+            Arg::new("strict-init")
+                .long("strict-init")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Unmount right away when the root directory, or with --preload-attrs \
+                    anything below it, cannot be read, instead of serving a partial tree",
+                ),
+        )
+        .arg(
+            Arg::new("attr-ttl")
+                .long("attr-ttl")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0")
+                .conflicts_with_all(["entry-timeout", "attr-timeout"])
+                .help(
+                    "Let the kernel cache names and attributes for MS milliseconds, faster for \
+                    metadata heavy builds but stats served from the cache are missing from the \
+                    trace and changes to the root go unnoticed that long",
+                ),
+        )
+        .arg(
+            Arg::new("entry-timeout")
+                .long("entry-timeout")
+                .value_name("SECONDS")
+                .value_parser(timeout)
+                .help(
+                    "Let the kernel cache names for SECONDS, along with the attributes replied \
+                    with them. Lookups served from the cache are missing from the trace, and \
+                    paths renamed or removed in the root behind the mount go unnoticed that long",
+                ),
+        )
+        .arg(
+            Arg::new("attr-timeout")
+                .long("attr-timeout")
+                .value_name("SECONDS")
+                .value_parser(timeout)
+                .help(
+                    "Let the kernel cache attributes for SECONDS, stats served from the cache \
+                    are missing from the trace and changes made to the root behind the mount go \
+                    unnoticed that long",
+                ),
+        )
+        .arg(
+            Arg::new("negative-timeout")
+                .long("negative-timeout")
+                .value_name("SECONDS")
+                .value_parser(timeout)
+                .help(
+                    "Let the kernel remember missing names for SECONDS, only the first lookup \
+                    of them is traced and files created in the root behind the mount stay \
+                    missing that long",
+                ),
+        )
+        .arg(
+            Arg::new("no-default-permissions")
+                .long("no-default-permissions")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Check permissions in the filesystem instead of leaving it to the kernel, \
+                    only on open and access, so not together with allow_other or allow_root",
+                ),
+        )
+        .arg(
+            Arg::new("log")
+                .long("log")
+                .visible_alias("log-file")
+                .value_name("PATH")
+                .env("CAIRN_FUSE_LOG")
+                .help(
+                    "File to append the traces to, `-` for stderr, must not be inside the \
+                    mountpoint, reopened on SIGHUP [default: <root>/tracer.log]",
+                ),
+        )
+        .arg(
+            Arg::new("log-max-size")
+                .long("log-max-size")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help(
+                    "Start a new log once it grows past BYTES, the previous one is renamed to \
+                    PATH.1, the one before that to PATH.2 and so on",
+                ),
+        )
+        .arg(
+            Arg::new("log-keep")
+                .long("log-keep")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("5")
+                .help("Number of logs kept by --log-max-size, older ones are deleted"),
+        )
+        .arg(
+            Arg::new("option")
+                .short('o')
+                .value_name("OPTION")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(mount_option)
+                .help("Mount options like `allow_other`, `noexec` or `fsname=<name>`, repeatable"),
+        )
+        .arg(
+            Arg::new("allow-other")
+                .long("allow-other")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("allow-root")
+                .help(
+                    "Let other users into the mount, which needs `user_allow_other` in \
+                    /etc/fuse.conf unless mounted by root",
+                ),
+        )
+        .arg(
+            Arg::new("allow-root")
+                .long("allow-root")
+                .action(ArgAction::SetTrue)
+                .help("Let root into the mount as well, with the same requirement as --allow-other"),
+        )
+        .arg(
+            Arg::new("mkdir")
+                .long("mkdir")
+                .action(ArgAction::SetTrue)
+                .help("Create the mountpoint directory if it does not exist"),
+        )
+        .arg(
+            Arg::new("ready-file")
+                .long("ready-file")
+                .value_name("PATH")
+                .help(
+                    "Create PATH once the filesystem is up, `.cairn-fuse-ready` in the working \
+                    directory by default",
+                ),
+        )
+        .arg(
+            Arg::new("ready-fd")
+                .long("ready-fd")
+                .value_name("FD")
+                .value_parser(clap::value_parser!(i32).range(0..))
+                .help(
+                    "Write a newline to the inherited descriptor FD and close it once the \
+                    filesystem is up, no ready file is created unless --ready-file is given",
+                ),
+        )
+        .arg(
+            Arg::new("trace-socket")
+                .long("trace-socket")
+                .value_name("PATH")
+                .help(
+                    "Also listen on the unix socket PATH and send the traces to every connected \
+                    consumer, one that falls behind is disconnected",
+                ),
+        )
+        .arg(
+            Arg::new("metrics")
+                .long("metrics")
+                .value_name("PATH")
+                .help(
+                    "Listen on the unix socket PATH and reply to every connection with the \
+                    operation counters, open files and cached inodes in the Prometheus text format",
+                ),
+        )
+        .arg(
+            Arg::new("trace-per-process")
+                .long("trace-per-process")
+                .value_name("DIR")
+                .help(
+                    "Also write the traces of every process tree to DIR/trace.<pid>.<start>.log, \
+                    and with a manifest its dependencies to DIR/manifest.<pid>.<start>.json, \
+                    one for every compile of a parallel build",
+                ),
+        )
+        .arg(
+            Arg::new("process-tree-parents")
+                .long("process-tree-parents")
+                .value_name("COMM,..")
+                .value_delimiter(',')
+                .default_value("make,ninja,sh,bash,dash,chroot")
+                .help(
+                    "Start a process tree of --trace-per-process right below the nearest \
+                    ancestor running one of these, rather than at the topmost ancestor",
+                ),
+        )
+        .arg(
+            Arg::new("ring")
+                .long("ring")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help(
+                    "Keep the last N events in memory, listed by the read-only .cairn-events \
+                    file in the root of the mount",
+                ),
+        )
+        .arg(
+            Arg::new("relative-paths")
+                .long("relative-paths")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Trace paths relative to the root, `.` for the root itself, so the traces of \
+                    the same build in another root are identical",
+                ),
+        )
+        .arg(
+            Arg::new("dedup")
+                .long("dedup")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Coalesce a run of the same operation by the same process on the same file \
+                    into one event with a count, written once the run ends",
+                ),
+        )
+        .arg(
+            Arg::new("hash-outputs")
+                .long("hash-outputs")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Record the SHA-256 of every file written through the mount, in its last \
+                    release and the manifest",
+                ),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help(
+                    "Serve the reads and writes of open files on N threads, so those of different \
+                    files proceed in parallel. Events stay in order for every file, and are \
+                    ordered across files by their monotonic_us",
+                ),
+        )
+        .arg(
+            Arg::new("trace-sessions")
+                .long("trace-sessions")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Trace a single output or input event per open file once it is closed, with \
+                    the bytes written or read through it, instead of its opens, reads, writes \
+                    and releases",
+                ),
+        )
+        .arg(
+            Arg::new("trace-sync")
+                .long("trace-sync")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Write every trace before the operation returns instead of on a writer \
+                    thread, slower but nothing is lost to a crash or a writer falling behind",
+                ),
+        )
+        .arg(
+            Arg::new("trace-dest")
+                .long("trace-dest")
+                .value_name("DEST")
+                .help("Send the traces to `file:<path>`, `unix:<socket>`, `stdout` or a binary `fbs:<path>` instead of the log"),
+        )
+        .arg(
+            Arg::new("trace-format")
+                .long("trace-format")
+                .value_name("FORMAT")
+                .value_parser(["lines", "chrome"])
+                .default_value("lines")
+                .help(
+                    "Write the traces as lines in --format, or as a `chrome` trace for \
+                    about://tracing and Perfetto, which needs a `file:<path>` destination and \
+                    replaces the file",
+                ),
+        )
+}
+
+// The file the traces are appended to, None when they go to stderr
+fn log_path(matches: &ArgMatches, root: &str) -> Option<PathBuf> {
+    match matches.get_one::<String>("log").map(String::as_str) {
+        Some("-") => None,
+        Some(path) => Some(path.into()),
+        None => Some(Path::new(root).join("tracer.log")),
+    }
 }
 
-fn time_now() -> (i64, u32) {
-    time_from_system_time(&SystemTime::now())
+// The size a log may grow to and how many of the previous ones are kept, if it is rotated at all
+fn log_rotation(matches: &ArgMatches) -> Option<(u64, usize)> {
+    let max_size = *matches.get_one::<u64>("log-max-size")?;
+    Some((max_size, *matches.get_one::<usize>("log-keep").unwrap()))
+}
+
+// The log is opened again when `reopen` is set, see on_hangup()
+fn log_target(
+    log: Option<&Path>,
+    mountpoint: &str,
+    rotation: Option<(u64, usize)>,
+    reopen: &'static AtomicBool,
+) -> Result<env_logger::Target, String> {
+    let path = match log {
+        Some(x) => x,
+        None => return Ok(env_logger::Target::Stderr),
+    };
+
+    // every write to it would be traced in turn, and wait on the filesystem doing the writing
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let mountpoint = cwd.join(mountpoint);
+    let canonical = fs::canonicalize(&mountpoint).unwrap_or_else(|_| mountpoint.clone());
+    let absolute = cwd.join(path);
+    // the directories of the log may not exist yet, nor may the mountpoint
+    let existing = absolute
+        .ancestors()
+        .find_map(|dir| fs::canonicalize(dir).ok())
+        .unwrap_or_default();
+    if absolute.starts_with(&mountpoint) || existing.starts_with(&canonical) {
+        return Err(format!(
+            "log '{}' is inside the mountpoint '{}'",
+            path.display(),
+            mountpoint.display()
+        ));
+    }
+
+    let file = path.to_path_buf();
+    let open = move || -> io::Result<Box<dyn Write + Send>> {
+        match rotation {
+            Some((max_size, keep)) => {
+                Ok(Box::new(RotatingFile::open(file.clone(), max_size, keep)?))
+            }
+            None => Ok(Box::new(create_new(&file)?)),
+        }
+    };
+    fs::create_dir_all(absolute.parent().unwrap_or(&absolute))
+        .and_then(|_| ReopenableLog::open(open, reopen))
+        .map(|log| env_logger::Target::Pipe(Box::new(log)))
+        .map_err(|e| format!("cannot write the log '{}': {e}", path.display()))
+}
+
+fn mount_option(option: &str) -> Result<MountOption, String> {
+    let option = match option {
+        "ro" => MountOption::RO,
+        "rw" => MountOption::RW,
+        "allow_other" => MountOption::AllowOther,
+        "allow_root" => MountOption::AllowRoot,
+        "auto_unmount" => MountOption::AutoUnmount,
+        "dev" => MountOption::Dev,
+        "nodev" => MountOption::NoDev,
+        "suid" => MountOption::Suid,
+        "nosuid" => MountOption::NoSuid,
+        "exec" => MountOption::Exec,
+        "noexec" => MountOption::NoExec,
+        "sync" => MountOption::Sync,
+        "async" => MountOption::Async,
+        "dirsync" => MountOption::DirSync,
+        "atime" => MountOption::Atime,
+        "noatime" => MountOption::NoAtime,
+        _ => match option.split_once('=') {
+            Some(("fsname", name)) => MountOption::FSName(name.to_string()),
+            _ => return Err(format!("unsupported mount option `{option}`")),
+        },
+    };
+    Ok(option)
+}
+
+// Only what was asked for on the command line, other users are kept out unless `allow_other`
+fn mount_options(matches: &ArgMatches) -> Vec<MountOption> {
+    let mut mount_options: Vec<MountOption> = matches
+        .get_many::<MountOption>("option")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    if matches.get_flag("allow-other") {
+        mount_options.push(MountOption::AllowOther);
+    }
+    if matches.get_flag("allow-root") {
+        mount_options.push(MountOption::AllowRoot);
+    }
+    if !mount_options
+        .iter()
+        .any(|option| matches!(option, MountOption::FSName(_)))
+    {
+        mount_options.push(MountOption::FSName("cairn-fuse".to_string()));
+    }
+    // the kernel checks permissions itself, access() and open() only matter without it
+    if !matches.get_flag("no-default-permissions") {
+        mount_options.push(MountOption::DefaultPermissions);
+    }
+    mount_options
 }
 
-fn system_time_from_time(secs: i64, nsecs: u32) -> SystemTime {
-    if secs >= 0 {
-        UNIX_EPOCH + Duration::new(secs as u64, nsecs)
+fn trace_sink(dest: Option<&String>, format: TraceFormat) -> io::Result<Box<dyn TraceSink + Send>> {
+    let dest = match dest {
+        Some(x) => x.as_str(),
+        None => return Ok(Box::new(LogSink { format })),
+    };
+
+    if dest == "stdout" {
+        Ok(Box::new(WriterSink::new(io::stdout(), format)))
+    } else if let Some(path) = dest.strip_prefix("file:") {
+        Ok(Box::new(WriterSink::new(create_new(path)?, format)))
+    } else if let Some(path) = dest.strip_prefix("unix:") {
+        Ok(Box::new(UnixSink::new(path.into(), format)))
+    } else if let Some(path) = dest.strip_prefix("fbs:") {
+        Ok(Box::new(FlatBufferSink::new(create_new(path)?)))
     } else {
-        UNIX_EPOCH - Duration::new((-secs) as u64, nsecs)
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown trace destination `{dest}`"),
+        ))
     }
 }
 
-fn time_from_system_time(system_time: &SystemTime) -> (i64, u32) {
-    // Convert to signed 64-bit time with epoch at 0
-    match system_time.duration_since(UNIX_EPOCH) {
-        Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
-        Err(before_epoch_error) => (
-            -(before_epoch_error.duration().as_secs() as i64),
-            before_epoch_error.duration().subsec_nanos(),
-        ),
+// A Chrome trace goes back over its closing bracket, so only a file will do
+fn chrome_sink(dest: Option<&String>) -> io::Result<Box<dyn TraceSink + Send>> {
+    match dest.and_then(|x| x.strip_prefix("file:")) {
+        Some(path) => Ok(Box::new(ChromeSink::new(File::create(path)?)?)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "chrome traces need a `--trace-dest file:<path>`",
+        )),
     }
 }
 
-#[derive(Clone)]
-struct InodeAttributes {
-    // pub metadata: fs::Metadata,
-    pub ino: u64,
-    pub uid: u32,
-    pub gid: u32,
-    pub mode: u32,
-    pub atime: (i64, u32),
-    pub mtime: (i64, u32),
-    pub kind: FileKind,
-    pub len: u64,
-    pub nlinks: u64,
-    pub blksize: u64,
-    pub blocks: u64,
-    pub rdev: u64,
-    pub real_path: String,
+// The sentinel startup.sh polls for, unless a supervisor is told through a descriptor instead
+fn ready_file(matches: &ArgMatches) -> Option<PathBuf> {
+    match matches.get_one::<String>("ready-file") {
+        Some(path) => Some(PathBuf::from(path)),
+        None if matches.contains_id("ready-fd") => None,
+        None => Some(PathBuf::from(".cairn-fuse-ready")),
+    }
 }
 
-impl From<(fs::Metadata, String)> for InodeAttributes {
-    fn from(payload: (fs::Metadata, String)) -> Self {
-        let ino = payload.0.ino();
-        let uid = payload.0.uid();
-        let gid = payload.0.gid();
-        let mode = payload.0.mode();
-        let kind = as_file_kind(payload.0.mode());
-        let len = payload.0.len();
-        let nlinks = payload.0.nlink();
-        let blksize = payload.0.blksize();
-        let blocks = payload.0.blocks();
-        let rdev = payload.0.rdev();
-        let real_path = payload.1;
-
-        let atime = time_from_system_time(&match payload.0.accessed() {
-            Ok(x) => x,
-            Err(_) => panic!("Access time not supported on this platform."),
-        });
-        let mtime = time_from_system_time(&match payload.0.modified() {
-            Ok(x) => x,
-            Err(_) => panic!("Modification time not supported on this platform."),
-        });
+fn inherited_fd(fd: RawFd) -> io::Result<File> {
+    // owning a descriptor that is not open would close an unrelated one later on
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// The files the tracer writes itself, reading them through the mount would trace the trace
+fn own_files(matches: &ArgMatches, root: &str, ready_file: Option<&Path>) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = ready_file.into_iter().map(Path::to_path_buf).collect();
+    files.extend(log_path(matches, root));
+    if let Some(dest) = matches.get_one::<String>("trace-dest") {
+        if let Some(path) = dest.strip_prefix("file:").or(dest.strip_prefix("fbs:")) {
+            files.push(path.into());
+        }
+    }
+    files.extend(matches.get_one::<String>("trace-socket").map(PathBuf::from));
+    files.extend(matches.get_one::<String>("metrics").map(PathBuf::from));
+    files.extend(
+        matches
+            .get_one::<String>("trace-per-process")
+            .map(PathBuf::from),
+    );
+    files.extend(summary_path(matches, root));
+    files
+}
+
+// The summary of the operations goes next to the file the traces end up in, if there is one
+fn summary_path(matches: &ArgMatches, root: &str) -> Option<PathBuf> {
+    let trace = match matches.get_one::<String>("trace-dest") {
+        Some(dest) => dest
+            .strip_prefix("file:")
+            .or(dest.strip_prefix("fbs:"))
+            .map(PathBuf::from)?,
+        None => log_path(matches, root)?,
+    };
+    Some(trace.with_extension("summary.json"))
+}
+
+// Globs of whichever of the own files are below the root, and of what is in the directories
+fn own_globs(root: &str, own_files: &[PathBuf]) -> Result<Vec<Glob>, globset::Error> {
+    let mut globs = Vec::new();
+    let cwd = std::env::current_dir().unwrap_or_default();
+    for file in own_files {
+        if let Ok(relative) = cwd.join(file).strip_prefix(cwd.join(root)) {
+            let relative = globset::escape(&relative.to_string_lossy());
+            globs.push(Glob::new(&relative)?);
+            if cwd.join(file).is_dir() {
+                globs.push(Glob::new(&format!("{relative}/**"))?);
+            }
+        }
+    }
+    Ok(globs)
+}
+
+// The globs of --trace-exclude and whichever of the own files are below the root
+fn trace_excludes(
+    matches: &ArgMatches,
+    root: &str,
+    own_files: &[PathBuf],
+) -> Result<GlobSet, globset::Error> {
+    let mut globs = GlobSetBuilder::new();
+    for glob in matches
+        .get_many::<Glob>("trace-exclude")
+        .into_iter()
+        .flatten()
+    {
+        globs.add(glob.clone());
+    }
+    for glob in own_globs(root, own_files)? {
+        globs.add(glob);
+    }
+    globs.build()
+}
+
+// The globs of the --allowlist file, blank lines and `#` comments are skipped. The own files of
+// the tracer are always allowed, a script waits for the ready file through the mount
+fn allowlist(path: &str, root: &str, own_files: &[PathBuf]) -> Result<Allowlist, String> {
+    let list = fs::read_to_string(path).map_err(|e| format!("cannot read '{path}': {e}"))?;
+    let mut globs = own_globs(root, own_files).map_err(|e| e.to_string())?;
+    for (n, line) in list.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        globs.push(Glob::new(line).map_err(|e| format!("{path}:{}: {e}", n + 1))?);
+    }
+    Allowlist::new(&globs).map_err(|e| format!("{path}: {e}"))
+}
 
-        InodeAttributes {
-            ino,
-            uid,
-            gid,
-            mode,
-            atime,
-            mtime,
-            kind,
-            len,
-            nlinks,
-            blksize,
-            blocks,
-            rdev,
-            real_path,
+// Fractional seconds, like the timeouts of libfuse
+fn timeout(seconds: &str) -> Result<Duration, String> {
+    let seconds: f64 = seconds.parse().map_err(|e| format!("{e}"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{e}"))
+}
+
+// The files of the --inject manifest, by the path they are presented at
+fn injections(path: &str) -> Result<Injections, String> {
+    let manifest = fs::read_to_string(path).map_err(|e| format!("cannot read '{path}': {e}"))?;
+    let files: BTreeMap<PathBuf, PathBuf> =
+        serde_json::from_str(&manifest).map_err(|e| format!("{path}: {e}"))?;
+    Injections::new(files).map_err(|e| format!("{path}: {e}"))
+}
+
+// The globs of --trace-include, None when every path is traced
+fn trace_includes(matches: &ArgMatches) -> Result<Option<GlobSet>, globset::Error> {
+    let globs = match matches.get_many::<Glob>("trace-include") {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let mut include = GlobSetBuilder::new();
+    for glob in globs {
+        include.add(glob.clone());
+    }
+    include.build().map(Some)
+}
+
+// `--manifest` without a path puts it into the root, next to the tracer log
+fn manifest_path(matches: &ArgMatches, root: &str) -> Option<PathBuf> {
+    if !matches.contains_id("manifest") {
+        return None;
+    }
+    match matches.get_one::<String>("manifest") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(Path::new(root).join(".cairn-manifest.json")),
+    }
+}
+
+// The filesystem serves one request at a time, one that leads back into the mount waits on itself.
+// So does every lookup of the mountpoint when the root holds it, and a write to an own file in it
+fn overlaps(root: &str, mountpoint: &str, own_files: &[PathBuf]) -> Result<(), String> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let mnt = resolve(&cwd.join(mountpoint));
+    let backing = resolve(&cwd.join(root));
+    if backing.starts_with(&mnt) {
+        return Err(format!(
+            "root '{root}' is inside the mountpoint '{mountpoint}'"
+        ));
+    }
+    if mnt.starts_with(&backing) {
+        return Err(format!(
+            "mountpoint '{mountpoint}' is inside the root '{root}'"
+        ));
+    }
+    for file in own_files {
+        if resolve(&cwd.join(file)).starts_with(&mnt) {
+            return Err(format!(
+                "'{}' is inside the mountpoint '{mountpoint}', the tracer would write to its own \
+                mount",
+                file.display()
+            ));
         }
     }
+    Ok(())
+}
+
+// Whether fusermount lets this user mount with allow_other or allow_root, root mounts without it
+fn allow_other_permitted() -> bool {
+    let root = unsafe { libc::geteuid() } == 0;
+    let conf = fs::read_to_string("/etc/fuse.conf").unwrap_or_default();
+    root || conf.lines().any(|line| line.trim() == "user_allow_other")
 }
 
-impl From<InodeAttributes> for fuser::FileAttr {
-    fn from(attrs: InodeAttributes) -> Self {
-        fuser::FileAttr {
-            ino: attrs.ino,
-            size: attrs.len,
-            blocks: attrs.blocks,
-            atime: system_time_from_time(attrs.atime.0, attrs.atime.1),
-            mtime: system_time_from_time(attrs.mtime.0, attrs.mtime.1),
-            ctime: system_time_from_time(attrs.mtime.0, attrs.mtime.1),
-            crtime: SystemTime::UNIX_EPOCH,
-            kind: attrs.kind.into(),
-            perm: attrs.mode as u16,
-            nlink: attrs.nlinks as u32,
-            uid: attrs.uid,
-            gid: attrs.gid,
-            rdev: attrs.rdev as u32,
-            blksize: attrs.blksize as u32,
-            flags: 0,
+// The error of the mount syscall alone rarely tells what is wrong with the mountpoint
+fn mount_error(mountpoint: &str, options: &[MountOption], e: &io::Error) -> String {
+    let path = Path::new(mountpoint);
+    let metadata = match fs::metadata(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return format!("mountpoint '{mountpoint}' does not exist");
+        }
+        // left behind by a filesystem that died without being unmounted
+        Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => {
+            return format!("mountpoint '{mountpoint}' is a stale mount, unmount it first");
         }
+        Err(e) => return format!("mountpoint '{mountpoint}' is not accessible: {e}"),
+    };
+    if !metadata.is_dir() {
+        return format!("mountpoint '{mountpoint}' is not a directory");
+    }
+
+    // a mountpoint is on another device than the directory it is in
+    let mounted = path
+        .join("..")
+        .metadata()
+        .is_ok_and(|parent| parent.dev() != metadata.dev());
+    // fusermount only says so on stderr, the error is an opaque one
+    let shared = options
+        .iter()
+        .find(|x| matches!(x, MountOption::AllowOther | MountOption::AllowRoot));
+    if mounted || e.raw_os_error() == Some(libc::EBUSY) {
+        format!("mountpoint '{mountpoint}' is already mounted")
+    } else if let (Some(option), false) = (shared, allow_other_permitted()) {
+        let option = match option {
+            MountOption::AllowRoot => "allow_root",
+            _ => "allow_other",
+        };
+        format!(
+            "failed to mount '{mountpoint}' with {option}: add `user_allow_other` to \
+            /etc/fuse.conf, or mount without it to keep other users out"
+        )
+    } else if e.kind() == io::ErrorKind::PermissionDenied {
+        format!("permission denied mounting '{mountpoint}': {e}")
+    } else {
+        format!("failed to mount '{mountpoint}': {e}")
     }
 }
 
-// In memory storing of the attributes of the files
-struct TracerFS {
-    root: String,
-    attrs: BTreeMap<u64, InodeAttributes>,
-    destroy: Sender<()>,
+fn level_filter(matches: &ArgMatches) -> LevelFilter {
+    match matches.get_one::<String>("log-level").map(String::as_str) {
+        Some("error") => LevelFilter::Error,
+        Some("warn") => LevelFilter::Warn,
+        Some("debug") => LevelFilter::Debug,
+        Some("trace") => LevelFilter::Trace,
+        Some(_) => LevelFilter::Info,
+        // traces are logged at info, every single operation at debug
+        None => match matches.get_count("verbose") {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        },
+    }
 }
 
-impl TracerFS {
-    fn new(root: String, destroy: Sender<()>) -> TracerFS {
-        {
-            TracerFS {
-                root,
-                attrs: BTreeMap::new(),
-                destroy,
-            }
-        }
+fn main() {
+    let matches = cli().get_matches();
+
+    let level_filter = level_filter(&matches);
+    let root = matches.get_one::<String>("root").unwrap().to_string();
+    let mountpoint = matches.get_one::<String>("mount-point").unwrap();
+    let ready_file = ready_file(&matches);
+    let own_files = own_files(&matches, &root, ready_file.as_deref());
+    if let Err(e) = overlaps(&root, mountpoint, &own_files) {
+        eprintln!("{e}");
+        process::exit(EXIT_CONFIG);
+    }
+    let log = log_path(&matches, &root);
+    let rotation = log_rotation(&matches);
+    let target = match log_target(log.as_deref(), mountpoint, rotation, &REOPEN_LOG) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(EXIT_OUTPUT);
+        }
+    };
+    if let (Some(path), None) = (&log, matches.get_one::<String>("log")) {
+        eprintln!("logging to '{}'", path.display());
+    }
+    let format = match matches.get_one::<String>("format").map(String::as_str) {
+        Some("json") => TraceFormat::Json,
+        _ => TraceFormat::Human,
+    };
+
+    if level_filter >= LevelFilter::Debug {
+        let _ = File::create("1_parsed_matches");
+    }
+
+    Builder::new()
+        .format(get_logger_format(format))
+        .target(target)
+        .filter_level(level_filter)
+        .init();
+
+    if level_filter >= LevelFilter::Debug {
+        let _ = File::create("2_init_logger");
+    }
+
+    // unmount filesystem automatically when SIGINT or SIGTERM is received, reopen the log on SIGHUP
+    let (drop_send, drop_recv) = std::sync::mpsc::channel();
+    let destroy = drop_send.clone();
+    if let Err(e) = on_termination(drop_send) {
+        eprintln!("cannot handle the termination signals: {e}");
+        process::exit(EXIT_SIGNALS);
+    }
+    if let Err(e) = on_hangup() {
+        eprintln!("cannot handle SIGHUP: {e}");
+        process::exit(EXIT_SIGNALS);
+    }
+
+    if level_filter >= LevelFilter::Debug {
+        let _ = File::create("3_create_channel");
+    }
+
+    let mut mount_options = mount_options(&matches);
+    // without the kernel only open(), opendir() and access() check the caller, which is no guard
+    // against the other users allow_other lets in
+    let shared = mount_options
+        .iter()
+        .any(|x| matches!(x, MountOption::AllowOther | MountOption::AllowRoot));
+    if shared && matches.get_flag("no-default-permissions") {
+        eprintln!("--no-default-permissions cannot be combined with allow_other or allow_root");
+        process::exit(EXIT_CONFIG);
+    }
+    let dest = matches.get_one::<String>("trace-dest");
+    let sink = match matches
+        .get_one::<String>("trace-format")
+        .map(String::as_str)
+    {
+        Some("chrome") => chrome_sink(dest),
+        _ => trace_sink(dest, format),
+    };
+    let mut sink = match sink {
+        Ok(x) => x,
+        // a destination it does not know, or one a chrome trace cannot go to
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+            eprintln!("{e}");
+            process::exit(EXIT_CONFIG);
+        }
+        Err(e) => {
+            let dest = dest.map_or("", String::as_str);
+            eprintln!("cannot write the traces to '{dest}': {e}");
+            process::exit(EXIT_OUTPUT);
+        }
+    };
+    // bound before mounting, consumers may connect as soon as the ready file shows up
+    if let Some(path) = matches.get_one::<String>("trace-socket") {
+        match BroadcastSink::bind(path.into(), format) {
+            Ok(broadcast) => sink = Box::new(TeeSink::new(sink, broadcast)),
+            Err(e) => {
+                eprintln!("cannot listen on '{path}': {e}");
+                process::exit(EXIT_OUTPUT);
+            }
+        }
+    }
+    let per_process = matches
+        .get_one::<String>("trace-per-process")
+        .map(PathBuf::from);
+    if let Some(dir) = &per_process {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("cannot create '{}': {e}", dir.display());
+            process::exit(EXIT_OUTPUT);
+        }
+        sink = Box::new(TeeSink::new(sink, PerProcessSink::new(dir.clone(), format)));
+    }
+    if matches.get_flag("dedup") {
+        sink = Box::new(DedupSink::new(sink));
+    }
+    if !matches.get_flag("trace-sync") {
+        sink = Box::new(AsyncSink::new(sink, QUEUE_LIMIT));
+    }
+    let mut tracer = TracerFS::new(root.clone(), destroy, sink)
+        .trace_ops(*matches.get_one::<TraceOps>("trace-ops").unwrap())
+        .mountpoint(Path::new(mountpoint));
+    if matches.get_flag("dependencies") {
+        tracer = tracer.track_dependencies();
+    }
+    if let Some(size) = matches.get_one::<usize>("ring") {
+        tracer = tracer.ring(*size);
+    }
+    if let Some(dir) = per_process {
+        let parents = matches.get_many::<String>("process-tree-parents");
+        tracer = tracer.per_process(parents.unwrap().cloned().collect(), dir);
+    }
+    if matches.get_flag("hash-outputs") {
+        tracer = tracer.hash_outputs();
+    }
+    if let Some(path) = matches.get_one::<String>("metrics") {
+        match Metrics::bind(path.into()) {
+            Ok(metrics) => tracer = tracer.metrics(metrics),
+            Err(e) => {
+                eprintln!("cannot listen on '{path}': {e}");
+                process::exit(EXIT_OUTPUT);
+            }
+        }
+    }
+    if let Some(threads) = matches.get_one::<usize>("threads") {
+        tracer = tracer.io_threads(*threads);
+    }
+    if matches.get_flag("trace-sessions") {
+        tracer = tracer.sessions();
+    }
+    if matches.get_flag("relative-paths") {
+        tracer = tracer.relative_paths();
+    }
+    if let Some(path) = manifest_path(&matches, &root) {
+        tracer = tracer.manifest(path);
+    }
+    if let Some(path) = summary_path(&matches, &root) {
+        tracer = tracer.summary_file(path);
+    }
+    if let Some(path) = &ready_file {
+        tracer = tracer.ready_file(path.clone());
+    }
+    match trace_excludes(&matches, &root, &own_files) {
+        Ok(globs) => tracer = tracer.trace_exclude(globs),
+        Err(e) => {
+            eprintln!("invalid --trace-exclude: {e}");
+            process::exit(EXIT_CONFIG);
+        }
+    }
+    match trace_includes(&matches) {
+        Ok(Some(globs)) => tracer = tracer.trace_include(globs),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("invalid --trace-include: {e}");
+            process::exit(EXIT_CONFIG);
+        }
+    }
+    if let Some(fd) = matches.get_one::<i32>("ready-fd") {
+        match inherited_fd(*fd) {
+            Ok(file) => tracer = tracer.ready_fd(file),
+            Err(e) => {
+                eprintln!("cannot use --ready-fd {fd}: {e}");
+                process::exit(EXIT_CONFIG);
+            }
+        }
+    }
+    if matches.get_flag("read-only") {
+        mount_options.push(MountOption::RO);
+        tracer = tracer.read_only();
+    }
+    if let Some(dirs) = matches.get_many::<String>("writable") {
+        tracer = tracer.writable_dirs(dirs.map(PathBuf::from).collect());
+    }
+    if let Some(upper) = matches.get_one::<String>("upper") {
+        if !Path::new(upper).is_dir() {
+            eprintln!("upper directory '{upper}' does not exist");
+            process::exit(EXIT_CONFIG);
+        }
+        tracer = tracer.overlay(PathBuf::from(upper));
+    }
+    if matches.get_flag("shadow") {
+        let scratch = std::env::temp_dir().join(format!("cairn-shadow.{}", process::id()));
+        if let Err(e) = fs::create_dir(&scratch) {
+            eprintln!("Failed to create {}: {e}", scratch.display());
+            process::exit(EXIT_OUTPUT);
+        }
+        tracer = tracer.shadow(scratch);
+    }
+    if let Some(path) = matches.get_one::<String>("inject") {
+        match injections(path) {
+            Ok(injections) => tracer = tracer.inject(injections),
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(EXIT_CONFIG);
+            }
+        }
+    }
+    if let Some(command) = matches.get_one::<String>("fetch-cmd") {
+        let under = matches.get_one::<String>("fetch-under").unwrap();
+        let timeout = *matches.get_one::<u64>("fetch-timeout").unwrap();
+        tracer = tracer.fetch(
+            command.clone(),
+            PathBuf::from(under),
+            Duration::from_secs(timeout),
+        );
+    }
+    if matches.get_flag("confine") {
+        tracer = tracer.confine();
+    }
+    if let Some(path) = matches.get_one::<String>("allowlist") {
+        match allowlist(path, &root, &own_files) {
+            Ok(allowlist) => tracer = tracer.allowlist(allowlist),
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(EXIT_CONFIG);
+            }
+        }
+        if matches.get_flag("deny-undeclared-writes") {
+            tracer = tracer.deny_undeclared_writes();
+        }
+    }
+    if matches.get_flag("no-cache") {
+        tracer = tracer.direct_io();
+    } else if matches.get_flag("keep-cache") {
+        tracer = tracer.keep_cache();
+    } else if matches.get_flag("auto-cache") {
+        tracer = tracer.auto_cache();
+    }
+    if matches.get_flag("writeback-cache") {
+        tracer = tracer.writeback_cache();
+    }
+    if matches.get_flag("preload-attrs") {
+        tracer = tracer.preload_attrs();
+    }
+    if matches.get_flag("strict-init") {
+        tracer = tracer.strict_init();
+    }
+    if let Some(ms) = matches.get_one::<u64>("attr-ttl") {
+        let ttl = Duration::from_millis(*ms);
+        tracer = tracer.entry_ttl(ttl).attr_ttl(ttl);
+    }
+    if let Some(ttl) = matches.get_one::<Duration>("attr-timeout") {
+        tracer = tracer.attr_ttl(*ttl);
+    }
+    if let Some(ttl) = matches.get_one::<Duration>("entry-timeout") {
+        tracer = tracer.entry_ttl(*ttl);
+    }
+    if let Some(ttl) = matches.get_one::<Duration>("negative-timeout") {
+        tracer = tracer.negative_ttl(*ttl);
+    }
+    if matches.get_flag("mkdir") {
+        if let Err(e) = fs::create_dir_all(mountpoint) {
+            eprintln!("failed to create mountpoint '{mountpoint}': {e}");
+            process::exit(EXIT_MOUNT);
+        }
+    }
+    let guard = match fuser::spawn_mount2(tracer, mountpoint, mount_options.as_slice()) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", mount_error(mountpoint, &mount_options, &e));
+            process::exit(EXIT_MOUNT);
+        }
+    };
+
+    if level_filter >= LevelFilter::Debug {
+        let _ = File::create("4_mount");
+    }
+
+    // the handlers keep a sender, the channel only closes if the filesystem went away unasked
+    let status = drop_recv.recv().unwrap_or(Ok(()));
+    shutdown(guard, ready_file.as_deref(), log.as_deref());
+    if let Err(errno) = status {
+        eprintln!(
+            "the filesystem failed to start: {}",
+            io::Error::from_raw_os_error(errno)
+        );
+        process::exit(EXIT_INIT);
+    }
+}
+
+// Shuts down gracefully on Ctrl-C, and on the SIGTERM of a supervisor. A second signal gives up
+// on a mount that stays busy
+fn on_termination(shutdown: Sender<Result<(), c_int>>) -> Result<(), ctrlc::Error> {
+    let signalled = AtomicBool::new(false);
+    ctrlc::set_handler(move || {
+        if signalled.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+        debug!("Received a termination signal, unmounting filesystem");
+        let _ = shutdown.send(Ok(()));
+    })
+}
+
+extern "C" fn hangup(_: c_int) {
+    REOPEN_LOG.store(true, Ordering::SeqCst);
+}
+
+// Reopens the log on SIGHUP, which logrotate sends once it moved the file away. Installed after
+// on_termination(), whose handler of ctrlc would unmount on it instead
+fn on_hangup() -> io::Result<()> {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = hangup as extern "C" fn(c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    if unsafe { libc::sigaction(libc::SIGHUP, &action, std::ptr::null_mut()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Unmounts and waits for destroy(), which drains and syncs the trace sink
+fn shutdown(guard: BackgroundSession, ready_file: Option<&Path>, log: Option<&Path>) {
+    if let Some(path) = ready_file {
+        let _ = fs::remove_file(path);
+    }
+    guard.join();
+    // the logger writes straight to the file, it only has to be synced
+    if let Some(path) = log {
+        if let Err(e) = File::open(path).and_then(|file| file.sync_data()) {
+            warn!("Failed to sync the log {}: {}", path.display(), e);
+        }
+    }
+}
+
+// todo make sure that all the tests can be run in parallel
+// the tests lean on Linux only syscalls and /proc to check what the mount did
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::{
+        Allowlist, Injections, LatencySummary, LogSink, TraceEvent, TraceFormat, TraceSink,
+        TracerFS,
+    };
+    use cairn_fuse::fbs;
+    use cairn_fuse::metrics::Metrics;
+    use cairn_fuse::sink::{
+        AsyncSink, BroadcastSink, ChromeSink, DedupSink, PerProcessSink, QUEUE_LIMIT,
+    };
+    use cairn_fuse::{TraceOp, TraceOps};
+    use fuser::{BackgroundSession, MountOption};
+    use globset::{Glob, GlobSetBuilder};
+    use log::{LevelFilter, Log, Metadata, Record};
+    use std::collections::{BTreeMap, HashSet};
+    use std::ffi::{CString, OsStr};
+    use std::fs::{File, OpenOptions};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{DirEntryExt, FileExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc::{self, Receiver};
+    use std::sync::{Arc, Mutex, Once};
+    use std::{fs, panic, thread};
+    use tempfile::TempDir;
+
+    static LOGGER_INIT: Once = Once::new();
+    static TRACES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    // the logs of the tests that are never reopened
+    static KEEP_LOG: AtomicBool = AtomicBool::new(false);
+
+    // Collects the trace lines of every mount, tests filter them by their own paths
+    struct CaptureLogger;
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            // debug lines of the filesystem itself, but not of fuser
+            metadata.level() <= LevelFilter::Info || metadata.target() == "cairn_fuse"
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                TRACES.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn traces_containing(needle: &str) -> Vec<String> {
+        TRACES
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains(needle))
+            .cloned()
+            .collect()
+    }
+
+    // A filesystem mounted over a fresh temporary root, unmounted on drop
+    struct TestMount {
+        session: Option<BackgroundSession>,
+        root: TempDir,
+        mnt: TempDir,
+        _destroy: Receiver<Result<(), libc::c_int>>,
+    }
+
+    impl TestMount {
+        fn new() -> TestMount {
+            Self::with_root(|_| {})
+        }
+
+        // `prepare` populates the backing root before the filesystem is mounted
+        fn with_root<F: FnOnce(&Path)>(prepare: F) -> TestMount {
+            Self::with_fs(prepare, |fs| fs)
+        }
+
+        // `configure` enables optional behavior of the filesystem under test
+        fn with_fs<F, C>(prepare: F, configure: C) -> TestMount
+        where
+            F: FnOnce(&Path),
+            C: FnOnce(TracerFS<Box<dyn TraceSink + Send>>) -> TracerFS<Box<dyn TraceSink + Send>>,
+        {
+            let sink = LogSink {
+                format: TraceFormat::Human,
+            };
+            Self::with_sink(prepare, Box::new(sink), configure)
+        }
+
+        // `sink` receives the traces instead of the captured log
+        fn with_sink<F, C>(prepare: F, sink: Box<dyn TraceSink + Send>, configure: C) -> TestMount
+        where
+            F: FnOnce(&Path),
+            C: FnOnce(TracerFS<Box<dyn TraceSink + Send>>) -> TracerFS<Box<dyn TraceSink + Send>>,
+        {
+            let mut mount_options = vec![MountOption::FSName("cairn-fuse-test".to_string())];
+            // other users are let in where fusermount allows it, most CI sandboxes do not
+            if super::allow_other_permitted() {
+                mount_options.push(MountOption::AllowOther);
+            }
+            Self::with_options(prepare, sink, configure, mount_options)
+        }
+
+        // `mount_options` replace the defaults, which let every user in if they can
+        fn with_options<F, C>(
+            prepare: F,
+            sink: Box<dyn TraceSink + Send>,
+            configure: C,
+            mount_options: Vec<MountOption>,
+        ) -> TestMount
+        where
+            F: FnOnce(&Path),
+            C: FnOnce(TracerFS<Box<dyn TraceSink + Send>>) -> TracerFS<Box<dyn TraceSink + Send>>,
+        {
+            LOGGER_INIT.call_once(|| {
+                log::set_logger(&CaptureLogger).unwrap();
+                log::set_max_level(LevelFilter::Debug);
+            });
+
+            let root = tempfile::tempdir().unwrap();
+            let mnt = tempfile::tempdir().unwrap();
+            prepare(root.path());
+
+            let (send, recv) = std::sync::mpsc::channel();
+            let session = fuser::spawn_mount2(
+                configure(TracerFS::new(
+                    root.path().to_str().unwrap().to_string(),
+                    send,
+                    sink,
+                )),
+                mnt.path(),
+                &mount_options,
+            )
+            .unwrap();
+
+            TestMount {
+                session: Some(session),
+                root,
+                mnt,
+                _destroy: recv,
+            }
+        }
+
+        fn root(&self) -> &Path {
+            self.root.path()
+        }
+
+        fn mnt(&self) -> &Path {
+            self.mnt.path()
+        }
+    }
+
+    impl Drop for TestMount {
+        fn drop(&mut self) {
+            // unmount and wait for destroy() before the temporary directories are removed
+            if let Some(session) = self.session.take() {
+                session.join();
+            }
+        }
+    }
+
+    // The JSON events about `path`, only the test that created it touches it
+    fn events_of(path: &Path) -> Vec<serde_json::Value> {
+        traces_containing(&format!("\"path\":{:?}", path.to_str().unwrap()))
+            .iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|event| event["path"] == path.to_str().unwrap())
+            .collect()
+    }
+
+    fn json_mount() -> TestMount {
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        TestMount::with_sink(|_| {}, Box::new(sink), |fs| fs)
+    }
+
+    #[test]
+    fn init() {
+        let mount = json_mount();
+        let status = Command::new("stat")
+            .args(["-f", "-c", "%b"])
+            .arg(mount.mnt())
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let events = events_of(mount.root());
+        let statfs = events.iter().find(|event| event["op"] == "statfs").unwrap();
+        assert_eq!(statfs["kind"], "q");
+        assert_eq!(statfs["result"], "ok");
+        assert!(statfs["monotonic_us"].is_u64());
+    }
+
+    #[test]
+    fn touch() {
+        let mount = json_mount();
+        let status = Command::new("touch")
+            .arg(mount.mnt().join("touch.txt"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let events = events_of(&mount.root().join("touch.txt"));
+        let ops: Vec<_> = events
+            .iter()
+            .filter(|event| event["op"] != "lookup_miss")
+            .map(|event| (&event["op"], &event["result"]))
+            .collect();
+        assert_eq!(ops[0], (&"create".into(), &"ok".into()));
+        assert!(ops.contains(&(&"utime".into(), &"ok".into())));
+        // the timestamps of a single mount never go backwards
+        let times: Vec<_> = events
+            .iter()
+            .map(|event| event["monotonic_us"].as_u64())
+            .collect();
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn mkdir() {
+        let mount = json_mount();
+        let status = Command::new("sh")
+            .args([
+                "-c",
+                "mkdir \"$1\" && touch \"$1/file\" && ! rmdir \"$1\"",
+                "sh",
+            ])
+            .arg(mount.mnt().join("mkdir"))
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let events = events_of(&mount.root().join("mkdir"));
+        let result = |op: &str| {
+            let event = events.iter().find(|event| event["op"] == op).unwrap();
+            event["result"].clone()
+        };
+        assert_eq!(result("mkdir"), "ok");
+        assert_eq!(result("rmdir"), libc::ENOTEMPTY);
+    }
+
+    #[test]
+    fn mkdir_applies_the_mode_and_umask() {
+        let mount = TestMount::new();
+        let status = Command::new("sh")
+            .args([
+                "-c",
+                "umask 022 && mkdir -m 0700 \"$1/private\" && umask 027 && mkdir \"$1/shared\"",
+                "sh",
+            ])
+            .arg(mount.mnt())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        for (name, mode) in [("private", "700"), ("shared", "750")] {
+            assert_eq!(stat(&mount.mnt().join(name), "%a"), mode);
+            assert_eq!(stat(&mount.root().join(name), "%a"), mode);
+        }
+    }
+
+    #[test]
+    fn writes_outside_the_writable_dirs_are_denied() {
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::create_dir(root.join("out")).unwrap();
+                fs::create_dir(root.join("src")).unwrap();
+                fs::write(root.join("src/a.c"), "int a;\n").unwrap();
+            },
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.writable_dirs(vec![PathBuf::from("out")]),
+        );
+        let mnt = mount.mnt();
+
+        fs::write(mnt.join("out/a.o"), "object").unwrap();
+        assert_eq!(fs::read(mount.root().join("out/a.o")).unwrap(), b"object");
+
+        let error = fs::write(mnt.join("src/new.c"), "int b;\n").unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EROFS));
+        let error = fs::write(mnt.join("src/a.c"), "changed").unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EROFS));
+        assert!(!mount.root().join("src/new.c").exists());
+        assert_eq!(fs::read(mount.root().join("src/a.c")).unwrap(), b"int a;\n");
+        // reading is not a change
+        assert_eq!(fs::read(mnt.join("src/a.c")).unwrap(), b"int a;\n");
+
+        let denied = |path: &Path| {
+            events_of(path)
+                .iter()
+                .any(|event| event["op"] == "denied_write" && event["kind"] == "x")
+        };
+        assert!(denied(&mount.root().join("src/new.c")));
+        assert!(denied(&mount.root().join("src/a.c")));
+        assert!(!denied(&mount.root().join("out/a.o")));
+    }
+
+    #[test]
+    fn readlink_dangling_symlink_outside_root() {
+        let mount = TestMount::new();
+        let link = mount.mnt().join("dangling");
+        let target = Path::new("/nonexistent/cairn-fuse/target");
+
+        std::os::unix::fs::symlink(target, &link).unwrap();
+
+        let metadata = fs::symlink_metadata(&link).unwrap();
+        assert!(metadata.file_type().is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), target);
+        assert_eq!(
+            fs::read_link(mount.root().join("dangling")).unwrap(),
+            target
+        );
+
+        let real_link = mount.root().join("dangling");
+        let expected = format!("{}|{}", real_link.display(), target.display());
+        assert!(!traces_containing(&format!("{expected}|symlink")).is_empty());
+        assert!(!traces_containing(&format!("{expected}|readlink")).is_empty());
+    }
+
+    #[test]
+    fn removing_a_link_leaves_its_target_alone() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("target.txt"), "target").unwrap();
+            std::os::unix::fs::symlink("/nonexistent/cairn-fuse/target", root.join("dangling"))
+                .unwrap();
+            std::os::unix::fs::symlink("target.txt", root.join("link")).unwrap();
+        });
+        let mnt = mount.mnt();
+
+        // the link itself goes, whether its target exists or not
+        fs::remove_file(mnt.join("dangling")).unwrap();
+        assert!(fs::symlink_metadata(mount.root().join("dangling")).is_err());
+
+        let file = File::open(mnt.join("target.txt")).unwrap();
+        // keeps the inode around without a handle of the filesystem, its attributes are only
+        // asked for through the inode
+        let held = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH)
+            .open(mnt.join("target.txt"))
+            .unwrap();
+        fs::remove_file(mnt.join("link")).unwrap();
+        assert!(mount.root().join("target.txt").exists());
+        drop(file);
+        // the release comes after close() has returned
+        let release = format!("{}|release", mount.root().join("target.txt").display());
+        for _ in 0..100 {
+            if !traces_containing(&release).is_empty() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(50));
+        }
+        assert!(!traces_containing(&release).is_empty());
+
+        assert_eq!(held.metadata().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn hard_link_shares_inode_and_data() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("source.txt"), "").unwrap();
+        });
+        let source = mount.mnt().join("source.txt");
+        let link = mount.mnt().join("link.txt");
+
+        let output = Command::new("ln").arg(&source).arg(&link).output().unwrap();
+        assert!(output.status.success());
+
+        fs::write(&source, "written through the source").unwrap();
+        assert_eq!(
+            fs::read_to_string(&link).unwrap(),
+            "written through the source"
+        );
+
+        let source_metadata = fs::metadata(&source).unwrap();
+        let link_metadata = fs::metadata(&link).unwrap();
+        assert_eq!(source_metadata.ino(), link_metadata.ino());
+        assert_eq!(source_metadata.nlink(), 2);
+        assert_eq!(link_metadata.nlink(), 2);
+    }
+
+    #[test]
+    fn non_utf8_file_name() {
+        let mount = TestMount::new();
+        let name = OsStr::from_bytes(b"invalid-\xff\xfe-utf8.txt");
+        let path = mount.mnt().join(name);
+
+        fs::write(&path, "contents").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "contents");
+        assert_eq!(fs::metadata(&path).unwrap().len(), 8);
+        assert!(mount.root().join(name).exists());
+        assert!(fs::read_dir(mount.mnt())
+            .unwrap()
+            .any(|entry| entry.unwrap().file_name() == name));
+    }
+
+    #[test]
+    fn create_exclusive_twice() {
+        let mount = TestMount::new();
+        let path = mount.mnt().join("exclusive.txt");
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"first").unwrap();
+
+        let err = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+        drop(file);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+    }
+
+    #[test]
+    fn rename_directory_updates_descendants() {
+        let mount = TestMount::with_root(|root| {
+            fs::create_dir_all(root.join("a/b")).unwrap();
+            fs::write(root.join("a/b/c.txt"), "nested").unwrap();
+        });
+        let cat = |path: &Path| Command::new("cat").arg(path).output().unwrap();
+
+        assert_eq!(cat(&mount.mnt().join("a/b/c.txt")).stdout, b"nested");
+
+        // the open inode is not looked up again, so truncating through the held descriptor
+        // relies on the cached path
+        let status = Command::new("sh")
+            .args([
+                "-c",
+                "exec 3<>\"$1/a/b/c.txt\" && mv \"$1/a\" \"$1/x\" && truncate -s 3 /proc/self/fd/3",
+                "sh",
+            ])
+            .arg(mount.mnt())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(cat(&mount.mnt().join("x/b/c.txt")).stdout, b"nes");
+    }
+
+    fn renameat2(from: &Path, to: &Path, flags: u32) -> std::io::Result<()> {
+        let from = CString::new(from.as_os_str().as_bytes()).unwrap();
+        let to = CString::new(to.as_os_str().as_bytes()).unwrap();
+        let result = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                from.as_ptr(),
+                libc::AT_FDCWD,
+                to.as_ptr(),
+                flags,
+            )
+        };
+        match result {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    #[test]
+    fn rename_noreplace() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("a.txt"), "a").unwrap();
+            fs::write(root.join("b.txt"), "b").unwrap();
+        });
+        let mnt = mount.mnt();
+
+        let err = renameat2(
+            &mnt.join("a.txt"),
+            &mnt.join("b.txt"),
+            libc::RENAME_NOREPLACE,
+        )
+        .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+        assert_eq!(fs::read_to_string(mnt.join("a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(mnt.join("b.txt")).unwrap(), "b");
+
+        renameat2(
+            &mnt.join("a.txt"),
+            &mnt.join("c.txt"),
+            libc::RENAME_NOREPLACE,
+        )
+        .unwrap();
+        assert!(!mnt.join("a.txt").exists());
+        assert_eq!(fs::read_to_string(mnt.join("c.txt")).unwrap(), "a");
+    }
+
+    #[test]
+    fn rename_exchange() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("a.txt"), "a").unwrap();
+            fs::write(root.join("b.txt"), "b").unwrap();
+            fs::create_dir(root.join("x")).unwrap();
+            fs::write(root.join("x/inner.txt"), "nested").unwrap();
+            fs::write(root.join("y"), "file").unwrap();
+        });
+        let mnt = mount.mnt();
+
+        renameat2(
+            &mnt.join("a.txt"),
+            &mnt.join("b.txt"),
+            libc::RENAME_EXCHANGE,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(mnt.join("a.txt")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(mnt.join("b.txt")).unwrap(), "a");
+
+        // the held inode is not looked up again, truncating it by its descriptor relies on the
+        // swapped path
+        let inner = File::open(mnt.join("x/inner.txt")).unwrap();
+        renameat2(&mnt.join("x"), &mnt.join("y"), libc::RENAME_EXCHANGE).unwrap();
+        let status = Command::new("truncate")
+            .arg("-s3")
+            .arg(format!(
+                "/proc/{}/fd/{}",
+                std::process::id(),
+                inner.as_raw_fd()
+            ))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(fs::read_to_string(mnt.join("y/inner.txt")).unwrap(), "nes");
+        assert_eq!(fs::read_to_string(mnt.join("x")).unwrap(), "file");
+        assert_eq!(fs::read_to_string(mount.root().join("x")).unwrap(), "file");
+    }
+
+    // The nodeid and generation the kernel encodes into the handle of an inode of the mount
+    fn file_handle(path: &Path) -> [u32; 3] {
+        #[repr(C)]
+        struct Handle {
+            bytes: u32,
+            kind: i32,
+            f_handle: [u32; 3],
+        }
+        // identifies the inode without the filesystem having to look handles up
+        const AT_HANDLE_FID: libc::c_int = 0x200;
+
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let mut handle = Handle {
+            bytes: 12,
+            kind: 0,
+            f_handle: [0; 3],
+        };
+        let mut mount_id: libc::c_int = 0;
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_name_to_handle_at,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                &mut handle as *mut Handle,
+                &mut mount_id as *mut libc::c_int,
+                AT_HANDLE_FID,
+            )
+        };
+        assert_eq!(result, 0, "{}", std::io::Error::last_os_error());
+        handle.f_handle
+    }
+
+    #[test]
+    fn reused_inode_gets_a_new_generation() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("old.txt"), "old").unwrap();
+        });
+        let mut old = mount.mnt().join("old.txt");
+
+        // the backing filesystem hands out the number of the removed file again, usually at once
+        for i in 0..10 {
+            let before = file_handle(&old);
+            let ino = fs::metadata(&old).unwrap().ino();
+            fs::remove_file(&old).unwrap();
+            let new = mount.mnt().join(format!("new{i}.txt"));
+            fs::write(&new, "new").unwrap();
+            if fs::metadata(&new).unwrap().ino() == ino {
+                let after = file_handle(&new);
+                assert_eq!(before[..2], after[..2]);
+                assert_ne!(before[2], after[2]);
+                assert_eq!(fs::read_to_string(&new).unwrap(), "new");
+                return;
+            }
+            old = new;
+        }
+        panic!("the inode number was not reused");
+    }
+
+    #[test]
+    fn sparse_files_report_their_allocation() {
+        let mount = TestMount::new();
+        let path = mount.mnt().join("sparse.bin");
+        let backing = mount.root().join("sparse.bin");
+        let blocks = |path: &Path| fs::metadata(path).unwrap().blocks();
+
+        // a single byte a gigabyte in
+        let file = File::create(&path).unwrap();
+        file.write_at(b"x", 1 << 30).unwrap();
+        drop(file);
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.len(), (1 << 30) + 1);
+        assert_eq!(metadata.blocks(), blocks(&backing));
+        assert!(metadata.blocks() < 1024, "{} blocks", metadata.blocks());
+
+        let fallocate = |args: &[&str]| {
+            let status = Command::new("fallocate")
+                .args(args)
+                .arg(&path)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            blocks(&path)
+        };
+        // allocated without growing, then a hole punched into it again
+        let allocated = fallocate(&["--keep-size", "-l", "8M"]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), (1 << 30) + 1);
+        assert!(allocated >= 8 << 11, "{allocated} blocks");
+        assert_eq!(allocated, blocks(&backing));
+        let punched = fallocate(&["--punch-hole", "-l", "4M"]);
+        assert!(punched < allocated, "{punched} blocks");
+        assert_eq!(punched, blocks(&backing));
+
+        let status = Command::new("truncate")
+            .args(["-s", "1M"])
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(blocks(&path), blocks(&backing));
+        assert!(blocks(&path) <= punched);
+    }
+
+    #[test]
+    fn concurrent_appenders() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("append.log"), "").unwrap();
+        });
+        let path = mount.mnt().join("append.log");
+        let script = "exec 3>>\"$1\"; for i in $(seq 100 199); do echo \"$2$i\" >&3; done";
+
+        let appenders: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|tag| {
+                Command::new("sh")
+                    .args(["-c", script, "sh"])
+                    .arg(&path)
+                    .arg(tag)
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        // an append behind the mount's back must not be overwritten either
+        OpenOptions::new()
+            .append(true)
+            .open(mount.root().join("append.log"))
+            .unwrap()
+            .write_all(b"c100\n")
+            .unwrap();
+        for mut appender in appenders {
+            assert!(appender.wait().unwrap().success());
+        }
+
+        let contents = fs::read_to_string(mount.root().join("append.log")).unwrap();
+        assert_eq!(contents.len(), 201 * 5);
+        for tag in ["a", "b"] {
+            for i in 100..200 {
+                assert!(contents.contains(&format!("{tag}{i}\n")));
+            }
+        }
+        assert!(contents.contains("c100\n"));
+    }
+
+    #[test]
+    fn unlink_while_open() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("deleted.txt"), "still readable").unwrap();
+        });
+        let path = mount.mnt().join("deleted.txt");
+
+        let output = Command::new("sh")
+            .args([
+                "-c",
+                "exec 3<\"$1\"; rm \"$1\" && cat <&3 && cat \"$1\"",
+                "sh",
+            ])
+            .arg(&path)
+            .output()
+            .unwrap();
+
+        assert_eq!(output.stdout, b"still readable");
+        assert!(!output.status.success());
+        assert!(!mount.root().join("deleted.txt").exists());
+        assert!(!traces_containing("deleted.txt|unlink").is_empty());
+    }
+
+    #[test]
+    fn truncate_updates_cached_size() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("truncated.txt"), "some contents").unwrap();
+        });
+
+        let output = Command::new("sh")
+            .args(["-c", "truncate -s 0 \"$1\" && stat -c %s \"$1\"", "sh"])
+            .arg(mount.mnt().join("truncated.txt"))
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"0\n");
+    }
+
+    #[test]
+    fn open_truncates_the_file() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("truncated.txt"), "some contents").unwrap();
+        });
+        let path = mount.mnt().join("truncated.txt");
+        assert_eq!(fs::metadata(&path).unwrap().len(), 13);
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        drop(file);
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        assert_eq!(fs::read(&path).unwrap(), b"");
+    }
+
+    #[test]
+    fn fsync_file_and_directory() {
+        let mount = TestMount::new();
+
+        let mut file = fs::File::create(mount.mnt().join("synced.txt")).unwrap();
+        file.write_all(b"durable").unwrap();
+        file.sync_data().unwrap();
+        file.sync_all().unwrap();
+        drop(file);
+
+        fs::File::open(mount.mnt()).unwrap().sync_all().unwrap();
+        assert_eq!(
+            fs::read_to_string(mount.root().join("synced.txt")).unwrap(),
+            "durable"
+        );
+    }
+
+    #[test]
+    fn forgotten_inodes_are_evicted() {
+        let mount = TestMount::new();
+        let create = "mkdir \"$1\" && for i in $(seq 10000); do : > \"$1/$i\"; done";
+        let remove = "cd \"$1\" && seq 10000 | xargs rm && cd .. && rmdir \"$1\"";
+
+        // created and removed through the mount
+        let status = Command::new("sh")
+            .args(["-c", &format!("{create} && {remove}"), "sh"])
+            .arg(mount.mnt().join("through"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // created and removed behind the mount's back, only looked up through it
+        let status = Command::new("sh")
+            .args(["-c", create, "sh"])
+            .arg(mount.root().join("behind"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let status = Command::new("sh")
+            .args([
+                "-c",
+                "cd \"$1\" && seq 10000 | xargs stat > /dev/null",
+                "sh",
+            ])
+            .arg(mount.mnt().join("behind"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::remove_dir_all(mount.root().join("behind")).unwrap();
+
+        // the failed lookup makes the kernel drop the directory and forget what it held
+        assert!(!mount.mnt().join("behind").exists());
+        thread::sleep(std::time::Duration::from_millis(500));
+
+        let root = mount.root().display().to_string();
+        drop(mount);
+
+        let destroyed = traces_containing(&format!("destroy(root={root}, "));
+        let counts = destroyed[0].trim_end_matches(')').split(", ").skip(1);
+        for count in counts {
+            let (name, count) = count.split_once('=').unwrap();
+            let count: usize = count.parse().unwrap();
+            assert!(count < 100, "{count} inodes are still {name}");
+        }
+    }
+
+    #[test]
+    fn close_after_write_succeeds() {
+        let mount = TestMount::new();
+        let file = mount.mnt().join("closed.txt");
+
+        // `exec 4>&3` dups the descriptor, so every close flushes the same handle
+        let status = Command::new("sh")
+            .args([
+                "-c",
+                "exec 3>\"$1\" && exec 4>&3 && echo hello >&3 && exec 4>&- && exec 3>&-",
+                "sh",
+            ])
+            .arg(&file)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // tee exits non-zero if closing its output file fails
+        let status = Command::new("sh")
+            .args(["-c", "echo hello | tee \"$1\" > /dev/null", "sh"])
+            .arg(&file)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(
+            fs::read(mount.root().join("closed.txt")).unwrap(),
+            b"hello\n"
+        );
+        assert!(!traces_containing("flush(").is_empty());
+    }
+
+    #[test]
+    fn release_closes_the_backing_file() {
+        let mount = json_mount();
+        let path = mount.root().join("released.txt");
+        let backing_fds = || {
+            fs::read_dir("/proc/self/fd")
+                .unwrap()
+                .filter_map(|entry| fs::read_link(entry.unwrap().path()).ok())
+                .filter(|target| *target == path)
+                .count()
+        };
+
+        let mut file = File::create(mount.mnt().join("released.txt")).unwrap();
+        file.write_all(b"durable").unwrap();
+        assert_eq!(backing_fds(), 1);
+        drop(file);
+
+        // the kernel sends the release once the file is closed, not before close() returns
+        let released = || {
+            events_of(&path)
+                .into_iter()
+                .find(|event| event["op"] == "release")
+        };
+        let start = std::time::Instant::now();
+        while released().is_none() && start.elapsed() < std::time::Duration::from_secs(5) {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(released().unwrap()["result"], "ok");
+        assert_eq!(backing_fds(), 0);
+        assert_eq!(fs::read(&path).unwrap(), b"durable");
+    }
+
+    #[test]
+    fn getattr_sees_backing_changes() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("grown.txt"), "abc").unwrap();
+        });
+        let size = |path: &Path| {
+            let output = Command::new("stat")
+                .args(["-c", "%s"])
+                .arg(path)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+
+        assert_eq!(size(&mount.mnt().join("grown.txt")), "3");
+        OpenOptions::new()
+            .append(true)
+            .open(mount.root().join("grown.txt"))
+            .unwrap()
+            .write_all(b"defgh")
+            .unwrap();
+        assert_eq!(size(&mount.mnt().join("grown.txt")), "8");
+    }
+
+    #[test]
+    fn lseek_finds_holes() {
+        let mount = TestMount::with_root(|root| {
+            let file = fs::File::create(root.join("sparse.bin")).unwrap();
+            file.write_all_at(&[1; 4096], 0).unwrap();
+            file.set_len(1 << 20).unwrap();
+        });
+        // SEEK_DATA = 3, SEEK_HOLE = 4
+        let seek = |offset: u64, whence: u8| {
+            let output = Command::new("perl")
+                .args([
+                    "-e",
+                    "open(F, '<', $ARGV[0]) or die; print sysseek(F, $ARGV[1], $ARGV[2]) // $!+0",
+                ])
+                .arg(mount.mnt().join("sparse.bin"))
+                .arg(offset.to_string())
+                .arg(whence.to_string())
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        let hole: u64 = seek(0, 4).trim_start_matches("0 but true").parse().unwrap();
+        assert!((4096..1 << 20).contains(&hole), "hole at {hole}");
+        assert_eq!(seek(0, 3), "0 but true");
+        assert_eq!(seek(2 << 20, 3), libc::ENXIO.to_string());
+    }
+
+    #[test]
+    fn seek_data_skips_a_gigabyte_hole() {
+        let mount = TestMount::with_root(|root| {
+            let file = fs::File::create(root.join("sparse.bin")).unwrap();
+            file.write_all_at(&[1; 4096], 1 << 29).unwrap();
+            file.set_len(1 << 30).unwrap();
+        });
+        let file = mount.mnt().join("sparse.bin");
+        let ino = fs::metadata(&file).unwrap().ino();
+
+        let output = Command::new("perl")
+            .args([
+                "-e",
+                "open(F, '<', $ARGV[0]) or die; print $$, ' ', sysseek(F, 0, 3) // $!+0",
+            ])
+            .arg(&file)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let (pid, offset) = stdout.split_once(' ').unwrap();
+        assert_eq!(offset, (1 << 29).to_string());
+        // inode numbers of files removed by other tests come back, the pid tells them apart
+        let calls = traces_containing(&format!("ino={ino}, fh="));
+        let calls: Vec<_> = calls
+            .iter()
+            .filter(|line| line.contains(&format!("pid={pid}, comm=perl, ")))
+            .collect();
+        assert!(calls.iter().any(|line| line.starts_with("lseek(")));
+        assert!(calls.iter().all(|line| !line.starts_with("read(")));
+    }
+
+    fn stat(path: &Path, format: &str) -> String {
+        let output = Command::new("stat")
+            .env("TZ", "UTC")
+            .args(["-c", format])
+            .arg(path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn cp_preserves_attributes() {
+        let mount = TestMount::new();
+        let source = mount.root().join("source.txt");
+        fs::write(&source, "preserved").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o640)).unwrap();
+        let status = Command::new("touch")
+            .args(["-d", "2001-02-03 04:05:06"])
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let copy = mount.mnt().join("copy.txt");
+        let status = Command::new("cp")
+            .arg("-p")
+            .arg(&source)
+            .arg(&copy)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(stat(&copy, "%a %Y %s"), stat(&source, "%a %Y %s"));
+    }
+
+    #[test]
+    fn tar_extract_preserves_attributes() {
+        let mount = TestMount::new();
+        let staging = TempDir::new().unwrap();
+        let source = staging.path().join("archived.txt");
+        fs::write(&source, "archived").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o604)).unwrap();
+        let status = Command::new("touch")
+            .args(["-d", "1999-12-31 23:59:59"])
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let archive = staging.path().join("archive.tar");
+        let status = Command::new("tar")
+            .arg("-cf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(staging.path())
+            .arg("archived.txt")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let status = Command::new("tar")
+            .arg("-xpf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(mount.mnt())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(
+            stat(&mount.mnt().join("archived.txt"), "%a %Y %s"),
+            stat(&source, "%a %Y %s")
+        );
+    }
+
+    #[test]
+    fn single_setattr_sets_both_times() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("touched.txt"), "").unwrap();
+        });
+        let path = mount.mnt().join("touched.txt");
+
+        // atime and mtime arrive in a single setattr() call
+        let status = Command::new("touch")
+            .args(["-d", "@981173106"])
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(stat(&path, "%X %Y"), "981173106 981173106");
+        assert_eq!(
+            stat(&mount.root().join("touched.txt"), "%X %Y"),
+            "981173106 981173106"
+        );
+    }
+
+    #[test]
+    fn log_file_is_checked_before_mounting() {
+        let dir = TempDir::new().unwrap();
+        let mnt = dir.path().join("mnt");
+        fs::create_dir(&mnt).unwrap();
+        let target =
+            |path: &Path| super::log_target(Some(path), mnt.to_str().unwrap(), None, &KEEP_LOG);
+
+        let nested = dir.path().join("logs/today/tracer.log");
+        assert!(target(&nested).is_ok());
+        assert!(nested.exists());
+
+        std::os::unix::fs::symlink(&mnt, dir.path().join("link")).unwrap();
+        for inside in [
+            mnt.join("tracer.log"),
+            dir.path().join("link/logs/tracer.log"),
+        ] {
+            let error = target(&inside).err().unwrap();
+            assert!(error.contains("is inside the mountpoint"), "{error}");
+        }
+        assert!(!mnt.join("logs").exists());
+
+        let error = target(Path::new("/proc/tracer.log")).err().unwrap();
+        assert!(
+            error.starts_with("cannot write the log '/proc/tracer.log': "),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn logs_rotate_between_records() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("tracer.log");
+        let matches = super::cli().get_matches_from([
+            "cairn-fuse",
+            "/srv/root",
+            "/mnt",
+            "--log-max-size",
+            "1000",
+            "--log-keep",
+            "3",
+        ]);
+        let rotation = super::log_rotation(&matches);
+        assert_eq!(rotation, Some((1000, 3)));
+        let log = match super::log_target(Some(&path), "/mnt", rotation, &KEEP_LOG).unwrap() {
+            env_logger::Target::Pipe(pipe) => Arc::new(Mutex::new(pipe)),
+            _ => panic!("expected a log file"),
+        };
+
+        // like the FUSE callbacks the threads share the logger, one record per write
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let log = log.clone();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        let record = serde_json::json!({"thread": thread, "record": i});
+                        writeln!(log.lock().unwrap(), "{}", record).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut files = vec![path.clone()];
+        files.extend((1..=3).map(|n| dir.path().join(format!("tracer.log.{n}"))));
+        for file in &files {
+            let contents = fs::read_to_string(file).unwrap();
+            assert!(contents.len() <= 1000, "{}", file.display());
+            assert!(contents.ends_with('\n'));
+            for line in contents.lines() {
+                let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                assert!(record["record"].is_u64());
+            }
+        }
+        assert!(!dir.path().join("tracer.log.4").exists());
+
+        // nothing is lost or reordered at a rollover, the records of a thread follow each other
+        // from the oldest file to the current one
+        let records: Vec<serde_json::Value> = files
+            .iter()
+            .rev()
+            .flat_map(|file| {
+                let contents = fs::read_to_string(file).unwrap();
+                contents
+                    .lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        for thread in 0..4 {
+            let numbers: Vec<u64> = records
+                .iter()
+                .filter(|record| record["thread"] == thread)
+                .map(|record| record["record"].as_u64().unwrap())
+                .collect();
+            assert!(numbers.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        }
+    }
+
+    #[test]
+    fn log_flag_selects_the_trace_file() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_str().unwrap();
+        let target = |args: &[&str]| {
+            let matches =
+                super::cli().get_matches_from([&["cairn-fuse", root, "/mnt"], args].concat());
+            let log = super::log_path(&matches, root);
+            super::log_target(log.as_deref(), "/mnt", None, &KEEP_LOG).unwrap()
+        };
+
+        assert!(matches!(target(&[]), env_logger::Target::Pipe(_)));
+        assert!(dir.path().join("tracer.log").exists());
+
+        let first = dir.path().join("first.log");
+        let second = dir.path().join("second.log");
+        for log in [&first, &second] {
+            match target(&["--log", log.to_str().unwrap()]) {
+                env_logger::Target::Pipe(mut pipe) => writeln!(pipe, "{}", log.display()).unwrap(),
+                _ => panic!("expected a log file"),
+            }
+        }
+        assert_eq!(
+            fs::read_to_string(&first).unwrap(),
+            format!("{}\n", first.display())
+        );
+        assert_eq!(
+            fs::read_to_string(&second).unwrap(),
+            format!("{}\n", second.display())
+        );
+
+        assert!(matches!(
+            target(&["--log", "-"]),
+            env_logger::Target::Stderr
+        ));
+    }
+
+    #[test]
+    fn utimens_keeps_nanoseconds() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("precise.txt"), "").unwrap();
+        });
+        let path = mount.mnt().join("precise.txt");
+
+        let status = Command::new("touch")
+            .args(["-m", "-d", "2021-01-01 00:00:00.123456789 UTC"])
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(stat(&path, "%y"), "2021-01-01 00:00:00.123456789 +0000");
+
+        // only the access time is touched, the modification time must be left alone
+        let status = Command::new("touch")
+            .args(["-a", "-d", "2022-02-02 00:00:00.5 UTC"])
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(stat(&path, "%x"), "2022-02-02 00:00:00.500000000 +0000");
+        assert_eq!(stat(&path, "%y"), "2021-01-01 00:00:00.123456789 +0000");
+    }
+
+    #[test]
+    fn verbosity_flags_select_the_level() {
+        let level = |args: &[&str]| {
+            let matches =
+                super::cli().get_matches_from([&["cairn-fuse", "/root", "/mnt"], args].concat());
+            super::level_filter(&matches)
+        };
+
+        assert_eq!(level(&[]), LevelFilter::Info);
+        assert_eq!(level(&["-v"]), LevelFilter::Debug);
+        assert_eq!(level(&["-vv"]), LevelFilter::Trace);
+        assert_eq!(level(&["-vvv"]), LevelFilter::Trace);
+        assert_eq!(level(&["--log-level", "warn"]), LevelFilter::Warn);
+        assert!(super::cli()
+            .try_get_matches_from(["cairn-fuse", "/root", "/mnt", "-v", "--log-level", "warn"])
+            .is_err());
+    }
+
+    #[test]
+    fn chmod_changes_ctime_only() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("changed.txt"), "").unwrap();
+        });
+        let path = mount.mnt().join("changed.txt");
+        let before = stat(&path, "%z|%y");
+
+        let status = Command::new("chmod")
+            .arg("600")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let after = stat(&path, "%z|%y");
+        let (ctime_before, mtime_before) = before.split_once('|').unwrap();
+        let (ctime_after, mtime_after) = after.split_once('|').unwrap();
+        assert_ne!(ctime_after, ctime_before);
+        assert_eq!(mtime_after, mtime_before);
+    }
+
+    #[test]
+    fn operations_record_the_calling_process() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("attributed.txt"), "mine").unwrap();
+        });
+
+        let mut child = Command::new("cat")
+            .arg(mount.mnt().join("attributed.txt"))
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        assert!(child.wait().unwrap().success());
+
+        let caller = format!("pid={pid}, comm=cat");
+        assert!(!traces_containing(&format!("open({caller}, ")).is_empty());
+        assert!(!traces_containing(&format!("read({caller}, ")).is_empty());
+    }
+
+    #[test]
+    fn json_traces_name_the_calling_process() {
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::set_permissions(root, fs::Permissions::from_mode(0o755)).unwrap();
+                fs::write(root.join("attributed.txt"), "theirs").unwrap();
+            },
+            Box::new(sink),
+            |fs| fs,
+        );
+        let file = mount.mnt().join("attributed.txt");
+
+        let output = Command::new("setpriv")
+            .args(["--reuid=65534", "--regid=65534", "--clear-groups", "cat"])
+            .arg(&file)
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"theirs");
+
+        let path = mount.root().join("attributed.txt");
+        let open = traces_containing("\"op\":\"open\"")
+            .iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|event| event["path"] == path.to_str().unwrap())
+            .unwrap();
+        assert_eq!(
+            (open["uid"].as_u64(), open["gid"].as_u64()),
+            (Some(65534), Some(65534))
+        );
+        assert_eq!(open["comm"], "cat");
+        assert_eq!(
+            open["cmdline"],
+            serde_json::json!(["cat", file.to_str().unwrap()])
+        );
+    }
+
+    #[test]
+    fn setuid_bit_survives_copy() {
+        let mount = TestMount::new();
+        let source = mount.root().join("setuid.bin");
+        fs::write(&source, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o4755)).unwrap();
+
+        let copy = mount.mnt().join("copy.bin");
+        let status = Command::new("cp")
+            .arg("-p")
+            .arg(&source)
+            .arg(&copy)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(stat(&copy, "%a %A %F"), "4755 -rwsr-xr-x regular file");
+        assert_eq!(stat(&mount.root().join("copy.bin"), "%a"), "4755");
+
+        let status = Command::new("chmod")
+            .arg("1755")
+            .arg(&copy)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(stat(&copy, "%a %F"), "1755 regular file");
+    }
+
+    #[test]
+    fn json_trace_lines_parse() {
+        let read = super::TraceEvent {
+            ino: Some(42),
+            offset: Some(4096),
+            size: Some(512),
+            ..super::TraceEvent::new(
+                std::process::id(),
+                'r',
+                TraceOp::Read,
+                "/root/a \"quoted\"\n.txt",
+            )
+        };
+        let renamed = super::TraceEvent {
+            target: Some("/root/new.txt".to_string()),
+            ..super::TraceEvent::new(std::process::id(), 'm', TraceOp::Rename, "/root/old.txt")
+        };
+        let failed = super::TraceEvent {
+            result: Err(libc::ENOENT),
+            ..super::TraceEvent::new(std::process::id(), 'r', TraceOp::Open, "/root/missing.txt")
+        };
+
+        let line = read.render(super::TraceFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["op"], "read");
+        assert_eq!(json["kind"], "r");
+        assert_eq!(json["ino"], 42);
+        assert_eq!(json["pid"], std::process::id());
+        assert_eq!(json["ppid"], std::os::unix::process::parent_id());
+        assert_eq!(json["path"], "/root/a \"quoted\"\n.txt");
+        assert_eq!(json["offset"], 4096);
+        assert_eq!(json["size"], 512);
+        assert_eq!(json["result"], "ok");
+
+        let line = renamed.render(super::TraceFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["target"], "/root/new.txt");
+        assert!(json.get("offset").is_none());
+
+        let line = failed.render(super::TraceFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["result"], libc::ENOENT);
+
+        // the human readable format stays what cairn-cli parses
+        assert_eq!(read.render(super::TraceFormat::Human), None);
+        assert_eq!(failed.render(super::TraceFormat::Human), None);
+        assert!(renamed
+            .render(super::TraceFormat::Human)
+            .unwrap()
+            .ends_with("|m|/root/old.txt|/root/new.txt|rename"));
+    }
+
+    #[test]
+    fn open_checks_the_callers_permissions() {
+        let mount = TestMount::with_root(|root| {
+            fs::set_permissions(root, fs::Permissions::from_mode(0o755)).unwrap();
+            fs::write(root.join("private.txt"), "secret").unwrap();
+            fs::set_permissions(root.join("private.txt"), fs::Permissions::from_mode(0o600))
+                .unwrap();
+            fs::write(root.join("public.txt"), "hello").unwrap();
+            fs::set_permissions(root.join("public.txt"), fs::Permissions::from_mode(0o644))
+                .unwrap();
+        });
+        let as_nobody = |script: &str| {
+            Command::new("setpriv")
+                .args([
+                    "--reuid=65534",
+                    "--regid=65534",
+                    "--clear-groups",
+                    "sh",
+                    "-c",
+                ])
+                .arg(script)
+                .arg("sh")
+                .arg(mount.mnt())
+                .output()
+                .unwrap()
+        };
+
+        let output = as_nobody("cat \"$1/private.txt\"");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Permission denied"));
+
+        let output = as_nobody("echo overwritten >> \"$1/public.txt\"");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Permission denied"));
+
+        let output = as_nobody("cat \"$1/public.txt\"");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello");
+        assert_eq!(fs::read(mount.root().join("public.txt")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn dependencies_are_split_into_inputs_and_outputs() {
+        let mount = TestMount::with_fs(
+            |root| fs::write(root.join("a.txt"), "input").unwrap(),
+            |fs| fs.track_dependencies(),
+        );
+
+        let child = Command::new("sh")
+            .args(["-c", "cat \"$1/a.txt\" > \"$1/b.txt\"", "sh"])
+            .arg(mount.mnt())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        assert!(child.wait_with_output().unwrap().status.success());
+
+        let a = mount.root().join("a.txt").display().to_string();
+        let b = mount.root().join("b.txt").display().to_string();
+        let root = mount.root().display().to_string();
+        drop(mount);
+
+        // the redirection is opened by the shell, the read is done by cat as a child process
+        let manifests: Vec<serde_json::Value> = traces_containing("\"inputs\"")
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|manifest: &serde_json::Value| {
+                let paths = [&manifest["inputs"], &manifest["outputs"]];
+                paths.iter().any(|paths| {
+                    paths
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .any(|path| path.as_str().unwrap().starts_with(&root))
+                })
+            })
+            .collect();
+        let contains = |key: &str, path: &str| {
+            manifests
+                .iter()
+                .any(|manifest| manifest[key].as_array().unwrap().iter().any(|p| p == path))
+        };
+
+        assert!(contains("inputs", &a));
+        assert!(contains("outputs", &b));
+        assert!(!contains("outputs", &a));
+        assert!(manifests.iter().any(|manifest| manifest["pid"] == pid));
+    }
+
+    #[test]
+    fn mount_errors_name_the_problem() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing");
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let error = |path: &Path, errno: i32| {
+            super::mount_error(
+                path.to_str().unwrap(),
+                &[],
+                &std::io::Error::from_raw_os_error(errno),
+            )
+        };
+
+        assert_eq!(
+            error(&missing, libc::ENOENT),
+            format!("mountpoint '{}' does not exist", missing.display())
+        );
+        assert_eq!(
+            error(&file, libc::ENOTDIR),
+            format!("mountpoint '{}' is not a directory", file.display())
+        );
+        assert!(error(dir.path(), libc::EPERM).starts_with("permission denied mounting"));
+
+        let mount = TestMount::new();
+        assert_eq!(
+            error(mount.mnt(), libc::EINVAL),
+            format!("mountpoint '{}' is already mounted", mount.mnt().display())
+        );
+
+        // fusermount refuses allow_other without user_allow_other, root is never refused
+        let shared = super::mount_error(
+            dir.path().to_str().unwrap(),
+            &[MountOption::AllowOther],
+            &std::io::Error::from_raw_os_error(libc::EPERM),
+        );
+        if super::allow_other_permitted() {
+            assert!(shared.starts_with("permission denied mounting"), "{shared}");
+        } else {
+            assert!(
+                shared.contains("add `user_allow_other` to /etc/fuse.conf"),
+                "{shared}"
+            );
+        }
+    }
+
+    #[test]
+    fn overlapping_paths_are_refused() {
+        let dir = TempDir::new().unwrap();
+        let (root, mnt) = (dir.path().join("root"), dir.path().join("mnt"));
+        fs::create_dir_all(root.join("mnt")).unwrap();
+        fs::create_dir(&mnt).unwrap();
+        let overlaps = |root: &Path, mnt: &Path, own_files: &[PathBuf]| {
+            super::overlaps(root.to_str().unwrap(), mnt.to_str().unwrap(), own_files)
+        };
+
+        assert_eq!(overlaps(&root, &mnt, &[root.join("tracer.log")]), Ok(()));
+        assert_eq!(
+            overlaps(&mnt.join("root"), &mnt, &[]),
+            Err(format!(
+                "root '{}' is inside the mountpoint '{}'",
+                mnt.join("root").display(),
+                mnt.display()
+            ))
+        );
+        assert_eq!(
+            overlaps(&root, &root.join("mnt"), &[]),
+            Err(format!(
+                "mountpoint '{}' is inside the root '{}'",
+                root.join("mnt").display(),
+                root.display()
+            ))
+        );
+        assert!(overlaps(&root, &root, &[]).is_err());
+
+        // through a symlink, and below a directory the mountpoint does not have yet
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&mnt, &link).unwrap();
+        let log = link.join("logs/tracer.log");
+        let error = format!("'{}' is inside the mountpoint", log.display());
+        assert!(overlaps(&root, &mnt, &[log])
+            .unwrap_err()
+            .starts_with(&error));
+    }
+
+    #[test]
+    fn ready_flags_replace_the_default_sentinel() {
+        let ready_file = |args: &[&str]| {
+            let matches =
+                super::cli().get_matches_from([&["cairn-fuse", "/root", "/mnt"], args].concat());
+            super::ready_file(&matches)
+        };
+
+        assert_eq!(ready_file(&[]), Some(".cairn-fuse-ready".into()));
+        assert_eq!(ready_file(&["--ready-fd", "3"]), None);
+        assert_eq!(
+            ready_file(&["--ready-fd", "3", "--ready-file", "/run/ready"]),
+            Some("/run/ready".into())
+        );
+        assert!(super::cli()
+            .try_get_matches_from(["cairn-fuse", "/root", "/mnt", "--ready-fd", "-1"])
+            .is_err());
+        assert!(super::inherited_fd(1 << 20).is_err());
+    }
+
+    #[test]
+    fn ready_fd_is_signalled_once_mounted() {
+        let mut fds = [0; 2];
+        // the processes spawned by other tests must not keep the write end open
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+        let writer = super::inherited_fd(fds[1]).unwrap();
+        let mount = TestMount::with_fs(
+            |root| fs::write(root.join("ready.txt"), "up").unwrap(),
+            |fs| fs.ready_fd(writer),
+        );
+
+        let mut signal = Vec::new();
+        reader.read_to_end(&mut signal).unwrap();
+        assert_eq!(signal, b"\n");
+        assert_eq!(
+            fs::read_to_string(mount.mnt().join("ready.txt")).unwrap(),
+            "up"
+        );
+    }
+
+    #[test]
+    fn trace_ops_parse_as_a_set() {
+        let ops = |list: &str| list.parse::<TraceOps>();
+
+        assert_eq!(ops("all"), Ok(TraceOps::ALL));
+        let some = ops("write, read,write").unwrap();
+        assert!(some.contains(TraceOp::Read) && some.contains(TraceOp::Write));
+        assert!(!some.contains(TraceOp::Open));
+        assert_eq!(some.to_string(), "read,write");
+        assert_eq!(ops(&TraceOps::ALL.to_string()), Ok(TraceOps::ALL));
+        let hits = ops("all,-lookup_miss").unwrap();
+        assert!(hits.contains(TraceOp::Open) && !hits.contains(TraceOp::LookupMiss));
+        assert_eq!(ops(&hits.to_string()), Ok(hits));
+        assert_eq!(ops("read,-read").unwrap().to_string(), "");
+        assert_eq!(
+            ops("read,getattr"),
+            Err("unknown operation `getattr`".to_string())
+        );
+        assert!(super::cli()
+            .try_get_matches_from(["cairn-fuse", "/root", "/mnt", "--trace-ops", "lookup"])
+            .is_err());
+    }
+
+    #[test]
+    fn failed_lookups_are_traced() {
+        let mount = json_mount();
+        let cat = Command::new("cat")
+            .arg(mount.mnt().join("nonexistent.h"))
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = cat.id();
+        assert!(!cat.wait_with_output().unwrap().status.success());
+
+        let events = events_of(&mount.root().join("nonexistent.h"));
+        let miss = events
+            .iter()
+            .find(|event| event["op"] == "lookup_miss")
+            .unwrap();
+        assert_eq!(miss["result"], libc::ENOENT);
+        assert_eq!(miss["pid"], pid);
+
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        let ops = "all,-lookup_miss".parse().unwrap();
+        let mount = TestMount::with_sink(|_| {}, Box::new(sink), |fs| fs.trace_ops(ops));
+        assert!(!mount.mnt().join("nonexistent.h").exists());
+        assert!(events_of(&mount.root().join("nonexistent.h")).is_empty());
+    }
+
+    #[test]
+    fn trace_ops_filter_the_recorded_events() {
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        let ops = "create,unlink".parse().unwrap();
+        let mount = TestMount::with_sink(|_| {}, Box::new(sink), |fs| fs.trace_ops(ops));
+        let file = mount.mnt().join("filtered.txt");
+
+        fs::write(&file, "left out").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "left out");
+        fs::remove_file(&file).unwrap();
+
+        let events = events_of(&mount.root().join("filtered.txt"));
+        let recorded: Vec<&str> = events
+            .iter()
+            .map(|event| event["op"].as_str().unwrap())
+            .collect();
+        assert_eq!(recorded, ["create", "unlink"]);
+        assert!(!traces_containing(r#"{"header":{"trace_ops":"create,unlink"}}"#).is_empty());
+    }
+
+    #[test]
+    fn trace_excludes_cover_the_own_files() {
+        let matches = super::cli().get_matches_from([
+            "cairn-fuse",
+            "/srv/root",
+            "/mnt",
+            "--trace-exclude",
+            ".git/**",
+            "--trace-dest",
+            "file:/srv/root/logs/trace[1].json",
+            "--trace-socket",
+            "/srv/root/live.sock",
+        ]);
+        let ready = Path::new("/srv/root/.cairn-fuse-ready");
+        let own_files = super::own_files(&matches, "/srv/root", Some(ready));
+        let excludes = super::trace_excludes(&matches, "/srv/root", &own_files).unwrap();
+
+        for path in [
+            ".git/HEAD",
+            ".cairn-fuse-ready",
+            "tracer.log",
+            "logs/trace[1].json",
+            "live.sock",
+        ] {
+            assert!(excludes.is_match(path), "{}", path);
+        }
+        assert!(!excludes.is_match("logs/trace1.json"));
+        assert!(!excludes.is_match("src/main.c"));
+    }
+
+    #[test]
+    fn include_flag_traces_only_matching_paths() {
+        let matches =
+            super::cli().get_matches_from(["cairn-fuse", "/srv", "/mnt", "--include", "*.c"]);
+        let include = super::trace_includes(&matches).unwrap().unwrap();
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::create_dir(root.join("src")).unwrap();
+                for name in ["src/main.c", "src/main.h", "notes.txt"] {
+                    fs::write(root.join(name), name).unwrap();
+                }
+            },
+            Box::new(sink),
+            |fs| fs.trace_include(include),
+        );
+
+        for name in ["src/main.c", "src/main.h", "notes.txt"] {
+            assert_eq!(fs::read_to_string(mount.mnt().join(name)).unwrap(), name);
+        }
+        assert!(!events_of(&mount.root().join("src/main.c")).is_empty());
+        assert!(events_of(&mount.root().join("src/main.h")).is_empty());
+        assert!(events_of(&mount.root().join("notes.txt")).is_empty());
+    }
+
+    #[test]
+    fn trace_globs_select_the_traced_paths() {
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        let globs = |patterns: &[&str]| {
+            let mut globs = GlobSetBuilder::new();
+            for pattern in patterns {
+                globs.add(Glob::new(pattern).unwrap());
+            }
+            globs.build().unwrap()
+        };
+        let mount = TestMount::with_sink(
+            |root| {
+                for dir in [".git", "src/__pycache__", "src/gen"] {
+                    fs::create_dir_all(root.join(dir)).unwrap();
+                }
+            },
+            Box::new(sink),
+            |fs| fs.trace_exclude(globs(&[".git/**", "**/__pycache__/**"])),
+        );
+
+        for path in [".git/HEAD", "src/__pycache__/main.pyc", "src/main.py"] {
+            fs::write(mount.mnt().join(path), "excluded or not").unwrap();
+            assert!(mount.root().join(path).exists());
+        }
+        assert!(events_of(&mount.root().join(".git/HEAD")).is_empty());
+        assert!(events_of(&mount.root().join("src/__pycache__/main.pyc")).is_empty());
+        assert!(!events_of(&mount.root().join("src/main.py")).is_empty());
+        drop(mount);
+
+        // the allowlist wins over the excludes
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        let mount = TestMount::with_sink(
+            |root| fs::create_dir_all(root.join("src/gen")).unwrap(),
+            Box::new(sink),
+            |fs| {
+                fs.trace_exclude(globs(&["src/gen/**"]))
+                    .trace_include(globs(&["src/**"]))
+            },
+        );
+        for path in ["src/gen/parser.c", "notes.txt"] {
+            fs::write(mount.mnt().join(path), "included or not").unwrap();
+        }
+        assert!(!events_of(&mount.root().join("src/gen/parser.c")).is_empty());
+        assert!(events_of(&mount.root().join("notes.txt")).is_empty());
+    }
+
+    #[test]
+    fn manifest_flag_defaults_to_the_root() {
+        let manifest = |args: &[&str]| {
+            let matches =
+                super::cli().get_matches_from([&["cairn-fuse", "/root", "/mnt"], args].concat());
+            super::manifest_path(&matches, "/root")
+        };
+
+        assert_eq!(manifest(&[]), None);
+        assert_eq!(
+            manifest(&["--manifest"]),
+            Some(Path::new("/root/.cairn-manifest.json").to_path_buf())
+        );
+        assert_eq!(
+            manifest(&["--manifest", "/tmp/deps.json"]),
+            Some(Path::new("/tmp/deps.json").to_path_buf())
+        );
+    }
+
+    // Runs `script` in the mount and returns the manifest, with the paths relative to the root
+    fn session_manifest<F: FnOnce(&Path)>(prepare: F, script: &str) -> serde_json::Value {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        let manifest = path.clone();
+        let mount = TestMount::with_fs(prepare, |fs| fs.manifest(manifest));
+
+        let status = Command::new("sh")
+            .args(["-c", script])
+            .current_dir(mount.mnt())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let root = format!("{}/", mount.root().display());
+        drop(mount);
+
+        let json = fs::read_to_string(path).unwrap();
+        let mut manifest: serde_json::Value = serde_json::from_str(&json).unwrap();
+        for paths in manifest.as_object_mut().unwrap().values_mut() {
+            for path in paths.as_array_mut().unwrap() {
+                *path = path.as_str().unwrap().replacen(&root, "", 1).into();
+            }
+        }
+        manifest
+    }
+
+    #[test]
+    fn manifest_of_a_copy() {
+        let manifest = session_manifest(
+            |root| {
+                fs::write(root.join("src.txt"), "copied").unwrap();
+                fs::write(root.join("stale.txt"), "old").unwrap();
+            },
+            "cp src.txt dst.txt && rm stale.txt",
+        );
+
+        assert_eq!(manifest["inputs"], serde_json::json!(["src.txt"]));
+        assert_eq!(manifest["outputs"], serde_json::json!(["dst.txt"]));
+        assert_eq!(manifest["removed"], serde_json::json!(["stale.txt"]));
+        assert_eq!(manifest["temporaries"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn manifest_of_a_compilation() {
+        // preprocess, compile and assemble through intermediate files, like cc does without -pipe
+        let manifest = session_manifest(
+            |root| {
+                fs::write(root.join("main.c"), "#include \"main.h\"\n").unwrap();
+                fs::write(root.join("main.h"), "int main(void);\n").unwrap();
+            },
+            "test -e config.h || true
+            cat main.c main.h > main.i
+            cp main.i main.s && rm main.i
+            cat main.s > main.o.tmp && mv main.o.tmp main.o && rm main.s
+            cat main.o > /dev/null",
+        );
+
+        // main.o is read back, but it was written by the build itself
+        assert_eq!(manifest["inputs"], serde_json::json!(["main.c", "main.h"]));
+        assert_eq!(manifest["outputs"], serde_json::json!(["main.o"]));
+        assert_eq!(
+            manifest["temporaries"],
+            serde_json::json!(["main.i", "main.o.tmp", "main.s"])
+        );
+        assert_eq!(manifest["removed"], serde_json::json!([]));
+        let stat_only = manifest["stat_only"].as_array().unwrap();
+        assert!(stat_only.iter().any(|path| path == "config.h"));
+        // looked up before the build created them, they were no dependency of it
+        assert_eq!(manifest["missing"], serde_json::json!(["config.h"]));
     }
 
-    fn get_path(&mut self, parent: u64, name: &OsStr) -> Result<PathBuf, c_int> {
-        let parent_context = match self.attrs.get(&parent) {
-            Some(x) => x,
-            None => {
-                return Err(libc::ENOENT);
-            }
+    #[test]
+    fn supplementary_groups_grant_access() {
+        let mount = TestMount::with_root(|root| {
+            fs::set_permissions(root, fs::Permissions::from_mode(0o755)).unwrap();
+            let path = root.join("group.txt");
+            fs::write(&path, "shared").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+            std::os::unix::fs::chown(&path, Some(0), Some(4242)).unwrap();
+        });
+        let cat = |groups: &str| {
+            Command::new("setpriv")
+                .args(["--reuid=65534", "--regid=65534"])
+                .arg(format!("--groups={groups}"))
+                .arg("cat")
+                .arg(mount.mnt().join("group.txt"))
+                .output()
+                .unwrap()
         };
-        let parent_path = Path::new(&parent_context.real_path);
-        Ok(parent_path.join(name))
+
+        let output = cat("4242");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"shared");
+
+        assert!(!cat("4243").status.success());
     }
 
-    fn lookup_name(&mut self, parent: u64, name: &OsStr) -> Result<InodeAttributes, c_int> {
-        let path = match self.get_path(parent, name) {
-            Ok(x) => x,
-            Err(c) => {
-                return Err(c);
+    #[test]
+    fn root_may_search_any_directory() {
+        let mount = TestMount::with_root(|root| {
+            for (name, mode) in [("private", 0o700), ("closed", 0o000)] {
+                fs::create_dir(root.join(name)).unwrap();
+                fs::set_permissions(root.join(name), fs::Permissions::from_mode(mode)).unwrap();
             }
+            fs::write(root.join("data.txt"), "not a program").unwrap();
+            fs::set_permissions(root.join("data.txt"), fs::Permissions::from_mode(0o644)).unwrap();
+        });
+        let test = |flag: &str, name: &str| {
+            Command::new("test")
+                .arg(flag)
+                .arg(mount.mnt().join(name))
+                .status()
+                .unwrap()
+                .success()
         };
-        let metadata = fs::metadata(path.clone());
-        match metadata {
-            Ok(metadata) => {
-                let real_path = path.to_str().unwrap().to_string();
-                let attrs: InodeAttributes = (metadata, real_path).into();
-                Ok(attrs)
-            }
-            Err(e) => Err(e.raw_os_error().unwrap_or(libc::EIO)),
-        }
+
+        assert!(test("-x", "private"));
+        assert!(test("-x", "closed"));
+        assert!(test("-r", "closed"));
+        assert!(!test("-x", "data.txt"));
     }
 
-    fn handle_metadata_on_removal<T>(
-        &mut self,
-        metadata: io::Result<fs::Metadata>,
-        result: io::Result<T>,
-        reply: ReplyEmpty,
-    ) {
-        match result {
-            Ok(_) => match metadata {
-                Ok(metadata) => {
-                    self.attrs.remove(&metadata.ino());
-                    reply.ok();
-                }
-                Err(e) => {
-                    reply.error(e.raw_os_error().unwrap_or(libc::EIO));
-                }
+    #[test]
+    fn overlay_leaves_the_root_alone() {
+        let upper = tempfile::tempdir().unwrap();
+        let upper_dir = upper.path().to_path_buf();
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::write(root.join("lower.txt"), "lower").unwrap();
+                fs::write(root.join("changed.txt"), "lower").unwrap();
+                fs::write(root.join("removed.txt"), "lower").unwrap();
+                fs::create_dir(root.join("dir")).unwrap();
+                fs::write(root.join("dir/nested.txt"), "lower").unwrap();
             },
-            Err(e) => {
-                reply.error(e.raw_os_error().unwrap_or(libc::EIO));
-            }
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.overlay(upper_dir),
+        );
+        let mnt = mount.mnt();
+
+        assert_eq!(fs::read(mnt.join("lower.txt")).unwrap(), b"lower");
+
+        // changes are made to copies in the upper directory
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(mnt.join("changed.txt"))
+            .unwrap();
+        file.write_all(b" upper").unwrap();
+        drop(file);
+        fs::write(mnt.join("dir/nested.txt"), "upper").unwrap();
+        for path in ["changed.txt", "dir/nested.txt"] {
+            assert_eq!(fs::read(mount.root().join(path)).unwrap(), b"lower");
         }
-    }
-    fn handle_metadata_on_change<T>(
-        &mut self,
-        path: &PathBuf,
-        result: io::Result<T>,
-        reply: Reply,
-    ) {
-        let handle_error = |e: io::Error, r: Reply| match r {
-            Reply::Entry(r) => {
-                r.error(e.raw_os_error().unwrap_or(libc::EIO));
-            }
-            Reply::Empty(r) => {
-                r.error(e.raw_os_error().unwrap_or(libc::EIO));
-            }
-            Reply::Attr(r) => {
-                r.error(e.raw_os_error().unwrap_or(libc::EIO));
-            }
+        assert_eq!(fs::read(mnt.join("changed.txt")).unwrap(), b"lower upper");
+        assert_eq!(
+            fs::read(upper.path().join("changed.txt")).unwrap(),
+            b"lower upper"
+        );
+        assert_eq!(fs::read(mnt.join("dir/nested.txt")).unwrap(), b"upper");
+
+        // removed files are hidden, until they are created again
+        fs::write(mnt.join("new.txt"), "upper").unwrap();
+        fs::remove_file(mnt.join("removed.txt")).unwrap();
+        assert!(mount.root().join("removed.txt").exists());
+        assert!(!mnt.join("removed.txt").exists());
+        let mut names: Vec<_> = fs::read_dir(mnt)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| !name.to_string_lossy().starts_with(".cairn"))
+            .collect();
+        names.sort();
+        assert_eq!(names, ["changed.txt", "dir", "lower.txt", "new.txt"]);
+        fs::write(mnt.join("removed.txt"), "upper").unwrap();
+        assert_eq!(fs::read(mnt.join("removed.txt")).unwrap(), b"upper");
+
+        let hit = |path: &Path, op: &str, layer: &str| {
+            events_of(path)
+                .iter()
+                .any(|event| event["op"] == op && event["layer"] == layer)
         };
+        assert!(hit(&mount.root().join("lower.txt"), "open", "lower"));
+        assert!(hit(&upper.path().join("changed.txt"), "open", "upper"));
+        assert!(hit(&upper.path().join("dir/nested.txt"), "open", "upper"));
+        assert!(hit(&mount.root().join("removed.txt"), "unlink", "lower"));
+    }
 
-        match result {
-            Ok(_) => match fs::metadata(path) {
-                Ok(metadata) => {
-                    let real_path = path.to_str().unwrap().to_string();
-                    let ino = metadata.ino();
-                    let new_attrs: InodeAttributes = (metadata, real_path).into();
-                    self.attrs.insert(ino, new_attrs.clone());
-                    match reply {
-                        Reply::Entry(reply) => {
-                            reply.entry(&Duration::new(0, 0), &new_attrs.into(), 0);
-                        }
-                        Reply::Attr(reply) => {
-                            reply.attr(&Duration::new(0, 0), &new_attrs.into());
-                        }
-                        Reply::Empty(reply) => {
-                            reply.ok();
-                        }
-                    }
-                }
-                Err(e) => {
-                    handle_error(e, reply);
-                }
+    #[test]
+    fn injected_files_show_up_read_only() {
+        let store = tempfile::tempdir().unwrap();
+        let header = store.path().canonicalize().unwrap().join("foo.h");
+        fs::write(&header, "#define FOO 1\n").unwrap();
+        let files = BTreeMap::from([
+            (PathBuf::from("include/foo.h"), header.clone()),
+            (PathBuf::from("src/foo.h"), header.clone()),
+        ]);
+        let injections = Injections::new(files).unwrap();
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::create_dir(root.join("src")).unwrap();
+                fs::write(root.join("src/foo.h"), "shadowed").unwrap();
             },
-            Err(e) => {
-                handle_error(e, reply);
-            }
-        }
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.inject(injections),
+        );
+        let mnt = mount.mnt();
+
+        let output = Command::new("cat")
+            .arg(mnt.join("include/foo.h"))
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"#define FOO 1\n");
+        assert_eq!(fs::read(mnt.join("src/foo.h")).unwrap(), b"#define FOO 1\n");
+        assert!(mnt.join("include").is_dir());
+        let names = |dir: &str| {
+            let mut names: Vec<_> = fs::read_dir(mnt.join(dir))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .filter(|name| !name.to_string_lossy().starts_with(".cairn"))
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(""), ["include", "src"]);
+        assert_eq!(names("include"), ["foo.h"]);
+        assert_eq!(names("src"), ["foo.h"]);
+
+        // neither the injected files nor the directories only they are in can be changed
+        let error = fs::write(mnt.join("include/foo.h"), "changed").unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EROFS));
+        assert!(fs::remove_file(mnt.join("src/foo.h")).is_err());
+        assert!(fs::write(mnt.join("include/bar.h"), "new").is_err());
+        assert_eq!(fs::read(&header).unwrap(), b"#define FOO 1\n");
+        assert!(!mount.root().join("include").exists());
+
+        // traced by the file they are read from
+        assert!(events_of(&header).iter().any(|event| event["op"] == "open"));
     }
-}
 
-impl Filesystem for TracerFS {
-    fn init(&mut self, _req: &Request, _config: &mut KernelConfig) -> Result<(), c_int> {
-        for entry in WalkDir::new(&self.root).into_iter().filter_map(|e| e.ok()) {
-            debug!("init() entry: {:?}", entry);
-            let metadata = entry.metadata().unwrap();
-            let real_path = entry.path().to_str().unwrap().to_string();
-
-            let inode = if real_path != self.root {
-                metadata.ino()
-            } else {
-                FUSE_ROOT_ID
-            };
+    #[test]
+    fn fetch_faults_in_missing_files() {
+        let store = tempfile::tempdir().unwrap();
+        fs::create_dir(store.path().join("deps")).unwrap();
+        fs::write(store.path().join("deps/x"), "fetched").unwrap();
+        fs::write(store.path().join("deps/slow"), "slow").unwrap();
+        let script = store.path().join("fetch.sh");
+        fs::write(
+            &script,
+            format!(
+                "echo \"$1\" >> {log:?}\n\
+                [ \"$1\" = deps/slow ] && sleep 1\n\
+                cp {store:?}/\"$1\" \"$1\" 2>/dev/null\n",
+                log = store.path().join("fetched"),
+                store = store.path(),
+            ),
+        )
+        .unwrap();
+        let command = format!("sh {:?}", script);
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::create_dir(root.join("deps")).unwrap();
+                fs::write(root.join("local.txt"), "local").unwrap();
+            },
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| {
+                fs.fetch(
+                    command,
+                    PathBuf::from("deps"),
+                    std::time::Duration::from_secs(10),
+                )
+            },
+        );
+        let mnt = mount.mnt();
 
-            let attrs: InodeAttributes = (metadata, real_path).into();
+        let output = Command::new("cat")
+            .arg(mnt.join("deps/x"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"fetched");
+        assert_eq!(fs::read(mount.root().join("deps/x")).unwrap(), b"fetched");
+        assert_eq!(fs::read(mnt.join("deps/x")).unwrap(), b"fetched");
+
+        // a slow fetch holds up nothing but the lookups of its path
+        let slow = mnt.join("deps/slow");
+        let fetching = thread::spawn(move || fs::read(slow).unwrap());
+        thread::sleep(std::time::Duration::from_millis(200));
+        let start = std::time::Instant::now();
+        assert_eq!(fs::read(mnt.join("local.txt")).unwrap(), b"local");
+        assert!(start.elapsed() < std::time::Duration::from_millis(500));
+        assert_eq!(fetching.join().unwrap(), b"slow");
+
+        assert!(fs::read(mnt.join("deps/missing")).is_err());
+        assert!(fs::read(mnt.join("missing")).is_err());
+        // traced once the filesystem is busy again
+        fs::metadata(mnt).unwrap();
+
+        let fetched = fs::read_to_string(store.path().join("fetched")).unwrap();
+        assert_eq!(fetched, "deps/x\ndeps/slow\ndeps/missing\n");
+        let opened = events_of(&mount.root().join("deps/x"));
+        assert!(opened
+            .iter()
+            .any(|event| event["op"] == "open" && event["materialized"] == true));
+        let failed = events_of(&mount.root().join("deps/missing"));
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["op"], "fetch_failed");
+        assert_eq!(failed[0]["result"], libc::ENOENT);
+        assert!(events_of(&mount.root().join("local.txt"))
+            .iter()
+            .all(|event| event.get("materialized").is_none()));
+    }
 
-            self.attrs.insert(inode, attrs);
+    #[test]
+    fn read_only_mount_rejects_changes() {
+        let mount = TestMount::with_sink(
+            |root| fs::write(root.join("frozen.txt"), "frozen").unwrap(),
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.read_only(),
+        );
+        let before = stat(&mount.root().join("frozen.txt"), "%Y %s");
+
+        for script in [
+            "touch \"$1/new.txt\"",
+            "touch \"$1/frozen.txt\"",
+            "echo thawed >> \"$1/frozen.txt\"",
+            "mkdir \"$1/dir\"",
+            "mv \"$1/frozen.txt\" \"$1/moved.txt\"",
+            "rm \"$1/frozen.txt\"",
+        ] {
+            let output = Command::new("sh")
+                .args(["-c", script, "sh"])
+                .arg(mount.mnt())
+                .output()
+                .unwrap();
+            assert!(!output.status.success(), "{script}");
+            assert!(
+                String::from_utf8_lossy(&output.stderr).contains("Read-only file system"),
+                "{script}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
         }
 
-        File::create(".cairn-fuse-ready").expect("Failed to create .cairn-fuse-ready");
+        let path = CString::new(mount.mnt().join("frozen.txt").as_os_str().as_bytes()).unwrap();
+        let name = c"user.cairn";
+        let set =
+            unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), b"x".as_ptr().cast(), 1, 0) };
+        assert_eq!(set, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EROFS)
+        );
+        assert_eq!(
+            unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) },
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EROFS)
+        );
 
-        Ok(())
-    }
+        assert_eq!(fs::read(mount.mnt().join("frozen.txt")).unwrap(), b"frozen");
+        assert_eq!(stat(&mount.root().join("frozen.txt"), "%Y %s"), before);
+        let entries: Vec<_> = fs::read_dir(mount.root()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
 
-    fn destroy(&mut self) {
-        debug!("destroy()");
-        self.destroy.send(()).unwrap();
+        // the attempts are in the trace, as the non-hermetic steps they are
+        let attempted = |path: &str, op: &str| {
+            events_of(&mount.root().join(path))
+                .iter()
+                .any(|event| event["op"] == op && event["result"] == libc::EROFS)
+        };
+        assert!(attempted("new.txt", "create"));
+        assert!(attempted("frozen.txt", "utime"));
+        assert!(attempted("frozen.txt", "open"));
+        assert!(attempted("dir", "mkdir"));
+        assert!(attempted("frozen.txt", "rename"));
+        assert!(attempted("frozen.txt", "unlink"));
+        assert!(attempted("frozen.txt", "setxattr"));
+        assert!(attempted("frozen.txt", "removexattr"));
+
+        let status = Command::new("touch")
+            .arg("-a")
+            .arg(mount.mnt().join("frozen.txt"))
+            .status()
+            .unwrap();
+        assert!(status.success());
     }
 
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        debug!("lookup(parent={}, name={:?})", parent, name);
+    #[test]
+    fn chown_enforces_ownership_rules() {
+        let mount = TestMount::with_root(|root| {
+            fs::set_permissions(root, fs::Permissions::from_mode(0o755)).unwrap();
+            fs::write(root.join("owned.txt"), "").unwrap();
+            std::os::unix::fs::chown(root.join("owned.txt"), Some(65534), Some(65534)).unwrap();
+        });
+        let as_nobody = |command: &str, owner: &str| {
+            Command::new("setpriv")
+                .args(["--reuid=65534", "--regid=65534", "--groups=4242"])
+                .arg(command)
+                .arg(owner)
+                .arg(mount.mnt().join("owned.txt"))
+                .output()
+                .unwrap()
+        };
+        let path = mount.root().join("owned.txt");
 
-        match self.lookup_name(parent, name) {
-            Ok(attrs) => {
-                self.attrs.insert(attrs.ino, attrs.clone());
-                reply.entry(&Duration::new(0, 0), &attrs.into(), 0);
-            }
-            Err(e) => {
-                reply.error(e);
-            }
-        }
+        let output = as_nobody("chown", "0");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Operation not permitted"));
+
+        let output = as_nobody("chgrp", "4243");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Operation not permitted"));
+        assert_eq!(stat(&path, "%u %g"), "65534 65534");
+
+        assert!(as_nobody("chgrp", "4242").status.success());
+        assert_eq!(stat(&path, "%u %g"), "65534 4242");
+
+        // giving away an executable also clears its setuid bit, which is left alone when the
+        // request as a whole is refused
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o4755)).unwrap();
+        assert!(!as_nobody("chown", "0").status.success());
+        assert_eq!(stat(&path, "%a %u"), "4755 65534");
     }
 
-    fn forget(&mut self, _req: &Request, _ino: u64, _nlookup: u64) {
-        debug!("forget(ino={}, nlookup={})", _ino, _nlookup);
+    #[test]
+    fn chown_clears_setuid_and_setgid() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("privileged.bin"), "").unwrap();
+            fs::set_permissions(
+                root.join("privileged.bin"),
+                fs::Permissions::from_mode(0o6755),
+            )
+            .unwrap();
+        });
+        let path = mount.mnt().join("privileged.bin");
+
+        let status = Command::new("chown")
+            .arg("65534")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(stat(&path, "%a %u"), "755 65534");
+        assert_eq!(stat(&mount.root().join("privileged.bin"), "%a"), "755");
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        debug!("getattr(ino={})", ino);
+    #[test]
+    fn df_reports_the_backing_filesystem() {
+        let mount = TestMount::new();
+
+        let df = |path: &Path| {
+            let output = Command::new("df").arg("-P").arg(path).output().unwrap();
+            assert!(output.status.success());
+            // Filesystem 1024-blocks Used Available Capacity Mounted on
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            let fields: Vec<String> = stdout
+                .lines()
+                .nth(1)
+                .unwrap()
+                .split_whitespace()
+                .map(String::from)
+                .collect();
+            fields[1].clone()
+        };
 
-        match self.attrs.get(&ino) {
-            Some(attrs) => {
-                reply.attr(&Duration::new(0, 0), &(*attrs).clone().into());
-            }
-            None => {
-                reply.error(libc::ENOENT);
-            }
-        }
+        assert_eq!(df(mount.mnt()), df(mount.root()));
+        assert!(!traces_containing("statfs").is_empty());
     }
 
-    fn setattr(
-        &mut self,
-        req: &Request<'_>,
-        ino: u64,
-        mode: Option<u32>,
-        uid: Option<u32>,
-        gid: Option<u32>,
-        size: Option<u64>,
-        atime: Option<TimeOrNow>,
-        mtime: Option<TimeOrNow>,
-        _ctime: Option<SystemTime>,
-        _fh: Option<u64>,
-        _crtime: Option<SystemTime>,
-        _chgtime: Option<SystemTime>,
-        _bkuptime: Option<SystemTime>,
-        _flags: Option<u32>,
-        reply: ReplyAttr,
-    ) {
-        let attrs = match self.attrs.get(&ino) {
-            Some(attrs) => attrs,
-            None => {
-                reply.error(libc::ENOENT);
-                return;
-            }
+    #[test]
+    fn statfs_is_the_same_for_every_file() {
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::create_dir(root.join("nested")).unwrap();
+                fs::write(root.join("top.txt"), "top").unwrap();
+                fs::write(root.join("nested/deep.txt"), "deep").unwrap();
+                std::os::unix::fs::symlink("top.txt", root.join("link")).unwrap();
+            },
+            Box::new(sink),
+            |fs| fs,
+        );
+        // totals only, other tests use the same filesystem and change what is free
+        let totals = |name: &str| {
+            let output = Command::new("stat")
+                .args(["-f", "-c", "%b %c %S"])
+                .arg(mount.mnt().join(name))
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
         };
 
-        if let Some(mode) = mode {
-            debug!("chmod() called with {:?}, {:o}", ino, mode);
-            if req.uid() != 0 && req.uid() != attrs.uid {
-                reply.error(libc::EPERM);
-                return;
-            }
+        let top = totals("top.txt");
+        assert_eq!(totals("nested/deep.txt"), top);
+        assert_eq!(totals("link"), top);
 
-            trace(req.pid(), 'w', vec![&attrs.real_path, "chmod"]);
+        let statfs: Vec<_> = events_of(mount.root())
+            .into_iter()
+            .filter(|event| event["op"] == "statfs")
+            .collect();
+        assert_eq!(statfs.len(), 3);
+    }
 
-            self.handle_metadata_on_change(
-                &PathBuf::from(&attrs.real_path),
-                fs::set_permissions(&attrs.real_path, PermissionsExt::from_mode(mode)),
-                Reply::Attr(reply),
-            );
+    #[test]
+    fn metrics_socket_serves_the_counters() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("metrics.sock");
+        let metrics = Metrics::bind(socket.clone()).unwrap();
+        let mount = TestMount::with_fs(
+            |root| fs::write(root.join("a.txt"), "a").unwrap(),
+            |fs| fs.metrics(metrics),
+        );
+        fs::read(mount.mnt().join("a.txt")).unwrap();
+        let _open = File::open(mount.mnt().join("a.txt")).unwrap();
+        // the gauges are those of the last operation taken
+        fs::metadata(mount.mnt()).unwrap();
+
+        let scrape = || {
+            let mut text = String::new();
+            let mut stream = UnixStream::connect(&socket).unwrap();
+            stream.read_to_string(&mut text).unwrap();
+            text
+        };
+        let value = |text: &str, name: &str| -> f64 {
+            let line = text.lines().find(|x| x.split(' ').next() == Some(name));
+            line.unwrap().split(' ').nth(1).unwrap().parse().unwrap()
+        };
+        let text = scrape();
+        assert!(text.contains("# TYPE cairn_read_total counter\n"));
+        assert!(value(&text, "cairn_read_total") >= 1.0);
+        assert!(value(&text, "cairn_read_bytes_total") >= 1.0);
+        assert!(value(&text, "cairn_open_files") >= 1.0);
+        assert!(value(&text, "cairn_inodes") >= 2.0);
+        // every connection gets a scrape of its own
+        assert!(value(&scrape(), "cairn_lookup_total") >= 1.0);
+
+        drop(mount);
+        assert!(!socket.exists());
+    }
 
-            return;
+    #[test]
+    fn trace_socket_streams_to_every_consumer() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("live.sock");
+        let sink = BroadcastSink::bind(socket.clone(), TraceFormat::Json).unwrap();
+        let mount = TestMount::with_sink(|_| {}, Box::new(sink), |fs| fs);
+        let path = mount.root().join("watched.txt");
+
+        let (connected, is_connected) = mpsc::channel();
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let socket = socket.clone();
+                let connected = connected.clone();
+                let path = path.clone();
+                thread::spawn(move || {
+                    let stream = UnixStream::connect(socket).unwrap();
+                    stream
+                        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+                        .unwrap();
+                    connected.send(()).unwrap();
+                    BufReader::new(stream)
+                        .lines()
+                        .map(|line| serde_json::from_str::<serde_json::Value>(&line.unwrap()))
+                        .map(Result::unwrap)
+                        .find(|event| {
+                            event["path"] == path.to_str().unwrap() && event["op"] != "lookup_miss"
+                        })
+                        .unwrap()
+                })
+            })
+            .collect();
+        for _ in 0..2 {
+            is_connected.recv().unwrap();
         }
 
-        if uid.is_some() || gid.is_some() {
-            debug!("chown() called with {:?} {:?} {:?}", ino, uid, gid);
-
-            trace(req.pid(), 'w', vec![&attrs.real_path, "chown"]);
+        let status = Command::new("touch")
+            .arg(mount.mnt().join("watched.txt"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        for consumer in consumers {
+            assert_eq!(consumer.join().unwrap()["op"], "create");
+        }
 
-            self.handle_metadata_on_change(
-                &PathBuf::from(&attrs.real_path),
-                ufs::chown(&attrs.real_path, uid, gid),
-                Reply::Attr(reply),
-            );
+        drop(mount);
+        assert!(!socket.exists());
+    }
 
-            return;
+    #[test]
+    fn trace_socket_drops_slow_consumers() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("live.sock");
+        // left behind by a crash
+        drop(UnixListener::bind(&socket).unwrap());
+        let mut sink = BroadcastSink::bind(socket.clone(), TraceFormat::Json).unwrap();
+        let mut slow = UnixStream::connect(&socket).unwrap();
+
+        // far more than the socket buffers, the sink must not wait for the consumer
+        let path = format!("/{}", "x".repeat(1000));
+        for pid in 0..10000 {
+            sink.record(TraceEvent::new(pid, 'r', TraceOp::Open, &path));
         }
 
-        if let Some(size) = size {
-            debug!("truncate() called with {:?} {:?}", ino, size);
+        let mut received = Vec::new();
+        slow.read_to_end(&mut received).unwrap();
+        assert!(!received.is_empty());
+        assert!(received.len() < 10000 * path.len());
+    }
 
-            // open file and truncate it
-            let file = match OpenOptions::new().write(true).open(&attrs.real_path) {
-                Ok(file) => file,
-                Err(err) => match err.kind() {
-                    io::ErrorKind::NotFound => {
-                        reply.error(libc::ENOENT);
-                        return;
-                    }
-                    io::ErrorKind::PermissionDenied => {
-                        reply.error(libc::EACCES);
-                        return;
-                    }
-                    io::ErrorKind::AlreadyExists => {
-                        reply.error(libc::EEXIST);
-                        return;
-                    }
-                    io::ErrorKind::InvalidInput => {
-                        reply.error(libc::EINVAL);
-                        return;
-                    }
-                    _ => {
-                        reply.error(libc::EIO);
-                        return;
-                    }
-                },
-            };
+    #[test]
+    fn unix_socket_receives_traces() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("traces.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let dest = format!("unix:{}", socket.display());
+        let sink = super::trace_sink(Some(&dest), TraceFormat::Json).unwrap();
+        let mount = TestMount::with_sink(|_| {}, sink, |fs| fs);
+
+        let status = Command::new("touch")
+            .arg(mount.mnt().join("new.txt"))
+            .status()
+            .unwrap();
+        assert!(status.success());
 
-            trace(req.pid(), 'w', vec![&attrs.real_path, "truncate"]);
+        let (stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let expected = mount.root().join("new.txt");
+        let event = BufReader::new(stream)
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(&line.unwrap()).unwrap())
+            .find(|event| event["op"] == "create")
+            .unwrap();
+        assert_eq!(event["kind"], "w");
+        assert_eq!(event["path"], expected.to_str().unwrap());
+    }
 
-            self.handle_metadata_on_change(
-                &PathBuf::from(&attrs.real_path),
-                file.set_len(size),
-                Reply::Attr(reply),
-            );
+    #[test]
+    fn flatbuffer_traces_decode() {
+        let dir = TempDir::new().unwrap();
+        let trace = dir.path().join("traces.fbs");
+        let dest = format!("fbs:{}", trace.display());
+        let sink = super::trace_sink(Some(&dest), TraceFormat::Human).unwrap();
+        let mount = TestMount::with_sink(|_| {}, sink, |fs| fs);
+
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg("echo binary > new.txt; mkdir full; touch full/x; rmdir full")
+            .current_dir(mount.mnt())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success());
+        let root = mount.root().to_path_buf();
+        drop(mount);
+
+        let bytes = fs::read(&trace).unwrap();
+        let events: Vec<TraceEvent> = fbs::decode(&bytes).map(Result::unwrap).collect();
+        let create = events
+            .iter()
+            .find(|event| event.op == TraceOp::Create)
+            .unwrap();
+        assert_eq!(create.kind, 'w');
+        assert_eq!(create.path, root.join("new.txt").to_str().unwrap());
+        assert_eq!(create.uid, Some(0));
+        assert!(create.comm.is_some());
+        // looked up before it was created
+        assert!(events
+            .iter()
+            .any(|event| event.op == TraceOp::LookupMiss && event.path == create.path));
+        let write = events
+            .iter()
+            .find(|event| event.op == TraceOp::Write)
+            .unwrap();
+        assert_eq!((write.offset, write.size), (Some(0), Some(7)));
+        let rmdir = events
+            .iter()
+            .find(|event| event.op == TraceOp::Rmdir)
+            .unwrap();
+        assert_eq!(rmdir.result, Err(libc::ENOTEMPTY));
 
-            return;
+        // a crash halfway through an event leaves the ones before it readable
+        let decoded = fbs::decode(&bytes[..bytes.len() - 1]).count();
+        assert_eq!(decoded, events.len() - 1);
+    }
+
+    #[test]
+    fn trace_dest_rejects_unknown_schemes() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("traces.log");
+        for dest in [
+            "stdout".to_string(),
+            format!("file:{}", file.display()),
+            "unix:/nonexistent/traces.sock".to_string(),
+            format!("fbs:{}", dir.path().join("traces.fbs").display()),
+        ] {
+            assert!(super::trace_sink(Some(&dest), TraceFormat::Human).is_ok());
         }
+        assert!(file.exists());
+        let error = super::trace_sink(Some(&"tcp:localhost:1".to_string()), TraceFormat::Human);
+        assert_eq!(
+            error.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+    }
 
-        let now = time_now();
-        if let Some(atime) = atime {
-            debug!("utime() called with {:?} {:?}", ino, atime);
+    #[test]
+    fn readdir_lists_large_directories_once() {
+        let mount = TestMount::with_root(|root| {
+            fs::create_dir(root.join("large")).unwrap();
+            for i in 0..10_000 {
+                fs::write(root.join("large").join(format!("file-{i:05}")), "").unwrap();
+            }
+        });
+        let mut expected = vec![".".to_string(), "..".to_string()];
+        expected.extend((0..10_000).map(|i| format!("file-{i:05}")));
 
-            trace(req.pid(), 't', vec![&attrs.real_path, "utime"]);
+        let output = Command::new("ls")
+            .arg("-a1")
+            .arg(mount.mnt().join("large"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let mut names: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        names.sort();
+        assert_eq!(names, expected);
+
+        // files created halfway through a listing belong to the next one, the pages already
+        // handed out neither repeat nor shift
+        let output = Command::new("perl")
+            .arg("-e")
+            .arg(
+                r#"opendir(my $d, $ARGV[0]) or die $!;
+                my @names = map { scalar readdir $d } 1..1000;
+                for (0..499) { open(my $f, '>', "$ARGV[0]/late-$_") or die $! }
+                while (defined(my $name = readdir $d)) { push @names, $name }
+                print "$_\n" for @names"#,
+            )
+            .arg(mount.mnt().join("large"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let mut names: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        names.sort();
+        assert_eq!(names, expected);
+
+        let output = Command::new("find")
+            .arg(mount.mnt())
+            .arg("-name")
+            .arg("file-09999")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().trim_end(),
+            mount.mnt().join("large/file-09999").to_str().unwrap()
+        );
+    }
 
-            self.handle_metadata_on_change(
-                &PathBuf::from(&attrs.real_path),
-                utime::set_file_times(
-                    &attrs.real_path,
-                    match atime {
-                        TimeOrNow::SpecificTime(atime) => time_from_system_time(&atime).0,
-                        TimeOrNow::Now => now.0,
-                    },
-                    attrs.mtime.0,
-                ),
-                Reply::Attr(reply),
-            );
+    #[test]
+    fn crtime_is_the_birth_time() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("born.txt");
+        fs::write(&path, "").unwrap();
+
+        let metadata = fs::metadata(&path).unwrap();
+        let attr: fuser::FileAttr = cairn_fuse::InodeAttributes::from((metadata, path)).into();
+        let age = std::time::SystemTime::now()
+            .duration_since(attr.crtime)
+            .unwrap();
+        assert!(
+            age < std::time::Duration::from_secs(60),
+            "{:?}",
+            attr.crtime
+        );
+    }
 
-            return;
-        }
+    #[test]
+    fn special_files_are_listed() {
+        let mount = TestMount::with_root(|root| {
+            let status = Command::new("mkfifo")
+                .arg(root.join("fifo"))
+                .status()
+                .unwrap();
+            assert!(status.success());
+            // the listener is closed right away, the socket file stays behind
+            UnixListener::bind(root.join("socket")).unwrap();
+        });
 
-        if let Some(mtime) = mtime {
-            debug!("utime() called with {:?} {:?}", ino, mtime);
+        let output = Command::new("ls")
+            .arg("-la")
+            .arg(mount.mnt())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let listing = String::from_utf8(output.stdout).unwrap();
+        assert!(listing
+            .lines()
+            .any(|line| line.starts_with('p') && line.ends_with(" fifo")));
+        assert!(listing
+            .lines()
+            .any(|line| line.starts_with('s') && line.ends_with(" socket")));
+
+        assert_eq!(stat(&mount.mnt().join("fifo"), "%F"), "fifo");
+        assert_eq!(stat(&mount.mnt().join("socket"), "%F"), "socket");
+    }
 
-            trace(req.pid(), 't', vec![&attrs.real_path, "utime"]);
+    #[test]
+    fn mknod_creates_special_files() {
+        let mount = TestMount::new();
 
-            self.handle_metadata_on_change(
-                &PathBuf::from(&attrs.real_path),
-                utime::set_file_times(
-                    &attrs.real_path,
-                    attrs.atime.0,
-                    match mtime {
-                        TimeOrNow::SpecificTime(mtime) => time_from_system_time(&mtime).0,
-                        TimeOrNow::Now => now.0,
-                    },
-                ),
-                Reply::Attr(reply),
-            );
+        let status = Command::new("mkfifo")
+            .arg(mount.mnt().join("fifo"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        UnixListener::bind(mount.mnt().join("socket")).unwrap();
+        assert_eq!(stat(&mount.root().join("fifo"), "%F"), "fifo");
+        assert_eq!(stat(&mount.root().join("socket"), "%F"), "socket");
 
+        // devices take CAP_MKNOD
+        if unsafe { libc::geteuid() } != 0 {
             return;
         }
+        let status = Command::new("mknod")
+            .arg(mount.mnt().join("null"))
+            .args(["c", "1", "3"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            stat(&mount.root().join("null"), "%F %t:%T"),
+            "character special file 1:3"
+        );
+        assert_eq!(
+            stat(&mount.mnt().join("null"), "%F"),
+            "character special file"
+        );
     }
 
-    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
-        debug!("readlink(ino={})", ino);
-
-        match self.attrs.get(&ino) {
-            Some(attrs) => {
-                if attrs.kind == FileKind::Symlink {
-                    let path = Path::new(&attrs.real_path);
-                    let link = match fs::read_link(path) {
-                        Ok(x) => x,
-                        Err(_) => {
-                            reply.error(libc::EIO);
-                            return;
-                        }
-                    };
-
-                    // open file at link and read it
-                    if let Ok(mut file) = File::open(link.clone()) {
-                        let file_size = match file.metadata() {
-                            Ok(x) => x,
-                            Err(_) => {
-                                reply.error(libc::EIO);
-                                return;
-                            }
-                        }
-                        .len();
-                        let mut buffer = vec![0; file_size as usize];
-                        match file.read_exact(&mut buffer) {
-                            Ok(x) => x,
-                            Err(_) => {
-                                reply.error(libc::EIO);
-                                return;
-                            }
-                        };
-
-                        //trace(req.pid(), 'r', &["readlink", &link.to_str().unwrap()]);
-
-                        reply.data(&buffer);
-                        return;
-                    } else {
-                        reply.error(libc::ENOENT);
-                        return;
-                    }
-                } else {
-                    reply.error(libc::EINVAL);
-                    return;
-                }
-            }
-            None => {
-                reply.error(libc::ENOENT);
-                return;
+    #[test]
+    fn child_numbered_like_the_root() {
+        // mounting the tmpfs takes CAP_SYS_ADMIN
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        struct Unmount(std::path::PathBuf);
+        impl Drop for Unmount {
+            fn drop(&mut self) {
+                let _ = Command::new("umount").arg(&self.0).status();
             }
         }
+
+        let mount = TestMount::with_root(|root| {
+            fs::create_dir(root.join("tmpfs")).unwrap();
+            let status = Command::new("mount")
+                .args(["-t", "tmpfs", "none"])
+                .arg(root.join("tmpfs"))
+                .status()
+                .unwrap();
+            assert!(status.success());
+            fs::write(root.join("tmpfs/inner.txt"), "inner").unwrap();
+            fs::write(root.join("outer.txt"), "outer").unwrap();
+        });
+        let _tmpfs = Unmount(mount.root().join("tmpfs"));
+        // the root of a fresh tmpfs is the first inode
+        assert_eq!(fs::metadata(mount.root().join("tmpfs")).unwrap().ino(), 1);
+
+        let child = fs::metadata(mount.mnt().join("tmpfs")).unwrap();
+        let root = fs::metadata(mount.mnt()).unwrap();
+        assert!(child.is_dir());
+        assert_ne!(child.ino(), root.ino());
+        assert_eq!(
+            fs::read_to_string(mount.mnt().join("outer.txt")).unwrap(),
+            "outer"
+        );
+        assert_eq!(
+            fs::read_to_string(mount.mnt().join("tmpfs/inner.txt")).unwrap(),
+            "inner"
+        );
+        let mut names: Vec<_> = fs::read_dir(mount.mnt())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["outer.txt", "tmpfs"]);
     }
 
-    fn mknod(
-        &mut self,
-        _req: &Request<'_>,
-        parent: u64,
-        name: &OsStr,
-        mode: u32,
-        _umask: u32,
-        rdev: u32,
-        reply: ReplyEntry,
-    ) {
-        debug!(
-            "mknod(parent={}, name={:?}, mode={}, rdev={})",
-            parent, name, mode, rdev
-        );
-        let path = match self.get_path(parent, name) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
-            }
+    #[test]
+    fn read_trusts_the_cached_length() {
+        let mount = TestMount::with_root(|root| {
+            fs::write(root.join("large.bin"), vec![7; 100 * 4096]).unwrap();
+        });
+        let file = mount.mnt().join("large.bin");
+        let ino = fs::metadata(&file).unwrap().ino();
+        let count = |op: &str| {
+            traces_containing(&format!("ino={ino}"))
+                .iter()
+                .filter(|line| line.starts_with(op))
+                .count()
+        };
+        // O_DIRECT keeps the page cache out of the way, every block is a read() of its own
+        let dd = |blocks: usize| {
+            let output = Command::new("dd")
+                .arg(format!("if={}", file.display()))
+                .args(["bs=4096", "iflag=direct", "status=none"])
+                .arg(format!("count={blocks}"))
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            output.stdout.len()
         };
 
-        let file_type = mode & libc::S_IFMT as u32;
-        if file_type != libc::S_IFREG as u32
-            && file_type != libc::S_IFLNK as u32
-            && file_type != libc::S_IFDIR as u32
-        {
-            // TODO
-            warn!("mknod() implementation is incomplete. Only supports regular files, symlinks, and directories. Got {:o}", mode);
-            reply.error(libc::ENOSYS);
-            return;
-        }
+        assert_eq!(dd(100), 100 * 4096);
+        assert!(count("read(") >= 100);
+        assert_eq!(count("read() re-stat"), 0);
 
-        // check if file already exists
-        if self.lookup_name(parent, name).is_ok() {
-            reply.error(libc::EEXIST);
-            return;
-        }
+        let status = Command::new("truncate")
+            .args(["-s", "4096"])
+            .arg(&file)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(dd(100), 4096);
+        assert_eq!(count("read() re-stat"), 1);
+    }
 
-        let result = File::create(path.clone());
-        self.handle_metadata_on_change(&path, result, Reply::Entry(reply));
-    }
-
-    fn mkdir(
-        &mut self,
-        _req: &Request<'_>,
-        parent: u64,
-        name: &OsStr,
-        mode: u32,
-        _umask: u32,
-        reply: ReplyEntry,
-    ) {
-        debug!("mkdir(parent={}, name={:?}, mode={})", parent, name, mode);
-        let path = match self.get_path(parent, name) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
+    #[test]
+    fn opendir_checks_read_access() {
+        let mount = TestMount::with_root(|root| {
+            fs::set_permissions(root, fs::Permissions::from_mode(0o755)).unwrap();
+            for (name, uid) in [("mine", 65534), ("theirs", 0)] {
+                let dir = root.join(name);
+                fs::create_dir(&dir).unwrap();
+                fs::write(dir.join("entry.txt"), "").unwrap();
+                std::os::unix::fs::chown(&dir, Some(uid), Some(uid)).unwrap();
+                fs::set_permissions(&dir, fs::Permissions::from_mode(0o500)).unwrap();
             }
+        });
+        let ls = |dir: &str| {
+            Command::new("setpriv")
+                .args(["--reuid=65534", "--regid=65534", "--clear-groups", "ls"])
+                .arg(mount.mnt().join(dir))
+                .output()
+                .unwrap()
         };
 
-        self.handle_metadata_on_change(&path, fs::create_dir(path.clone()), Reply::Entry(reply));
+        let output = ls("mine");
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "entry.txt\n");
+        assert!(!ls("theirs").status.success());
+
+        let output = Command::new("perl")
+            .arg("-MFcntl")
+            .arg("-e")
+            .arg("sysopen(my $fh, $ARGV[0], O_RDWR) or die \"$!\\n\";")
+            .arg(mount.mnt().join("mine"))
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "Is a directory\n"
+        );
     }
 
-    fn unlink(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        debug!("unlink(parent={}, name={:?})", parent, name);
-        let path = match self.get_path(parent, name) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
-            }
+    #[test]
+    fn direct_io_traces_every_read() {
+        let sink = LogSink {
+            format: TraceFormat::Json,
         };
-        let metadata = fs::metadata(path.clone());
+        let mount = TestMount::with_sink(
+            |root| fs::write(root.join("blocks.bin"), vec![1; 1000]).unwrap(),
+            Box::new(sink),
+            |fs| fs.direct_io(),
+        );
+        let file = mount.mnt().join("blocks.bin");
+
+        // the same small reads would otherwise be served from the page cache after the first
+        for _ in 0..2 {
+            let output = Command::new("dd")
+                .arg(format!("if={}", file.display()))
+                .args(["bs=100", "count=10", "status=none"])
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            assert_eq!(output.stdout.len(), 1000);
+        }
 
-        trace(req.pid(), 'd', vec![&path.to_str().unwrap(), "unlink"]);
-        self.handle_metadata_on_removal(metadata, fs::remove_file(path.clone()), reply);
+        let path = mount.root().join("blocks.bin");
+        let reads = traces_containing("\"op\":\"read\"")
+            .iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|event| event["path"] == path.to_str().unwrap() && event["size"] == 100)
+            .count();
+        assert_eq!(reads, 20);
     }
 
-    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        debug!("rmdir(parent={}, name={:?})", parent, name);
-        let path = match self.get_path(parent, name) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
-            }
-        };
-        let metadata = fs::metadata(path.clone());
+    fn writes_of(path: &Path) -> Vec<u64> {
+        events_of(path)
+            .iter()
+            .filter(|event| event["op"] == "write")
+            .map(|event| event["size"].as_u64().unwrap())
+            .collect()
+    }
 
-        self.handle_metadata_on_removal(metadata, fs::remove_dir(path), reply);
+    #[test]
+    fn large_writes_arrive_in_few_calls() {
+        let mount = json_mount();
+        let data = vec![7; 4 << 20];
+        fs::write(mount.mnt().join("big.bin"), &data).unwrap();
+        assert_eq!(fs::read(mount.root().join("big.bin")).unwrap(), data);
+
+        assert!(!traces_containing("max_write=1048576, max_readahead=").is_empty());
+        // 4 KiB writes without the negotiation, the kernel still caps them at 128 KiB
+        let writes = writes_of(&mount.root().join("big.bin"));
+        assert_eq!(writes.iter().sum::<u64>(), data.len() as u64);
+        assert!(writes.len() <= 32, "{} writes", writes.len());
     }
 
-    fn symlink(
-        &mut self,
-        _req: &Request<'_>,
-        parent: u64,
-        name: &OsStr,
-        link: &Path,
-        reply: ReplyEntry,
-    ) {
-        debug!(
-            "symlink(parent={}, name={:?}, link={:?})",
-            parent, name, link
+    #[test]
+    fn writeback_cache_gathers_small_writes() {
+        let mount = TestMount::with_sink(
+            |root| fs::write(root.join("log.txt"), "first\n").unwrap(),
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.writeback_cache(),
         );
-        let path = match self.get_path(parent, name) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
+        {
+            let mut file = File::create(mount.mnt().join("small.bin")).unwrap();
+            for i in 0..1000 {
+                file.write_all(&[i as u8; 100]).unwrap();
             }
+        }
+        let data: Vec<u8> = (0..1000).flat_map(|i| [i as u8; 100]).collect();
+        assert_eq!(fs::read(mount.root().join("small.bin")).unwrap(), data);
+        let writes = writes_of(&mount.root().join("small.bin"));
+        assert!(!traces_containing("writeback_cache=true").is_empty());
+        assert!(writes.len() < 100, "{} writes", writes.len());
+
+        // write-only and appending, the kernel picks the offsets
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(mount.mnt().join("log.txt"))
+            .unwrap();
+        file.write_all(b"second\n").unwrap();
+        drop(file);
+        assert_eq!(
+            fs::read_to_string(mount.root().join("log.txt")).unwrap(),
+            "first\nsecond\n"
+        );
+    }
+
+    #[test]
+    fn poll_reports_regular_files_ready() {
+        let mount = TestMount::with_root(|root| fs::write(root.join("input.txt"), "x").unwrap());
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(mount.mnt().join("input.txt"))
+            .unwrap();
+
+        let mut fd = libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLIN | libc::POLLOUT,
+            revents: 0,
         };
+        let start = std::time::Instant::now();
+        assert_eq!(unsafe { libc::poll(&mut fd, 1, 5000) }, 1);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(fd.revents, libc::POLLIN | libc::POLLOUT);
+        // answered by the filesystem, not the kernel's default for lacking poll()
+        let ino = file.metadata().unwrap().ino();
+        assert!(traces_containing(&format!(", ino={ino}, fh="))
+            .iter()
+            .any(|line| line.starts_with("poll(")));
+    }
 
-        self.handle_metadata_on_change(
-            &path,
-            ufs::symlink(link, path.clone()),
-            Reply::Entry(reply),
-        );
+    #[test]
+    fn untouched_files_are_found_without_a_scan() {
+        let mount = TestMount::with_root(|root| {
+            let dir = root.join("a/b/c/d/e");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("deep.txt"), "deep").unwrap();
+        });
+
+        let deep = mount.mnt().join("a/b/c/d/e/deep.txt");
+        assert_eq!(fs::read_to_string(deep).unwrap(), "deep");
+        let scanned = format!("init() entry: DirEntry({:?}", mount.root().join("a"));
+        assert!(traces_containing(&scanned).is_empty());
     }
 
-    fn rename(
-        &mut self,
-        req: &Request<'_>,
-        parent: u64,
-        name: &OsStr,
-        newparent: u64,
-        newname: &OsStr,
-        _flags: u32,
-        reply: ReplyEmpty,
-    ) {
-        debug!(
-            "rename(parent={}, name={:?}, newparent={}, newname={:?})",
-            parent, name, newparent, newname
-        );
-        let path = match self.get_path(parent, name) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
-            }
+    #[test]
+    fn unreadable_dirs_are_left_out_of_the_scan() {
+        // root reads whatever the mode says
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let prepare = |root: &Path| {
+            fs::create_dir_all(root.join("locked/inner")).unwrap();
+            fs::write(root.join("open.txt"), "open").unwrap();
+            fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
         };
-        let newpath = match self.get_path(newparent, newname) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
-            }
+        let unlock = |root: &Path| {
+            fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
         };
 
-        trace(
-            req.pid(),
-            'm',
-            vec![
-                &path.to_str().unwrap(),
-                &newpath.to_str().unwrap(),
-                "rename",
-            ],
+        let mount = TestMount::with_fs(prepare, |fs| fs.preload_attrs());
+        assert_eq!(
+            fs::read_to_string(mount.mnt().join("open.txt")).unwrap(),
+            "open"
+        );
+        let warning = format!("Failed to scan {}: ", mount.root().join("locked").display());
+        assert!(traces_containing(&warning)
+            .iter()
+            .any(|line| line.contains("Permission denied")));
+        unlock(mount.root());
+        drop(mount);
+
+        // nothing is served rather than a partial tree
+        let mount = TestMount::with_fs(prepare, |fs| fs.preload_attrs().strict_init());
+        assert!(fs::read_dir(mount.mnt()).is_err());
+        let root = mount.root().display().to_string();
+        assert!(!traces_containing(&format!("Not serving a partial tree of {root}")).is_empty());
+        unlock(mount.root());
+    }
+
+    #[test]
+    fn init_does_not_wait_for_big_roots() {
+        let mount = TestMount::with_root(|root| {
+            for i in 0..100 {
+                let dir = root.join(format!("dir-{i:03}"));
+                fs::create_dir(&dir).unwrap();
+                for j in 0..1000 {
+                    File::create(dir.join(format!("file-{j:04}"))).unwrap();
+                }
+            }
+        });
+
+        // the first request waits for init() to finish
+        let start = std::time::Instant::now();
+        fs::metadata(mount.mnt()).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_millis(500));
+        assert!(mount.mnt().join("dir-099/file-0999").exists());
+    }
+
+    #[test]
+    fn shadow_changes_leave_the_root_alone() {
+        let scratch = tempfile::tempdir().unwrap();
+        let scratch_dir = scratch.path().join("scratch");
+        fs::create_dir(&scratch_dir).unwrap();
+        let mount = TestMount::with_sink(
+            |_| {},
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.shadow(scratch_dir.clone()),
         );
+        let mnt = mount.mnt();
+
+        fs::create_dir(mnt.join("out")).unwrap();
+        fs::write(mnt.join("out/a.o"), "object").unwrap();
+        assert_eq!(fs::read(mnt.join("out/a.o")).unwrap(), b"object");
+        fs::rename(mnt.join("out/a.o"), mnt.join("out/b.o")).unwrap();
+        assert_eq!(fs::read(mnt.join("out/b.o")).unwrap(), b"object");
+        assert_eq!(fs::read_dir(mount.root()).unwrap().count(), 0);
+
+        // traced by the paths the changes were meant for
+        let root = mount.root();
+        let ops = |path: &Path| -> Vec<String> {
+            let events = events_of(path).into_iter();
+            events
+                .map(|x| x["op"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert!(ops(&root.join("out")).contains(&"mkdir".to_string()));
+        assert!(ops(&root.join("out/a.o")).contains(&"write".to_string()));
+        let renames = traces_containing("\"op\":\"rename\"");
+        let target = format!("\"target\":{:?}", root.join("out/b.o").to_str().unwrap());
+        assert!(renames.iter().any(|line| line.contains(&target)));
+        assert!(!traces_containing(scratch_dir.to_str().unwrap())
+            .iter()
+            .any(|line| line.contains("\"path\"")));
+
+        drop(mount);
+        assert!(!scratch_dir.exists());
+    }
 
-        self.handle_metadata_on_change(
-            &newpath,
-            fs::rename(path.clone(), newpath.clone()),
-            Reply::Empty(reply),
+    #[test]
+    fn io_threads_serve_files_in_parallel() {
+        let mount = TestMount::with_sink(
+            |_| {},
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.io_threads(8),
         );
+        let mnt = mount.mnt().to_path_buf();
+        let chunk = 64 << 10;
+        let workers: Vec<_> = (0..8u8)
+            .map(|i| {
+                let path = mnt.join(format!("file-{i}"));
+                thread::spawn(move || {
+                    let mut file = File::create(&path).unwrap();
+                    for _ in 0..16 {
+                        file.write_all(&vec![i; chunk]).unwrap();
+                    }
+                    drop(file);
+                    assert_eq!(fs::read(&path).unwrap(), vec![i; 16 * chunk]);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // the last events are traced on unmount, those of a file in the order it was written
+        let root = mount.root().to_path_buf();
+        drop(mount);
+        for i in 0..8 {
+            let writes: Vec<_> = events_of(&root.join(format!("file-{i}")))
+                .into_iter()
+                .filter(|event| event["op"] == "write")
+                .collect();
+            let sizes = writes.iter().map(|x| x["size"].as_u64().unwrap());
+            assert_eq!(sizes.sum::<u64>(), 16 * chunk as u64);
+            let offsets: Vec<_> = writes
+                .iter()
+                .map(|x| x["offset"].as_i64().unwrap())
+                .collect();
+            assert!(offsets.windows(2).all(|x| x[0] < x[1]));
+        }
     }
 
-    fn link(
-        &mut self,
-        _req: &Request<'_>,
-        ino: u64,
-        newparent: u64,
-        newname: &OsStr,
-        reply: ReplyEntry,
-    ) {
-        debug!(
-            "link(ino={}, newparent={}, newname={:?})",
-            ino, newparent, newname
+    #[test]
+    fn listings_hand_out_the_current_inode() {
+        let mount =
+            TestMount::with_root(|root| fs::write(root.join("replaced.txt"), "old").unwrap());
+        let old = fs::metadata(mount.mnt().join("replaced.txt"))
+            .unwrap()
+            .ino();
+
+        // taken by opendir(), before the name stands for another file
+        let listing = fs::read_dir(mount.mnt()).unwrap();
+        fs::write(mount.root().join("new.txt"), "new").unwrap();
+        fs::rename(
+            mount.root().join("new.txt"),
+            mount.root().join("replaced.txt"),
+        )
+        .unwrap();
+        let listed = listing
+            .map(|entry| entry.unwrap())
+            .find(|entry| entry.file_name() == "replaced.txt")
+            .unwrap()
+            .ino();
+
+        let new = fs::metadata(mount.mnt().join("replaced.txt"))
+            .unwrap()
+            .ino();
+        assert_ne!(new, old);
+        assert_eq!(listed, new);
+        assert_eq!(fs::read(mount.mnt().join("replaced.txt")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn readdirplus_saves_lookups() {
+        // the listed entries are only kept as long as looked up ones
+        let mount = TestMount::with_fs(
+            |root| {
+                for dir in ["by-name", "listed"] {
+                    fs::create_dir(root.join(dir)).unwrap();
+                    for i in 0..1000 {
+                        fs::write(root.join(dir).join(format!("file-{i:04}")), "").unwrap();
+                    }
+                }
+            },
+            |fs| {
+                let ttl = std::time::Duration::from_secs(60);
+                fs.entry_ttl(ttl).attr_ttl(ttl)
+            },
         );
-        let path = match self.get_path(ino, OsStr::new("")) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
-            }
-        };
-        let newpath = match self.get_path(newparent, newname) {
-            Ok(x) => x,
-            Err(c) => {
-                reply.error(c);
-                return;
-            }
+        // inode numbers are reused by the directories of other tests, the caller tells them apart
+        let lookups = |dir: &str, caller: &str| {
+            let parent = fs::metadata(mount.root().join(dir)).unwrap().ino();
+            traces_containing(&format!("parent={parent}, name="))
+                .iter()
+                .filter(|line| line.starts_with("lookup(") && line.contains(caller))
+                .count()
         };
 
-        self.handle_metadata_on_change(
-            &newpath,
-            fs::hard_link(path.clone(), newpath.clone()),
-            Reply::Entry(reply),
+        // without a listing every stat() by name is a lookup() of its own
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg("seq -f file-%04g 0 999 | xargs stat --format=%s > /dev/null")
+            .current_dir(mount.mnt().join("by-name"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(lookups("by-name", "") >= 1000);
+
+        let child = Command::new("ls")
+            .arg("-l")
+            .arg(mount.mnt().join("listed"))
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let ls = format!("(pid={}, ", child.id());
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().lines().count(),
+            1001
         );
+        assert!(lookups("listed", &ls) < 100, "{}", lookups("listed", &ls));
+
+        // nor is a getattr() needed, the listing came with the attributes
+        let children: HashSet<String> = fs::read_dir(mount.root().join("listed"))
+            .unwrap()
+            .map(|entry| format!("ino={})", entry.unwrap().ino()))
+            .collect();
+        let getattrs = traces_containing(&ls)
+            .iter()
+            .filter(|line| line.starts_with("getattr("))
+            .filter(|line| children.iter().any(|ino| line.ends_with(ino.as_str())))
+            .count();
+        assert_eq!(getattrs, 0);
     }
 
-    fn open(&mut self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
-        debug!("open(ino={}, flags={})", ino, flags);
-        let (_access_mask, read, write) = match flags & libc::O_ACCMODE {
-            libc::O_RDONLY => {
-                // Behavior is undefined, but most filesystems return EACCES
-                if flags & libc::O_TRUNC != 0 {
-                    reply.error(libc::EACCES);
-                    return;
-                }
-                if flags & FMODE_EXEC != 0 {
-                    // Open is from internal exec syscall
-                    (libc::X_OK, true, false)
-                } else {
-                    (libc::R_OK, true, false)
-                }
-            }
-            libc::O_WRONLY => (libc::W_OK, false, true),
-            libc::O_RDWR => (libc::R_OK | libc::W_OK, true, true),
-            // Exactly one access mode flag must be specified
-            _ => {
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
+    // Keeps the summary around after the filesystem has been moved into its session
+    struct SummarySink(Arc<Mutex<Option<LatencySummary>>>);
 
-        match self.attrs.get(&ino) {
-            Some(attrs) => {
-                if attrs.kind == FileKind::File {
-                    let file = match OpenOptions::new()
-                        .read(read)
-                        .write(write)
-                        .open(&attrs.real_path)
-                    {
-                        Ok(x) => x,
-                        Err(_) => {
-                            reply.error(libc::EIO);
-                            return;
-                        }
-                    };
-
-                    let file_handle = file.as_raw_fd() as u64;
-
-                    // access mode has already been checked, so we can safely default to a read trace
-                    let mode = if write { 'w' } else { 'r' };
-                    trace(req.pid(), mode, vec![&attrs.real_path, "open"]);
-                    reply.opened(file_handle, 0);
-                } else {
-                    reply.error(libc::EISDIR);
-                }
-            }
-            None => {
-                reply.error(libc::ENOENT);
-            }
+    impl TraceSink for SummarySink {
+        fn record(&mut self, event: TraceEvent) {
+            assert!(event.latency_us.is_some());
         }
-    }
 
-    fn read(
-        &mut self,
-        _req: &Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
-        size: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyData,
-    ) {
-        debug!(
-            "read(ino={}, fh={}, offset={}, size={})",
-            ino, fh, offset, size
-        );
-        match self.attrs.get(&ino) {
-            Some(attrs) => {
-                if attrs.kind == FileKind::File {
-                    let read = |file: File| -> io::Result<Vec<u8>> {
-                        let file_size = file.metadata()?.len();
-                        let read_size = min(size, file_size.saturating_sub(offset as u64) as u32);
-                        let mut buffer = vec![0; read_size as usize];
-                        file.read_exact_at(&mut buffer, offset as u64)?;
-                        Ok(buffer)
-                    };
-
-                    if let Ok(file) = File::open(&attrs.real_path) {
-                        match read(file) {
-                            Ok(buffer) => {
-                                reply.data(&buffer);
-
-                                // trace(req.pid(), 'r', &["read", &attrs.real_path]);
-                            }
-                            Err(e) => {
-                                reply.error(e.raw_os_error().unwrap_or(libc::EIO));
-                            }
-                        }
-                    } else {
-                        reply.error(libc::ENOENT)
-                    }
-                } else {
-                    reply.error(libc::EISDIR);
-                }
-            }
-            None => {
-                reply.error(libc::ENOENT);
-            }
+        fn summary(&mut self, summary: &LatencySummary) {
+            *self.0.lock().unwrap() = Some(summary.clone());
         }
     }
 
-    fn write(
-        &mut self,
-        _req: &Request<'_>,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        data: &[u8],
-        _write_flags: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyWrite,
-    ) {
-        debug!(
-            "write(ino={}, fh={}, offset={}, size={})",
-            ino,
-            _fh,
-            offset,
-            data.len()
-        );
-        let attrs = match self.attrs.get(&ino) {
-            Some(x) => x,
-            None => {
-                reply.error(libc::ENOENT);
-                return;
-            }
-        };
-
-        let write = || -> io::Result<Metadata> {
-            let mut file = OpenOptions::new().write(true).open(&attrs.real_path)?;
-            file.seek(SeekFrom::Start(offset as u64))?;
-            file.write_all(data)?;
-            let metadata = file.metadata()?;
-            Ok(metadata)
-        };
-
-        match write() {
-            Ok(metadata) => {
-                // //trace(req.pid(), 'w', &["write", &attrs.real_path]);
+    // Holds up the writer thread in the first event until the test lets it go on
+    struct StalledSink {
+        stalled: Option<(mpsc::Sender<()>, Receiver<()>)>,
+        events: Arc<Mutex<Vec<u32>>>,
+    }
 
-                self.attrs
-                    .insert(ino, (metadata, attrs.real_path.clone()).into());
-                reply.written(data.len() as u32);
-            }
-            Err(e) => {
-                reply.error(e.raw_os_error().unwrap_or(libc::EIO));
+    impl TraceSink for StalledSink {
+        fn record(&mut self, event: TraceEvent) {
+            if let Some((stalled, resume)) = self.stalled.take() {
+                stalled.send(()).unwrap();
+                resume.recv().unwrap();
             }
+            self.events.lock().unwrap().push(event.pid);
         }
     }
 
-    fn release(
-        &mut self,
-        _req: &Request<'_>,
-        ino: u64,
-        fh: u64,
-        flags: i32,
-        _lock_owner: Option<u64>,
-        _flush: bool,
-        reply: ReplyEmpty,
-    ) {
-        debug!("release(ino={}, fh={}, flags={})", ino, fh, flags);
-        reply.ok();
-    }
-
-    fn opendir(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
-        debug!("opendir(ino={}, flags={})", ino, flags);
-        let (_access_mask, read, write) = match flags & libc::O_ACCMODE {
-            libc::O_RDONLY => {
-                // Behavior is undefined, but most filesystems return EACCES
-                if flags & libc::O_TRUNC != 0 {
-                    reply.error(libc::EACCES);
-                    return;
-                }
-                if flags & FMODE_EXEC != 0 {
-                    // Open is from internal exec syscall
-                    (libc::X_OK, true, false)
-                } else {
-                    (libc::R_OK, true, false)
-                }
-            }
-            libc::O_WRONLY => (libc::W_OK, false, true),
-            libc::O_RDWR => (libc::R_OK | libc::W_OK, true, true),
-            // Exactly one access mode flag must be specified
-            _ => {
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
+    #[test]
+    fn async_sink_drops_instead_of_blocking() {
+        let (stalled, is_stalled) = mpsc::channel();
+        let (resume, resumed) = mpsc::channel();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = AsyncSink::new(
+            StalledSink {
+                stalled: Some((stalled, resumed)),
+                events: events.clone(),
+            },
+            4,
+        );
+        let event = |pid| TraceEvent::new(pid, 'r', TraceOp::Read, "/input.txt");
 
-        match self.attrs.get(&ino) {
-            Some(attrs) => {
-                if attrs.kind == FileKind::Directory {
-                    let file = match OpenOptions::new()
-                        .write(write)
-                        .read(read)
-                        .open(&attrs.real_path)
-                    {
-                        Ok(x) => x,
-                        Err(_) => {
-                            reply.error(libc::EIO);
-                            return;
-                        }
-                    };
-
-                    let file_handle = file.as_raw_fd() as u64;
-                    reply.opened(file_handle, 0);
-                } else {
-                    reply.error(libc::ENOTDIR);
-                }
-            }
-            None => {
-                reply.error(libc::ENOENT);
-            }
+        sink.record(event(0));
+        is_stalled.recv().unwrap();
+        for pid in 1..10 {
+            sink.record(event(pid));
         }
+        assert_eq!(sink.dropped(), 5);
+
+        resume.send(()).unwrap();
+        sink.flush();
+        assert_eq!(*events.lock().unwrap(), [0, 1, 2, 3, 4]);
+
+        sink.record(event(10));
+        drop(sink);
+        assert_eq!(*events.lock().unwrap(), [0, 1, 2, 3, 4, 10]);
     }
 
-    fn readdir(
-        &mut self,
-        _req: &Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
-    ) {
-        debug!("readdir(ino={}, fh={}, offset={})", ino, fh, offset);
-        if let Some(attrs) = self.attrs.get(&ino) {
-            if attrs.kind == FileKind::Directory {
-                let mut entries = Vec::new();
-                for entry in match fs::read_dir(&attrs.real_path) {
-                    Ok(x) => x,
-                    Err(_) => {
-                        reply.error(libc::EIO);
-                        return;
-                    }
-                } {
-                    let entry = match entry {
-                        Ok(x) => x,
-                        Err(_) => {
-                            reply.error(libc::EIO);
-                            return;
-                        }
-                    };
-                    let metadata = match entry.metadata() {
-                        Ok(x) => x,
-                        Err(_) => {
-                            reply.error(libc::EIO);
-                            return;
-                        }
-                    };
-                    let kind = as_file_kind(metadata.mode());
-                    let file_name = entry.file_name();
-                    let inode = metadata.ino();
-
-                    entries.push((inode, kind, file_name));
-                }
+    #[test]
+    fn async_sink_is_drained_on_unmount() {
+        let sink = LogSink {
+            format: TraceFormat::Json,
+        };
+        let mount =
+            TestMount::with_sink(|_| {}, Box::new(AsyncSink::new(sink, QUEUE_LIMIT)), |fs| fs);
+        let paths: Vec<_> = (0..100)
+            .map(|i| mount.root().join(format!("queued-{i}.txt")))
+            .collect();
+
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg("for i in $(seq 0 99); do echo queued > queued-$i.txt; done")
+            .current_dir(mount.mnt())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        drop(mount);
 
-                for (i, (inode, kind, name)) in entries.into_iter().enumerate() {
-                    if i as i64 >= offset {
-                        let full_name = OsStr::new(&name).to_owned();
-                        let buffer_full =
-                            reply.add(inode, offset + i as i64 + 1, kind.into(), &full_name);
-                        if buffer_full {
-                            break;
-                        }
-                    }
-                }
-                reply.ok();
-            } else {
-                reply.error(libc::ENOTDIR);
-            }
-        } else {
-            reply.error(libc::ENOENT);
+        for path in &paths {
+            assert!(!events_of(path).is_empty(), "{}", path.display());
         }
     }
 
-    fn releasedir(&mut self, _req: &Request<'_>, ino: u64, fh: u64, flags: i32, reply: ReplyEmpty) {
-        debug!("releasedir(ino={}, fh={}, flags={})", ino, fh, flags);
-        reply.ok();
+    #[test]
+    fn unmount_flushes_the_trace() {
+        let dir = TempDir::new().unwrap();
+        let trace = dir.path().join("traces.jsonl");
+        let dest = format!("file:{}", trace.display());
+        let sink = super::trace_sink(Some(&dest), TraceFormat::Json).unwrap();
+        let mount =
+            TestMount::with_sink(|_| {}, Box::new(AsyncSink::new(sink, QUEUE_LIMIT)), |fs| fs);
+
+        fs::write(mount.mnt().join("last.txt"), "last").unwrap();
+        let path = mount.root().join("last.txt");
+        drop(mount);
+
+        let events: Vec<serde_json::Value> = fs::read_to_string(&trace)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(events
+            .iter()
+            .any(|event| event["op"] == "write" && event["path"] == path.to_str().unwrap()));
     }
 
-    fn statfs(&mut self, req: &Request<'_>, ino: u64, reply: ReplyStatfs) {
-        debug!("statfs(ino={})", ino);
-
-        let mut statfs: libc::statvfs = unsafe { std::mem::zeroed() };
-        let attrs = match self.attrs.get(&ino) {
-            Some(x) => x,
-            None => {
-                reply.error(libc::ENOENT);
-                return;
-            }
-        };
-        let path = Path::new(&attrs.real_path);
-        let fd = match path.as_os_str().to_str() {
-            Some(x) => x,
-            None => {
-                reply.error(libc::ENOENT);
-                return;
-            }
-        };
+    // Passes the events on to the test, which no longer owns the sink
+    struct SharedSink(Arc<Mutex<Vec<TraceEvent>>>);
 
-        unsafe {
-            libc::statvfs(fd.as_ptr() as *const i8, &mut statfs);
+    impl TraceSink for SharedSink {
+        fn record(&mut self, event: TraceEvent) {
+            self.0.lock().unwrap().push(event);
         }
+    }
 
-        trace(req.pid(), 'q', vec![&attrs.real_path, "statfs"]);
+    #[test]
+    fn relative_paths_leave_out_the_root() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::create_dir(root.join("a")).unwrap();
+                fs::write(root.join("a/b.c"), "int main;").unwrap();
+            },
+            Box::new(SharedSink(events.clone())),
+            |fs| fs.relative_paths(),
+        );
+        let root = mount.root().to_str().unwrap().to_string();
 
-        reply.statfs(
-            statfs.f_blocks.into(),
-            statfs.f_bfree.into(),
-            statfs.f_bavail.into(),
-            statfs.f_files.into(),
-            statfs.f_ffree.into(),
-            statfs.f_bsize as u32,
-            statfs.f_namemax as u32,
-            statfs.f_frsize as u32,
+        assert_eq!(
+            fs::read_to_string(mount.mnt().join("a/b.c")).unwrap(),
+            "int main;"
         );
+        std::os::unix::fs::symlink(mount.root().join("a/b.c"), mount.mnt().join("link")).unwrap();
+        let output = Command::new("stat")
+            .arg("-f")
+            .arg(mount.mnt())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        drop(mount);
+
+        let events = events.lock().unwrap();
+        let traced = |op: TraceOp, path: &str| {
+            events
+                .iter()
+                .find(|event| event.op == op && event.path == path)
+                .unwrap_or_else(|| panic!("no {op:?} of {path} in {events:?}"))
+        };
+        traced(TraceOp::Read, "a/b.c");
+        traced(TraceOp::Statfs, ".");
+        // the link by its own path, its target as well since it points below the root
+        let link = traced(TraceOp::Symlink, "link");
+        assert_eq!(link.target.as_deref(), Some("a/b.c"));
+        assert!(events.iter().all(|event| !event.path.contains(&root)));
     }
 
-    fn access(&mut self, req: &Request<'_>, ino: u64, mask: i32, reply: ReplyEmpty) {
-        debug!("access(ino={}, mask={})", ino, mask);
-        match self.attrs.get(&ino) {
-            Some(attrs) => {
-                if check_access(attrs.uid, attrs.gid, attrs.mode, req.uid(), req.gid(), mask) {
-                    reply.ok();
-                } else {
-                    reply.error(libc::EACCES);
+    #[test]
+    fn release_reports_the_coverage() {
+        let mount = TestMount::with_sink(
+            |root| {
+                let archive = File::create(root.join("archive.bin")).unwrap();
+                archive.set_len(100 << 20).unwrap();
+            },
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs,
+        );
+        // the kernel releases the file after close() has returned
+        let release = |name: &str, nth: usize| {
+            for _ in 0..100 {
+                let release = events_of(&mount.root().join(name))
+                    .into_iter()
+                    .filter(|event| event["op"] == "release")
+                    .nth(nth);
+                if let Some(release) = release {
+                    return release;
                 }
+                thread::sleep(std::time::Duration::from_millis(50));
             }
-            None => {
-                reply.error(libc::ENOENT);
-            }
-        }
-    }
+            panic!("{name} was not released");
+        };
 
-    // No need to implement this, as it will call mknod() and open() instead
-    // fn create(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, mode: u32, umask: u32, flags: i32, reply: ReplyCreate)
-
-    fn fallocate(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _fh: u64,
-        _offset: i64,
-        _length: i64,
-        _mode: i32,
-        _reply: ReplyEmpty,
-    ) {
-        todo!("fallocate()")
-    }
-
-    fn copy_file_range(
-        &mut self,
-        _req: &Request<'_>,
-        _ino_in: u64,
-        _fh_in: u64,
-        _offset_in: i64,
-        _ino_out: u64,
-        _fh_out: u64,
-        _offset_out: i64,
-        _len: u64,
-        _flags: u32,
-        _reply: ReplyWrite,
-    ) {
-        todo!("copy_file_range()")
+        let output = Command::new("dd")
+            .arg(format!("if={}", mount.mnt().join("archive.bin").display()))
+            .args(["of=/dev/null", "bs=1M", "count=1", "status=none"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let archive = release("archive.bin", 0);
+        assert_eq!(archive["bytes_written"], 0);
+        let coverage = archive["coverage"].as_f64().unwrap();
+        // the kernel reads ahead of dd
+        assert!((0.01..0.02).contains(&coverage), "{archive}");
+        assert!(archive["bytes_read"].as_u64().unwrap() >= 1 << 20);
+
+        // overwritten bytes are only counted once
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg("printf 0123456789 > out.bin; printf abcde | dd of=out.bin bs=1 seek=3 conv=notrunc status=none")
+            .current_dir(mount.mnt())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let out = release("out.bin", 1);
+        assert_eq!(out["bytes_written"], 10);
+        assert_eq!(out["bytes_read"], 0);
+        assert_eq!(out["coverage"], 0.0);
     }
-}
 
-fn check_access(
-    file_uid: u32,
-    file_gid: u32,
-    file_mode: u32,
-    uid: u32,
-    gid: u32,
-    mut access_mask: i32,
-) -> bool {
-    // F_OK tests for existence of file
-    if access_mask == libc::F_OK {
-        return true;
-    }
-
-    let file_mode: i32 = Wrapping(file_mode as i32).0;
-
-    // root is allowed to read & write anything
-    if uid == 0 {
-        // root only allowed to exec if one of the X bits is set
-        access_mask &= libc::X_OK;
-        access_mask -= access_mask & (file_mode >> 6);
-        access_mask -= access_mask & (file_mode >> 3);
-        access_mask -= access_mask & file_mode;
-        return access_mask == 0;
-    }
-
-    if uid == file_uid {
-        access_mask -= access_mask & (file_mode >> 6);
-    } else if gid == file_gid {
-        access_mask -= access_mask & (file_mode >> 3);
-    } else {
-        access_mask -= access_mask & file_mode;
+    fn sha256sum(path: &Path) -> String {
+        let output = Command::new("sha256sum").arg(path).output().unwrap();
+        assert!(output.status.success());
+        let output = String::from_utf8(output.stdout).unwrap();
+        output.split_whitespace().next().unwrap().to_string()
     }
 
-    return access_mask == 0;
-}
+    #[test]
+    fn hash_outputs_on_the_last_release() {
+        let dir = TempDir::new().unwrap();
+        let manifest = dir.path().join("manifest.json");
+        let mount = TestMount::with_sink(
+            |_| {},
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.hash_outputs().manifest(manifest.clone()),
+        );
+        let out = mount.mnt().join("out.bin");
+        let backing = mount.root().join("out.bin");
+        // a hashed release is traced along with the next operation
+        let digest = |nth: usize| {
+            for _ in 0..100 {
+                fs::read_dir(mount.mnt()).unwrap().for_each(drop);
+                let digest = events_of(&backing)
+                    .into_iter()
+                    .filter_map(|event| event["sha256"].as_str().map(str::to_string))
+                    .nth(nth);
+                if let Some(digest) = digest {
+                    return digest;
+                }
+                thread::sleep(std::time::Duration::from_millis(50));
+            }
+            panic!("out.bin was not hashed");
+        };
 
-fn as_file_kind(mut mode: u32) -> FileKind {
-    mode &= libc::S_IFMT as u32;
+        fs::write(&out, vec![7; 3 << 20]).unwrap();
+        assert_eq!(digest(0), sha256sum(&backing));
+
+        // reading it back does not hash it again, writing to it does
+        fs::read(&out).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&out).unwrap();
+        file.write_all(b"more").unwrap();
+        drop(file);
+        let second = digest(1);
+        assert_eq!(second, sha256sum(&backing));
+        let releases = events_of(&backing)
+            .into_iter()
+            .filter(|event| event["op"] == "release")
+            .count();
+        assert!(releases >= 3);
+        assert_eq!(
+            events_of(&backing)
+                .iter()
+                .filter(|event| event.get("sha256").is_some())
+                .count(),
+            2
+        );
 
-    if mode == libc::S_IFREG as u32 {
-        return FileKind::File;
-    } else if mode == libc::S_IFLNK as u32 {
-        return FileKind::Symlink;
-    } else if mode == libc::S_IFDIR as u32 {
-        return FileKind::Directory;
-    } else {
-        unimplemented!("{}", mode);
+        let path = backing.display().to_string();
+        drop(mount);
+        let manifest: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(manifest).unwrap()).unwrap();
+        assert_eq!(manifest["digests"][&path], second);
     }
-}
 
-fn create_new(path: &str) -> io::Result<File> {
-    let mut c = false;
-    if Path::new(&path).exists() {
-        c = true;
+    #[test]
+    fn chrome_trace_stays_valid() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("trace.json");
+        let mut sink = ChromeSink::new(File::create(&path).unwrap()).unwrap();
+        let parse = || -> Vec<serde_json::Value> {
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+        };
+        assert!(parse().is_empty());
+
+        let event = |pid, op, monotonic_us| TraceEvent {
+            comm: Some("cc".to_string()),
+            tid: Some(7),
+            latency_us: Some(30),
+            monotonic_us: Some(monotonic_us),
+            ..TraceEvent::new(pid, 'r', op, "/src/main.c")
+        };
+        sink.record(event(42, TraceOp::Open, 100));
+        let trace = parse();
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace[0]["ph"], "M");
+        assert_eq!(trace[0]["args"]["name"], "cc");
+        assert_eq!(trace[1]["ph"], "X");
+        assert_eq!(trace[1]["cat"], "open");
+        assert_eq!(trace[1]["ts"], 70);
+        assert_eq!(trace[1]["dur"], 30);
+        assert_eq!(trace[1]["pid"], 42);
+        assert_eq!(trace[1]["tid"], 7);
+        assert_eq!(trace[1]["args"]["path"], "/src/main.c");
+
+        // the process is only named once
+        sink.record(event(42, TraceOp::Read, 200));
+        sink.record(event(43, TraceOp::Read, 300));
+        let trace = parse();
+        let phases: Vec<_> = trace.iter().map(|x| x["ph"].as_str().unwrap()).collect();
+        assert_eq!(phases, ["M", "X", "X", "M", "X"]);
     }
 
-    return OpenOptions::new()
-        .create(c)
-        .write(true)
-        .append(true)
-        .open(path);
-}
-
-fn get_logger_format() -> impl Fn(&mut Formatter, &Record) -> io::Result<()> {
-    return |buf: &mut Formatter, record: &Record| {
-        writeln!(buf, "[{}] {}", record.level(), record.args())
-    };
-}
-
-fn trace(
-    pid: u32,
-    op: char,
-    #[cfg(not(debug_assertions))] mut paths: Vec<&str>,
-    #[cfg(debug_assertions)] paths: Vec<&str>,
-) {
-    #[cfg(not(debug_assertions))]
-    paths.pop();
-    let path_str = paths.join("|");
-
-    let ppid_result = std::process::Command::new("ps")
-        .args(&["-o", "ppid= ", &pid.to_string()])
-        .output();
-
-    let ppid: i32 = match ppid_result {
-        Ok(output) => {
-            let ppid_str = String::from_utf8_lossy(&output.stdout);
-            ppid_str.trim().parse::<i32>().unwrap_or_else(|_| -1)
-        }
-        Err(_) => -1,
-    };
-
-    let time = time_from_system_time(&SystemTime::now());
-
-    info!("-> {}: {}|{}|{}|{}", time.0, pid, ppid, op, path_str)
-}
-
-fn main() {
-    let matches = Command::new("Cairn")
-        .author("xelahalo <xelahalo@gmail.com>")
-        .version(crate_version!())
-        .about("Filesystem implementation for tracing I/O operations for forward build systems")
-        .arg(
-            Arg::new("root")
-                .help("Root directory for the filesystem")
-                .required(true),
-        )
-        .arg(
-            Arg::new("mount-point")
-                .help("Mountpoint for the filesystem")
-                .required(true),
-        )
-        // .arg(Arg::new("v").short('v').help("Sets the level of verbosity"))
-        .get_matches();
-
-    let level_filter = LevelFilter::Trace;
-    let root = matches.get_one::<String>("root").unwrap().to_string();
-    let mountpoint = matches.get_one::<String>("mount-point").unwrap();
-    let target = Box::new(create_new(format!("{root}/tracer.log").as_str()).unwrap());
-
-    if level_filter >= LevelFilter::Debug {
-        File::create("1_parsed_matches").expect("Failed to create 1");
+    #[test]
+    fn chrome_trace_of_a_mount() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("trace.json");
+        let sink = ChromeSink::new(File::create(&path).unwrap()).unwrap();
+        let mount = TestMount::with_sink(|_| {}, Box::new(sink), |fs| fs);
+        fs::write(mount.mnt().join("out.txt"), "chrome").unwrap();
+        // the kernel passes on the thread that made the request
+        let pid = unsafe { libc::gettid() } as u32;
+        drop(mount);
+
+        let trace: Vec<serde_json::Value> =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let write = trace
+            .iter()
+            .find(|x| x["ph"] == "X" && x["name"] == "write")
+            .unwrap();
+        assert_eq!(write["pid"], pid);
+        // served by the filesystem thread, not the writer
+        assert_ne!(write["tid"], pid);
+        assert!(write["ts"].as_u64().unwrap() > 0);
+        assert!(trace
+            .iter()
+            .any(|x| x["ph"] == "M" && x["pid"] == pid && x["args"]["name"].is_string()));
     }
 
-    Builder::new()
-        .format(get_logger_format())
-        .target(env_logger::Target::Pipe(target))
-        .filter_level(level_filter)
-        .init();
+    #[test]
+    fn traces_and_manifests_per_process() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().to_path_buf();
+        let sink = PerProcessSink::new(out.clone(), TraceFormat::Json);
+        let parents = vec!["sh".to_string()];
+        let manifest = out.join("manifest.json");
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::write(root.join("a.txt"), "a").unwrap();
+                fs::write(root.join("b.txt"), "b").unwrap();
+            },
+            Box::new(sink),
+            |fs| fs.per_process(parents, out).manifest(manifest),
+        );
+        // `true` last, so the shell does not replace itself with the second cat
+        let status = Command::new("sh")
+            .args(["-c", "cat a.txt >/dev/null; cat b.txt >/dev/null; true"])
+            .current_dir(mount.mnt())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let root = format!("{}/", mount.root().display());
+        drop(mount);
+
+        let files = |prefix: &str| -> Vec<String> {
+            let mut files: Vec<_> = fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name.starts_with(prefix))
+                .map(|name| fs::read_to_string(dir.path().join(name)).unwrap())
+                .collect();
+            files.sort();
+            files
+        };
+        // one cat each, the reads of one do not show up in the trace of the other
+        let traces = files("trace.");
+        let only = |a: &str, b: &str| {
+            traces
+                .iter()
+                .filter(|trace| trace.contains(a) && !trace.contains(b))
+                .count()
+        };
+        assert_eq!(only("a.txt", "b.txt"), 1);
+        assert_eq!(only("b.txt", "a.txt"), 1);
+
+        let inputs: HashSet<_> = files("manifest.")
+            .iter()
+            .map(|json| {
+                let manifest: serde_json::Value = serde_json::from_str(json).unwrap();
+                manifest["inputs"].to_string().replace(&root, "")
+            })
+            .collect();
+        assert!(inputs.contains(r#"["a.txt"]"#));
+        assert!(inputs.contains(r#"["b.txt"]"#));
+    }
 
-    if level_filter >= LevelFilter::Debug {
-        File::create("2_init_logger").expect("Failed to create 2");
+    #[test]
+    fn stats_of_the_tracer() {
+        let mount = json_mount();
+        fs::write(mount.mnt().join("a.txt"), "abc").unwrap();
+        let stats = mount.mnt().join(".cairn/stats");
+
+        let output = Command::new("cat").arg(&stats).output().unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["ops"]["write"]["count"], 1);
+        assert_eq!(json["ops"]["write"]["bytes_written"], 3);
+        assert_eq!(json["bytes_written"], 3);
+        assert_eq!(json["open_files"], 0);
+        assert!(json["inodes"].as_u64().unwrap() >= 2);
+        assert!(json["uptime_s"].is_u64());
+
+        let listing: Vec<_> = fs::read_dir(mount.mnt().join(".cairn"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(listing, ["stats", "control"]);
+        // reserved, and never part of the root listing
+        let eperm = Some(libc::EPERM);
+        assert_eq!(fs::write(&stats, "").unwrap_err().raw_os_error(), eperm);
+        assert_eq!(fs::remove_file(&stats).unwrap_err().raw_os_error(), eperm);
+        let dir = mount.mnt().join(".cairn");
+        assert_eq!(fs::remove_dir(&dir).unwrap_err().raw_os_error(), eperm);
+        let new = dir.join("new.txt");
+        assert_eq!(fs::write(new, "").unwrap_err().raw_os_error(), eperm);
+        assert!(!fs::read_dir(mount.mnt())
+            .unwrap()
+            .any(|entry| entry.unwrap().file_name() == ".cairn"));
+        assert!(!mount.root().join(".cairn").exists());
+        assert!(traces_containing(&format!("{}/.cairn", mount.root().display())).is_empty());
     }
 
-    // unmount filesystem automatically when SIGINT is received
-    let (drop_send, drop_recv) = std::sync::mpsc::channel();
-    let ctrlc = drop_send.clone();
-    let destroy = drop_send.clone();
+    #[test]
+    fn markers_through_the_control_file() {
+        let mount = json_mount();
+        let control = mount.mnt().join(".cairn/control");
+        let root = mount.root().display().to_string();
+        let command = |command: String| fs::write(&control, command);
+
+        command(format!("marker begin touch {}\n", root)).unwrap();
+        let status = Command::new("touch")
+            .arg(mount.mnt().join("stamp.txt"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        command(format!("marker end touch {}\nflush\n", root)).unwrap();
+
+        // the debug lines of the operations mention the root as well
+        let events: Vec<serde_json::Value> = traces_containing(&root)
+            .iter()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let position = |predicate: &dyn Fn(&serde_json::Value) -> bool| {
+            events.iter().position(predicate).unwrap()
+        };
+        let begin = position(&|event| event["path"] == format!("begin touch {}", root));
+        let stamp = mount.root().join("stamp.txt");
+        let create = position(&|event| event["path"] == stamp.to_str().unwrap());
+        let end = position(&|event| event["path"] == format!("end touch {}", root));
+        assert!(begin < create && create < end, "{:?}", (begin, create, end));
+        assert_eq!(events[begin]["op"], "marker");
+        assert_eq!(events[begin]["pid"], unsafe { libc::gettid() } as u32);
+
+        assert_eq!(fs::read_to_string(&control).unwrap(), "tracing\n");
+        command("pause\n".to_string()).unwrap();
+        assert_eq!(fs::read_to_string(&control).unwrap(), "paused\n");
+        fs::write(mount.mnt().join("unseen.txt"), "").unwrap();
+        command(format!("marker unseen {}\nresume\n", root)).unwrap();
+        fs::write(mount.mnt().join("seen.txt"), "").unwrap();
+        assert!(events_of(&mount.root().join("unseen.txt")).is_empty());
+        assert!(!events_of(&mount.root().join("seen.txt")).is_empty());
+        assert!(events_of(Path::new(&format!("unseen {}", root))).is_empty());
+
+        // nothing is taken from a write with an unknown command
+        let e = command("pause\nrestart\n".to_string()).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+        assert_eq!(fs::read_to_string(&control).unwrap(), "tracing\n");
+    }
 
-    // handle graceful shutdown on ctrl-c
-    ctrlc::set_handler(move || {
-        debug!("Received SIGINT, unmounting filesystem");
-        ctrlc.send(()).unwrap();
-    })
-    .unwrap();
+    #[test]
+    fn allowlist_denies_undeclared_reads() {
+        let declared = |deny_writes: bool| {
+            move |fs: TracerFS<Box<dyn TraceSink + Send>>| {
+                let globs = [Glob::new("src/lib/declared.h").unwrap()];
+                let fs = fs.allowlist(Allowlist::new(&globs).unwrap());
+                if deny_writes {
+                    fs.deny_undeclared_writes()
+                } else {
+                    fs
+                }
+            }
+        };
+        let prepare = |root: &Path| {
+            fs::create_dir_all(root.join("src/lib")).unwrap();
+            fs::write(root.join("src/lib/declared.h"), "declared").unwrap();
+            fs::write(root.join("src/lib/secret.h"), "secret").unwrap();
+            fs::write(root.join("secret.txt"), "secret").unwrap();
+        };
+        let sink = || {
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            })
+        };
+        let listing = |dir: &Path| -> Vec<_> {
+            let mut names: Vec<_> = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            names.sort();
+            names
+        };
+        let eacces = Some(libc::EACCES);
 
-    if level_filter >= LevelFilter::Debug {
-        File::create("3_create_channel").expect("Failed to create 3");
-    }
-
-    let mount_options = [
-        MountOption::AllowOther,
-        MountOption::FSName("cairn-fuse".to_string()),
-    ];
-    let guard = match fuser::spawn_mount2(
-        TracerFS::new(root.clone(), destroy),
-        mountpoint,
-        mount_options.as_slice(),
-    ) {
-        Ok(x) => x,
-        Err(_) => todo!(),
-    };
+        let mount = TestMount::with_sink(prepare, sink(), declared(false));
+        let lib = mount.mnt().join("src/lib");
+        assert_eq!(
+            fs::read_to_string(lib.join("declared.h")).unwrap(),
+            "declared"
+        );
+        let e = fs::read_to_string(lib.join("secret.h")).unwrap_err();
+        assert_eq!(e.raw_os_error(), eacces);
+        let e = fs::read_to_string(mount.mnt().join("secret.txt")).unwrap_err();
+        assert_eq!(e.raw_os_error(), eacces);
+        let denied = events_of(&mount.root().join("src/lib/secret.h"));
+        assert!(denied
+            .iter()
+            .any(|event| event["op"] == "denied" && event["result"] == libc::EACCES));
+
+        // the way to the declared file can be listed, without anything else
+        assert_eq!(listing(mount.mnt()), ["src"]);
+        assert_eq!(listing(&lib), ["declared.h"]);
+
+        // outputs of the build are its own to use
+        fs::write(mount.mnt().join("out.o"), "out").unwrap();
+        assert_eq!(
+            fs::read_to_string(mount.mnt().join("out.o")).unwrap(),
+            "out"
+        );
+        drop(mount);
 
-    if level_filter >= LevelFilter::Debug {
-        File::create("4_mount").expect("Failed to create 4");
+        let mount = TestMount::with_sink(prepare, sink(), declared(true));
+        let e = fs::write(mount.mnt().join("out.o"), "out").unwrap_err();
+        assert_eq!(e.raw_os_error(), eacces);
+        assert!(!mount.root().join("out.o").exists());
     }
 
-    let () = drop_recv.recv().unwrap();
-    let _ = fs::remove_file(".cairn-fuse-ready");
-    drop(guard);
-}
+    #[test]
+    fn ring_of_recent_events() {
+        let ops = "all,-lookup_miss".parse().unwrap();
+        let mount = TestMount::with_fs(|_| {}, |fs| fs.ring(3).trace_ops(ops));
+        let events = mount.mnt().join(".cairn-events");
+        let listing = || -> Vec<serde_json::Value> {
+            fs::read_to_string(&events)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
 
-// todo make sure that all the tests can be run in parallel
-#[cfg(test)]
-mod tests {
-    use super::TracerFS;
-    use fuser::MountOption;
-    use std::fs::OpenOptions;
-    use std::io::Write;
-    use std::process::Command;
-    use std::{fs, panic, thread};
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            fs::create_dir(mount.mnt().join(name)).unwrap();
+        }
+        let recent = listing();
+        let paths: Vec<_> = recent.iter().map(|x| x["path"].as_str().unwrap()).collect();
+        let root = mount.root();
+        assert_eq!(
+            paths,
+            ["b.txt", "c.txt", "d.txt"].map(|x| root.join(x).display().to_string())
+        );
+        assert!(recent.iter().all(|x| x["op"] == "mkdir"));
+
+        // neither traced nor listed
+        assert_eq!(listing(), recent);
+        assert!(!traces_containing(".cairn-events")
+            .iter()
+            .any(|line| line.starts_with("-> ")));
+        let names: Vec<_> = fs::read_dir(mount.mnt())
+            .unwrap()
+            .map(|x| x.unwrap().file_name())
+            .collect();
+        assert!(!names.contains(&".cairn-events".into()));
+        assert!(fs::write(&events, "").is_err());
+
+        // only there with a ring
+        let plain = TestMount::new();
+        assert!(!plain.mnt().join(".cairn-events").exists());
+    }
 
-    const DIRS: [&str; 2] = ["./temp/mnt", "./temp/root"];
+    #[test]
+    fn dedup_coalesces_runs() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = DedupSink::new(SharedSink(events.clone()));
+        let event = |pid, op, size| {
+            let mut event = TraceEvent::new(pid, 'r', op, "/input.c");
+            event.ino = Some(5);
+            event.size = Some(size);
+            event
+        };
 
-    fn run_test<T>(test: T, target: &str) -> ()
-    where
-        T: FnOnce() -> () + panic::UnwindSafe,
-    {
-        setup();
-
-        let (send, _) = std::sync::mpsc::channel();
-        let mount_options = [
-            MountOption::AllowOther,
-            MountOption::FSName("cairn-fuse-test".to_string()),
-        ];
-
-        let destroy = send.clone();
-        thread::spawn(move || {
-            let guard = fuser::spawn_mount2(
-                TracerFS::new(DIRS[0].to_string(), destroy),
-                DIRS[1],
-                &mount_options,
-            )
+        for (pid, op, times) in [
+            (1, TraceOp::Read, 3),
+            (2, TraceOp::Read, 2),
+            (2, TraceOp::Write, 1),
+            (2, TraceOp::Read, 1),
+        ] {
+            for _ in 0..times {
+                sink.record(event(pid, op, 100));
+            }
+        }
+        assert_eq!(events.lock().unwrap().len(), 3);
+        sink.flush();
+
+        let runs: Vec<_> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| (event.pid, event.op, event.count, event.size))
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                (1, TraceOp::Read, Some(3), Some(300)),
+                (2, TraceOp::Read, Some(2), Some(200)),
+                (2, TraceOp::Write, None, Some(100)),
+                (2, TraceOp::Read, None, Some(100)),
+            ]
+        );
+        let line = events.lock().unwrap()[0]
+            .render(TraceFormat::Human)
             .unwrap();
+        assert!(line.ends_with(" x3"), "{line}");
+    }
 
-            drop(guard);
-            teardown();
+    #[test]
+    fn dedup_counts_repeated_reads() {
+        let sink = DedupSink::new(LogSink {
+            format: TraceFormat::Json,
         });
+        let mount = TestMount::with_sink(
+            |root| fs::write(root.join("header.h"), vec![b'h'; 1000]).unwrap(),
+            Box::new(sink),
+            |fs| fs.direct_io(),
+        );
+        let path = mount.root().join("header.h");
 
-        // wait for the filesystem to be mounted
-        // TODO: remove this and wait for session to be mounted
-        thread::sleep(std::time::Duration::from_secs(1));
+        let output = Command::new("dd")
+            .arg(format!("if={}", mount.mnt().join("header.h").display()))
+            .args(["bs=10", "count=100", "status=none"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        drop(mount);
+
+        let reads: Vec<_> = events_of(&path)
+            .into_iter()
+            .filter(|event| event["op"] == "read")
+            .collect();
+        assert_eq!(reads.len(), 1, "{:?}", reads);
+        assert_eq!(reads[0]["count"], 100);
+        assert_eq!(reads[0]["size"], 1000);
+    }
 
-        let result = panic::catch_unwind(|| {
-            test();
-        });
+    #[test]
+    fn unmount_summarizes_latencies() {
+        let summary = Arc::new(Mutex::new(None));
+        let mount = TestMount::with_sink(
+            |root| fs::write(root.join("blocks.bin"), vec![1; 1000]).unwrap(),
+            Box::new(SummarySink(summary.clone())),
+            |fs| fs.direct_io(),
+        );
 
-        send.send(()).unwrap();
+        let output = Command::new("dd")
+            .arg(format!("if={}", mount.mnt().join("blocks.bin").display()))
+            .args(["bs=100", "count=10", "status=none"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        drop(mount);
+
+        let summary = summary.lock().unwrap().take().unwrap();
+        let read = &summary["read"];
+        assert_eq!(read.count, 10);
+        assert!(read.p50_us <= read.p95_us && read.p95_us <= read.total_us);
+        assert!(summary.contains_key("lookup") && summary.contains_key("open"));
+    }
 
-        // assert equality of the log files
-        match compare_contents(get_current_log_path(target), get_previous_log_path(target)) {
-            Ok(are_equal) => {
-                assert!(are_equal);
-                return;
-            }
-            Err(_) => {
-                // Some of the paths didn't exist, in that case ignore
-            }
+    #[test]
+    fn sessions_sum_up_a_handle() {
+        let mount = TestMount::with_sink(
+            |root| fs::write(root.join("in.txt"), "input").unwrap(),
+            Box::new(LogSink {
+                format: TraceFormat::Json,
+            }),
+            |fs| fs.sessions(),
+        );
+        let mut file = File::create(mount.mnt().join("out.txt")).unwrap();
+        for chunk in ["first ", "second ", "third"] {
+            file.write_all(chunk.as_bytes()).unwrap();
         }
+        drop(file);
+        let input = fs::read_to_string(mount.mnt().join("in.txt")).unwrap();
+        assert_eq!(input, "input");
+        // the kernel passes on the thread that made the request
+        let pid = unsafe { libc::gettid() } as u32;
+        // releases are asynchronous, the session only ends some time after close()
+        let session = |name: &str| {
+            for _ in 0..100 {
+                let events: Vec<_> = events_of(&mount.root().join(name))
+                    .into_iter()
+                    .filter(|event| event["op"] != "lookup_miss")
+                    .collect();
+                if !events.is_empty() {
+                    return events;
+                }
+                thread::sleep(std::time::Duration::from_millis(50));
+            }
+            panic!("{name} was not released");
+        };
 
-        // assert that logfile contains the target
-        let contents = fs::read_to_string(get_current_log_path(target)).unwrap();
-        assert!(contents.contains(target));
+        let events = session("out.txt");
+        assert_eq!(events.len(), 1, "{:?}", events);
+        assert_eq!(events[0]["op"], "output");
+        assert_eq!(events[0]["bytes_written"], 18);
+        assert_eq!(events[0]["first_offset"], 0);
+        assert_eq!(events[0]["last_offset"], 18);
+        assert_eq!(events[0]["pid"], pid);
+
+        let events = session("in.txt");
+        assert_eq!(events.len(), 1, "{:?}", events);
+        assert_eq!(events[0]["op"], "input");
+        assert_eq!(events[0]["bytes_read"], 5);
+    }
 
-        if result.is_ok() {
-            let contents = fs::read_to_string(get_current_log_path(target)).unwrap();
+    #[test]
+    fn unmount_counts_operations() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("summary.json");
+        let summary_file = path.clone();
+        let summary = Arc::new(Mutex::new(None));
+        let mount = TestMount::with_sink(
+            |root| {
+                fs::write(root.join("blocks.bin"), vec![1; 1000]).unwrap();
+                fs::create_dir_all(root.join("full/child")).unwrap();
+            },
+            Box::new(SummarySink(summary.clone())),
+            |fs| fs.direct_io().summary_file(summary_file),
+        );
 
-            let mut f = OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .create(true)
-                .open(get_previous_log_path(target))
-                .unwrap();
-            f.write_all(contents.as_bytes()).unwrap();
-            f.flush().unwrap();
+        let output = Command::new("dd")
+            .arg(format!("if={}", mount.mnt().join("blocks.bin").display()))
+            .arg(format!("of={}", mount.mnt().join("copy.bin").display()))
+            .args(["bs=100", "count=10", "status=none"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let e = fs::remove_dir(mount.mnt().join("full")).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTEMPTY));
+        drop(mount);
+
+        let summary = summary.lock().unwrap().take().unwrap();
+        let (read, write) = (&summary["read"], &summary["write"]);
+        assert_eq!(
+            (read.count, read.bytes_read, read.bytes_written),
+            (10, 1000, 0)
+        );
+        assert_eq!((write.count, write.bytes_written), (10, 1000));
+        assert!(read.errors.is_empty() && write.errors.is_empty());
+        assert_eq!(summary["rmdir"].count, 1);
+        assert_eq!(summary["rmdir"].errors, [(libc::ENOTEMPTY, 1)].into());
+        assert!(read.p50_us <= read.p95_us && read.p95_us <= read.p99_us);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["read"]["count"], 10);
+        assert_eq!(json["write"]["bytes_written"], 1000);
+        assert_eq!(json["rmdir"]["errors"][libc::ENOTEMPTY.to_string()], 1);
+    }
 
-            fs::remove_file(get_current_log_path(target)).unwrap();
+    #[test]
+    fn attributes_are_cached_for_the_ttl() {
+        let mount = TestMount::with_fs(
+            |root| fs::write(root.join("cached.txt"), "cached").unwrap(),
+            |fs| {
+                let ttl = std::time::Duration::from_secs(60);
+                fs.entry_ttl(ttl).attr_ttl(ttl)
+            },
+        );
+        let path = mount.mnt().join("cached.txt");
+        let backing = fs::metadata(mount.root().join("cached.txt")).unwrap();
+        let first = fs::metadata(&path).unwrap();
+        assert_eq!(
+            (first.mtime(), first.mtime_nsec(), first.atime_nsec()),
+            (backing.mtime(), backing.mtime_nsec(), backing.atime_nsec())
+        );
 
-            assert!(true);
-            return;
+        for _ in 0..50 {
+            assert_eq!(fs::metadata(&path).unwrap().len(), 6);
         }
-
-        assert!(false)
+        let pid = unsafe { libc::gettid() } as u32;
+        let ino = format!("ino={})", first.ino());
+        let stats = traces_containing(&format!("(pid={}, ", pid))
+            .iter()
+            .filter(|line| line.starts_with("getattr(") && line.ends_with(ino.as_str()))
+            .count();
+        let lookups = traces_containing(&format!("(pid={}, ", pid))
+            .iter()
+            .filter(|line| line.starts_with("lookup(") && line.ends_with("name=\"cached.txt\")"))
+            .count();
+        assert!(
+            stats + lookups <= 2,
+            "{} getattr, {} lookup",
+            stats,
+            lookups
+        );
     }
 
-    fn setup() {
-        for dir in DIRS.iter() {
-            Command::new("mkdir").args(&["-p", dir]).output().unwrap();
-        }
+    #[test]
+    fn timeouts_spare_repeated_lookups() {
+        let tree = |root: &Path| {
+            for dir in ["a", "a/b", "c"] {
+                fs::create_dir(root.join(dir)).unwrap();
+                for n in 0..10 {
+                    fs::write(root.join(dir).join(n.to_string()), "x").unwrap();
+                }
+            }
+        };
+        // the lookups and stats of each of 5 runs of stat over the tree and names missing from
+        // it, the way a configure script probes for headers
+        let runs = |mount: &TestMount| -> Vec<usize> {
+            let paths: Vec<_> = ["a", "a/b", "c"]
+                .iter()
+                .flat_map(|dir| (0..12).map(move |n| mount.mnt().join(dir).join(n.to_string())))
+                .collect();
+            (0..5)
+                .map(|_| {
+                    let child = Command::new("stat")
+                        .args(["-c", "%s"])
+                        .args(&paths)
+                        .stdout(std::process::Stdio::null())
+                        .stderr(std::process::Stdio::null())
+                        .spawn()
+                        .unwrap();
+                    let pid = child.id();
+                    child.wait_with_output().unwrap();
+                    traces_containing(&format!("(pid={}, ", pid))
+                        .iter()
+                        .filter(|line| line.starts_with("lookup(") || line.starts_with("getattr("))
+                        .count()
+                })
+                .collect()
+        };
 
-        // INIT.call_once(|| {
-        //     let target = Box::new(create_new(&path).unwrap());
-        //     env_logger::Builder::new()
-        //         .format(super::get_logger_format())
-        //         .target(env_logger::Target::Pipe(target))
-        //         .filter_level(log::LevelFilter::Trace)
-        //         .is_test(true)
-        //         .init();
-        // })
-    }
+        let uncached = runs(&TestMount::with_root(tree));
+        assert!(uncached.iter().all(|n| *n >= 36), "{:?}", uncached);
 
-    fn teardown() {
-        // somehow unmounting is not working as expected so I have to call the umount util manually
-        Command::new("umount").args(&[DIRS[0]]).output().unwrap();
-        for dir in DIRS.iter() {
-            Command::new("rm").args(&["-rf", dir]).output().unwrap();
-        }
+        let mount = TestMount::with_fs(tree, |fs| {
+            let ttl = std::time::Duration::from_secs(1);
+            fs.entry_ttl(ttl).attr_ttl(ttl).negative_ttl(ttl)
+        });
+        let cached = runs(&mount);
+        assert!(cached[0] >= 36, "{:?}", cached);
+        assert!(
+            cached[1..].iter().sum::<usize>() < cached[0],
+            "{:?}",
+            cached
+        );
+
+        // remembered as missing, until created through the mount
+        assert!(!mount.mnt().join("a/10").exists());
+        fs::write(mount.mnt().join("a/10"), "found").unwrap();
+        assert_eq!(fs::read(mount.mnt().join("a/10")).unwrap(), b"found");
     }
 
-    fn compare_contents(old: String, new: String) -> std::io::Result<bool> {
-        let old_contents = fs::read_to_string(old)?;
-        let new_contents = fs::read_to_string(new)?;
+    #[test]
+    fn flock_serializes_processes() {
+        let mount = TestMount::with_root(|root| fs::write(root.join("build.lock"), "").unwrap());
+        let dir = TempDir::new().unwrap();
+        let log = dir.path().join("steps.log");
+        let step = |name: &str| {
+            Command::new("flock")
+                .arg(mount.mnt().join("build.lock"))
+                .arg("sh")
+                .arg("-c")
+                .arg(format!(
+                    "echo {name} >> {log}; sleep 0.3; echo {name} >> {log}",
+                    log = log.display()
+                ))
+                .spawn()
+                .unwrap()
+        };
 
-        Ok(old_contents == new_contents)
+        let steps = [step("a"), step("b")];
+        for mut step in steps {
+            assert!(step.wait().unwrap().success());
+        }
 
-        // // let d = normalized_damerau_levenshtein(&old_contents, &new_contents);
-        // // let min_d = std::cmp::min(old_contents.len(), new_contents.len());
-        // // let d = hamming(&old_contents, &new_contents).expect("Could not compare contents");
-        // // let sim = 1.0 - (d as f64 / min_d as f64);
-        // let d = jaro(&old_contents, &new_contents);
-        // println!("Distance: {}", d);
-        // Ok((1.0 - d) < 0.15)
+        let log = fs::read_to_string(&log).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0] == lines[1] && lines[2] == lines[3], "{lines:?}");
     }
 
-    fn get_current_log_path(target: &str) -> String {
-        return format!("./test-dir/{target}.log");
-    }
+    #[test]
+    fn locks_reach_the_backing_file() {
+        let mount = TestMount::with_root(|root| fs::write(root.join("build.lock"), "").unwrap());
+        let flock = || {
+            Command::new("flock")
+                .arg("-n")
+                .arg(mount.mnt().join("build.lock"))
+                .arg("true")
+                .status()
+                .unwrap()
+                .success()
+        };
 
-    fn get_previous_log_path(target: &str) -> String {
-        return format!("./test-dir/previous/{target}.log");
-    }
+        // a process outside the mount holds a write lock until its stdin is closed
+        let mut holder = Command::new("perl")
+            .arg("-MFcntl")
+            .arg("-e")
+            .arg(
+                "open(F, '+<', $ARGV[0]) or die; $| = 1; \
+                my $lock = pack('ssx4qqix4', F_WRLCK, 0, 0, 0, 0); \
+                fcntl(F, F_SETLK, $lock) or die; \
+                print \"locked\\n\"; <STDIN>",
+            )
+            .arg(mount.root().join("build.lock"))
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(holder.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!(line, "locked\n");
 
-    #[test]
-    fn init() {
-        run_test(|| {}, "init")
+        assert!(!flock());
+        drop(holder.stdin.take());
+        assert!(holder.wait().unwrap().success());
+        assert!(flock());
     }
 
     #[test]
-    fn touch() {
-        run_test(
-            || {
-                Command::new("touch")
-                    .args(&[format!("{}/touch.txt", DIRS[1])])
-                    .output()
-                    .unwrap();
-            },
-            "touch",
-        )
+    fn allow_other_is_opt_in() {
+        let options = |args: &[&str]| {
+            let matches =
+                super::cli().get_matches_from([&["cairn-fuse", "/root", "/mnt"], args].concat());
+            super::mount_options(&matches)
+        };
+        assert_eq!(
+            options(&[]),
+            [
+                MountOption::FSName("cairn-fuse".to_string()),
+                MountOption::DefaultPermissions
+            ]
+        );
+        assert_eq!(
+            options(&["-o", "allow_other,fsname=shared", "-o", "noexec"]),
+            [
+                MountOption::AllowOther,
+                MountOption::FSName("shared".to_string()),
+                MountOption::NoExec,
+                MountOption::DefaultPermissions
+            ]
+        );
+        assert_eq!(
+            options(&["--allow-root"]),
+            [
+                MountOption::AllowRoot,
+                MountOption::FSName("cairn-fuse".to_string()),
+                MountOption::DefaultPermissions
+            ]
+        );
+        assert!(options(&["--allow-other"]).contains(&MountOption::AllowOther));
+        for args in [&["-o", "bogus"], &["--allow-other", "--allow-root"]] {
+            let args = [&["cairn-fuse", "/root", "/mnt"], args.as_slice()].concat();
+            assert!(super::cli().try_get_matches_from(args).is_err());
+        }
+
+        let mount = |mount_options: Vec<MountOption>| {
+            let sink = LogSink {
+                format: TraceFormat::Human,
+            };
+            TestMount::with_options(
+                |root| fs::set_permissions(root, fs::Permissions::from_mode(0o755)).unwrap(),
+                Box::new(sink),
+                |fs| fs,
+                mount_options,
+            )
+        };
+        let ls = |mount: &TestMount| {
+            Command::new("setpriv")
+                .args(["--reuid=65534", "--regid=65534", "--clear-groups", "ls"])
+                .arg(mount.mnt())
+                .status()
+                .unwrap()
+                .success()
+        };
+
+        let private = mount(options(&[]));
+        assert!(fs::read_dir(private.mnt()).is_ok());
+        assert!(!ls(&private));
+        if super::allow_other_permitted() {
+            let shared = mount(options(&["--allow-other"]));
+            assert!(ls(&shared));
+        }
     }
 
     #[test]
-    fn mkdir() {
-        run_test(
-            || {
-                Command::new("mkdir")
-                    .args(&[format!("{}/mkdir", DIRS[1])])
-                    .output()
-                    .unwrap();
+    fn confined_symlinks_stay_in_the_root() {
+        let mount = TestMount::with_fs(
+            |root| {
+                fs::create_dir(root.join("data")).unwrap();
+                fs::write(root.join("data/inside.txt"), "inside").unwrap();
+                std::os::unix::fs::symlink("/etc/passwd", root.join("passwd")).unwrap();
+                std::os::unix::fs::symlink("data/../data/inside.txt", root.join("inside")).unwrap();
+                std::os::unix::fs::symlink("../../../../../../../tmp", root.join("up")).unwrap();
             },
-            "mkdir",
-        )
+            |fs| fs.confine(),
+        );
+        let cat = |name: &str| {
+            Command::new("cat")
+                .arg(mount.mnt().join(name))
+                .output()
+                .unwrap()
+        };
+
+        let output = cat("passwd");
+        assert!(!output.status.success());
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Permission denied"));
+        assert!(fs::read_link(mount.mnt().join("up")).is_err());
+        assert_eq!(cat("inside").stdout, b"inside");
+        assert_eq!(cat("data/inside.txt").stdout, b"inside");
     }
 
     // #[test]
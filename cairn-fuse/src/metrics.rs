@@ -0,0 +1,122 @@
+use crate::sink::listen;
+use crate::Latencies;
+use log::warn;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+// What the filesystem holds on to, as of the last operation it took. Scrapes read them without
+// waiting for it
+#[derive(Default)]
+pub(crate) struct Gauges {
+    pub open_files: AtomicUsize,
+    pub open_dirs: AtomicUsize,
+    pub inodes: AtomicUsize,
+}
+
+// Serves the counters of the tracer in the Prometheus text format to every connection to a unix
+// socket, on a thread of its own. A scrape only holds the lock of the operation counters long
+// enough to copy them
+pub struct Metrics {
+    path: PathBuf,
+    listener: Option<UnixListener>,
+    stop: Arc<AtomicBool>,
+    pub(crate) gauges: Arc<Gauges>,
+}
+
+impl Metrics {
+    pub fn bind(path: PathBuf) -> io::Result<Metrics> {
+        let listener = listen(&path)?;
+        Ok(Metrics {
+            path,
+            listener: Some(listener),
+            stop: Arc::default(),
+            gauges: Arc::default(),
+        })
+    }
+
+    pub(crate) fn serve(&mut self, latencies: Arc<Mutex<Latencies>>, mounted: Instant) {
+        let listener = match self.listener.take() {
+            Some(x) => x,
+            None => return,
+        };
+        let (stop, gauges) = (self.stop.clone(), self.gauges.clone());
+        let spawned = thread::Builder::new()
+            .name("metrics".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    let result = stream.and_then(|mut stream| {
+                        let text = render(&latencies, &gauges, mounted);
+                        stream.write_all(text.as_bytes())
+                    });
+                    if let Err(e) = result {
+                        warn!("Failed to serve a metrics scrape: {}", e);
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("Failed to serve the metrics: {}", e);
+        }
+    }
+}
+
+impl Drop for Metrics {
+    fn drop(&mut self) {
+        // wakes the thread up from accept() to notice
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = UnixStream::connect(&self.path);
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn render(latencies: &Mutex<Latencies>, gauges: &Gauges, mounted: Instant) -> String {
+    let (ops, bytes_read, bytes_written) = {
+        let latencies = latencies.lock().unwrap();
+        let ops: Vec<_> = (latencies.ops.iter())
+            .filter(|(_, stats)| stats.count > 0)
+            .map(|(op, stats)| {
+                let errors = stats.errors.values().sum::<u64>();
+                (*op, stats.count, stats.total_us, errors)
+            })
+            .collect();
+        let stats = latencies.ops.values();
+        let bytes_read = stats.clone().map(|x| x.bytes_read).sum::<u64>();
+        (ops, bytes_read, stats.map(|x| x.bytes_written).sum::<u64>())
+    };
+
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, value: &dyn std::fmt::Display| {
+        let _ = writeln!(text, "# TYPE cairn_{name} {kind}");
+        let _ = writeln!(text, "cairn_{name} {value}");
+    };
+    for (op, count, total_us, errors) in ops {
+        metric(&format!("{op}_total"), "counter", &count);
+        metric(&format!("{op}_errors_total"), "counter", &errors);
+        metric(
+            &format!("{op}_seconds_total"),
+            "counter",
+            &(total_us as f64 / 1e6),
+        );
+    }
+    metric("read_bytes_total", "counter", &bytes_read);
+    metric("written_bytes_total", "counter", &bytes_written);
+    let open_files = gauges.open_files.load(Ordering::Relaxed);
+    metric("open_files", "gauge", &open_files);
+    let open_dirs = gauges.open_dirs.load(Ordering::Relaxed);
+    metric("open_dirs", "gauge", &open_dirs);
+    metric("inodes", "gauge", &gauges.inodes.load(Ordering::Relaxed));
+    let uptime = mounted.elapsed().as_secs();
+    metric("uptime_seconds", "gauge", &uptime);
+    text
+}
@@ -0,0 +1,806 @@
+use crate::fbs;
+use crate::{
+    render_header, render_summary, LatencySummary, TraceEvent, TraceFormat, TraceHeader, TraceSink,
+};
+use flatbuffers::FlatBufferBuilder;
+use log::warn;
+use serde_json::json;
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// events kept around while the peer of a socket is not up yet, older ones are dropped
+const PENDING_LIMIT: usize = 4096;
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(100);
+// events waiting for the writer thread of an AsyncSink, more are dropped instead of waited for
+pub const QUEUE_LIMIT: usize = 65536;
+
+// Listens on the unix socket `path`, replacing the one left behind by a run that did not get to
+// remove it. Nobody is listening on that one anymore
+pub(crate) fn listen(path: &Path) -> io::Result<UnixListener> {
+    let stale = fs::symlink_metadata(path).is_ok_and(|x| x.file_type().is_socket())
+        && UnixStream::connect(path).is_err();
+    if stale {
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+// fsync() of a trace, there is nothing to sync for the pipes and terminals of stdout
+fn sync_data<F: AsFd>(file: &F) {
+    let result = file
+        .as_fd()
+        .try_clone_to_owned()
+        .and_then(|fd| File::from(fd).sync_data());
+    match result {
+        Err(e) if e.raw_os_error() != Some(libc::EINVAL) => warn!("Failed to sync trace: {}", e),
+        _ => {}
+    }
+}
+
+// Writes one event per line, used for files and stdout
+pub struct WriterSink<W: Write> {
+    writer: W,
+    format: TraceFormat,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W, format: TraceFormat) -> WriterSink<W> {
+        WriterSink { writer, format }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.writer, "{}", line).and_then(|_| self.writer.flush()) {
+            warn!("Failed to write trace: {}", e);
+        }
+    }
+}
+
+impl<W: Write + AsFd> TraceSink for WriterSink<W> {
+    fn header(&mut self, header: &TraceHeader) {
+        self.write_line(&render_header(header, self.format));
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        if let Some(line) = event.render(self.format) {
+            self.write_line(&line);
+        }
+    }
+
+    fn summary(&mut self, summary: &LatencySummary) {
+        for line in render_summary(summary, self.format) {
+            self.write_line(&line);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            warn!("Failed to write trace: {}", e);
+        }
+    }
+
+    fn sync(&mut self) {
+        self.flush();
+        sync_data(&self.writer);
+    }
+}
+
+// Streams newline delimited events to a listening process, connecting lazily so the peer may
+// come up after the filesystem has been mounted
+pub struct UnixSink {
+    path: PathBuf,
+    format: TraceFormat,
+    stream: Option<UnixStream>,
+    last_attempt: Option<Instant>,
+    pending: VecDeque<String>,
+}
+
+impl UnixSink {
+    pub fn new(path: PathBuf, format: TraceFormat) -> UnixSink {
+        UnixSink {
+            path,
+            format,
+            stream: None,
+            last_attempt: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn connect(&mut self) {
+        if self
+            .last_attempt
+            .is_some_and(|last| last.elapsed() < RECONNECT_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_attempt = Some(Instant::now());
+        // the peer not listening yet is expected, the events stay pending until it does
+        if let Ok(stream) = UnixStream::connect(&self.path) {
+            self.stream = Some(stream);
+        }
+    }
+
+    fn send_pending(&mut self) {
+        let stream = match self.stream.as_mut() {
+            Some(x) => x,
+            None => return,
+        };
+
+        while let Some(line) = self.pending.front() {
+            if let Err(e) = stream.write_all(line.as_bytes()) {
+                warn!("Lost the connection to {}: {}", self.path.display(), e);
+                self.stream = None;
+                return;
+            }
+            self.pending.pop_front();
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        if self.pending.len() == PENDING_LIMIT {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(format!("{}\n", line));
+
+        if self.stream.is_none() {
+            self.connect();
+        }
+        self.send_pending();
+    }
+}
+
+impl TraceSink for UnixSink {
+    fn header(&mut self, header: &TraceHeader) {
+        self.send(&render_header(header, self.format));
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        if let Some(line) = event.render(self.format) {
+            self.send(&line);
+        }
+    }
+
+    fn summary(&mut self, summary: &LatencySummary) {
+        for line in render_summary(summary, self.format) {
+            self.send(&line);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.stream.is_none() {
+            self.connect();
+        }
+        self.send_pending();
+    }
+}
+
+// Listens on a unix socket and writes every event to each consumer connected at the time. Sockets
+// are not blocked on, a consumer that falls behind is disconnected instead
+pub struct BroadcastSink {
+    path: PathBuf,
+    format: TraceFormat,
+    listener: UnixListener,
+    consumers: Vec<UnixStream>,
+}
+
+impl BroadcastSink {
+    pub fn bind(path: PathBuf, format: TraceFormat) -> io::Result<BroadcastSink> {
+        let listener = listen(&path)?;
+        listener.set_nonblocking(true)?;
+        Ok(BroadcastSink {
+            path,
+            format,
+            listener,
+            consumers: Vec::new(),
+        })
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.consumers.push(stream),
+                    Err(e) => warn!("Failed to accept a trace consumer: {}", e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("Failed to accept a trace consumer: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        self.accept();
+        let line = format!("{}\n", line);
+        self.consumers
+            .retain_mut(|consumer| match consumer.write_all(line.as_bytes()) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Disconnected a trace consumer: {}", e);
+                    false
+                }
+            });
+    }
+}
+
+impl TraceSink for BroadcastSink {
+    fn header(&mut self, header: &TraceHeader) {
+        self.send(&render_header(header, self.format));
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        if let Some(line) = event.render(self.format) {
+            self.send(&line);
+        }
+    }
+
+    fn summary(&mut self, summary: &LatencySummary) {
+        for line in render_summary(summary, self.format) {
+            self.send(&line);
+        }
+    }
+}
+
+impl Drop for BroadcastSink {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// Hands every event to both sinks, like the trace log and the live consumers of a socket
+pub struct TeeSink<A: TraceSink, B: TraceSink> {
+    first: A,
+    second: B,
+}
+
+impl<A: TraceSink, B: TraceSink> TeeSink<A, B> {
+    pub fn new(first: A, second: B) -> TeeSink<A, B> {
+        TeeSink { first, second }
+    }
+}
+
+impl<A: TraceSink, B: TraceSink> TraceSink for TeeSink<A, B> {
+    fn header(&mut self, header: &TraceHeader) {
+        self.first.header(header);
+        self.second.header(header);
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        self.first.record(event.clone());
+        self.second.record(event);
+    }
+
+    fn summary(&mut self, summary: &LatencySummary) {
+        self.first.summary(summary);
+        self.second.summary(summary);
+    }
+
+    fn flush(&mut self) {
+        self.first.flush();
+        self.second.flush();
+    }
+
+    fn sync(&mut self) {
+        self.first.sync();
+        self.second.sync();
+    }
+}
+
+// Writes size prefixed FlatBuffers events, see schema/trace.fbs. The header and the summary have
+// no place in the schema, the summary can be recomputed from the latencies of the events
+pub struct FlatBufferSink<W: Write> {
+    writer: W,
+    builder: FlatBufferBuilder<'static>,
+}
+
+impl<W: Write> FlatBufferSink<W> {
+    pub fn new(writer: W) -> FlatBufferSink<W> {
+        FlatBufferSink {
+            writer,
+            builder: FlatBufferBuilder::new(),
+        }
+    }
+}
+
+impl<W: Write + AsFd> TraceSink for FlatBufferSink<W> {
+    fn record(&mut self, event: TraceEvent) {
+        let buf = fbs::encode(&mut self.builder, &event);
+        if let Err(e) = self.writer.write_all(buf).and_then(|_| self.writer.flush()) {
+            warn!("Failed to write trace: {}", e);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            warn!("Failed to write trace: {}", e);
+        }
+    }
+
+    fn sync(&mut self) {
+        self.flush();
+        sync_data(&self.writer);
+    }
+}
+
+// Writes a JSON array of Chrome trace events, for about://tracing and Perfetto. Every operation
+// is a complete event spanning its latency, the closing bracket is written after each one and
+// overwritten by the next, so the file stays valid JSON when the daemon is killed
+pub struct ChromeSink<W: Write + Seek> {
+    writer: W,
+    empty: bool,
+    // processes already given a name in the trace
+    named: HashSet<u32>,
+}
+
+impl<W: Write + Seek> ChromeSink<W> {
+    pub fn new(mut writer: W) -> io::Result<ChromeSink<W>> {
+        writer.write_all(b"[\n]\n")?;
+        writer.flush()?;
+        Ok(ChromeSink {
+            writer,
+            empty: true,
+            named: HashSet::new(),
+        })
+    }
+
+    fn append(&mut self, entries: &[serde_json::Value]) -> io::Result<()> {
+        // back over the closing bracket, and the newline ahead of it after an entry
+        let back = if self.empty { 2 } else { 3 };
+        let mut chunk = String::new();
+        for entry in entries {
+            if !self.empty {
+                chunk.push_str(",\n");
+            }
+            chunk.push_str(&entry.to_string());
+            self.empty = false;
+        }
+        chunk.push_str("\n]\n");
+
+        self.writer.seek(SeekFrom::End(-back))?;
+        self.writer.write_all(chunk.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek + AsFd> TraceSink for ChromeSink<W> {
+    fn record(&mut self, event: TraceEvent) {
+        let mut entries = Vec::new();
+        if let Some(comm) = &event.comm {
+            if self.named.insert(event.pid) {
+                entries.push(json!({
+                    "ph": "M",
+                    "name": "process_name",
+                    "pid": event.pid,
+                    "args": { "name": comm },
+                }));
+            }
+        }
+        let dur = event.latency_us.unwrap_or(0);
+        entries.push(json!({
+            "ph": "X",
+            "name": event.op.name(),
+            "cat": event.op.name(),
+            "ts": event.monotonic_us.unwrap_or(0).saturating_sub(dur),
+            "dur": dur,
+            "pid": event.pid,
+            "tid": event.tid.unwrap_or(event.pid),
+            "args": event,
+        }));
+
+        if let Err(e) = self.append(&entries) {
+            warn!("Failed to write trace: {}", e);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            warn!("Failed to write trace: {}", e);
+        }
+    }
+
+    fn sync(&mut self) {
+        self.flush();
+        sync_data(&self.writer);
+    }
+}
+
+// trace files of process trees kept open by a PerProcessSink, the least recently written one is
+// closed for another, and reopened for appending when its tree shows up again
+const OPEN_TRACES: usize = 64;
+
+// Writes the events of every process tree to DIR/trace.<pid>.<start>.log, see
+// TracerFS::per_process. Events without a process tree are not written
+pub struct PerProcessSink {
+    dir: PathBuf,
+    format: TraceFormat,
+    header: Option<String>,
+    // most recently written last
+    files: Vec<((u32, u64), File)>,
+}
+
+impl PerProcessSink {
+    pub fn new(dir: PathBuf, format: TraceFormat) -> PerProcessSink {
+        PerProcessSink {
+            dir,
+            format,
+            header: None,
+            files: Vec::new(),
+        }
+    }
+
+    fn file(&mut self, group: (u32, u64)) -> io::Result<&mut File> {
+        match self.files.iter().position(|(x, _)| *x == group) {
+            Some(i) => {
+                let entry = self.files.remove(i);
+                self.files.push(entry);
+            }
+            None => {
+                let (pid, start) = group;
+                let path = self.dir.join(format!("trace.{pid}.{start}.log"));
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                if file.metadata()?.len() == 0 {
+                    if let Some(header) = &self.header {
+                        writeln!(file, "{}", header)?;
+                    }
+                }
+                if self.files.len() == OPEN_TRACES {
+                    self.files.remove(0);
+                }
+                self.files.push((group, file));
+            }
+        }
+        Ok(&mut self.files.last_mut().unwrap().1)
+    }
+}
+
+impl TraceSink for PerProcessSink {
+    fn header(&mut self, header: &TraceHeader) {
+        self.header = Some(render_header(header, self.format));
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        let (group, line) = match (event.group, event.render(self.format)) {
+            (Some(group), Some(line)) => (group, line),
+            _ => return,
+        };
+        if let Err(e) = self.file(group).and_then(|file| writeln!(file, "{}", line)) {
+            warn!("Failed to write trace: {}", e);
+        }
+    }
+
+    // the traces closed for others were complete, and are left to the kernel
+    fn sync(&mut self) {
+        for (_, file) in &self.files {
+            sync_data(file);
+        }
+    }
+}
+
+// nearly every message is an event, boxing them would allocate for each one
+#[allow(clippy::large_enum_variant)]
+enum Message {
+    Header(TraceHeader),
+    Event(TraceEvent),
+    Summary(LatencySummary),
+    // answered once everything sent before has been written
+    Flush(mpsc::Sender<()>),
+    // answered once everything sent before is on disk
+    Sync(mpsc::Sender<()>),
+}
+
+// Hands the events to a writer thread, so a slow disk or peer does not hold up the filesystem.
+// When the writer falls that far behind that the queue fills up, events are dropped and counted
+// rather than stalling the kernel
+pub struct AsyncSink {
+    sender: Option<SyncSender<Message>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: u64,
+    reported: u64,
+}
+
+impl AsyncSink {
+    pub fn new<S: TraceSink + Send + 'static>(mut inner: S, capacity: usize) -> AsyncSink {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let writer = thread::Builder::new()
+            .name("trace-writer".to_string())
+            .spawn(move || {
+                for message in receiver {
+                    match message {
+                        Message::Header(header) => inner.header(&header),
+                        Message::Event(event) => inner.record(event),
+                        Message::Summary(summary) => inner.summary(&summary),
+                        Message::Flush(done) => {
+                            inner.flush();
+                            let _ = done.send(());
+                        }
+                        Message::Sync(done) => {
+                            inner.sync();
+                            let _ = done.send(());
+                        }
+                    }
+                }
+                inner.flush();
+            })
+            .expect("failed to start the trace writer");
+
+        AsyncSink {
+            sender: Some(sender),
+            writer: Some(writer),
+            dropped: 0,
+            reported: 0,
+        }
+    }
+
+    // Events lost to a full queue so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn send(&mut self, message: Message) {
+        let sender = match &self.sender {
+            Some(x) => x,
+            None => return,
+        };
+        match sender.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == self.reported {
+                    warn!("Trace writer fell behind, dropping events");
+                }
+                self.dropped += 1;
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("Trace writer stopped, dropping events");
+                self.sender = None;
+            }
+        }
+    }
+
+    // waits for the writer to get through the queue up to `message`
+    fn wait(&mut self, message: fn(mpsc::Sender<()>) -> Message) {
+        if let Some(sender) = &self.sender {
+            let (done, finished) = mpsc::channel();
+            if sender.send(message(done)).is_ok() {
+                let _ = finished.recv();
+            }
+        }
+        self.report_dropped();
+    }
+
+    fn report_dropped(&mut self) {
+        if self.dropped > self.reported {
+            warn!(
+                "Dropped {} trace events, the trace writer fell behind",
+                self.dropped - self.reported
+            );
+            self.reported = self.dropped;
+        }
+    }
+}
+
+impl TraceSink for AsyncSink {
+    fn header(&mut self, header: &TraceHeader) {
+        self.send(Message::Header(header.clone()));
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        self.send(Message::Event(event));
+    }
+
+    // waits for room in the queue, it is sent once and has to arrive
+    fn summary(&mut self, summary: &LatencySummary) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Message::Summary(summary.clone()));
+        }
+    }
+
+    fn flush(&mut self) {
+        self.wait(Message::Flush);
+    }
+
+    fn sync(&mut self) {
+        self.wait(Message::Sync);
+    }
+}
+
+impl Drop for AsyncSink {
+    fn drop(&mut self) {
+        // the writer drains the queue and stops once the channel is closed
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        self.report_dropped();
+    }
+}
+
+// Coalesces a run of the same operation by the same process on the same file, like the reads of a
+// compiler, into the first event of the run with a count and the sizes added up. A run is only
+// written once it ends, with another event, or on a flush
+pub struct DedupSink<S: TraceSink> {
+    inner: S,
+    pending: Option<TraceEvent>,
+}
+
+impl<S: TraceSink> DedupSink<S> {
+    pub fn new(inner: S) -> DedupSink<S> {
+        DedupSink {
+            inner,
+            pending: None,
+        }
+    }
+
+    fn release(&mut self) {
+        if let Some(event) = self.pending.take() {
+            self.inner.record(event);
+        }
+    }
+}
+
+// a failure in the middle of a run is worth a line of its own
+fn same_run(a: &TraceEvent, b: &TraceEvent) -> bool {
+    a.op == b.op && a.pid == b.pid && a.ino == b.ino && a.path == b.path && a.result == b.result
+}
+
+impl<S: TraceSink> TraceSink for DedupSink<S> {
+    fn header(&mut self, header: &TraceHeader) {
+        self.release();
+        self.inner.header(header);
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        match &mut self.pending {
+            Some(pending) if same_run(pending, &event) => {
+                pending.count = Some(pending.count.unwrap_or(1) + 1);
+                if let (Some(size), Some(more)) = (pending.size, event.size) {
+                    pending.size = Some(size.saturating_add(more));
+                }
+            }
+            _ => {
+                self.release();
+                self.pending = Some(event);
+            }
+        }
+    }
+
+    fn summary(&mut self, summary: &LatencySummary) {
+        self.release();
+        self.inner.summary(summary);
+    }
+
+    fn flush(&mut self) {
+        self.release();
+        self.inner.flush();
+    }
+
+    fn sync(&mut self) {
+        self.release();
+        self.inner.sync();
+    }
+}
+
+// A log file that starts over once it grows past max_size, the previous ones are kept as PATH.1,
+// the most recent, up to PATH.keep. Files are only switched between lines, each one can be
+// parsed on its own
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+    // the last write ended a line
+    at_boundary: bool,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+            at_boundary: true,
+        })
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // renames replace the file they are moved onto, the oldest one falls off the end
+        for n in (1..self.keep).rev() {
+            let from = self.numbered(n);
+            if from.exists() {
+                fs::rename(from, self.numbered(n + 1))?;
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, self.numbered(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_boundary && self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        if let Some(last) = buf.last() {
+            self.at_boundary = *last == b'\n';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// A log that is opened again once `reopen` is set, by the SIGHUP of logrotate after it moved the
+// file away. The switch happens before a write, which the logger makes one per record, the
+// records before it all end up in the moved file
+pub struct ReopenableLog {
+    open: Box<dyn Fn() -> io::Result<Box<dyn Write + Send>> + Send>,
+    writer: Box<dyn Write + Send>,
+    reopen: &'static AtomicBool,
+}
+
+impl ReopenableLog {
+    pub fn open(
+        open: impl Fn() -> io::Result<Box<dyn Write + Send>> + Send + 'static,
+        reopen: &'static AtomicBool,
+    ) -> io::Result<ReopenableLog> {
+        Ok(ReopenableLog {
+            writer: open()?,
+            open: Box::new(open),
+            reopen,
+        })
+    }
+}
+
+impl Write for ReopenableLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.reopen.swap(false, Ordering::SeqCst) {
+            let _ = self.writer.flush();
+            // the logger is busy writing this very record, the old file is kept on
+            match (self.open)() {
+                Ok(writer) => self.writer = writer,
+                Err(e) => eprintln!("Failed to reopen the log: {e}"),
+            }
+        }
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
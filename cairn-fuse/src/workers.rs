@@ -0,0 +1,155 @@
+use crate::{errno, gettid, read_at, write_at, OpTimer, TraceEvent};
+use fuser::{ReplyData, ReplyWrite};
+use std::fs::File;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+pub(crate) enum Io {
+    // `len` is the cached length of the file, None when it has to be stat()ed again. The file
+    // stays dirty either way, a later write may have been taken meanwhile
+    Read {
+        size: u32,
+        len: Option<u64>,
+        reply: ReplyData,
+    },
+    Write {
+        data: Vec<u8>,
+        append: bool,
+        reply: ReplyWrite,
+    },
+}
+
+pub(crate) struct Job {
+    pub fh: u64,
+    pub ino: u64,
+    pub file: Arc<File>,
+    pub offset: i64,
+    pub io: Io,
+    pub event: TraceEvent,
+    pub timer: OpTimer,
+}
+
+// A read or write replied to by a worker, handed back to the filesystem with `finished`
+pub(crate) struct Done {
+    pub fh: u64,
+    pub offset: i64,
+    pub event: TraceEvent,
+    // the bytes moved
+    pub result: io::Result<usize>,
+}
+
+// Serves the reads and writes of open files on worker threads, so those of different files
+// proceed in parallel while the filesystem takes the next request. The jobs of a file all go to
+// the same worker and are traced in the order they were taken, events of different files are
+// ordered by their monotonic_us at best
+pub(crate) struct Workers {
+    jobs: Vec<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    finished: Receiver<Done>,
+}
+
+impl Workers {
+    pub fn new(threads: usize, mounted: Instant) -> Workers {
+        let (done, finished) = mpsc::channel();
+        let (jobs, workers) = (0..threads)
+            .map(|_| {
+                let (jobs, queue) = mpsc::channel::<Job>();
+                let done = done.clone();
+                let worker = thread::Builder::new()
+                    .name("io-worker".to_string())
+                    .spawn(move || {
+                        for job in queue {
+                            serve(job, mounted, &done);
+                        }
+                    })
+                    .expect("failed to start an I/O worker");
+                (jobs, worker)
+            })
+            .unzip();
+
+        Workers {
+            jobs,
+            workers,
+            finished,
+        }
+    }
+
+    pub fn submit(&self, job: Job) {
+        let jobs = &self.jobs[job.ino as usize % self.jobs.len()];
+        let _ = jobs.send(job);
+    }
+
+    // The jobs replied to since the last call
+    pub fn finished(&self) -> Vec<Done> {
+        self.finished.try_iter().collect()
+    }
+
+    // Waits for the outstanding jobs, no more are taken afterwards
+    pub fn finish(&mut self) -> Vec<Done> {
+        self.jobs.clear();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.finished()
+    }
+}
+
+fn serve(job: Job, mounted: Instant, done: &Sender<Done>) {
+    let Job {
+        fh,
+        ino: _,
+        file,
+        offset,
+        io,
+        mut event,
+        timer,
+    } = job;
+    // traced before the kernel hears back, so whatever the reply leads to comes after it
+    let finish = |mut event: TraceEvent, result: io::Result<usize>| {
+        event.latency_us = Some(timer.start.elapsed().as_micros() as u64);
+        event.monotonic_us = Some(mounted.elapsed().as_micros() as u64);
+        event.tid = Some(gettid());
+        let _ = done.send(Done {
+            fh,
+            offset,
+            event,
+            result,
+        });
+    };
+
+    match io {
+        Io::Read { size, len, reply } => {
+            let (result, buffer) = match read_at(&file, offset, size, len) {
+                Ok((buffer, _)) => (Ok(buffer.len()), buffer),
+                Err(e) => (Err(e), Vec::new()),
+            };
+            // the handle may be released as soon as the kernel has the reply, and closed with it
+            drop(file);
+            // the size is what was read, which ends short at the end of the file
+            event.size = Some(result.as_ref().map_or(size, |x| *x as u32));
+            let failed = result.as_ref().err().map(errno);
+            finish(event, result);
+            match failed {
+                None => reply.data(&buffer),
+                Some(e) => reply.error(e),
+            }
+        }
+        Io::Write {
+            data,
+            append,
+            reply,
+        } => {
+            let result = write_at(&file, offset, &data, append).map(|_| data.len());
+            drop(file);
+            let failed = result.as_ref().err().map(errno);
+            finish(event, result);
+            match failed {
+                None => reply.written(data.len() as u32),
+                Some(e) => reply.error(e),
+            }
+        }
+    }
+}
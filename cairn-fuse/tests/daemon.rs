@@ -0,0 +1,117 @@
+// Runs the cairn-fuse binary the way a supervisor does, the unit tests only mount in process
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn spawn(root: &Path, mnt: &Path, dir: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_cairn-fuse"))
+        .arg(root)
+        .arg(mnt)
+        .arg("--log")
+        .arg(dir.join("tracer.log"))
+        .arg("--ready-file")
+        .arg(dir.join("ready"))
+        .args(args)
+        .spawn()
+        .unwrap()
+}
+
+fn wait_for(path: &Path) {
+    for _ in 0..100 {
+        if path.exists() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("{} did not show up", path.display());
+}
+
+fn mounted(mnt: &Path) -> bool {
+    let mounts = fs::read_to_string("/proc/mounts").unwrap();
+    mounts
+        .lines()
+        .any(|line| line.split(' ').nth(1) == mnt.to_str())
+}
+
+#[test]
+fn sigterm_unmounts_the_daemon() {
+    let dir = TempDir::new().unwrap();
+    let (root, mnt) = (dir.path().join("root"), dir.path().join("mnt"));
+    fs::create_dir(&root).unwrap();
+    fs::create_dir(&mnt).unwrap();
+    fs::write(root.join("input.txt"), "input").unwrap();
+    let mut daemon = spawn(&root, &mnt, dir.path(), &[]);
+    wait_for(&dir.path().join("ready"));
+    assert_eq!(fs::read_to_string(mnt.join("input.txt")).unwrap(), "input");
+
+    unsafe { libc::kill(daemon.id() as i32, libc::SIGTERM) };
+    let status = daemon.wait().unwrap();
+
+    assert!(status.success(), "{status}");
+    assert!(!dir.path().join("ready").exists());
+    assert!(!mounted(&mnt));
+    // the read was traced before the daemon went away
+    let log = fs::read_to_string(dir.path().join("tracer.log")).unwrap();
+    assert!(log.contains("input.txt"), "{log}");
+}
+
+#[test]
+fn sighup_reopens_the_log_of_the_daemon() {
+    let dir = TempDir::new().unwrap();
+    let (root, mnt) = (dir.path().join("root"), dir.path().join("mnt"));
+    fs::create_dir(&root).unwrap();
+    fs::create_dir(&mnt).unwrap();
+    fs::write(root.join("first.txt"), "first").unwrap();
+    fs::write(root.join("second.txt"), "second").unwrap();
+    let mut daemon = spawn(&root, &mnt, dir.path(), &["--trace-sync"]);
+    wait_for(&dir.path().join("ready"));
+
+    fs::read(mnt.join("first.txt")).unwrap();
+    let (log, rotated) = (
+        dir.path().join("tracer.log"),
+        dir.path().join("tracer.log.1"),
+    );
+    fs::rename(&log, &rotated).unwrap();
+    unsafe { libc::kill(daemon.id() as i32, libc::SIGHUP) };
+    // still serving, the handler only asks for the log to be reopened
+    thread::sleep(Duration::from_millis(100));
+    fs::read(mnt.join("second.txt")).unwrap();
+    assert!(mounted(&mnt));
+
+    unsafe { libc::kill(daemon.id() as i32, libc::SIGTERM) };
+    assert!(daemon.wait().unwrap().success());
+    assert!(fs::read_to_string(&rotated).unwrap().contains("first.txt"));
+    let reopened = fs::read_to_string(&log).unwrap();
+    assert!(reopened.contains("second.txt"), "{reopened}");
+    assert!(!reopened.contains("first.txt"));
+}
+
+#[test]
+fn mount_failure_exits_with_its_own_code() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("root");
+    fs::create_dir(&root).unwrap();
+    // not created without --mkdir
+    let mnt = dir.path().join("missing");
+    let status = spawn(&root, &mnt, dir.path(), &[]).wait().unwrap();
+
+    assert_eq!(status.code(), Some(4));
+}
+
+#[test]
+fn no_default_permissions_keeps_other_users_out() {
+    let dir = TempDir::new().unwrap();
+    let (root, mnt) = (dir.path().join("root"), dir.path().join("mnt"));
+    fs::create_dir(&root).unwrap();
+    fs::create_dir(&mnt).unwrap();
+    let args = ["--no-default-permissions", "-o", "allow_other"];
+    let status = spawn(&root, &mnt, dir.path(), &args).wait().unwrap();
+
+    assert_eq!(status.code(), Some(1));
+    assert!(!mounted(&mnt));
+}
@@ -0,0 +1,852 @@
+// automatically generated by the FlatBuffers compiler, do not modify
+
+
+// @generated
+
+use core::mem;
+use core::cmp::Ordering;
+
+extern crate flatbuffers;
+use self::flatbuffers::{EndianScalar, Follow};
+
+#[allow(unused_imports, dead_code)]
+pub mod cairn {
+
+  use core::mem;
+  use core::cmp::Ordering;
+
+  extern crate flatbuffers;
+  use self::flatbuffers::{EndianScalar, Follow};
+#[allow(unused_imports, dead_code)]
+pub mod trace {
+
+  use core::mem;
+  use core::cmp::Ordering;
+
+  extern crate flatbuffers;
+  use self::flatbuffers::{EndianScalar, Follow};
+
+#[deprecated(since = "2.0.0", note = "Use associated constants instead. This will no longer be generated in 2021.")]
+pub const ENUM_MIN_OP: u8 = 0;
+#[deprecated(since = "2.0.0", note = "Use associated constants instead. This will no longer be generated in 2021.")]
+pub const ENUM_MAX_OP: u8 = 26;
+#[deprecated(since = "2.0.0", note = "Use associated constants instead. This will no longer be generated in 2021.")]
+#[allow(non_camel_case_types)]
+pub const ENUM_VALUES_OP: [Op; 27] = [
+  Op::Unknown,
+  Op::Open,
+  Op::Create,
+  Op::Read,
+  Op::Write,
+  Op::Readlink,
+  Op::Chmod,
+  Op::Chown,
+  Op::Truncate,
+  Op::Utime,
+  Op::Mknod,
+  Op::Mkdir,
+  Op::Unlink,
+  Op::Rmdir,
+  Op::Symlink,
+  Op::Rename,
+  Op::Link,
+  Op::Statfs,
+  Op::Release,
+  Op::LookupMiss,
+  Op::Fallocate,
+  Op::Output,
+  Op::Input,
+  Op::Marker,
+  Op::Denied,
+  Op::FetchFailed,
+  Op::DeniedWrite,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(transparent)]
+pub struct Op(pub u8);
+#[allow(non_upper_case_globals)]
+impl Op {
+  pub const Unknown: Self = Self(0);
+  pub const Open: Self = Self(1);
+  pub const Create: Self = Self(2);
+  pub const Read: Self = Self(3);
+  pub const Write: Self = Self(4);
+  pub const Readlink: Self = Self(5);
+  pub const Chmod: Self = Self(6);
+  pub const Chown: Self = Self(7);
+  pub const Truncate: Self = Self(8);
+  pub const Utime: Self = Self(9);
+  pub const Mknod: Self = Self(10);
+  pub const Mkdir: Self = Self(11);
+  pub const Unlink: Self = Self(12);
+  pub const Rmdir: Self = Self(13);
+  pub const Symlink: Self = Self(14);
+  pub const Rename: Self = Self(15);
+  pub const Link: Self = Self(16);
+  pub const Statfs: Self = Self(17);
+  pub const Release: Self = Self(18);
+  pub const LookupMiss: Self = Self(19);
+  pub const Fallocate: Self = Self(20);
+  pub const Output: Self = Self(21);
+  pub const Input: Self = Self(22);
+  pub const Marker: Self = Self(23);
+  pub const Denied: Self = Self(24);
+  pub const FetchFailed: Self = Self(25);
+  pub const DeniedWrite: Self = Self(26);
+
+  pub const ENUM_MIN: u8 = 0;
+  pub const ENUM_MAX: u8 = 26;
+  pub const ENUM_VALUES: &'static [Self] = &[
+    Self::Unknown,
+    Self::Open,
+    Self::Create,
+    Self::Read,
+    Self::Write,
+    Self::Readlink,
+    Self::Chmod,
+    Self::Chown,
+    Self::Truncate,
+    Self::Utime,
+    Self::Mknod,
+    Self::Mkdir,
+    Self::Unlink,
+    Self::Rmdir,
+    Self::Symlink,
+    Self::Rename,
+    Self::Link,
+    Self::Statfs,
+    Self::Release,
+    Self::LookupMiss,
+    Self::Fallocate,
+    Self::Output,
+    Self::Input,
+    Self::Marker,
+    Self::Denied,
+    Self::FetchFailed,
+    Self::DeniedWrite,
+  ];
+  /// Returns the variant's name or "" if unknown.
+  pub fn variant_name(self) -> Option<&'static str> {
+    match self {
+      Self::Unknown => Some("Unknown"),
+      Self::Open => Some("Open"),
+      Self::Create => Some("Create"),
+      Self::Read => Some("Read"),
+      Self::Write => Some("Write"),
+      Self::Readlink => Some("Readlink"),
+      Self::Chmod => Some("Chmod"),
+      Self::Chown => Some("Chown"),
+      Self::Truncate => Some("Truncate"),
+      Self::Utime => Some("Utime"),
+      Self::Mknod => Some("Mknod"),
+      Self::Mkdir => Some("Mkdir"),
+      Self::Unlink => Some("Unlink"),
+      Self::Rmdir => Some("Rmdir"),
+      Self::Symlink => Some("Symlink"),
+      Self::Rename => Some("Rename"),
+      Self::Link => Some("Link"),
+      Self::Statfs => Some("Statfs"),
+      Self::Release => Some("Release"),
+      Self::LookupMiss => Some("LookupMiss"),
+      Self::Fallocate => Some("Fallocate"),
+      Self::Output => Some("Output"),
+      Self::Input => Some("Input"),
+      Self::Marker => Some("Marker"),
+      Self::Denied => Some("Denied"),
+      Self::FetchFailed => Some("FetchFailed"),
+      Self::DeniedWrite => Some("DeniedWrite"),
+      _ => None,
+    }
+  }
+}
+impl core::fmt::Debug for Op {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    if let Some(name) = self.variant_name() {
+      f.write_str(name)
+    } else {
+      f.write_fmt(format_args!("<UNKNOWN {:?}>", self.0))
+    }
+  }
+}
+impl<'a> flatbuffers::Follow<'a> for Op {
+  type Inner = Self;
+  #[inline]
+  unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+    let b = flatbuffers::read_scalar_at::<u8>(buf, loc);
+    Self(b)
+  }
+}
+
+impl flatbuffers::Push for Op {
+    type Output = Op;
+    #[inline]
+    unsafe fn push(&self, dst: &mut [u8], _written_len: usize) {
+        flatbuffers::emplace_scalar::<u8>(dst, self.0);
+    }
+}
+
+impl flatbuffers::EndianScalar for Op {
+  type Scalar = u8;
+  #[inline]
+  fn to_little_endian(self) -> u8 {
+    self.0.to_le()
+  }
+  #[inline]
+  #[allow(clippy::wrong_self_convention)]
+  fn from_little_endian(v: u8) -> Self {
+    let b = u8::from_le(v);
+    Self(b)
+  }
+}
+
+impl<'a> flatbuffers::Verifiable for Op {
+  #[inline]
+  fn run_verifier(
+    v: &mut flatbuffers::Verifier, pos: usize
+  ) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+    use self::flatbuffers::Verifiable;
+    u8::run_verifier(v, pos)
+  }
+}
+
+impl flatbuffers::SimpleToVerifyInSlice for Op {}
+
+pub enum TraceEventOffset {}
+#[derive(Copy, Clone, PartialEq)]
+
+pub struct TraceEvent<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for TraceEvent<'a> {
+  type Inner = TraceEvent<'a>;
+  #[inline]
+  unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+    Self { _tab: flatbuffers::Table::new(buf, loc) }
+  }
+}
+
+impl<'a> TraceEvent<'a> {
+  pub const VT_TS: flatbuffers::VOffsetT = 4;
+  pub const VT_MONOTONIC_US: flatbuffers::VOffsetT = 6;
+  pub const VT_LATENCY_US: flatbuffers::VOffsetT = 8;
+  pub const VT_PID: flatbuffers::VOffsetT = 10;
+  pub const VT_PPID: flatbuffers::VOffsetT = 12;
+  pub const VT_UID: flatbuffers::VOffsetT = 14;
+  pub const VT_GID: flatbuffers::VOffsetT = 16;
+  pub const VT_COMM: flatbuffers::VOffsetT = 18;
+  pub const VT_CMDLINE: flatbuffers::VOffsetT = 20;
+  pub const VT_KIND: flatbuffers::VOffsetT = 22;
+  pub const VT_OP: flatbuffers::VOffsetT = 24;
+  pub const VT_INO: flatbuffers::VOffsetT = 26;
+  pub const VT_PATH: flatbuffers::VOffsetT = 28;
+  pub const VT_TARGET: flatbuffers::VOffsetT = 30;
+  pub const VT_OFFSET: flatbuffers::VOffsetT = 32;
+  pub const VT_SIZE: flatbuffers::VOffsetT = 34;
+  pub const VT_ERRNO: flatbuffers::VOffsetT = 36;
+  pub const VT_COUNT: flatbuffers::VOffsetT = 38;
+  pub const VT_BYTES_READ: flatbuffers::VOffsetT = 40;
+  pub const VT_BYTES_WRITTEN: flatbuffers::VOffsetT = 42;
+  pub const VT_COVERAGE: flatbuffers::VOffsetT = 44;
+  pub const VT_SHA256: flatbuffers::VOffsetT = 46;
+  pub const VT_TID: flatbuffers::VOffsetT = 48;
+  pub const VT_FIRST_OFFSET: flatbuffers::VOffsetT = 50;
+  pub const VT_LAST_OFFSET: flatbuffers::VOffsetT = 52;
+  pub const VT_LAYER: flatbuffers::VOffsetT = 54;
+  pub const VT_MATERIALIZED: flatbuffers::VOffsetT = 56;
+
+  #[inline]
+  pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+    TraceEvent { _tab: table }
+  }
+  #[allow(unused_mut)]
+  pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+    _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+    args: &'args TraceEventArgs<'args>
+  ) -> flatbuffers::WIPOffset<TraceEvent<'bldr>> {
+    let mut builder = TraceEventBuilder::new(_fbb);
+    if let Some(x) = args.last_offset { builder.add_last_offset(x); }
+    if let Some(x) = args.first_offset { builder.add_first_offset(x); }
+    if let Some(x) = args.coverage { builder.add_coverage(x); }
+    if let Some(x) = args.bytes_written { builder.add_bytes_written(x); }
+    if let Some(x) = args.bytes_read { builder.add_bytes_read(x); }
+    if let Some(x) = args.count { builder.add_count(x); }
+    if let Some(x) = args.offset { builder.add_offset(x); }
+    if let Some(x) = args.ino { builder.add_ino(x); }
+    if let Some(x) = args.latency_us { builder.add_latency_us(x); }
+    if let Some(x) = args.monotonic_us { builder.add_monotonic_us(x); }
+    builder.add_ts(args.ts);
+    if let Some(x) = args.layer { builder.add_layer(x); }
+    if let Some(x) = args.tid { builder.add_tid(x); }
+    if let Some(x) = args.sha256 { builder.add_sha256(x); }
+    builder.add_errno(args.errno);
+    if let Some(x) = args.size { builder.add_size(x); }
+    if let Some(x) = args.target { builder.add_target(x); }
+    if let Some(x) = args.path { builder.add_path(x); }
+    if let Some(x) = args.cmdline { builder.add_cmdline(x); }
+    if let Some(x) = args.comm { builder.add_comm(x); }
+    if let Some(x) = args.gid { builder.add_gid(x); }
+    if let Some(x) = args.uid { builder.add_uid(x); }
+    builder.add_ppid(args.ppid);
+    builder.add_pid(args.pid);
+    if let Some(x) = args.materialized { builder.add_materialized(x); }
+    builder.add_op(args.op);
+    builder.add_kind(args.kind);
+    builder.finish()
+  }
+
+
+  #[inline]
+  pub fn ts(&self) -> i64 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i64>(TraceEvent::VT_TS, Some(0)).unwrap()}
+  }
+
+  #[inline]
+  pub fn monotonic_us(&self) -> Option<u64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TraceEvent::VT_MONOTONIC_US, None)}
+  }
+
+  #[inline]
+  pub fn latency_us(&self) -> Option<u64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TraceEvent::VT_LATENCY_US, None)}
+  }
+
+  #[inline]
+  pub fn pid(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(TraceEvent::VT_PID, Some(0)).unwrap()}
+  }
+
+  #[inline]
+  pub fn ppid(&self) -> i32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i32>(TraceEvent::VT_PPID, Some(0)).unwrap()}
+  }
+
+  #[inline]
+  pub fn uid(&self) -> Option<u32> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(TraceEvent::VT_UID, None)}
+  }
+
+  #[inline]
+  pub fn gid(&self) -> Option<u32> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(TraceEvent::VT_GID, None)}
+  }
+
+  #[inline]
+  pub fn comm(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TraceEvent::VT_COMM, None)}
+  }
+
+  #[inline]
+  pub fn cmdline(&self) -> Option<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>>>(TraceEvent::VT_CMDLINE, None)}
+  }
+
+  #[inline]
+  pub fn kind(&self) -> u8 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u8>(TraceEvent::VT_KIND, Some(0)).unwrap()}
+  }
+
+  #[inline]
+  pub fn op(&self) -> Op {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<Op>(TraceEvent::VT_OP, Some(Op::Unknown)).unwrap()}
+  }
+
+  #[inline]
+  pub fn ino(&self) -> Option<u64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TraceEvent::VT_INO, None)}
+  }
+
+  #[inline]
+  pub fn path(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TraceEvent::VT_PATH, None)}
+  }
+
+  #[inline]
+  pub fn target(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TraceEvent::VT_TARGET, None)}
+  }
+
+  #[inline]
+  pub fn offset(&self) -> Option<i64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i64>(TraceEvent::VT_OFFSET, None)}
+  }
+
+  #[inline]
+  pub fn size(&self) -> Option<u32> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(TraceEvent::VT_SIZE, None)}
+  }
+
+  #[inline]
+  pub fn errno(&self) -> i32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i32>(TraceEvent::VT_ERRNO, Some(0)).unwrap()}
+  }
+
+  #[inline]
+  pub fn count(&self) -> Option<u64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TraceEvent::VT_COUNT, None)}
+  }
+
+  #[inline]
+  pub fn bytes_read(&self) -> Option<u64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TraceEvent::VT_BYTES_READ, None)}
+  }
+
+  #[inline]
+  pub fn bytes_written(&self) -> Option<u64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TraceEvent::VT_BYTES_WRITTEN, None)}
+  }
+
+  #[inline]
+  pub fn coverage(&self) -> Option<f64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<f64>(TraceEvent::VT_COVERAGE, None)}
+  }
+
+  #[inline]
+  pub fn sha256(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TraceEvent::VT_SHA256, None)}
+  }
+
+  #[inline]
+  pub fn tid(&self) -> Option<u32> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(TraceEvent::VT_TID, None)}
+  }
+
+  #[inline]
+  pub fn first_offset(&self) -> Option<i64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i64>(TraceEvent::VT_FIRST_OFFSET, None)}
+  }
+
+  #[inline]
+  pub fn last_offset(&self) -> Option<i64> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i64>(TraceEvent::VT_LAST_OFFSET, None)}
+  }
+
+  #[inline]
+  pub fn layer(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TraceEvent::VT_LAYER, None)}
+  }
+
+  #[inline]
+  pub fn materialized(&self) -> Option<bool> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<bool>(TraceEvent::VT_MATERIALIZED, None)}
+  }
+}
+
+impl flatbuffers::Verifiable for TraceEvent<'_> {
+  #[inline]
+  fn run_verifier(
+    v: &mut flatbuffers::Verifier, pos: usize
+  ) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+    use self::flatbuffers::Verifiable;
+    v.visit_table(pos)?
+     .visit_field::<i64>("ts", Self::VT_TS, false)?
+     .visit_field::<u64>("monotonic_us", Self::VT_MONOTONIC_US, false)?
+     .visit_field::<u64>("latency_us", Self::VT_LATENCY_US, false)?
+     .visit_field::<u32>("pid", Self::VT_PID, false)?
+     .visit_field::<i32>("ppid", Self::VT_PPID, false)?
+     .visit_field::<u32>("uid", Self::VT_UID, false)?
+     .visit_field::<u32>("gid", Self::VT_GID, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("comm", Self::VT_COMM, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<&'_ str>>>>("cmdline", Self::VT_CMDLINE, false)?
+     .visit_field::<u8>("kind", Self::VT_KIND, false)?
+     .visit_field::<Op>("op", Self::VT_OP, false)?
+     .visit_field::<u64>("ino", Self::VT_INO, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("path", Self::VT_PATH, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("target", Self::VT_TARGET, false)?
+     .visit_field::<i64>("offset", Self::VT_OFFSET, false)?
+     .visit_field::<u32>("size", Self::VT_SIZE, false)?
+     .visit_field::<i32>("errno", Self::VT_ERRNO, false)?
+     .visit_field::<u64>("count", Self::VT_COUNT, false)?
+     .visit_field::<u64>("bytes_read", Self::VT_BYTES_READ, false)?
+     .visit_field::<u64>("bytes_written", Self::VT_BYTES_WRITTEN, false)?
+     .visit_field::<f64>("coverage", Self::VT_COVERAGE, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("sha256", Self::VT_SHA256, false)?
+     .visit_field::<u32>("tid", Self::VT_TID, false)?
+     .visit_field::<i64>("first_offset", Self::VT_FIRST_OFFSET, false)?
+     .visit_field::<i64>("last_offset", Self::VT_LAST_OFFSET, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("layer", Self::VT_LAYER, false)?
+     .visit_field::<bool>("materialized", Self::VT_MATERIALIZED, false)?
+     .finish();
+    Ok(())
+  }
+}
+pub struct TraceEventArgs<'a> {
+    pub ts: i64,
+    pub monotonic_us: Option<u64>,
+    pub latency_us: Option<u64>,
+    pub pid: u32,
+    pub ppid: i32,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub comm: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub cmdline: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>>>,
+    pub kind: u8,
+    pub op: Op,
+    pub ino: Option<u64>,
+    pub path: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub target: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub offset: Option<i64>,
+    pub size: Option<u32>,
+    pub errno: i32,
+    pub count: Option<u64>,
+    pub bytes_read: Option<u64>,
+    pub bytes_written: Option<u64>,
+    pub coverage: Option<f64>,
+    pub sha256: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub tid: Option<u32>,
+    pub first_offset: Option<i64>,
+    pub last_offset: Option<i64>,
+    pub layer: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub materialized: Option<bool>,
+}
+impl<'a> Default for TraceEventArgs<'a> {
+  #[inline]
+  fn default() -> Self {
+    TraceEventArgs {
+      ts: 0,
+      monotonic_us: None,
+      latency_us: None,
+      pid: 0,
+      ppid: 0,
+      uid: None,
+      gid: None,
+      comm: None,
+      cmdline: None,
+      kind: 0,
+      op: Op::Unknown,
+      ino: None,
+      path: None,
+      target: None,
+      offset: None,
+      size: None,
+      errno: 0,
+      count: None,
+      bytes_read: None,
+      bytes_written: None,
+      coverage: None,
+      sha256: None,
+      tid: None,
+      first_offset: None,
+      last_offset: None,
+      layer: None,
+      materialized: None,
+    }
+  }
+}
+
+pub struct TraceEventBuilder<'a: 'b, 'b> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> TraceEventBuilder<'a, 'b> {
+  #[inline]
+  pub fn add_ts(&mut self, ts: i64) {
+    self.fbb_.push_slot::<i64>(TraceEvent::VT_TS, ts, 0);
+  }
+  #[inline]
+  pub fn add_monotonic_us(&mut self, monotonic_us: u64) {
+    self.fbb_.push_slot_always::<u64>(TraceEvent::VT_MONOTONIC_US, monotonic_us);
+  }
+  #[inline]
+  pub fn add_latency_us(&mut self, latency_us: u64) {
+    self.fbb_.push_slot_always::<u64>(TraceEvent::VT_LATENCY_US, latency_us);
+  }
+  #[inline]
+  pub fn add_pid(&mut self, pid: u32) {
+    self.fbb_.push_slot::<u32>(TraceEvent::VT_PID, pid, 0);
+  }
+  #[inline]
+  pub fn add_ppid(&mut self, ppid: i32) {
+    self.fbb_.push_slot::<i32>(TraceEvent::VT_PPID, ppid, 0);
+  }
+  #[inline]
+  pub fn add_uid(&mut self, uid: u32) {
+    self.fbb_.push_slot_always::<u32>(TraceEvent::VT_UID, uid);
+  }
+  #[inline]
+  pub fn add_gid(&mut self, gid: u32) {
+    self.fbb_.push_slot_always::<u32>(TraceEvent::VT_GID, gid);
+  }
+  #[inline]
+  pub fn add_comm(&mut self, comm: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TraceEvent::VT_COMM, comm);
+  }
+  #[inline]
+  pub fn add_cmdline(&mut self, cmdline: flatbuffers::WIPOffset<flatbuffers::Vector<'b , flatbuffers::ForwardsUOffset<&'b  str>>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TraceEvent::VT_CMDLINE, cmdline);
+  }
+  #[inline]
+  pub fn add_kind(&mut self, kind: u8) {
+    self.fbb_.push_slot::<u8>(TraceEvent::VT_KIND, kind, 0);
+  }
+  #[inline]
+  pub fn add_op(&mut self, op: Op) {
+    self.fbb_.push_slot::<Op>(TraceEvent::VT_OP, op, Op::Unknown);
+  }
+  #[inline]
+  pub fn add_ino(&mut self, ino: u64) {
+    self.fbb_.push_slot_always::<u64>(TraceEvent::VT_INO, ino);
+  }
+  #[inline]
+  pub fn add_path(&mut self, path: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TraceEvent::VT_PATH, path);
+  }
+  #[inline]
+  pub fn add_target(&mut self, target: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TraceEvent::VT_TARGET, target);
+  }
+  #[inline]
+  pub fn add_offset(&mut self, offset: i64) {
+    self.fbb_.push_slot_always::<i64>(TraceEvent::VT_OFFSET, offset);
+  }
+  #[inline]
+  pub fn add_size(&mut self, size: u32) {
+    self.fbb_.push_slot_always::<u32>(TraceEvent::VT_SIZE, size);
+  }
+  #[inline]
+  pub fn add_errno(&mut self, errno: i32) {
+    self.fbb_.push_slot::<i32>(TraceEvent::VT_ERRNO, errno, 0);
+  }
+  #[inline]
+  pub fn add_count(&mut self, count: u64) {
+    self.fbb_.push_slot_always::<u64>(TraceEvent::VT_COUNT, count);
+  }
+  #[inline]
+  pub fn add_bytes_read(&mut self, bytes_read: u64) {
+    self.fbb_.push_slot_always::<u64>(TraceEvent::VT_BYTES_READ, bytes_read);
+  }
+  #[inline]
+  pub fn add_bytes_written(&mut self, bytes_written: u64) {
+    self.fbb_.push_slot_always::<u64>(TraceEvent::VT_BYTES_WRITTEN, bytes_written);
+  }
+  #[inline]
+  pub fn add_coverage(&mut self, coverage: f64) {
+    self.fbb_.push_slot_always::<f64>(TraceEvent::VT_COVERAGE, coverage);
+  }
+  #[inline]
+  pub fn add_sha256(&mut self, sha256: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TraceEvent::VT_SHA256, sha256);
+  }
+  #[inline]
+  pub fn add_tid(&mut self, tid: u32) {
+    self.fbb_.push_slot_always::<u32>(TraceEvent::VT_TID, tid);
+  }
+  #[inline]
+  pub fn add_first_offset(&mut self, first_offset: i64) {
+    self.fbb_.push_slot_always::<i64>(TraceEvent::VT_FIRST_OFFSET, first_offset);
+  }
+  #[inline]
+  pub fn add_last_offset(&mut self, last_offset: i64) {
+    self.fbb_.push_slot_always::<i64>(TraceEvent::VT_LAST_OFFSET, last_offset);
+  }
+  #[inline]
+  pub fn add_layer(&mut self, layer: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TraceEvent::VT_LAYER, layer);
+  }
+  #[inline]
+  pub fn add_materialized(&mut self, materialized: bool) {
+    self.fbb_.push_slot_always::<bool>(TraceEvent::VT_MATERIALIZED, materialized);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> TraceEventBuilder<'a, 'b> {
+    let start = _fbb.start_table();
+    TraceEventBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<TraceEvent<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
+impl core::fmt::Debug for TraceEvent<'_> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let mut ds = f.debug_struct("TraceEvent");
+      ds.field("ts", &self.ts());
+      ds.field("monotonic_us", &self.monotonic_us());
+      ds.field("latency_us", &self.latency_us());
+      ds.field("pid", &self.pid());
+      ds.field("ppid", &self.ppid());
+      ds.field("uid", &self.uid());
+      ds.field("gid", &self.gid());
+      ds.field("comm", &self.comm());
+      ds.field("cmdline", &self.cmdline());
+      ds.field("kind", &self.kind());
+      ds.field("op", &self.op());
+      ds.field("ino", &self.ino());
+      ds.field("path", &self.path());
+      ds.field("target", &self.target());
+      ds.field("offset", &self.offset());
+      ds.field("size", &self.size());
+      ds.field("errno", &self.errno());
+      ds.field("count", &self.count());
+      ds.field("bytes_read", &self.bytes_read());
+      ds.field("bytes_written", &self.bytes_written());
+      ds.field("coverage", &self.coverage());
+      ds.field("sha256", &self.sha256());
+      ds.field("tid", &self.tid());
+      ds.field("first_offset", &self.first_offset());
+      ds.field("last_offset", &self.last_offset());
+      ds.field("layer", &self.layer());
+      ds.field("materialized", &self.materialized());
+      ds.finish()
+  }
+}
+#[inline]
+/// Verifies that a buffer of bytes contains a `TraceEvent`
+/// and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_trace_event_unchecked`.
+pub fn root_as_trace_event(buf: &[u8]) -> Result<TraceEvent, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::root::<TraceEvent>(buf)
+}
+#[inline]
+/// Verifies that a buffer of bytes contains a size prefixed
+/// `TraceEvent` and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `size_prefixed_root_as_trace_event_unchecked`.
+pub fn size_prefixed_root_as_trace_event(buf: &[u8]) -> Result<TraceEvent, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::size_prefixed_root::<TraceEvent>(buf)
+}
+#[inline]
+/// Verifies, with the given options, that a buffer of bytes
+/// contains a `TraceEvent` and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_trace_event_unchecked`.
+pub fn root_as_trace_event_with_opts<'b, 'o>(
+  opts: &'o flatbuffers::VerifierOptions,
+  buf: &'b [u8],
+) -> Result<TraceEvent<'b>, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::root_with_opts::<TraceEvent<'b>>(opts, buf)
+}
+#[inline]
+/// Verifies, with the given verifier options, that a buffer of
+/// bytes contains a size prefixed `TraceEvent` and returns
+/// it. Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_trace_event_unchecked`.
+pub fn size_prefixed_root_as_trace_event_with_opts<'b, 'o>(
+  opts: &'o flatbuffers::VerifierOptions,
+  buf: &'b [u8],
+) -> Result<TraceEvent<'b>, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::size_prefixed_root_with_opts::<TraceEvent<'b>>(opts, buf)
+}
+#[inline]
+/// Assumes, without verification, that a buffer of bytes contains a TraceEvent and returns it.
+/// # Safety
+/// Callers must trust the given bytes do indeed contain a valid `TraceEvent`.
+pub unsafe fn root_as_trace_event_unchecked(buf: &[u8]) -> TraceEvent {
+  flatbuffers::root_unchecked::<TraceEvent>(buf)
+}
+#[inline]
+/// Assumes, without verification, that a buffer of bytes contains a size prefixed TraceEvent and returns it.
+/// # Safety
+/// Callers must trust the given bytes do indeed contain a valid size prefixed `TraceEvent`.
+pub unsafe fn size_prefixed_root_as_trace_event_unchecked(buf: &[u8]) -> TraceEvent {
+  flatbuffers::size_prefixed_root_unchecked::<TraceEvent>(buf)
+}
+#[inline]
+pub fn finish_trace_event_buffer<'a, 'b>(
+    fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+    root: flatbuffers::WIPOffset<TraceEvent<'a>>) {
+  fbb.finish(root, None);
+}
+
+#[inline]
+pub fn finish_size_prefixed_trace_event_buffer<'a, 'b>(fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>, root: flatbuffers::WIPOffset<TraceEvent<'a>>) {
+  fbb.finish_size_prefixed(root, None);
+}
+}  // pub mod trace
+}  // pub mod cairn